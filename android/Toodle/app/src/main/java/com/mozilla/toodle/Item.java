@@ -7,12 +7,11 @@ package com.mozilla.toodle;
 
 import android.content.Context;
 
-import com.mozilla.toodle.rust.NativeItem;
+import com.mozilla.toodle.rust.ItemRecord;
 import com.mozilla.toodle.rust.Toodle;
 
 import java.util.ArrayList;
 import java.util.Calendar;
-import java.util.List;
 
 public class Item {
     private String uuid;
@@ -57,44 +56,30 @@ public class Item {
         return this;
     }
 
-    private static Item fromNativeItem(NativeItem nativeItem) {
+    private static Item fromRecord(ItemRecord record) {
         final Item item = new Item();
-        item.uuid = nativeItem.uuid;
-        item.name = nativeItem.itemName;
-        if (nativeItem.dueDate != null) {
-            item.dueDate = nativeItem.dueDate.getValue().longValue();
-            if (item.dueDate == 0) {
-                item.dueDate = null;
-            }
-        }
-        if (nativeItem.completionDate != null) {
-            item.completionDate = nativeItem.completionDate.getValue().longValue();
-            if (item.completionDate == 0) {
-                item.completionDate = null;
-            }
-        }
+        item.uuid = record.uuid;
+        item.name = record.name;
+        item.dueDate = record.dueDate;
+        item.completionDate = record.completionDate;
         return item;
     }
 
-    static ArrayList<Item> fromNativeItems(List<NativeItem> nativeItems) {
-        final ArrayList<Item> items = new ArrayList<>(nativeItems.size());
+    static ArrayList<Item> fromRecords(ItemRecord[] records) {
+        final ArrayList<Item> items = new ArrayList<>(records.length);
 
-        for (NativeItem nativeItem : nativeItems) {
-            items.add(fromNativeItem(nativeItem));
+        for (ItemRecord record : records) {
+            items.add(fromRecord(record));
         }
 
         return items;
     }
 
     void create(Context context) {
-        try (final Toodle toodle = new Toodle(context)) {
-            toodle.createItem(this);
-        }
+        Toodle.shared(context).createItem(this);
     }
 
     void update(Context context) {
-        try (final Toodle toodle = new Toodle(context)) {
-            toodle.updateItem(this);
-        }
+        Toodle.shared(context).updateItem(this);
     }
 }
\ No newline at end of file
@@ -8,7 +8,6 @@ package com.mozilla.toodle;
 import android.content.Context;
 import android.os.Handler;
 import android.os.Looper;
-import android.support.annotation.Nullable;
 import android.support.v7.widget.RecyclerView;
 import android.util.Log;
 import android.view.LayoutInflater;
@@ -18,10 +17,10 @@ import android.widget.CheckBox;
 import android.widget.LinearLayout;
 import android.widget.TextView;
 
-import com.mozilla.toodle.rust.NativeItemSet;
-import com.mozilla.toodle.rust.NativeItemsCallback;
+import com.mozilla.toodle.rust.ItemRecord;
 import com.mozilla.toodle.rust.NativeItemsChangedCallback;
 import com.mozilla.toodle.rust.Toodle;
+import com.sun.jna.Pointer;
 
 import java.lang.ref.WeakReference;
 import java.text.SimpleDateFormat;
@@ -42,51 +41,41 @@ public class ListAdapter extends RecyclerView.Adapter<ListAdapter.ViewHolder> {
         }
 
         @Override
-        public void items() {
+        public void changed(Pointer context, int topic, int kind, Pointer ids, int count) {
+            items();
+        }
+
+        void items() {
             final ListAdapter listAdapter = listAdapterWeakReference.get();
             if (listAdapter == null) {
                 return;
             }
 
             Log.i(LOG_TAG, "Items changed!");
-            try (final Toodle toodle = new Toodle(listAdapter.context)) {
-                toodle.getAllItems(new NativeItemsCallback() {
-                    @Override
-                    public void items(@Nullable NativeItemSet.ByReference itemSet) {
-                        if (itemSet == null) {
-                            Log.i(LOG_TAG, "Got no items!");
-                            listAdapter.dataset = new ArrayList<>(0);
-                            return;
-                        }
-                        Log.i(LOG_TAG, "Got " + itemSet.size() + " items!");
-                        listAdapter.dataset = Item.fromNativeItems(itemSet.getItems());
-
-                        new Handler(Looper.getMainLooper()).post(new Runnable() {
-                            @Override
-                            public void run() {
-                                listAdapter.notifyDataSetChanged();
-                            }
-                        });
-
-                        itemSet.close();
-                    }
-                });
-            }
+            final ItemRecord[] records = Toodle.shared(listAdapter.context).allItems();
+            Log.i(LOG_TAG, "Got " + records.length + " items!");
+            listAdapter.dataset = Item.fromRecords(records);
+
+            new Handler(Looper.getMainLooper()).post(new Runnable() {
+                @Override
+                public void run() {
+                    listAdapter.notifyDataSetChanged();
+                }
+            });
         }
     }
 
     // We must keep reference to the callback around, otherwise it'll get GC'd and the native code
     // will call an empty stub instead of our callback.
-    private final NativeItemsChangedCallback nativeItemsChangedCallback = new NativeItemsCallbackInner(
+    private final NativeItemsCallbackInner nativeItemsChangedCallback = new NativeItemsCallbackInner(
             new WeakReference<>(this)
     );
 
     ListAdapter(Context context) {
         this.context = context;
 
-        try (final Toodle toodle = new Toodle(context)) {
-            toodle.registerChangedItemsCallback(nativeItemsChangedCallback);
-        }
+        Toodle.shared(context).registerChangedItemsCallback(nativeItemsChangedCallback);
+        nativeItemsChangedCallback.items();
     }
 
     static class ViewHolder extends RecyclerView.ViewHolder {
@@ -0,0 +1,25 @@
+/* -*- Mode: Java; c-basic-offset: 4; tab-width: 20; indent-tabs-mode: nil; -*-
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+package com.mozilla.toodle.rust;
+
+/**
+ * An item as read over JNI. Dates are in seconds since the epoch.
+ */
+public final class ItemRecord {
+    public final String uuid;
+    public final String name;
+    public final Long dueDate;
+    public final Long completionDate;
+    public final LabelRecord[] labels;
+
+    ItemRecord(String uuid, String name, Long dueDate, Long completionDate, LabelRecord[] labels) {
+        this.uuid = uuid;
+        this.name = name;
+        this.dueDate = dueDate;
+        this.completionDate = completionDate;
+        this.labels = labels;
+    }
+}
@@ -18,12 +18,17 @@ public interface JNA extends Library {
 
     JNA INSTANCE = (JNA) Native.loadLibrary(JNA_LIBRARY_NAME, JNA.class);
 
-    Pointer new_toodle(String dbPath);
-    void toodle_destroy(Pointer toodle);
+    long new_toodle(String dbPath, String key, NativeError.ByReference error);
+    void toodle_destroy(long toodle, NativeError.ByReference error);
+    void toodle_rekey(long toodle, String key, NativeError.ByReference error);
 
-    void toodle_create_item(Pointer listManager, String name, NativeLongByReference dueDate);
-    void toodle_update_item_by_uuid(Pointer listManager, String uuid, String name, NativeLongByReference dueDate, NativeLongByReference completionDate);
-    void toodle_on_items_changed(NativeItemsChangedCallback callback);
-    void toodle_all_items(Pointer listManager, NativeItemsCallback callback);
-    void item_c_destroy(Pointer item);
+    long toodle_create_item(long toodle, String name, NativeLongByReference dueDate, NativeError.ByReference error);
+    void toodle_update_item_by_uuid(long toodle, String uuid, String name, NativeLongByReference dueDate, NativeLongByReference completionDate, NativeError.ByReference error);
+    void toodle_delete_item_by_uuid(long toodle, String uuid, NativeError.ByReference error);
+    long toodle_register_observer(long toodle, int topics, Pointer context, NativeItemsChangedCallback callback, NativeError.ByReference error);
+    int toodle_unregister_observer(long toodle, long token, NativeError.ByReference error);
+    void item_destroy(long item, NativeError.ByReference error);
+    Pointer toodle_call(long toodle, String request);
+    void toodle_string_destroy(Pointer string);
+    void toodle_error_destroy(NativeError error);
 }
@@ -5,8 +5,12 @@
 
 package com.mozilla.toodle.rust;
 
-import com.sun.jna.Callback;
+public final class LabelRecord {
+    public final String name;
+    public final String color;
 
-public interface NativeItemsCallback extends Callback {
-    void items(NativeItemSet.ByReference items);
+    LabelRecord(String name, String color) {
+        this.name = name;
+        this.color = color;
+    }
 }
@@ -0,0 +1,45 @@
+/* -*- Mode: Java; c-basic-offset: 4; tab-width: 20; indent-tabs-mode: nil; -*-
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+package com.mozilla.toodle.rust;
+
+import android.util.Log;
+
+import com.sun.jna.Pointer;
+import com.sun.jna.Structure;
+
+import java.util.Arrays;
+import java.util.List;
+
+/**
+ * Mirrors ExternError: filled in by every fallible native call.
+ */
+public class NativeError extends Structure {
+    public static class ByReference extends NativeError implements Structure.ByReference {
+    }
+
+    public static final int SUCCESS = 0;
+    public static final int WRONG_KEY = 9;
+
+    public int code;
+    public Pointer message;
+
+    @Override
+    protected List<String> getFieldOrder() {
+        return Arrays.asList("code", "message");
+    }
+
+    /**
+     * Logs and releases the error, if there was one. Returns true if the call succeeded.
+     */
+    public boolean consume() {
+        if (code == SUCCESS) {
+            return true;
+        }
+        Log.e("NativeError", "Toodle error " + code + ": " + (message != null ? message.getString(0, "utf8") : ""));
+        JNA.INSTANCE.toodle_error_destroy(this);
+        return false;
+    }
+}
@@ -6,7 +6,16 @@
 package com.mozilla.toodle.rust;
 
 import com.sun.jna.Callback;
+import com.sun.jna.Pointer;
 
 public interface NativeItemsChangedCallback extends Callback {
-    void items();
+    int TOPIC_ITEMS = 1;
+    int TOPIC_LABELS = 2;
+
+    int CHANGE_CREATED = 0;
+    int CHANGE_UPDATED = 1;
+    int CHANGE_DELETED = 2;
+
+    // `ids` is only valid for the duration of the call.
+    void changed(Pointer context, int topic, int kind, Pointer ids, int count);
 }
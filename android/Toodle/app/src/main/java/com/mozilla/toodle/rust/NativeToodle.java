@@ -0,0 +1,36 @@
+/* -*- Mode: Java; c-basic-offset: 4; tab-width: 20; indent-tabs-mode: nil; -*-
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+package com.mozilla.toodle.rust;
+
+/**
+ * JNI entry points, implemented in the library's jni module. Unlike the {@link JNA} calls
+ * these build Java objects directly, and report failures by throwing: IllegalArgumentException
+ * for bad arguments, IllegalStateException for a destroyed Toodle and {@link ToodleException}
+ * for everything else.
+ *
+ * Toodles are the same handles {@link JNA} uses, so the two can be mixed.
+ */
+final class NativeToodle {
+    static {
+        System.loadLibrary("toodle");
+    }
+
+    private NativeToodle() {}
+
+    static native long newToodle(String dbPath);
+    static native void destroy(long toodle);
+
+    static native ItemRecord createItem(long toodle, String name, Long dueDate);
+    static native void updateItem(long toodle, String uuid, String name, Long dueDate, Long completionDate);
+    static native void deleteItem(long toodle, String uuid);
+    static native ItemRecord[] allItems(long toodle);
+    static native ItemRecord[] searchItems(long toodle, String query);
+
+    static native LabelRecord[] allLabels(long toodle);
+    static native LabelRecord createLabel(long toodle, String name, String color);
+    static native void deleteLabel(long toodle, String name);
+    static native void setItemLabel(long toodle, String uuid, String labelName, boolean add);
+}
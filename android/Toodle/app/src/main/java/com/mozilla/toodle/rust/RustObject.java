@@ -5,10 +5,8 @@
 
 package com.mozilla.toodle.rust;
 
-import com.sun.jna.Pointer;
-
 import java.io.Closeable;
 
 /* package-private */ abstract class RustObject implements Closeable {
-    Pointer rawPointer;
+    long handle;
 }
@@ -10,27 +10,62 @@ import android.util.Log;
 
 import com.mozilla.toodle.Item;
 import com.sun.jna.NativeLong;
+import com.sun.jna.Pointer;
 import com.sun.jna.ptr.NativeLongByReference;
 
 public class Toodle extends RustObject {
-    static {
-        System.loadLibrary("toodle");
-    }
-
     private static final String DB_NAME = "toodle.db";
 
+    private static Toodle shared;
+
+    // Observers only hear about changes made through the instance they're registered with,
+    // so everything that reads or writes the list in this process goes through this one.
+    public static synchronized Toodle shared(Context context) {
+        if (shared == null) {
+            shared = new Toodle(context.getApplicationContext());
+        }
+        return shared;
+    }
+
     public Toodle(Context context) {
-        this.rawPointer = JNA.INSTANCE.new_toodle(
-                context.getDatabasePath(DB_NAME).getAbsolutePath()
+        this(context, null);
+    }
+
+    /**
+     * Opens the store encrypted with {@code key}, or unencrypted if it's null. Encrypted
+     * stores need the library to have been built with the sqlcipher feature.
+     */
+    public Toodle(Context context, String key) {
+        final NativeError.ByReference error = new NativeError.ByReference();
+        this.handle = JNA.INSTANCE.new_toodle(
+                context.getDatabasePath(DB_NAME).getAbsolutePath(),
+                key,
+                error
         );
+        error.consume();
+    }
+
+    /**
+     * Re-encrypts a store that was opened with a key. Returns false if that failed.
+     */
+    public boolean rekey(String key) {
+        final NativeError.ByReference error = new NativeError.ByReference();
+        JNA.INSTANCE.toodle_rekey(handle, key, error);
+        return error.consume();
     }
 
     public void createItem(Item item) {
-        JNA.INSTANCE.toodle_create_item(
-                rawPointer,
+        final NativeError.ByReference error = new NativeError.ByReference();
+        final long created = JNA.INSTANCE.toodle_create_item(
+                handle,
                 item.name(),
-                new NativeLongByReference(new NativeLong(item.dueDate()))
+                new NativeLongByReference(new NativeLong(item.dueDate())),
+                error
         );
+        if (error.consume()) {
+            JNA.INSTANCE.item_destroy(created, error);
+            error.consume();
+        }
     }
 
     public void updateItem(Item item) {
@@ -41,26 +76,64 @@ public class Toodle extends RustObject {
             completionDateRef = null;
         }
 
+        final NativeError.ByReference error = new NativeError.ByReference();
         JNA.INSTANCE.toodle_update_item_by_uuid(
-                rawPointer,
+                handle,
                 item.uuid(),
                 item.name(),
                 new NativeLongByReference(new NativeLong(item.dueDate())),
-                completionDateRef
+                completionDateRef,
+                error
+        );
+        error.consume();
+    }
+
+    public void deleteItem(Item item) {
+        final NativeError.ByReference error = new NativeError.ByReference();
+        JNA.INSTANCE.toodle_delete_item_by_uuid(handle, item.uuid(), error);
+        error.consume();
+    }
+
+    public long registerChangedItemsCallback(NativeItemsChangedCallback callback) {
+        final NativeError.ByReference error = new NativeError.ByReference();
+        final long token = JNA.INSTANCE.toodle_register_observer(
+                handle, NativeItemsChangedCallback.TOPIC_ITEMS, null, callback, error
         );
+        error.consume();
+        return token;
     }
 
-    public void registerChangedItemsCallback(NativeItemsChangedCallback callback) {
-        JNA.INSTANCE.toodle_on_items_changed(callback);
+    public boolean unregisterChangedItemsCallback(long token) {
+        final NativeError.ByReference error = new NativeError.ByReference();
+        final int unregistered = JNA.INSTANCE.toodle_unregister_observer(handle, token, error);
+        return error.consume() && unregistered != 0;
     }
 
-    public void getAllItems(NativeItemsCallback callback) {
-        JNA.INSTANCE.toodle_all_items(rawPointer, callback);
+    /**
+     * Reads every item over JNI. Throws if the read fails.
+     */
+    public ItemRecord[] allItems() {
+        return NativeToodle.allItems(handle);
+    }
+
+    /**
+     * Answers a JSON request; see the library's api module for the schema. Failures are
+     * reported in the response.
+     */
+    public String call(String request) {
+        final Pointer response = JNA.INSTANCE.toodle_call(handle, request);
+        try {
+            return response.getString(0, "UTF-8");
+        } finally {
+            JNA.INSTANCE.toodle_string_destroy(response);
+        }
     }
 
     @Override
     public void close() {
         Log.i("Toodle", "close");
-        JNA.INSTANCE.toodle_destroy(rawPointer);
+        final NativeError.ByReference error = new NativeError.ByReference();
+        JNA.INSTANCE.toodle_destroy(handle, error);
+        error.consume();
     }
 }
\ No newline at end of file
@@ -0,0 +1,15 @@
+/* -*- Mode: Java; c-basic-offset: 4; tab-width: 20; indent-tabs-mode: nil; -*-
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+package com.mozilla.toodle.rust;
+
+/**
+ * Thrown by {@link NativeToodle} calls that fail for a reason other than a bad argument.
+ */
+public class ToodleException extends RuntimeException {
+    ToodleException(String message) {
+        super(message);
+    }
+}
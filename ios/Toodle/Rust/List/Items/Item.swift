@@ -4,23 +4,23 @@
 
 import Foundation
 
-class Item {
-    var raw: UnsafePointer<CItem>
+class Item: RustObject {
+    var raw: UInt64
 
-    required init(raw: UnsafePointer<CItem>) {
+    required init(raw: UInt64) {
         self.raw = raw
     }
 
-    func intoRaw() -> UnsafePointer<CItem> {
+    func intoRaw() -> UInt64 {
         return self.raw
     }
 
     deinit {
-        item_destroy(raw)
+        checked { item_destroy(raw, $0) }
     }
 
     var uuid: String? {
-        if let uuid = raw.pointee.uuid {
+        if let uuid = checked({ item_get_uuid(raw, $0) }) {
             return String(cString: uuid)
         }
         return nil
@@ -28,45 +28,83 @@ class Item {
 
     var name: String {
         get {
-            return String(cString: raw.pointee.name)
+            guard let name = checked({ item_get_name(raw, $0) }) else {
+                return ""
+            }
+            return String(cString: name)
         }
         set {
-            item_set_name(UnsafeMutablePointer<CItem>(mutating: raw), newValue)
+            checked { item_set_name(raw, newValue, $0) }
         }
     }
 
     var dueDate: Date? {
         get {
-            guard let date = raw.pointee.dueDate else {
+            guard let date = checked({ item_get_due_date(raw, $0) }) else {
                 return nil
             }
             return Date(timeIntervalSince1970: Double(date.pointee))
         }
         set {
             if let d = newValue {
-                let timestamp = d.timeIntervalSince1970
-                var date = Int64(timestamp)
-                item_set_due_date(UnsafeMutablePointer<CItem>(mutating: raw), AutoreleasingUnsafeMutablePointer<Int64>(&date))
+                var date = Int(d.timeIntervalSince1970)
+                checked { item_set_due_date(raw, &date, $0) }
             }
         }
     }
 
     var completionDate: Date? {
         get {
-            guard let date = raw.pointee.completionDate else {
+            guard let date = checked({ item_get_completion_date(raw, $0) }) else {
                 return nil
             }
             return Date(timeIntervalSince1970: Double(date.pointee))
         }
         set {
             if let d = newValue {
-                let timestamp = d.timeIntervalSince1970
-                var date = Int64(timestamp)
-                item_set_completion_date(UnsafeMutablePointer<CItem>(mutating: raw), AutoreleasingUnsafeMutablePointer<Int64>(&date))
+                var date = Int(d.timeIntervalSince1970)
+                checked { item_set_completion_date(raw, &date, $0) }
+            }
+        }
+    }
+
+    var notes: String? {
+        get {
+            guard let notes = checked({ item_get_notes(raw, $0) }) else {
+                return nil
+            }
+            return String(cString: notes)
+        }
+        set {
+            checked { item_set_notes(raw, newValue, $0) }
+        }
+    }
+
+    var priority: Int64? {
+        get {
+            guard let priority = checked({ item_get_priority(raw, $0) }) else {
+                return nil
+            }
+            return priority.pointee
+        }
+        set {
+            if var p = newValue {
+                checked { item_set_priority(raw, &p, $0) }
+            } else {
+                checked { item_set_priority(raw, nil, $0) }
             }
         }
     }
 
+    var starred: Bool {
+        get {
+            return checked({ item_get_starred(raw, $0) }) != 0
+        }
+        set {
+            checked { item_set_starred(raw, newValue ? 1 : 0, $0) }
+        }
+    }
+
     fileprivate var _labels: [Label]?
 
     var labels: [Label] {
@@ -74,10 +112,10 @@ class Item {
             if _labels == nil {
                 _labels = []
                 // TODO: When we get labels in, put this back!
-//                let ls = item_get_labels(self.raw)
+//                let ls = checked { item_get_labels(self.raw, $0) }
 //                _labels = []
-//                for index in 0..<item_labels_count(ls) {
-//                    let label = Label(raw: item_label_at(ls, index)!)
+//                for index in 0..<checked({ label_list_count(ls, $0) }) {
+//                    let label = Label(raw: checked { item_label_at(ls, index, $0) })
 //                    _labels?.append(label)
 //                }
             }
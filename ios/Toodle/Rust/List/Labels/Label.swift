@@ -6,31 +6,37 @@ import Foundation
 import UIKit
 
 class Label: RustObject {
-    var raw: OpaquePointer
+    var raw: UInt64
 
-    required init(raw: OpaquePointer) {
+    required init(raw: UInt64) {
         self.raw = raw
     }
 
-    func intoRaw() -> OpaquePointer {
+    func intoRaw() -> UInt64 {
         return self.raw
     }
 
     deinit {
-        label_destroy(raw)
+        checked { label_destroy(raw, $0) }
     }
 
     var name: String {
-        return String(cString: label_get_name(raw))
+        guard let name = checked({ label_get_name(raw, $0) }) else {
+            return ""
+        }
+        return String(cString: name)
     }
 
     var color: UIColor {
         get {
-            return UIColor(hex: String(cString: label_get_color(raw))) ?? UIColor.gray
+            guard let color = checked({ label_get_color(raw, $0) }) else {
+                return UIColor.gray
+            }
+            return UIColor(hex: String(cString: color)) ?? UIColor.gray
         }
         set {
             if let hex = newValue.toHex() {
-                label_set_color(raw, hex)
+                checked { label_set_color(raw, hex, $0) }
             }
         }
     }
@@ -5,6 +5,6 @@
 import Foundation
 
 protocol RustObject {
-    init(raw: OpaquePointer)
-    func intoRaw() -> OpaquePointer
+    init(raw: UInt64)
+    func intoRaw() -> UInt64
 }
@@ -5,6 +5,18 @@
 import Foundation
 import UIKit
 
+/// Calls `call` with an error out-parameter, logging and releasing any error it reports.
+func checked<T>(_ call: (UnsafeMutablePointer<ExternError>) -> T) -> T {
+    var error = ExternError(code: ErrorCodeSuccess, message: nil)
+    let result = call(&error)
+    if error.code != ErrorCodeSuccess {
+        let message = error.message.map { String(cString: $0) } ?? ""
+        print("Toodle error \(error.code.rawValue): \(message)")
+        toodle_error_destroy(&error)
+    }
+    return result
+}
+
 class ToodleLib: RustObject {
 
     class var sharedInstance: ToodleLib {
@@ -14,54 +26,90 @@ class ToodleLib: RustObject {
         return Static.instance
     }
 
-    var raw: OpaquePointer
+    var raw: UInt64
 
-    required init(raw: OpaquePointer) {
+    required init(raw: UInt64) {
         self.raw = raw
     }
 
-    func intoRaw() -> OpaquePointer {
+    func intoRaw() -> UInt64 {
         return self.raw
     }
 
-    convenience init() {
+    /// Opens the store encrypted with `key`, or unencrypted without one. Encrypted stores
+    /// need the library to have been built with the sqlcipher feature.
+    convenience init(key: String? = nil) {
         let paths = FileManager.default.urls(for: .documentDirectory, in: .userDomainMask)
         let documentsURL = paths[0]
         let storeURI = documentsURL.appendingPathComponent("todolist.db", isDirectory: false).absoluteString
-        self.init(raw: new_toodle(storeURI))
+        self.init(raw: checked { error -> UInt64 in
+            guard let key = key else {
+                return new_toodle(storeURI, nil, error)
+            }
+            return new_toodle(storeURI, key, error)
+        })
+    }
+
+    /// Re-encrypts a store that was opened with a key, so it must be opened with `key` from now on.
+    func rekey(_ key: String) {
+        checked { toodle_rekey(self.raw, key, $0) }
     }
 
         deinit {
-            toodle_destroy(raw)
+            checked { toodle_destroy(raw, $0) }
         }
 
-    fileprivate func toPointerArray(list: [RustObject]) -> OpaquePointer {
-        var pointerArray = list.map({ $0.intoRaw() })
-        return OpaquePointer(AutoreleasingUnsafeMutablePointer<[OpaquePointer]>(&pointerArray))
-    }
-
     func allItems() -> [Item] {
-        let items = toodle_get_all_items(self.raw)
+        let items = checked { toodle_get_all_items(self.raw, $0) }
+        guard items != 0 else {
+            return []
+        }
+        defer { checked { item_list_destroy(items, $0) } }
         var allItems: [Item] = []
-        for index in 0..<item_list_count(items) {
-            let item = Item(raw: item_list_entry_at(items, Int(index))!)
-            allItems.append(item)
+        for index in 0..<checked({ item_list_count(items, $0) }) {
+            let item = checked { item_list_item_at(items, index, $0) }
+            if item != 0 {
+                allItems.append(Item(raw: item))
+            }
         }
         return allItems
     }
 
-    //    func allLabels() -> [Label] {
-    //        let labels = list_manager_get_all_labels(self.raw)
-    //        var allLabels: [Label] = []
-    //        for index in 0..<label_list_count(labels) {
-    //            let label = Label(raw: label_list_entry_at(labels, index))
-    //            allLabels.append(label)
-    //        }
-    //        return allLabels
-    //    }
+    func allLabels() -> [Label] {
+        let labels = checked { toodle_get_all_labels(self.raw, $0) }
+        guard labels != 0 else {
+            return []
+        }
+        defer { checked { label_list_destroy(labels, $0) } }
+        var allLabels: [Label] = []
+        for index in 0..<checked({ label_list_count(labels, $0) }) {
+            let label = checked { label_list_entry_at(labels, index, $0) }
+            if label != 0 {
+                allLabels.append(Label(raw: label))
+            }
+        }
+        return allLabels
+    }
+
+    /// Answers a JSON request; see the library's api module for the schema.
+    func call(_ request: String) -> String? {
+        guard let response = toodle_call(self.raw, request) else {
+            return nil
+        }
+        defer { toodle_string_destroy(response) }
+        return String(cString: response)
+    }
+
+    fileprivate func toLabelList(labels: [Label]) -> UInt64 {
+        let list = label_list_new()
+        for label in labels {
+            checked { add_label(list, label.raw, $0) }
+        }
+        return list
+    }
 
     func createLabel(withName name: String, color: UIColor) -> Label {
-        return Label(raw: toodle_create_label(self.raw, name, color.toHex()!))
+        return Label(raw: checked { toodle_create_label(self.raw, name, color.toHex()!, $0) })
     }
 
     func createItem(withName name: String, dueDate: Date?, completionDate: Date?, labels: [Label]) -> Item? {
@@ -70,11 +118,15 @@ class ToodleLib: RustObject {
             var d = due.asInt64Timestamp()
             dd = UnsafeMutablePointer<Int64>(&d)
         }
-        var pointerArray = self.toPointerArray(list: labels as [RustObject])
-        return Item(raw: toodle_create_item(self.raw,
-                                                  name,
-                                                  dd,
-                                                  UnsafeMutablePointer<OpaquePointer>(&pointerArray))!)
+        let raw = checked { toodle_create_item(self.raw, name, dd, $0) }
+        guard raw != 0 else {
+            return nil
+        }
+        let item = Item(raw: raw)
+        if !labels.isEmpty {
+            update(item: item, name: name, dueDate: dueDate, completionDate: completionDate, labels: labels)
+        }
+        return item
     }
 
     func update(item: Item, name: String, dueDate: Date?, completionDate: Date?, labels: [Label]) {
@@ -88,13 +140,15 @@ class ToodleLib: RustObject {
             var c = completion.asInt64Timestamp()
             cd = AutoreleasingUnsafeMutablePointer<Int64>(&c)
         }
-        var pointerArray = self.toPointerArray(list: labels as [RustObject])
-        toodle_update_item(raw,
-                                 item.raw,
-                                 name,
-                                 dd,
-                                 cd,
-                                 UnsafeMutablePointer<OpaquePointer>(&pointerArray))
+        let labelList = self.toLabelList(labels: labels)
+        defer { checked { label_list_destroy(labelList, $0) } }
+        checked { toodle_update_item(raw,
+                                     item.raw,
+                                     name,
+                                     dd,
+                                     cd,
+                                     labelList,
+                                     $0) }
     }
 }
 
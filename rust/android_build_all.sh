@@ -1,3 +1,4 @@
+cargo check --features android
 cargo build --target i686-linux-android
 cargo build --target armv7-linux-androideabi
 cargo build --target aarch64-linux-android
@@ -0,0 +1,153 @@
+// Copyright 2016 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! Compares hydrating a whole list with `fetch_items` against the per-row hydration it
+//! replaced, which ran a query for each of an item's due date, completion date and labels,
+//! and against fetching every item individually with `fetch_item`.
+//!
+//! Run with `cargo bench --bench fetch_items [-- <item count>]`.
+
+extern crate mentat;
+extern crate mentat_core;
+extern crate store;
+extern crate time;
+extern crate toodle;
+
+use std::env;
+use std::fs;
+use std::process;
+
+use mentat::query::{
+    IntoResult,
+    Variable,
+};
+use mentat_core::{
+    TypedValue,
+    Uuid,
+};
+
+use store::{
+    Store,
+    StoreConnection,
+    ToInner,
+    ToTypedValue,
+};
+
+use time::{
+    Timespec,
+    now_utc,
+    precise_time_ns,
+};
+
+use toodle::Toodle;
+use toodle::items::Item;
+
+/// Hydrates every item the way `fetch_items` used to: one query for the rows, then three
+/// more for each row.
+fn fetch_items_per_row(connection: &StoreConnection) -> usize {
+    let rows = connection.query(r#"[:find ?eid ?uuid ?name
+                                    :where
+                                    [?eid :item/uuid ?uuid]
+                                    [?eid :item/name ?name]]"#)
+                         .into_rel_result()
+                         .expect("expected rows");
+    let mut hydrated = 0;
+    for row in rows.into_iter() {
+        let uuid: Uuid = row[1].clone().to_inner();
+        let _due_date = item_date(connection, &uuid, "due_date");
+        let _completion_date = item_date(connection, &uuid, "completion_date");
+        let _labels = connection.query_args(r#"[:find ?l ?name ?color
+                                                :in ?uuid
+                                                :where
+                                                [?i :item/uuid ?uuid]
+                                                [?i :item/label ?l]
+                                                [?l :label/name ?name]
+                                                [?l :label/color ?color]]"#,
+                                            vec![(Variable::from_valid_name("?uuid"), uuid.to_typed_value())])
+                                .into_rel_result()
+                                .expect("expected labels");
+        hydrated += 1;
+    }
+    hydrated
+}
+
+fn item_date(connection: &StoreConnection, uuid: &Uuid, attribute: &str) -> Option<Timespec> {
+    let query = format!(r#"[:find ?date .
+                            :in ?uuid
+                            :where
+                            [?eid :item/uuid ?uuid]
+                            [?eid :item/{} ?date]]"#, attribute);
+    let date: Option<TypedValue> = connection.query_args(&query, vec![(Variable::from_valid_name("?uuid"), uuid.to_typed_value())])
+                                             .into_scalar_result()
+                                             .expect("expected a date option");
+    date.and_then(|date| date.to_inner())
+}
+
+fn main() {
+    let count = env::args()
+                    .skip(1)
+                    .filter_map(|arg| arg.parse::<usize>().ok())
+                    .next()
+                    .unwrap_or(5000);
+
+    // The per-row baseline needs a connection of its own, so the store lives in a file.
+    let path = env::temp_dir().join(format!("toodle-bench-fetch-items-{}.db", process::id()));
+    let uri = path.to_str().expect("expected a UTF-8 path").to_string();
+    let mut toodle = Toodle::new(uri.clone(), None).expect("Expected a Toodle");
+    let labels: Vec<_> = (0..5).map(|i| {
+        toodle.create_label(format!("label{}", i), "#000000".to_string())
+              .expect("expected a label option")
+              .expect("expected a label")
+    }).collect();
+
+    let date = now_utc().to_timespec();
+    let mut uuids = Vec::with_capacity(count);
+    for i in 0..count {
+        let item = Item {
+            id: None,
+            uuid: Uuid::nil(),
+            name: format!("item {}", i),
+            due_date: if i % 2 == 0 { Some(date) } else { None },
+            completion_date: if i % 3 == 0 { Some(date) } else { None },
+            notes: None,
+            priority: None,
+            starred: false,
+            recurrence: None,
+            labels: vec![labels[i % labels.len()].clone()],
+        };
+        uuids.push(toodle.create_item(&item).expect("expected a uuid"));
+    }
+
+    let connection = Store::new_store(uri, None).expect("expected a store");
+    let start = precise_time_ns();
+    assert_eq!(fetch_items_per_row(&connection), count);
+    let per_row = precise_time_ns() - start;
+
+    let start = precise_time_ns();
+    let items = toodle.fetch_items().expect("expected items");
+    let batched = precise_time_ns() - start;
+    assert_eq!(items.vec.len(), count);
+
+    let start = precise_time_ns();
+    for uuid in uuids.iter() {
+        toodle.fetch_item(uuid).expect("expected an item option").expect("expected an item");
+    }
+    let per_item = precise_time_ns() - start;
+
+    drop(connection);
+    drop(toodle);
+    let _ = fs::remove_file(&path);
+
+    println!("{} items", count);
+    println!("per-row hydration:      {:>8.2} ms", per_row as f64 / 1_000_000.0);
+    println!("fetch_items (batched):  {:>8.2} ms", batched as f64 / 1_000_000.0);
+    println!("fetch_item (per item):  {:>8.2} ms", per_item as f64 / 1_000_000.0);
+    println!("speedup over per-row:   {:>8.2}x", per_row as f64 / batched as f64);
+}
@@ -0,0 +1,408 @@
+// Copyright 2016 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+use time;
+
+use toodle::api::FilterParams;
+use toodle::filters::SortKey;
+
+pub const USAGE: &'static str = "\
+usage: toodle [--db PATH] COMMAND [ARGS]
+
+The store is PATH, $TOODLE_DB or ~/.toodle/toodle.db. Dates are YYYY-MM-DD (UTC) or
+seconds since the epoch. Syncing with --keys encrypts everything the server keeps with the
+key bundle in FILE, which every store that syncs through it needs a copy of.
+
+An encrypted store is opened with the key in $TOODLE_KEY, which needs a build with the
+sqlcipher feature. rekey re-encrypts it with the key in $TOODLE_NEW_KEY.
+
+--repeat takes an RRULE such as FREQ=WEEKLY;BYDAY=MO,TH; completing the item adds its
+next occurrence.
+
+commands:
+  add NAME [--due DATE] [--label NAME]... [--parent UUID] [--repeat RULE]
+  list [--done | --todo] [--due-before DATE] [--due-after DATE] [--overdue]
+       [--label NAME]... [--all-labels NAME]... [--without-label NAME]...
+       [--starred] [--min-priority N] [--sort name|due|completed|priority] [--desc]
+       [--limit N] [--offset N] [--json]
+  done UUID [--undo]
+  move UUID [--parent UUID]
+  tree [UUID]
+  edit UUID [--name NAME] [--due DATE|none] [--completed DATE|none]
+       [--label NAME]... [--unlabel NAME]...
+  label add NAME COLOR
+  label rm NAME
+  label list
+  search QUERY [--json]
+  export [FILE]
+  import FILE
+  import-legacy FILE
+  sync DIRECTORY|URL [--keys FILE]
+  keys new FILE
+  keys rotate FILE
+  rekey
+";
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Command {
+    Add { name: String, due: Option<i64>, labels: Vec<String>, parent: Option<String>, repeat: Option<String> },
+    List { filter: FilterParams, json: bool },
+    Done { uuid: String, undo: bool },
+    /// Moves an item under another, or to the top level if there's no parent.
+    Move { uuid: String, parent: Option<String> },
+    /// Shows every item, or one item, with its subtasks.
+    Tree { uuid: Option<String> },
+    Edit {
+        uuid: String,
+        name: Option<String>,
+        due: Option<Option<i64>>,
+        completed: Option<Option<i64>>,
+        add_labels: Vec<String>,
+        remove_labels: Vec<String>,
+    },
+    LabelAdd { name: String, color: String },
+    LabelRemove { name: String },
+    LabelList,
+    Search { query: String, json: bool },
+    /// Writes to stdout if there's no path.
+    Export { path: Option<String> },
+    Import { path: String },
+    /// Imports a list kept in the plain SQL schema from before Mentat.
+    ImportLegacy { path: String },
+    /// Syncs through a log kept in a directory, or on a server if it's an `http://` URL.
+    Sync { server: String, keys: Option<String> },
+    /// Writes a new key bundle.
+    KeysNew { path: String },
+    /// Adds a new current key to a key bundle.
+    KeysRotate { path: String },
+    /// Re-encrypts the store with the key in `$TOODLE_NEW_KEY`.
+    Rekey,
+    Help,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Options {
+    pub db: Option<String>,
+    pub command: Command,
+}
+
+struct Args {
+    args: Vec<String>,
+    next: usize,
+}
+
+impl Args {
+    fn next(&mut self) -> Option<String> {
+        let arg = self.args.get(self.next).cloned();
+        self.next += 1;
+        arg
+    }
+
+    fn required(&mut self, name: &str) -> Result<String, String> {
+        match self.next() {
+            Some(ref arg) if arg.starts_with("--") => Err(format!("expected {}, not {}", name, arg)),
+            Some(arg) => Ok(arg),
+            None => Err(format!("missing {}", name)),
+        }
+    }
+
+    fn value(&mut self, flag: &str) -> Result<String, String> {
+        self.next().ok_or_else(|| format!("{} needs a value", flag))
+    }
+
+    fn finish(&mut self) -> Result<(), String> {
+        match self.next() {
+            Some(arg) => Err(format!("unexpected argument {}", arg)),
+            None => Ok(()),
+        }
+    }
+}
+
+pub fn parse_date(date: &str) -> Result<i64, String> {
+    if let Ok(seconds) = date.parse::<i64>() {
+        return Ok(seconds);
+    }
+    time::strptime(date, "%Y-%m-%d")
+        .map(|tm| tm.to_timespec().sec)
+        .map_err(|_| format!("{:?} is not a date", date))
+}
+
+fn parse_optional_date(date: &str) -> Result<Option<i64>, String> {
+    if date == "none" {
+        Ok(None)
+    } else {
+        parse_date(date).map(Some)
+    }
+}
+
+fn parse_number(flag: &str, number: &str) -> Result<usize, String> {
+    number.parse().map_err(|_| format!("{} needs a number, not {:?}", flag, number))
+}
+
+fn parse_list(args: &mut Args) -> Result<Command, String> {
+    let mut filter = FilterParams::default();
+    let mut json = false;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--done" => filter.completed = Some(true),
+            "--todo" => filter.completed = Some(false),
+            "--due-before" => filter.due_before = Some(parse_date(&args.value(&arg)?)?),
+            "--due-after" => filter.due_after = Some(parse_date(&args.value(&arg)?)?),
+            "--overdue" => filter.overdue = true,
+            "--label" => filter.any_labels.push(args.value(&arg)?),
+            "--all-labels" => filter.all_labels.push(args.value(&arg)?),
+            "--without-label" => filter.no_labels.push(args.value(&arg)?),
+            "--starred" => filter.starred = Some(true),
+            "--min-priority" => {
+                let value = args.value(&arg)?;
+                filter.min_priority = Some(value.parse().map_err(|_| format!("{} needs a number, not {:?}", arg, value))?);
+            },
+            "--sort" => {
+                filter.sort_by = Some(match args.value(&arg)?.as_str() {
+                    "name" => SortKey::Name,
+                    "due" => SortKey::DueDate,
+                    "completed" => SortKey::CompletionDate,
+                    "priority" => SortKey::Priority,
+                    other => return Err(format!("can't sort by {:?}", other)),
+                });
+            },
+            "--desc" => filter.descending = true,
+            "--limit" => filter.limit = Some(parse_number(&arg, &args.value(&arg)?)?),
+            "--offset" => filter.offset = parse_number(&arg, &args.value(&arg)?)?,
+            "--json" => json = true,
+            _ => return Err(format!("unexpected argument {}", arg)),
+        }
+    }
+    Ok(Command::List { filter: filter, json: json })
+}
+
+fn parse_command(command: &str, args: &mut Args) -> Result<Command, String> {
+    let parsed = match command {
+        "add" => {
+            let name = args.required("a name")?;
+            let (mut due, mut parent, mut repeat) = (None, None, None);
+            let mut labels = vec![];
+            while let Some(arg) = args.next() {
+                match arg.as_str() {
+                    "--due" => due = Some(parse_date(&args.value(&arg)?)?),
+                    "--label" => labels.push(args.value(&arg)?),
+                    "--parent" => parent = Some(args.value(&arg)?),
+                    "--repeat" => repeat = Some(args.value(&arg)?),
+                    _ => return Err(format!("unexpected argument {}", arg)),
+                }
+            }
+            Command::Add { name: name, due: due, labels: labels, parent: parent, repeat: repeat }
+        },
+        "list" => parse_list(args)?,
+        "done" => {
+            let uuid = args.required("a uuid")?;
+            let undo = match args.next() {
+                Some(ref arg) if arg == "--undo" => true,
+                Some(arg) => return Err(format!("unexpected argument {}", arg)),
+                None => false,
+            };
+            Command::Done { uuid: uuid, undo: undo }
+        },
+        "move" => {
+            let uuid = args.required("a uuid")?;
+            let parent = match args.next() {
+                Some(ref arg) if arg == "--parent" => Some(args.value(arg)?),
+                Some(arg) => return Err(format!("unexpected argument {}", arg)),
+                None => None,
+            };
+            args.finish()?;
+            Command::Move { uuid: uuid, parent: parent }
+        },
+        "tree" => {
+            let uuid = args.next();
+            args.finish()?;
+            Command::Tree { uuid: uuid }
+        },
+        "edit" => {
+            let uuid = args.required("a uuid")?;
+            let (mut name, mut due, mut completed) = (None, None, None);
+            let (mut add_labels, mut remove_labels) = (vec![], vec![]);
+            while let Some(arg) = args.next() {
+                match arg.as_str() {
+                    "--name" => name = Some(args.value(&arg)?),
+                    "--due" => due = Some(parse_optional_date(&args.value(&arg)?)?),
+                    "--completed" => completed = Some(parse_optional_date(&args.value(&arg)?)?),
+                    "--label" => add_labels.push(args.value(&arg)?),
+                    "--unlabel" => remove_labels.push(args.value(&arg)?),
+                    _ => return Err(format!("unexpected argument {}", arg)),
+                }
+            }
+            Command::Edit {
+                uuid: uuid,
+                name: name,
+                due: due,
+                completed: completed,
+                add_labels: add_labels,
+                remove_labels: remove_labels,
+            }
+        },
+        "label" => {
+            let parsed = match args.required("add, rm or list")?.as_str() {
+                "add" => Command::LabelAdd { name: args.required("a name")?, color: args.required("a color")? },
+                "rm" => Command::LabelRemove { name: args.required("a name")? },
+                "list" => Command::LabelList,
+                other => return Err(format!("unknown label command {:?}", other)),
+            };
+            args.finish()?;
+            parsed
+        },
+        "search" => {
+            let query = args.required("a query")?;
+            let json = match args.next() {
+                Some(ref arg) if arg == "--json" => true,
+                Some(arg) => return Err(format!("unexpected argument {}", arg)),
+                None => false,
+            };
+            Command::Search { query: query, json: json }
+        },
+        "export" => {
+            let path = args.next();
+            args.finish()?;
+            Command::Export { path: path }
+        },
+        "import" => {
+            let path = args.required("a file")?;
+            args.finish()?;
+            Command::Import { path: path }
+        },
+        "import-legacy" => {
+            let path = args.required("a file")?;
+            args.finish()?;
+            Command::ImportLegacy { path: path }
+        },
+        "sync" => {
+            let server = args.required("a directory or URL")?;
+            let keys = match args.next() {
+                Some(ref arg) if arg == "--keys" => Some(args.value(arg)?),
+                Some(arg) => return Err(format!("unexpected argument {}", arg)),
+                None => None,
+            };
+            args.finish()?;
+            Command::Sync { server: server, keys: keys }
+        },
+        "keys" => {
+            let parsed = match args.required("new or rotate")?.as_str() {
+                "new" => Command::KeysNew { path: args.required("a file")? },
+                "rotate" => Command::KeysRotate { path: args.required("a file")? },
+                other => return Err(format!("unknown keys command {:?}", other)),
+            };
+            args.finish()?;
+            parsed
+        },
+        "rekey" => {
+            args.finish()?;
+            Command::Rekey
+        },
+        "help" | "--help" | "-h" => Command::Help,
+        _ => return Err(format!("unknown command {:?}", command)),
+    };
+    Ok(parsed)
+}
+
+/// Parses the arguments that follow the program name.
+pub fn parse(args: Vec<String>) -> Result<Options, String> {
+    let mut args = Args { args: args, next: 0 };
+    let mut db = None;
+    loop {
+        match args.next() {
+            Some(ref arg) if arg == "--db" => db = Some(args.value(arg)?),
+            Some(command) => {
+                let command = parse_command(&command, &mut args)?;
+                return Ok(Options { db: db, command: command });
+            },
+            None => return Ok(Options { db: db, command: Command::Help }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse_str(args: &str) -> Result<Options, String> {
+        parse(args.split_whitespace().map(|arg| arg.to_string()).collect())
+    }
+
+    #[test]
+    fn test_parse_commands() {
+        assert_eq!(parse_str("--db /tmp/a.db add milk --due 2017-07-14 --label home"), Ok(Options {
+            db: Some("/tmp/a.db".to_string()),
+            command: Command::Add { name: "milk".to_string(), due: Some(1499990400), labels: vec!["home".to_string()], parent: None, repeat: None },
+        }));
+        assert_eq!(parse_str("add pack --parent abc").map(|options| options.command),
+                   Ok(Command::Add { name: "pack".to_string(), due: None, labels: vec![], parent: Some("abc".to_string()), repeat: None }));
+        assert_eq!(parse_str("add bins --repeat FREQ=WEEKLY").map(|options| options.command),
+                   Ok(Command::Add { name: "bins".to_string(), due: None, labels: vec![], parent: None, repeat: Some("FREQ=WEEKLY".to_string()) }));
+        assert_eq!(parse_str("move abc --parent def").map(|options| options.command),
+                   Ok(Command::Move { uuid: "abc".to_string(), parent: Some("def".to_string()) }));
+        assert_eq!(parse_str("move abc").map(|options| options.command), Ok(Command::Move { uuid: "abc".to_string(), parent: None }));
+        assert_eq!(parse_str("tree").map(|options| options.command), Ok(Command::Tree { uuid: None }));
+        assert_eq!(parse_str("done abc --undo").map(|options| options.command),
+                   Ok(Command::Done { uuid: "abc".to_string(), undo: true }));
+        assert_eq!(parse_str("edit abc --due none --completed 100 --unlabel home").map(|options| options.command), Ok(Command::Edit {
+            uuid: "abc".to_string(),
+            name: None,
+            due: Some(None),
+            completed: Some(Some(100)),
+            add_labels: vec![],
+            remove_labels: vec!["home".to_string()],
+        }));
+        assert_eq!(parse_str("label rm home").map(|options| options.command), Ok(Command::LabelRemove { name: "home".to_string() }));
+        assert_eq!(parse_str("sync http://localhost:8088/users/alice --keys keys.json").map(|options| options.command), Ok(Command::Sync {
+            server: "http://localhost:8088/users/alice".to_string(),
+            keys: Some("keys.json".to_string()),
+        }));
+        assert_eq!(parse_str("keys rotate keys.json").map(|options| options.command), Ok(Command::KeysRotate { path: "keys.json".to_string() }));
+        assert_eq!(parse_str("import-legacy old.db").map(|options| options.command), Ok(Command::ImportLegacy { path: "old.db".to_string() }));
+        assert_eq!(parse_str("rekey").map(|options| options.command), Ok(Command::Rekey));
+        assert_eq!(parse_str("").map(|options| options.command), Ok(Command::Help));
+    }
+
+    #[test]
+    fn test_parse_list_filters() {
+        let mut expected = FilterParams::default();
+        expected.completed = Some(false);
+        expected.any_labels = vec!["work".to_string()];
+        expected.sort_by = Some(SortKey::DueDate);
+        expected.descending = true;
+        expected.limit = Some(5);
+        assert_eq!(parse_str("list --todo --label work --sort due --desc --limit 5").map(|options| options.command),
+                   Ok(Command::List { filter: expected, json: false }));
+    }
+
+    #[test]
+    fn test_parse_list_priorities() {
+        let mut expected = FilterParams::default();
+        expected.starred = Some(true);
+        expected.min_priority = Some(2);
+        expected.sort_by = Some(SortKey::Priority);
+        assert_eq!(parse_str("list --starred --min-priority 2 --sort priority").map(|options| options.command),
+                   Ok(Command::List { filter: expected, json: false }));
+        assert!(parse_str("list --min-priority high").is_err());
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(parse_str("frobnicate").is_err());
+        assert!(parse_str("add").is_err());
+        assert!(parse_str("add milk --due yesterday").is_err());
+        assert!(parse_str("list --limit lots").is_err());
+        assert!(parse_str("list --sort colour").is_err());
+        assert!(parse_str("label add home").is_err());
+        assert!(parse_str("sync a b").is_err());
+        assert!(parse_str("sync a --keys").is_err());
+        assert!(parse_str("keys forget keys.json").is_err());
+    }
+}
@@ -0,0 +1,447 @@
+// Copyright 2016 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+use std::collections::{
+    BTreeMap,
+    HashSet,
+};
+use std::env;
+use std::fs::File;
+use std::io::{
+    Read,
+    Write,
+};
+use std::path::{
+    Path,
+    PathBuf,
+};
+
+use serde_json::{
+    self,
+    Value,
+};
+use time::{
+    self,
+    Timespec,
+};
+
+use toodle::Toodle;
+use toodle::api::{
+    self,
+    parse_item_uuid,
+    FilterParams,
+    ItemChanges,
+    ItemRecord,
+    ItemTreeRecord,
+    LabelRecord,
+    NewItem,
+    Request,
+};
+use toodle::clock::{
+    Clock,
+    SystemClock,
+};
+use toodle::encryption::{
+    sync_with_keys,
+    KeyBundle,
+};
+use toodle::errors::{
+    Error,
+    ErrorKind,
+};
+use toodle::import::Import;
+use toodle::legacy;
+use toodle::remote::RemoteServer;
+
+use args::{
+    Command,
+    USAGE,
+};
+use directory_server::DirectoryServer;
+
+/// The format of `export` and `import`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Export {
+    pub version: u64,
+    pub labels: Vec<LabelRecord>,
+    pub items: Vec<ItemRecord>,
+    /// The uuid of each subtask's parent, by the subtask's uuid.
+    #[serde(default)]
+    pub parents: BTreeMap<String, String>,
+}
+
+const EXPORT_VERSION: u64 = 1;
+
+fn io_failed(path: &str, e: ::std::io::Error) -> Error {
+    ErrorKind::InvalidArgument(format!("{}: {}", path, e)).into()
+}
+
+fn read_file(path: &str) -> Result<String, Error> {
+    let mut contents = String::new();
+    File::open(path).and_then(|mut file| file.read_to_string(&mut contents)).map_err(|e| io_failed(path, e))?;
+    Ok(contents)
+}
+
+fn write_file(path: &str, contents: &str) -> Result<(), Error> {
+    File::create(path).and_then(|mut file| file.write_all(contents.as_bytes())).map_err(|e| io_failed(path, e))
+}
+
+fn read_keys(path: &str) -> Result<KeyBundle, Error> {
+    serde_json::from_str(&read_file(path)?)
+        .map_err(|e| ErrorKind::InvalidArgument(format!("{} isn't a key bundle: {}", path, e)).into())
+}
+
+fn write_keys(path: &str, keys: &KeyBundle) -> Result<(), Error> {
+    write_file(path, &(serde_json::to_string_pretty(keys)? + "\n"))
+}
+
+fn format_date(seconds: i64) -> String {
+    time::at_utc(Timespec::new(seconds, 0)).strftime("%Y-%m-%d").map(|date| date.to_string()).unwrap_or_else(|_| seconds.to_string())
+}
+
+fn format_item(item: &ItemRecord) -> String {
+    let mut line = format!("{} [{}] {}", item.uuid, if item.completion_date.is_some() { "x" } else { " " }, item.name);
+    if let Some(due) = item.due_date {
+        line.push_str(&format!(" (due {})", format_date(due)));
+    }
+    for label in item.labels.iter() {
+        line.push_str(&format!(" #{}", label.name));
+    }
+    if let Some(ref rule) = item.recurrence {
+        line.push_str(&format!(" (repeats {})", rule));
+    }
+    line
+}
+
+/// An item and its subtasks, indented by depth, with the share of subtasks that are done.
+fn format_tree(tree: &ItemTreeRecord, depth: usize) -> String {
+    let mut output = format!("{}{}", "  ".repeat(depth), format_item(&tree.item));
+    if tree.progress.total > 0 {
+        output.push_str(&format!(" ({}/{})", tree.progress.completed, tree.progress.total));
+    }
+    output.push('\n');
+    for child in tree.children.iter() {
+        output.push_str(&format_tree(child, depth + 1));
+    }
+    output
+}
+
+fn format_items(items: Value, json: bool) -> Result<String, Error> {
+    if json {
+        return Ok(serde_json::to_string_pretty(&items)? + "\n");
+    }
+    let items: Vec<ItemRecord> = serde_json::from_value(items)?;
+    Ok(items.iter().map(|item| format_item(item) + "\n").collect())
+}
+
+fn item(value: Value) -> Result<ItemRecord, Error> {
+    Ok(serde_json::from_value(value)?)
+}
+
+fn existing_item(toodle: &mut Toodle, uuid: &str) -> Result<ItemRecord, Error> {
+    let found = api::execute(toodle, Request::GetItem { uuid: uuid.to_string() })?;
+    if found.is_null() {
+        return Err(ErrorKind::NotFound(format!("item {}", uuid)).into());
+    }
+    item(found)
+}
+
+fn no_changes(uuid: String) -> ItemChanges {
+    ItemChanges {
+        uuid: uuid,
+        name: None,
+        due_date: None,
+        completion_date: None,
+        labels: None,
+        notes: None,
+        priority: None,
+        starred: None,
+        recurrence: None,
+    }
+}
+
+fn labels(toodle: &mut Toodle) -> Result<Vec<LabelRecord>, Error> {
+    Ok(serde_json::from_value(api::execute(toodle, Request::GetLabels)?)?)
+}
+
+fn collect_parents(tree: &ItemTreeRecord, parents: &mut BTreeMap<String, String>) {
+    for child in tree.children.iter() {
+        parents.insert(child.item.uuid.clone(), tree.item.uuid.clone());
+        collect_parents(child, parents);
+    }
+}
+
+fn export(toodle: &mut Toodle) -> Result<String, Error> {
+    let items = api::execute(toodle, Request::GetItems { filter: FilterParams::default() })?;
+    let trees: Vec<ItemTreeRecord> = serde_json::from_value(api::execute(toodle, Request::GetItemTrees)?)?;
+    let mut parents = BTreeMap::new();
+    for tree in trees.iter() {
+        collect_parents(tree, &mut parents);
+    }
+    let export = Export {
+        version: EXPORT_VERSION,
+        labels: labels(toodle)?,
+        items: serde_json::from_value(items)?,
+        parents: parents,
+    };
+    Ok(serde_json::to_string_pretty(&export)? + "\n")
+}
+
+/// Creates the labels and items in `export` that aren't already in `toodle`, all at once
+/// or not at all. Items keep their uuids and parents; an item whose uuid is already here
+/// is skipped, so importing a store's own export changes nothing.
+fn import(toodle: &mut Toodle, export: Export) -> Result<String, Error> {
+    if export.version != EXPORT_VERSION {
+        return Err(ErrorKind::InvalidArgument(format!("can't import version {} exports", export.version)).into());
+    }
+    let existing: HashSet<String> = labels(toodle)?.into_iter().map(|label| label.name).collect();
+    let mut import = Import::default();
+    import.labels = export.labels.iter().filter(|label| !existing.contains(&label.name)).map(LabelRecord::to_label).collect();
+    let mut skipped_items = 0;
+    for record in export.items.iter() {
+        let item = record.to_item()?;
+        if toodle.fetch_item(&item.uuid)?.is_some() {
+            skipped_items += 1;
+            continue;
+        }
+        import.items.push(item);
+    }
+    for (child, parent) in export.parents.iter() {
+        let child = parse_item_uuid(child)?;
+        if import.items.iter().any(|item| item.uuid == child) {
+            import.parents.insert(child, parse_item_uuid(parent)?);
+        }
+    }
+    toodle.import(&import)?;
+    Ok(format!("imported {} labels and {} items; skipped {} items that were already here\n", import.labels.len(), import.items.len(), skipped_items))
+}
+
+/// Runs `command` against `toodle`, returning what to print.
+pub fn run(toodle: &mut Toodle, command: Command) -> Result<String, Error> {
+    let output = match command {
+        Command::Add { name, due, labels, parent, repeat } => {
+            let created = item(api::execute(toodle, Request::CreateItem(NewItem {
+                name: name,
+                due_date: due,
+                completion_date: None,
+                labels: labels,
+                notes: None,
+                priority: None,
+                starred: false,
+                parent: parent,
+                recurrence: repeat,
+            }))?)?;
+            format_item(&created) + "\n"
+        },
+        Command::List { filter, json } => format_items(api::execute(toodle, Request::GetItems { filter: filter })?, json)?,
+        Command::Done { uuid, undo } => {
+            let mut changes = no_changes(uuid);
+            changes.completion_date = Some(if undo { None } else { Some(SystemClock.now().sec) });
+            format_item(&item(api::execute(toodle, Request::UpdateItem(changes))?)?) + "\n"
+        },
+        Command::Move { uuid, parent } => {
+            format_item(&item(api::execute(toodle, Request::MoveItem { uuid: uuid, parent: parent })?)?) + "\n"
+        },
+        Command::Tree { uuid: Some(uuid) } => {
+            let tree = api::execute(toodle, Request::GetItemTree { uuid: uuid.clone() })?;
+            if tree.is_null() {
+                return Err(ErrorKind::NotFound(format!("item {}", uuid)).into());
+            }
+            format_tree(&serde_json::from_value(tree)?, 0)
+        },
+        Command::Tree { uuid: None } => {
+            let trees: Vec<ItemTreeRecord> = serde_json::from_value(api::execute(toodle, Request::GetItemTrees)?)?;
+            trees.iter().map(|tree| format_tree(tree, 0)).collect()
+        },
+        Command::Edit { uuid, name, due, completed, add_labels, remove_labels } => {
+            let mut changes = no_changes(uuid.clone());
+            changes.name = name;
+            changes.due_date = due;
+            changes.completion_date = completed;
+            if !add_labels.is_empty() || !remove_labels.is_empty() {
+                let mut names: Vec<String> = existing_item(toodle, &uuid)?.labels.into_iter()
+                    .map(|label| label.name)
+                    .filter(|name| !remove_labels.contains(name) && !add_labels.contains(name))
+                    .collect();
+                names.extend(add_labels);
+                changes.labels = Some(names);
+            }
+            format_item(&item(api::execute(toodle, Request::UpdateItem(changes))?)?) + "\n"
+        },
+        Command::LabelAdd { name, color } => {
+            api::execute(toodle, Request::CreateLabel { name: name, color: color })?;
+            String::new()
+        },
+        Command::LabelRemove { name } => {
+            api::execute(toodle, Request::DeleteLabel { name: name })?;
+            String::new()
+        },
+        Command::LabelList => labels(toodle)?.iter().map(|label| format!("{} {}\n", label.name, label.color)).collect(),
+        Command::Search { query, json } => format_items(api::execute(toodle, Request::SearchItems { query: query })?, json)?,
+        Command::Export { path: None } => export(toodle)?,
+        Command::Export { path: Some(path) } => {
+            write_file(&path, &export(toodle)?)?;
+            String::new()
+        },
+        Command::Import { path } => {
+            let export: Export = serde_json::from_str(&read_file(&path)?)
+                .map_err(|e| Error::from(ErrorKind::InvalidArgument(format!("{} isn't an export: {}", path, e))))?;
+            import(toodle, export)?
+        },
+        Command::ImportLegacy { path } => {
+            let report = legacy::import_file(toodle, &path)?;
+            let mut output = format!("imported {} labels and {} items with {} labels between them\n", report.labels, report.items, report.links);
+            for skipped in report.skipped {
+                output.push_str(&format!("skipped {}: {}\n", skipped.what, skipped.reason));
+            }
+            output
+        },
+        Command::Sync { server, keys } => {
+            let keys = match keys {
+                Some(path) => Some(read_keys(&path)?),
+                None => None,
+            };
+            let report = if server.starts_with("http://") {
+                sync_with_keys(toodle, RemoteServer::new(&server)?, keys.as_ref())?
+            } else {
+                sync_with_keys(toodle, DirectoryServer::open(PathBuf::from(server))?, keys.as_ref())?
+            };
+            format!("uploaded {} and downloaded {} changes\n", report.uploaded, report.downloaded)
+        },
+        Command::KeysNew { path } => {
+            if Path::new(&path).exists() {
+                return Err(ErrorKind::InvalidArgument(format!("{} already exists", path)).into());
+            }
+            write_keys(&path, &KeyBundle::generate()?)?;
+            format!("wrote new keys to {}; every store that syncs with them needs a copy\n", path)
+        },
+        Command::KeysRotate { path } => {
+            let mut keys = read_keys(&path)?;
+            let id = keys.rotate()?;
+            write_keys(&path, &keys)?;
+            format!("key {} is now current; every store that syncs with these keys needs a new copy\n", id)
+        },
+        Command::Rekey => {
+            let key = env::var("TOODLE_NEW_KEY")
+                .map_err(|_| Error::from(ErrorKind::InvalidArgument("set $TOODLE_NEW_KEY to the new key".to_string())))?;
+            toodle.rekey(&key)?;
+            "re-encrypted the store; open it with the new key from now on\n".to_string()
+        },
+        Command::Help => USAGE.to_string(),
+    };
+    Ok(output)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use args::parse;
+
+    fn run_str(toodle: &mut Toodle, args: &str) -> String {
+        let options = parse(args.split_whitespace().map(|arg| arg.to_string()).collect()).expect("expected valid arguments");
+        run(toodle, options.command).expect("expected the command to succeed")
+    }
+
+    fn uuid_of(line: &str) -> String {
+        line.split_whitespace().next().expect("expected a uuid").to_string()
+    }
+
+    #[test]
+    fn test_add_edit_and_list() {
+        let mut toodle = Toodle::new(String::new(), None).expect("expected a new toodle");
+        run_str(&mut toodle, "label add work #00ff00");
+        let added = run_str(&mut toodle, "add report --due 2017-07-14 --label work");
+        assert!(added.ends_with(" [ ] report (due 2017-07-14) #work\n"));
+        let uuid = uuid_of(&added);
+        run_str(&mut toodle, "add flights");
+
+        run_str(&mut toodle, &format!("done {}", uuid));
+        assert_eq!(run_str(&mut toodle, "list --todo").lines().count(), 1);
+        assert!(run_str(&mut toodle, "list --done").contains(" [x] report"));
+
+        let edited = run_str(&mut toodle, &format!("edit {} --name summary --due none --unlabel work", uuid));
+        assert!(edited.ends_with(" [x] summary\n"));
+        run_str(&mut toodle, &format!("done {} --undo", uuid));
+        let listed = run_str(&mut toodle, "list --todo --sort name --desc");
+        let names: Vec<&str> = listed.lines().map(|line| line.splitn(2, "] ").nth(1).expect("expected a name")).collect();
+        assert_eq!(names, vec!["summary", "flights"]);
+    }
+
+    #[test]
+    fn test_subtasks() {
+        let mut toodle = Toodle::new(String::new(), None).expect("expected a new toodle");
+        let chores = uuid_of(&run_str(&mut toodle, "add chores"));
+        let dishes = uuid_of(&run_str(&mut toodle, &format!("add dishes --parent {}", chores)));
+        run_str(&mut toodle, &format!("add laundry --parent {}", chores));
+        run_str(&mut toodle, &format!("done {}", dishes));
+
+        let lines: Vec<String> = run_str(&mut toodle, "tree").lines().map(|line| line.replace(&chores, "").replace(&dishes, "")).collect();
+        assert_eq!(lines[0], " [ ] chores (1/2)");
+        assert_eq!(lines[1], "   [x] dishes");
+        assert!(lines[2].starts_with("  ") && lines[2].ends_with(" [ ] laundry"));
+
+        run_str(&mut toodle, &format!("move {}", dishes));
+        assert!(run_str(&mut toodle, &format!("tree {}", chores)).ends_with(" [ ] laundry\n"));
+        assert_eq!(run_str(&mut toodle, "tree").lines().filter(|line| !line.starts_with(" ")).count(), 2);
+    }
+
+    #[test]
+    fn test_export_and_import() {
+        let mut source = Toodle::new(String::new(), None).expect("expected a new toodle");
+        run_str(&mut source, "label add home #ff0000");
+        run_str(&mut source, "add milk --due 100 --label home --repeat FREQ=MONTHLY");
+        let exported = run(&mut source, Command::Export { path: None }).expect("expected an export");
+
+        let mut target = Toodle::new(String::new(), None).expect("expected a new toodle");
+        let export: Export = serde_json::from_str(&exported).expect("expected an export");
+        assert_eq!(import(&mut target, export).expect("expected to import"),
+                   "imported 1 labels and 1 items; skipped 0 items that were already here\n");
+        let imported = run_str(&mut target, "list");
+        assert!(imported.ends_with(" [ ] milk (due 1970-01-01) #home (repeats FREQ=MONTHLY)\n"));
+
+        // Importing a store's own export skips what it already has.
+        let export: Export = serde_json::from_str(&exported).expect("expected an export");
+        assert_eq!(import(&mut source, export).expect("expected to import"),
+                   "imported 0 labels and 0 items; skipped 1 items that were already here\n");
+    }
+
+    #[test]
+    fn test_import_keeps_uuids_and_parents() {
+        let mut source = Toodle::new(String::new(), None).expect("expected a new toodle");
+        let chores = uuid_of(&run_str(&mut source, "add chores"));
+        let dishes = uuid_of(&run_str(&mut source, &format!("add dishes --parent {}", chores)));
+        let exported = run(&mut source, Command::Export { path: None }).expect("expected an export");
+
+        let mut target = Toodle::new(String::new(), None).expect("expected a new toodle");
+        let export: Export = serde_json::from_str(&exported).expect("expected an export");
+        import(&mut target, export).expect("expected to import");
+        let tree = run_str(&mut target, &format!("tree {}", chores));
+        assert!(tree.contains(&format!("{} [ ] chores", chores)));
+        assert!(tree.contains(&format!("  {} [ ] dishes", dishes)));
+    }
+
+    #[test]
+    fn test_failed_imports_change_nothing() {
+        let mut source = Toodle::new(String::new(), None).expect("expected a new toodle");
+        run_str(&mut source, "label add home #ff0000");
+        run_str(&mut source, "add milk --label home");
+        run_str(&mut source, "add bread");
+        let exported = run(&mut source, Command::Export { path: None }).expect("expected an export");
+
+        // One item names a label that neither the export nor the store has.
+        let mut export: Export = serde_json::from_str(&exported).expect("expected an export");
+        export.items[0].labels.push(LabelRecord { name: "missing".to_string(), color: "#000000".to_string() });
+        let mut target = Toodle::new(String::new(), None).expect("expected a new toodle");
+        assert!(import(&mut target, export).is_err());
+        assert_eq!(run_str(&mut target, "list"), "");
+        assert_eq!(run_str(&mut target, "label list"), "");
+    }
+}
@@ -0,0 +1,85 @@
+// Copyright 2016 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+use std::fs::{
+    self,
+    File,
+};
+use std::io::{
+    self,
+    Read,
+    Write,
+};
+use std::path::PathBuf;
+
+use toodle::errors::{
+    Error,
+    ErrorKind,
+};
+use toodle::sync::{
+    SyncServer,
+    UploadOutcome,
+};
+
+/// A `SyncServer` whose log is a directory with one file per record, named by version.
+/// Any number of stores can sync through the same directory, one at a time.
+pub struct DirectoryServer {
+    directory: PathBuf,
+}
+
+fn sync_failed(e: io::Error) -> Error {
+    ErrorKind::SyncFailed(e.to_string()).into()
+}
+
+impl DirectoryServer {
+    pub fn open(directory: PathBuf) -> Result<DirectoryServer, Error> {
+        fs::create_dir_all(&directory).map_err(sync_failed)?;
+        Ok(DirectoryServer { directory: directory })
+    }
+
+    fn record_path(&self, version: u64) -> PathBuf {
+        self.directory.join(format!("{:010}.record", version))
+    }
+
+    fn head(&self) -> u64 {
+        let mut head = 0;
+        while self.record_path(head + 1).exists() {
+            head += 1;
+        }
+        head
+    }
+
+    fn read_record(&self, version: u64) -> io::Result<Vec<u8>> {
+        let mut record = vec![];
+        File::open(self.record_path(version))?.read_to_end(&mut record)?;
+        Ok(record)
+    }
+}
+
+impl SyncServer for DirectoryServer {
+    fn download_since(&mut self, version: u64) -> Result<Vec<(u64, Vec<u8>)>, Error> {
+        ((version + 1)..(self.head() + 1)).map(|version| {
+            self.read_record(version).map(|record| (version, record)).map_err(sync_failed)
+        }).collect()
+    }
+
+    fn upload(&mut self, base: u64, records: Vec<Vec<u8>>) -> Result<UploadOutcome, Error> {
+        let head = self.head();
+        if base != head {
+            return Ok(UploadOutcome::OutOfDate { head: head });
+        }
+        for (offset, record) in records.iter().enumerate() {
+            File::create(self.record_path(head + 1 + offset as u64))
+                .and_then(|mut file| file.write_all(record))
+                .map_err(sync_failed)?;
+        }
+        Ok(UploadOutcome::Accepted { head: head + records.len() as u64 })
+    }
+}
@@ -0,0 +1,69 @@
+// Copyright 2016 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! `toodle`, a command-line client for Toodle stores. Run `toodle help` for usage.
+//!
+//! Exits with 2 for bad arguments and 1 when a command fails.
+
+extern crate serde;
+#[macro_use] extern crate serde_derive;
+extern crate serde_json;
+extern crate time;
+extern crate toodle;
+
+mod args;
+mod commands;
+mod directory_server;
+
+use std::env;
+use std::io::{
+    self,
+    Write,
+};
+use std::path::PathBuf;
+use std::process;
+
+use toodle::Toodle;
+use toodle::ffi_errors::describe;
+
+use args::{
+    Command,
+    USAGE,
+};
+
+fn fail(code: i32, message: &str) -> ! {
+    eprintln!("toodle: {}", message);
+    process::exit(code);
+}
+
+fn main() {
+    let options = match args::parse(env::args().skip(1).collect()) {
+        Ok(options) => options,
+        Err(message) => fail(2, &format!("{}\n\n{}", message, USAGE)),
+    };
+    if options.command == Command::Help {
+        print!("{}", USAGE);
+        return;
+    }
+    let path = match options.db {
+        Some(path) => PathBuf::from(path),
+        None => Toodle::default_path().unwrap_or_else(|e| fail(1, &format!("can't create the store's directory: {}", e))),
+    };
+    let key = env::var("TOODLE_KEY").ok();
+    let mut toodle = Toodle::new(path.to_string_lossy().into_owned(), key.as_ref().map(|key| key.as_str()))
+        .unwrap_or_else(|e| fail(1, &format!("can't open {}: {}", path.display(), describe(&e))));
+    match commands::run(&mut toodle, options.command) {
+        Ok(output) => {
+            let stdout = io::stdout();
+            let _ = stdout.lock().write_all(output.as_bytes());
+        },
+        Err(e) => fail(1, &describe(&e)),
+    }
+}
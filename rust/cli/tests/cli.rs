@@ -0,0 +1,178 @@
+// Copyright 2016 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! Runs the `toodle` binary against stores on disk.
+
+use std::env;
+use std::fs::{
+    self,
+    File,
+};
+use std::io::Read;
+use std::path::PathBuf;
+use std::process::{
+    self,
+    Command,
+};
+use std::sync::atomic::{
+    AtomicUsize,
+    Ordering,
+};
+
+fn toodle_binary() -> PathBuf {
+    // Tests are built in target/<profile>/deps, next to which cargo puts the binary.
+    let mut path = env::current_exe().expect("expected the test's path");
+    path.pop();
+    if path.ends_with("deps") {
+        path.pop();
+    }
+    path.push(format!("toodle{}", env::consts::EXE_SUFFIX));
+    path
+}
+
+/// A fresh path in a directory of its own under the temporary directory, so that tests
+/// running at once, here or in another checkout, can't share it.
+fn scratch(name: &str) -> PathBuf {
+    static NEXT_DIRECTORY: AtomicUsize = AtomicUsize::new(0);
+    let directory = env::temp_dir().join(format!("toodle-cli-{}-{}", process::id(), NEXT_DIRECTORY.fetch_add(1, Ordering::SeqCst)));
+    let _ = fs::remove_dir_all(&directory);
+    fs::create_dir_all(&directory).expect("expected a temporary directory");
+    directory.join(name)
+}
+
+/// Runs `toodle --db <db> <args>`, returning its exit code and stdout.
+fn toodle(db: &PathBuf, args: &[&str]) -> (i32, String) {
+    let output = Command::new(toodle_binary())
+        .arg("--db")
+        .arg(db)
+        .args(args)
+        .output()
+        .expect("expected toodle to run");
+    (output.status.code().unwrap_or(-1), String::from_utf8(output.stdout).expect("expected utf-8"))
+}
+
+fn ok(db: &PathBuf, args: &[&str]) -> String {
+    let (code, stdout) = toodle(db, args);
+    assert_eq!(code, 0, "toodle {:?} failed", args);
+    stdout
+}
+
+fn names(listing: &str) -> Vec<String> {
+    listing.lines().map(|line| line.splitn(2, "] ").nth(1).expect("expected a name").split(" (").next().unwrap().split(" #").next().unwrap().to_string()).collect()
+}
+
+#[test]
+fn test_items_and_labels() {
+    let db = scratch("items.db");
+    ok(&db, &["label", "add", "work", "#00ff00"]);
+    assert_eq!(ok(&db, &["label", "list"]), "work #00ff00\n");
+
+    let report = ok(&db, &["add", "Write report", "--due", "2017-07-14", "--label", "work"]);
+    assert!(report.ends_with("[ ] Write report (due 2017-07-14) #work\n"));
+    let report = report.split_whitespace().next().unwrap().to_string();
+    ok(&db, &["add", "Book flights", "--due", "2017-07-01"]);
+    ok(&db, &["add", "Answer email"]);
+
+    assert_eq!(names(&ok(&db, &["list"])), vec!["Answer email", "Book flights", "Write report"]);
+    assert_eq!(names(&ok(&db, &["list", "--label", "work"])), vec!["Write report"]);
+    assert_eq!(names(&ok(&db, &["list", "--due-before", "2017-07-10"])), vec!["Book flights"]);
+    assert_eq!(names(&ok(&db, &["list", "--sort", "due", "--limit", "2"])), vec!["Book flights", "Write report"]);
+
+    ok(&db, &["done", report.as_str()]);
+    assert_eq!(names(&ok(&db, &["list", "--done"])), vec!["Write report"]);
+    assert_eq!(names(&ok(&db, &["list", "--todo"])), vec!["Answer email", "Book flights"]);
+
+    ok(&db, &["edit", report.as_str(), "--name", "Write summary", "--unlabel", "work"]);
+    assert_eq!(names(&ok(&db, &["search", "summary"])), vec!["Write summary"]);
+    assert!(ok(&db, &["list", "--label", "work"]).is_empty());
+
+    ok(&db, &["label", "rm", "work"]);
+    assert!(ok(&db, &["label", "list"]).is_empty());
+}
+
+#[test]
+fn test_export_then_import() {
+    let source = scratch("export-source.db");
+    let target = scratch("export-target.db");
+    let export = scratch("export.json");
+    ok(&source, &["label", "add", "home", "#ff0000"]);
+    ok(&source, &["add", "Buy milk", "--label", "home"]);
+    ok(&source, &["export", export.to_str().unwrap()]);
+
+    assert!(ok(&target, &["import", export.to_str().unwrap()]).starts_with("imported 1 labels and 1 items"));
+    assert!(ok(&target, &["list"]).ends_with("[ ] Buy milk #home\n"));
+    assert_eq!(ok(&target, &["label", "list"]), "home #ff0000\n");
+}
+
+#[test]
+fn test_sync_through_a_directory() {
+    let first = scratch("sync-first.db");
+    let second = scratch("sync-second.db");
+    let log = scratch("sync-log");
+    ok(&first, &["add", "From the first store"]);
+    ok(&first, &["sync", log.to_str().unwrap()]);
+    ok(&second, &["add", "From the second store"]);
+    ok(&second, &["sync", log.to_str().unwrap()]);
+    ok(&first, &["sync", log.to_str().unwrap()]);
+
+    let expected = vec!["From the first store", "From the second store"];
+    assert_eq!(names(&ok(&first, &["list"])), expected);
+    assert_eq!(names(&ok(&second, &["list"])), expected);
+}
+
+#[test]
+fn test_encrypted_sync_through_a_directory() {
+    let first = scratch("encrypted-first.db");
+    let second = scratch("encrypted-second.db");
+    let log = scratch("encrypted-log");
+    let keys = scratch("encrypted-keys.json");
+    let (log, keys) = (log.to_str().unwrap(), keys.to_str().unwrap());
+    ok(&first, &["keys", "new", keys]);
+    assert_eq!(toodle(&first, &["keys", "new", keys]).0, 1);
+
+    ok(&first, &["add", "Plan the surprise party"]);
+    ok(&first, &["sync", log, "--keys", keys]);
+    assert!(ok(&first, &["keys", "rotate", keys]).starts_with("key 2 is now current"));
+    ok(&first, &["add", "Order the cake"]);
+    ok(&first, &["sync", log, "--keys", keys]);
+    ok(&second, &["sync", log, "--keys", keys]);
+    assert_eq!(names(&ok(&second, &["list"])), vec!["Order the cake", "Plan the surprise party"]);
+
+    for entry in fs::read_dir(log).expect("expected the log") {
+        let mut record = vec![];
+        File::open(entry.expect("expected a record").path()).and_then(|mut file| file.read_to_end(&mut record)).expect("expected to read a record");
+        assert!(!String::from_utf8_lossy(&record).contains("surprise party"));
+    }
+    // Without the keys, the log can't be read.
+    assert_eq!(toodle(&scratch("encrypted-third.db"), &["sync", log]).0, 1);
+}
+
+#[test]
+fn test_failures() {
+    let db = scratch("failures.db");
+    assert_eq!(toodle(&db, &["frobnicate"]).0, 2);
+    assert_eq!(toodle(&db, &["add"]).0, 2);
+    assert_eq!(toodle(&db, &["done", "not-a-uuid"]).0, 1);
+    assert_eq!(toodle(&db, &["label", "rm", "missing"]).0, 1);
+    assert_eq!(toodle(&db, &["import", "/nonexistent/export.json"]).0, 1);
+}
+
+#[test]
+fn test_import_legacy() {
+    let db = scratch("legacy.db");
+    let sql_test = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("..").join("sql_test");
+    let sql_test = sql_test.to_string_lossy().into_owned();
+    assert_eq!(ok(&db, &["import-legacy", &sql_test]), "imported 2 labels and 1 items with 2 labels between them\n");
+    assert_eq!(names(&ok(&db, &["list"])), vec!["test item"]);
+
+    let again = ok(&db, &["import-legacy", &sql_test]);
+    assert!(again.starts_with("imported 0 labels and 0 items"));
+    assert!(again.contains("skipped item f102e78af68c49c1a08e16d8cc9ec446 (\"test item\"): an item with that uuid is already in the store\n"));
+}
@@ -0,0 +1,491 @@
+// Copyright 2016 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! Generates a C header from the `#[no_mangle] extern "C"` functions, `#[repr(C)]` types
+//! and `extern "C" fn` type aliases in a crate's sources.
+//!
+//! This isn't a Rust parser: it understands the handful of shapes the toodle crate uses
+//! for its FFI, one signature per line, and panics on anything else so that an
+//! unsupported export can't silently go missing from the header.
+
+use std::collections::BTreeSet;
+use std::fs;
+use std::io::{
+    self,
+    Read,
+};
+use std::path::Path;
+
+/// Rust types whose C name differs from their Rust name.
+const RENAMES: &'static [(&'static str, &'static str)] = &[
+    ("ItemC", "CItem"),
+    ("ItemCList", "CItemList"),
+    ("Vec<Label>", "LabelList"),
+];
+
+const PRIMITIVES: &'static [(&'static str, &'static str)] = &[
+    ("c_char", "char"),
+    ("c_int", "int"),
+    ("c_void", "void"),
+    ("i32", "int32_t"),
+    ("i64", "int64_t"),
+    ("size_t", "size_t"),
+    ("time_t", "time_t"),
+    ("u32", "uint32_t"),
+    ("u64", "uint64_t"),
+    ("u8", "uint8_t"),
+];
+
+#[derive(Clone, Debug, PartialEq)]
+struct Function {
+    name: String,
+    args: Vec<(String, String)>,
+    ret: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Definition {
+    Struct { name: String, fields: Vec<(String, String)> },
+    Enum { name: String, variants: Vec<(String, String)> },
+}
+
+#[derive(Debug, Default)]
+struct Source {
+    functions: Vec<(String, Vec<Function>)>,
+    definitions: Vec<Definition>,
+    callbacks: Vec<(String, Vec<(String, String)>, Option<String>)>,
+}
+
+fn renamed(name: &str) -> &str {
+    RENAMES.iter().find(|&&(rust, _)| rust == name).map_or(name, |&(_, c)| c)
+}
+
+fn primitive(name: &str) -> Option<&'static str> {
+    PRIMITIVES.iter().find(|&&(rust, _)| rust == name).map(|&(_, c)| c)
+}
+
+/// Splits on commas that aren't nested inside brackets. The `>` of a `->` doesn't close
+/// anything.
+fn split_top_level(s: &str) -> Vec<String> {
+    let mut parts = vec![];
+    let mut depth = 0;
+    let mut current = String::new();
+    for c in s.chars() {
+        match c {
+            '(' | '<' | '[' => depth += 1,
+            '>' if current.ends_with('-') => (),
+            ')' | '>' | ']' => depth -= 1,
+            ',' if depth == 0 => {
+                parts.push(current.trim().to_string());
+                current = String::new();
+                continue;
+            },
+            _ => (),
+        }
+        current.push(c);
+    }
+    if !current.trim().is_empty() {
+        parts.push(current.trim().to_string());
+    }
+    parts
+}
+
+/// The index of the parenthesis that closes the one at `open`.
+fn closing_paren(s: &str, open: usize) -> usize {
+    let mut depth = 0;
+    for (i, c) in s.char_indices().skip_while(|&(i, _)| i < open) {
+        match c {
+            '(' => depth += 1,
+            ')' => {
+                depth -= 1;
+                if depth == 0 {
+                    return i;
+                }
+            },
+            _ => (),
+        }
+    }
+    panic!("unbalanced parentheses in {:?}", s)
+}
+
+/// Parses `name(args) -> ret` into its arguments and return type.
+fn parse_signature(s: &str) -> (String, Vec<(String, String)>, Option<String>) {
+    let open = s.find('(').unwrap_or_else(|| panic!("expected arguments in {:?}", s));
+    let close = closing_paren(s, open);
+    let name = s[..open].trim().to_string();
+    let args = split_top_level(&s[open + 1..close]).into_iter().map(|arg| {
+        match arg.find(':') {
+            Some(colon) => (arg[..colon].trim().to_string(), arg[colon + 1..].trim().to_string()),
+            None => (String::new(), arg),
+        }
+    }).collect();
+    let rest = s[close + 1..].trim();
+    let ret = if rest.starts_with("->") {
+        Some(rest[2..].trim().to_string())
+    } else {
+        None
+    };
+    (name, args, ret)
+}
+
+fn parse_function(line: &str) -> Function {
+    let start = line.find("fn ").unwrap_or_else(|| panic!("expected a function in {:?}", line)) + 3;
+    let end = line.rfind('{').unwrap_or(line.len());
+    let (name, args, ret) = parse_signature(&line[start..end]);
+    Function { name: name, args: args, ret: ret }
+}
+
+fn parse_definition(lines: &[&str]) -> Definition {
+    let header = lines[0].trim();
+    let name = header.split_whitespace().nth(2).expect("expected a type name").trim_end_matches('{').to_string();
+    let body: Vec<&str> = lines[1..].iter()
+                                    .map(|line| line.trim().trim_end_matches(','))
+                                    .filter(|line| !line.is_empty() && !line.starts_with("//"))
+                                    .collect();
+    if header.starts_with("pub struct") {
+        let fields = body.iter().map(|field| {
+            let field = field.trim_start_matches("pub ").trim();
+            let colon = field.find(':').unwrap_or_else(|| panic!("expected a field in {:?}", field));
+            (field[..colon].trim().to_string(), field[colon + 1..].trim().to_string())
+        }).collect();
+        Definition::Struct { name: name, fields: fields }
+    } else {
+        let variants = body.iter().map(|variant| {
+            let mut parts = variant.splitn(2, '=');
+            let name = parts.next().unwrap().trim().to_string();
+            let value = parts.next().unwrap_or_else(|| panic!("{} needs an explicit discriminant", name)).trim().to_string();
+            (name, value)
+        }).collect();
+        Definition::Enum { name: name, variants: variants }
+    }
+}
+
+fn parse_source(file_name: &str, text: &str, source: &mut Source) {
+    let lines: Vec<&str> = text.lines().collect();
+    let mut functions = vec![];
+    let mut i = 0;
+    while i < lines.len() {
+        let line = lines[i].trim();
+        if line == "#[cfg(test)]" {
+            break;
+        }
+        if line == "#[no_mangle]" {
+            let signature = lines[i + 1].trim();
+            if !signature.starts_with("pub") || !signature.contains("extern \"C\" fn") {
+                panic!("{}: #[no_mangle] on something other than a pub extern \"C\" fn: {:?}", file_name, signature);
+            }
+            // JNI entry points are looked up by the JVM, not called from C.
+            if !signature.contains("fn Java_") {
+                functions.push(parse_function(signature));
+            }
+            i += 2;
+            continue;
+        }
+        if line == "#[repr(C)]" {
+            let mut start = i + 1;
+            while lines[start].trim().starts_with("#[") {
+                start += 1;
+            }
+            let mut end = start;
+            while lines[end].trim() != "}" {
+                end += 1;
+            }
+            source.definitions.push(parse_definition(&lines[start..end]));
+            i = end + 1;
+            continue;
+        }
+        if line.starts_with("pub type ") && line.contains("= extern \"C\" fn") {
+            let name = line["pub type ".len()..line.find('=').unwrap()].trim().to_string();
+            let fn_start = line.find("fn").unwrap() + 2;
+            let (_, args, ret) = parse_signature(&format!("f{}", line[fn_start..].trim_end_matches(';')));
+            source.callbacks.push((name, args, ret));
+        }
+        i += 1;
+    }
+    if !functions.is_empty() {
+        source.functions.push((file_name.to_string(), functions));
+    }
+}
+
+struct Types<'a> {
+    source: &'a Source,
+    opaque: BTreeSet<String>,
+}
+
+impl<'a> Types<'a> {
+    fn is_enum(&self, name: &str) -> bool {
+        self.source.definitions.iter().any(|definition| match definition {
+            &Definition::Enum { name: ref n, .. } => n == name,
+            _ => false,
+        })
+    }
+
+    fn is_callback(&self, name: &str) -> bool {
+        self.source.callbacks.iter().any(|&(ref n, _, _)| n == name)
+    }
+
+    /// Whether a `#[repr(C)]` struct can be spelled out in C, rather than left opaque.
+    fn is_c_struct(&self, definition: &Definition) -> bool {
+        match definition {
+            &Definition::Struct { ref fields, .. } => fields.iter().all(|&(_, ref ty)| self.try_c_type(ty, &mut BTreeSet::new()).is_some()),
+            _ => false,
+        }
+    }
+
+    /// Renders `ty` in C, recording the structs that have to be forward declared.
+    fn try_c_type(&self, ty: &str, structs: &mut BTreeSet<String>) -> Option<String> {
+        let ty = ty.trim();
+        for &(prefix, is_const) in [("*const ", true), ("*mut ", false)].iter() {
+            if ty.starts_with(prefix) {
+                let pointee = &ty[prefix.len()..];
+                let inner = if pointee.starts_with('*') {
+                    self.try_c_type(pointee, structs)?
+                } else if let Some(c) = primitive(pointee) {
+                    c.to_string()
+                } else if self.is_enum(pointee) {
+                    format!("enum {}", renamed(pointee))
+                } else {
+                    if pointee.contains('<') && renamed(pointee) == pointee {
+                        return None;
+                    }
+                    structs.insert(renamed(pointee).to_string());
+                    format!("struct {}", renamed(pointee))
+                };
+                return Some(match (is_const, inner.ends_with('*')) {
+                    (true, true) => format!("{} const*", inner),
+                    (true, false) => format!("const {}*", inner),
+                    (false, _) => format!("{}*", inner),
+                });
+            }
+        }
+        if ty.starts_with("Option<&") && ty.ends_with('>') {
+            return self.try_c_type(&format!("*const {}", &ty["Option<&".len()..ty.len() - 1]), structs);
+        }
+        if let Some(c) = primitive(ty) {
+            return Some(c.to_string());
+        }
+        if self.is_enum(ty) {
+            return Some(format!("enum {}", ty));
+        }
+        if self.is_callback(ty) {
+            return Some(ty.to_string());
+        }
+        None
+    }
+
+    fn c_type(&self, ty: &str, context: &str) -> String {
+        let mut structs = BTreeSet::new();
+        self.try_c_type(ty, &mut structs).unwrap_or_else(|| panic!("{}: no C equivalent for {:?}", context, ty))
+    }
+
+    fn declaration(&mut self, ty: &str, name: &str, context: &str) -> String {
+        if ty.starts_with("extern \"C\" fn") {
+            let (_, args, ret) = parse_signature(&format!("f{}", &ty["extern \"C\" fn".len()..]));
+            return format!("{} (*{})({})", self.return_type(ret.as_ref(), context), name, self.arguments(&args, context));
+        }
+        let mut structs = BTreeSet::new();
+        let c = self.try_c_type(ty, &mut structs).unwrap_or_else(|| panic!("{}: no C equivalent for {:?}", context, ty));
+        self.opaque.extend(structs);
+        if name.is_empty() { c } else { format!("{} {}", c, name) }
+    }
+
+    fn return_type(&mut self, ret: Option<&String>, context: &str) -> String {
+        match ret {
+            Some(ty) => self.declaration(ty, "", context),
+            None => "void".to_string(),
+        }
+    }
+
+    fn arguments(&mut self, args: &[(String, String)], context: &str) -> String {
+        if args.is_empty() {
+            return "void".to_string();
+        }
+        args.iter().map(|&(ref name, ref ty)| self.declaration(ty, name, context)).collect::<Vec<_>>().join(", ")
+    }
+}
+
+fn generate_from(source: &Source, guard: &str) -> String {
+    let mut types = Types { source: source, opaque: BTreeSet::new() };
+
+    let mut callbacks = String::new();
+    for &(ref name, ref args, ref ret) in source.callbacks.iter() {
+        let ret = types.return_type(ret.as_ref(), name);
+        let args = types.arguments(args, name);
+        callbacks.push_str(&format!("typedef {} (*{})({});\n", ret, name, args));
+    }
+
+    let mut functions = String::new();
+    for &(ref file_name, ref list) in source.functions.iter() {
+        functions.push_str(&format!("\n// {}\n", file_name));
+        for function in list.iter() {
+            let ret = types.return_type(function.ret.as_ref(), &function.name);
+            let args = types.arguments(&function.args, &function.name);
+            functions.push_str(&format!("{} {}({});\n", ret, function.name, args));
+        }
+    }
+
+    let mut enums = String::new();
+    let mut structs = String::new();
+    for definition in source.definitions.iter() {
+        match definition {
+            &Definition::Enum { ref name, ref variants } => {
+                enums.push_str(&format!("enum {} {{\n", name));
+                for &(ref variant, ref value) in variants.iter() {
+                    enums.push_str(&format!("    {}{} = {},\n", name, variant, value));
+                }
+                enums.push_str("};\n\n");
+            },
+            &Definition::Struct { ref name, ref fields } if types.is_c_struct(definition) => {
+                let name = renamed(name);
+                types.opaque.remove(name);
+                structs.push_str(&format!("struct {} {{\n", name));
+                for &(ref field, ref ty) in fields.iter() {
+                    let c = types.c_type(ty, name);
+                    structs.push_str(&format!("    {} {};\n", c, field));
+                }
+                structs.push_str("};\n\n");
+            },
+            _ => (),
+        }
+    }
+
+    let mut header = String::new();
+    header.push_str("// Generated by header-gen from the toodle crate's sources; don't edit it by hand.\n");
+    header.push_str("// Run `cargo run -p header-gen` in the rust directory after changing an export.\n\n");
+    header.push_str(&format!("#ifndef {}\n#define {}\n\n", guard, guard));
+    header.push_str("#include <stddef.h>\n#include <stdint.h>\n#include <time.h>\n\n");
+    if !types.opaque.is_empty() {
+        for name in types.opaque.iter() {
+            header.push_str(&format!("struct {};\n", name));
+        }
+        header.push('\n');
+    }
+    header.push_str(&enums);
+    header.push_str(&structs);
+    header.push_str(&callbacks);
+    header.push_str(&functions);
+    header.push_str(&format!("\n#endif /* {} */\n", guard));
+    header
+}
+
+/// Generates a header from the given `(file name, contents)` pairs, in that order.
+pub fn generate(sources: &[(String, String)], guard: &str) -> String {
+    let mut source = Source::default();
+    for &(ref name, ref text) in sources.iter() {
+        parse_source(name, text, &mut source);
+    }
+    generate_from(&source, guard)
+}
+
+/// Generates a header from every `.rs` file directly inside `dir`, sorted by name.
+pub fn generate_for_dir(dir: &Path, guard: &str) -> io::Result<String> {
+    let mut paths: Vec<_> = fs::read_dir(dir)?
+                              .map(|entry| entry.map(|entry| entry.path()))
+                              .collect::<io::Result<_>>()?;
+    paths.retain(|path| path.extension().map_or(false, |extension| extension == "rs"));
+    paths.sort();
+
+    let mut sources = vec![];
+    for path in paths {
+        let mut text = String::new();
+        fs::File::open(&path)?.read_to_string(&mut text)?;
+        let name = path.file_name().expect("a file name").to_string_lossy().into_owned();
+        sources.push((name, text));
+    }
+    Ok(generate(&sources, guard))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const SOURCE: &'static str = r#"
+#[repr(C)]
+#[derive(Debug)]
+pub enum Color {
+    Red = 1,
+    Green = 2,
+}
+
+#[repr(C)]
+pub struct ItemC {
+    pub name: *mut c_char,
+    pub color: Color,
+}
+
+#[repr(C)]
+pub struct Opaque {
+    inner: Vec<u8>,
+}
+
+pub type Callback = extern "C" fn(context: *mut c_void, ids: *const *const c_char, count: c_int);
+
+#[no_mangle]
+pub unsafe extern "C" fn thing_new(name: *const c_char, labels: *const Vec<Label>) -> *mut Opaque {
+}
+
+#[no_mangle]
+pub extern "C" fn thing_each(thing: *mut Opaque, callback: extern "C" fn(Option<&ItemC>), other: Callback) {
+}
+
+#[no_mangle]
+pub extern "C" fn thing_visit(visit: extern "C" fn(u64, *const c_char) -> c_int, context: *mut c_void) -> u64 {
+}
+
+#[cfg(test)]
+mod test {
+    #[no_mangle]
+    pub extern "C" fn not_exported() {}
+}
+"#;
+
+    #[test]
+    fn test_generate() {
+        let header = generate(&[("things.rs".to_string(), SOURCE.to_string())], "things_h");
+        let expected = "\
+#ifndef things_h
+#define things_h
+
+#include <stddef.h>
+#include <stdint.h>
+#include <time.h>
+
+struct LabelList;
+struct Opaque;
+
+enum Color {
+    ColorRed = 1,
+    ColorGreen = 2,
+};
+
+struct CItem {
+    char* name;
+    enum Color color;
+};
+
+typedef void (*Callback)(void* context, const char* const* ids, int count);
+
+// things.rs
+struct Opaque* thing_new(const char* name, const struct LabelList* labels);
+void thing_each(struct Opaque* thing, void (*callback)(const struct CItem*), Callback other);
+uint64_t thing_visit(int (*visit)(uint64_t, const char*), void* context);
+
+#endif /* things_h */
+";
+        assert!(header.ends_with(expected), "unexpected header:\n{}", header);
+    }
+
+    #[test]
+    #[should_panic(expected = "no C equivalent")]
+    fn test_unsupported_type() {
+        let source = "#[no_mangle]\npub extern \"C\" fn f(v: Vec<u8>) {\n}\n";
+        generate(&[("f.rs".to_string(), source.to_string())], "f_h");
+    }
+}
@@ -0,0 +1,25 @@
+// Copyright 2016 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! Regenerates `src/toodle.h` from the toodle crate's sources.
+
+extern crate header_gen;
+
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+
+fn main() {
+    let crate_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("..");
+    let header = header_gen::generate_for_dir(&crate_dir.join("src"), "toodle_h").expect("read sources");
+    let path = crate_dir.join("src").join("toodle.h");
+    File::create(&path).and_then(|mut file| file.write_all(header.as_bytes())).expect("write header");
+    println!("wrote {}", path.display());
+}
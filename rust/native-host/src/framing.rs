@@ -0,0 +1,110 @@
+// Copyright 2016 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! The native-messaging framing: every message is UTF-8 JSON preceded by its length in
+//! bytes as a 32-bit unsigned integer in native byte order.
+
+use std::io::{
+    self,
+    Read,
+    Write,
+};
+
+/// Browsers refuse messages from a host that are larger than this.
+pub const MAX_OUTGOING_LENGTH: usize = 1024 * 1024;
+
+/// The longest message we'll read. Nothing the extension sends comes close, and a bigger
+/// length is more likely a corrupt header than a message worth allocating for.
+pub const MAX_INCOMING_LENGTH: usize = 1024 * 1024;
+
+fn decode_length(bytes: [u8; 4]) -> u32 {
+    let little_endian = (bytes[0] as u32) |
+                        (bytes[1] as u32) << 8 |
+                        (bytes[2] as u32) << 16 |
+                        (bytes[3] as u32) << 24;
+    if cfg!(target_endian = "little") { little_endian } else { little_endian.swap_bytes() }
+}
+
+fn encode_length(length: u32) -> [u8; 4] {
+    let length = if cfg!(target_endian = "little") { length } else { length.swap_bytes() };
+    [length as u8, (length >> 8) as u8, (length >> 16) as u8, (length >> 24) as u8]
+}
+
+/// Reads the next message, or returns `None` if the input ends before one starts. A
+/// message longer than `MAX_INCOMING_LENGTH` fails with `InvalidData`, without reading it.
+pub fn read_message<R: Read>(input: &mut R) -> io::Result<Option<Vec<u8>>> {
+    let mut header = [0u8; 4];
+    let mut filled = 0;
+    while filled < header.len() {
+        match input.read(&mut header[filled..]) {
+            Ok(0) if filled == 0 => return Ok(None),
+            Ok(0) => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "input ended inside a message length")),
+            Ok(read) => filled += read,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    let length = decode_length(header) as usize;
+    if length > MAX_INCOMING_LENGTH {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("a {} byte message is longer than the {} bytes we accept", length, MAX_INCOMING_LENGTH)));
+    }
+    let mut message = vec![0; length];
+    input.read_exact(&mut message)?;
+    Ok(Some(message))
+}
+
+pub fn write_message<W: Write>(output: &mut W, message: &[u8]) -> io::Result<()> {
+    output.write_all(&encode_length(message.len() as u32))?;
+    output.write_all(message)?;
+    output.flush()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::io::Cursor;
+
+    #[test]
+    fn test_round_trip() {
+        let mut buffer = vec![];
+        write_message(&mut buffer, b"{\"method\":\"getTodos\"}").expect("expected to write");
+        write_message(&mut buffer, b"").expect("expected to write");
+        assert_eq!(buffer.len(), 4 + 21 + 4);
+
+        let mut input = Cursor::new(buffer);
+        assert_eq!(read_message(&mut input).expect("expected to read"), Some(b"{\"method\":\"getTodos\"}".to_vec()));
+        assert_eq!(read_message(&mut input).expect("expected to read"), Some(vec![]));
+        assert_eq!(read_message(&mut input).expect("expected to read"), None);
+    }
+
+    #[test]
+    fn test_truncated_input() {
+        let mut buffer = vec![];
+        write_message(&mut buffer, b"[1, 2, 3]").expect("expected to write");
+
+        let mut short_body = Cursor::new(buffer[..8].to_vec());
+        assert_eq!(read_message(&mut short_body).unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+        let mut short_length = Cursor::new(buffer[..2].to_vec());
+        assert_eq!(read_message(&mut short_length).unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn test_oversized_messages() {
+        let mut buffer = encode_length(MAX_INCOMING_LENGTH as u32).to_vec();
+        buffer.extend(vec![b' '; MAX_INCOMING_LENGTH]);
+        assert_eq!(read_message(&mut Cursor::new(buffer)).expect("expected to read").map(|message| message.len()), Some(MAX_INCOMING_LENGTH));
+
+        // Only the length is read, however much it claims.
+        let mut too_long = Cursor::new(encode_length(u32::max_value()).to_vec());
+        assert_eq!(read_message(&mut too_long).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        assert_eq!(too_long.position(), 4);
+    }
+}
@@ -0,0 +1,88 @@
+// Copyright 2016 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! A native-messaging host that lets the WebExtension use a Toodle store on disk.
+//!
+//! The browser starts it and talks to it over stdin and stdout until the extension
+//! disconnects. The store is at `$TOODLE_DB` if that's set, or `~/.toodle/toodle.db`,
+//! and is opened with the key in `$TOODLE_KEY` if the store is encrypted.
+//! Anything meant for a person goes to stderr, which the browser logs.
+
+extern crate serde;
+#[macro_use] extern crate serde_derive;
+extern crate serde_json;
+extern crate toodle;
+
+mod framing;
+mod operations;
+
+use std::env;
+use std::io;
+use std::process;
+
+use toodle::Toodle;
+use toodle::api::ErrorRecord;
+use toodle::ffi_errors::{
+    describe,
+    ErrorCode,
+};
+
+use framing::{
+    MAX_OUTGOING_LENGTH,
+    read_message,
+    write_message,
+};
+use operations::Reply;
+
+fn serve(toodle: &mut Toodle) -> io::Result<()> {
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+    let mut input = stdin.lock();
+    let mut output = stdout.lock();
+    while let Some(message) = read_message(&mut input)? {
+        let reply = operations::handle(toodle, &message);
+        let mut encoded = serde_json::to_vec(&reply)?;
+        if encoded.len() > MAX_OUTGOING_LENGTH {
+            let too_large = Reply {
+                id: reply.id,
+                result: None,
+                error: Some(ErrorRecord {
+                    code: ErrorCode::Other as i32,
+                    message: format!("the reply is {} bytes, more than the browser accepts", encoded.len()),
+                }),
+            };
+            encoded = serde_json::to_vec(&too_large)?;
+        }
+        write_message(&mut output, &encoded)?;
+    }
+    Ok(())
+}
+
+fn main() {
+    let path = match Toodle::default_path() {
+        Ok(path) => path,
+        Err(e) => {
+            eprintln!("toodle-native-host: can't create the store's directory: {}", e);
+            process::exit(1);
+        },
+    };
+    let key = env::var("TOODLE_KEY").ok();
+    let mut toodle = match Toodle::new(path.to_string_lossy().into_owned(), key.as_ref().map(|key| key.as_str())) {
+        Ok(toodle) => toodle,
+        Err(e) => {
+            eprintln!("toodle-native-host: can't open {}: {}", path.display(), describe(&e));
+            process::exit(1);
+        },
+    };
+    if let Err(e) = serve(&mut toodle) {
+        eprintln!("toodle-native-host: {}", e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,223 @@
+// Copyright 2016 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! The operations of the WebExtension's `FakeApi`, served from a `Toodle`.
+//!
+//! A message `{"id": 3, "method": "todoAddLabel", "args": [uuid, "P0"]}` calls the
+//! method of that name with positional arguments, and is answered with
+//! `{"id": 3, "result": ...}` or `{"id": 3, "error": {"code": ..., "message": ...}}`.
+//! Todos look like `FakeApi`'s, which means their dates are in milliseconds.
+
+use std::panic::{
+    self,
+    AssertUnwindSafe,
+};
+
+use serde::de::DeserializeOwned;
+use serde_json::{
+    self,
+    Value,
+};
+
+use toodle::Toodle;
+use toodle::api::{
+    self,
+    ErrorRecord,
+    FilterParams,
+    ItemChanges,
+    ItemRecord,
+    LabelRecord,
+    NewItem,
+    Request,
+};
+use toodle::errors::{
+    Error,
+    ErrorKind,
+};
+use toodle::ffi_errors::{
+    describe,
+    panic_message,
+    ErrorCode,
+};
+
+#[derive(Debug, Deserialize)]
+pub struct Message {
+    #[serde(default)]
+    pub id: Option<Value>,
+    pub method: String,
+    #[serde(default)]
+    pub args: Vec<Value>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Reply {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<ErrorRecord>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Todo {
+    uuid: String,
+    name: String,
+    due_date: Option<i64>,
+    completion_date: Option<i64>,
+    labels: Vec<LabelRecord>,
+}
+
+impl From<ItemRecord> for Todo {
+    fn from(item: ItemRecord) -> Todo {
+        Todo {
+            uuid: item.uuid,
+            name: item.name,
+            due_date: item.due_date.map(|seconds| seconds * 1000),
+            completion_date: item.completion_date.map(|seconds| seconds * 1000),
+            labels: item.labels,
+        }
+    }
+}
+
+fn seconds(milliseconds: Option<i64>) -> Option<i64> {
+    milliseconds.map(|milliseconds| milliseconds / 1000)
+}
+
+/// Positional argument `index`, where a missing argument is `null`.
+fn arg<T: DeserializeOwned>(args: &[Value], index: usize, name: &str) -> Result<T, Error> {
+    let value = args.get(index).cloned().unwrap_or(Value::Null);
+    serde_json::from_value(value).map_err(|e| ErrorKind::InvalidArgument(format!("{}: {}", name, e)).into())
+}
+
+fn todo(item: Value) -> Result<Value, Error> {
+    let item: ItemRecord = serde_json::from_value(item)?;
+    Ok(serde_json::to_value(Todo::from(item))?)
+}
+
+fn todos(items: Value) -> Result<Value, Error> {
+    let items: Vec<ItemRecord> = serde_json::from_value(items)?;
+    Ok(serde_json::to_value(items.into_iter().map(Todo::from).collect::<Vec<_>>())?)
+}
+
+fn no_changes(uuid: String) -> ItemChanges {
+    ItemChanges {
+        uuid: uuid,
+        name: None,
+        due_date: None,
+        completion_date: None,
+        labels: None,
+        notes: None,
+        priority: None,
+        starred: None,
+        recurrence: None,
+    }
+}
+
+/// The names of the labels on the item `uuid`.
+fn label_names(toodle: &mut Toodle, uuid: &str) -> Result<Vec<String>, Error> {
+    let item = api::execute(toodle, Request::GetItem { uuid: uuid.to_string() })?;
+    if item.is_null() {
+        return Err(ErrorKind::NotFound(format!("item {}", uuid)).into());
+    }
+    let item: ItemRecord = serde_json::from_value(item)?;
+    Ok(item.labels.into_iter().map(|label| label.name).collect())
+}
+
+fn dispatch(toodle: &mut Toodle, method: &str, args: &[Value]) -> Result<Value, Error> {
+    match method {
+        "createTodo" => {
+            let new_item = NewItem {
+                name: arg(args, 0, "name")?,
+                due_date: None,
+                completion_date: None,
+                labels: vec![],
+                notes: None,
+                priority: None,
+                starred: false,
+                parent: None,
+                recurrence: None,
+            };
+            todo(api::execute(toodle, Request::CreateItem(new_item))?)
+        },
+        "removeTodo" => {
+            let uuid: String = arg(args, 0, "uuid")?;
+            api::execute(toodle, Request::DeleteItem { uuid: uuid.clone(), cascade: false })?;
+            Ok(Value::String(uuid))
+        },
+        "getTodos" => todos(api::execute(toodle, Request::GetItems { filter: FilterParams::default() })?),
+        "todoChangeName" => {
+            let mut changes = no_changes(arg(args, 0, "uuid")?);
+            changes.name = Some(arg(args, 1, "newTodoName")?);
+            todo(api::execute(toodle, Request::UpdateItem(changes))?)
+        },
+        "todoChangeDueDate" => {
+            let mut changes = no_changes(arg(args, 0, "uuid")?);
+            changes.due_date = Some(seconds(arg(args, 1, "dueDate")?));
+            todo(api::execute(toodle, Request::UpdateItem(changes))?)
+        },
+        "todoChangeCompletionDate" => {
+            let mut changes = no_changes(arg(args, 0, "uuid")?);
+            changes.completion_date = Some(seconds(arg(args, 1, "completionDate")?));
+            todo(api::execute(toodle, Request::UpdateItem(changes))?)
+        },
+        "todoAddLabel" | "todoRemoveLabel" => {
+            let uuid: String = arg(args, 0, "uuid")?;
+            let label_name: String = arg(args, 1, "labelName")?;
+            let mut names: Vec<String> = label_names(toodle, &uuid)?.into_iter().filter(|name| *name != label_name).collect();
+            if method == "todoAddLabel" {
+                names.push(label_name);
+            }
+            let mut changes = no_changes(uuid);
+            changes.labels = Some(names);
+            todo(api::execute(toodle, Request::UpdateItem(changes))?)
+        },
+        "getLabels" => api::execute(toodle, Request::GetLabels),
+        "addLabel" => {
+            let name = arg(args, 0, "name")?;
+            let color = arg(args, 1, "color")?;
+            api::execute(toodle, Request::CreateLabel { name: name, color: color })
+        },
+        "removeLabel" => {
+            let name: String = arg(args, 0, "labelName")?;
+            api::execute(toodle, Request::DeleteLabel { name: name.clone() })?;
+            Ok(Value::String(name))
+        },
+        _ => Err(ErrorKind::InvalidArgument(format!("unknown method {:?}", method)).into()),
+    }
+}
+
+fn error_record(error: &Error) -> ErrorRecord {
+    ErrorRecord {
+        code: ErrorCode::from(error) as i32,
+        message: describe(error),
+    }
+}
+
+/// Answers one message. Failures, and panics, are reported in the reply rather than
+/// ending the session.
+pub fn handle(toodle: &mut Toodle, message: &[u8]) -> Reply {
+    let message: Message = match serde_json::from_slice(message) {
+        Ok(message) => message,
+        Err(e) => {
+            let error = ErrorKind::InvalidArgument(format!("malformed message: {}", e)).into();
+            return Reply { id: None, result: None, error: Some(error_record(&error)) };
+        },
+    };
+    match panic::catch_unwind(AssertUnwindSafe(|| dispatch(toodle, &message.method, &message.args))) {
+        Ok(Ok(result)) => Reply { id: message.id, result: Some(result), error: None },
+        Ok(Err(e)) => Reply { id: message.id, result: None, error: Some(error_record(&e)) },
+        Err(payload) => {
+            let error = ErrorRecord { code: ErrorCode::Panic as i32, message: panic_message(payload) };
+            Reply { id: message.id, result: None, error: Some(error) }
+        },
+    }
+}
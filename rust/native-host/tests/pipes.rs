@@ -0,0 +1,217 @@
+// Copyright 2016 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! Drives the host binary over pipes, the way a browser does.
+
+#[macro_use] extern crate serde_json;
+
+use std::env;
+use std::fs;
+use std::io::{
+    Read,
+    Write,
+};
+use std::path::PathBuf;
+use std::process::{
+    self,
+    Child,
+    ChildStdin,
+    ChildStdout,
+    Command,
+    Stdio,
+};
+use std::sync::atomic::{
+    AtomicUsize,
+    Ordering,
+};
+
+use serde_json::Value;
+
+fn host_binary() -> PathBuf {
+    // Tests are built in target/<profile>/deps, next to which cargo puts the binary.
+    let mut path = env::current_exe().expect("expected the test's path");
+    path.pop();
+    if path.ends_with("deps") {
+        path.pop();
+    }
+    path.push(format!("toodle-native-host{}", env::consts::EXE_SUFFIX));
+    path
+}
+
+/// A fresh store for each test, in a directory of its own so that tests running at once,
+/// here or in another checkout, can't share it.
+fn database(name: &str) -> PathBuf {
+    static NEXT_DIRECTORY: AtomicUsize = AtomicUsize::new(0);
+    let directory = env::temp_dir().join(format!("toodle-native-host-{}-{}", process::id(), NEXT_DIRECTORY.fetch_add(1, Ordering::SeqCst)));
+    let _ = fs::remove_dir_all(&directory);
+    fs::create_dir_all(&directory).expect("expected a temporary directory");
+    directory.join(format!("{}.db", name))
+}
+
+struct Host {
+    child: Child,
+    stdin: Option<ChildStdin>,
+    stdout: ChildStdout,
+    next_id: u64,
+}
+
+impl Host {
+    fn start(database: &PathBuf) -> Host {
+        let mut child = Command::new(host_binary())
+            .env("TOODLE_DB", database)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .expect("expected the host to start");
+        let stdin = child.stdin.take();
+        let stdout = child.stdout.take().expect("expected stdout");
+        Host { child: child, stdin: stdin, stdout: stdout, next_id: 1 }
+    }
+
+    fn send_bytes(&mut self, bytes: &[u8]) {
+        let stdin = self.stdin.as_mut().expect("expected stdin to be open");
+        stdin.write_all(bytes).expect("expected to write");
+        stdin.flush().expect("expected to flush");
+    }
+
+    fn send(&mut self, message: &Value) {
+        let message = serde_json::to_vec(message).unwrap();
+        let length = message.len() as u32;
+        self.send_bytes(&unsafe { ::std::mem::transmute::<u32, [u8; 4]>(length) });
+        self.send_bytes(&message);
+    }
+
+    fn receive(&mut self) -> Value {
+        let mut length = [0u8; 4];
+        self.stdout.read_exact(&mut length).expect("expected a reply");
+        let length = unsafe { ::std::mem::transmute::<[u8; 4], u32>(length) };
+        let mut reply = vec![0; length as usize];
+        self.stdout.read_exact(&mut reply).expect("expected a whole reply");
+        serde_json::from_slice(&reply).expect("expected JSON")
+    }
+
+    /// Calls `method`, returning the whole reply after checking that its id matches.
+    fn call_reply(&mut self, method: &str, args: Value) -> Value {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.send(&json!({"id": id, "method": method, "args": args}));
+        let reply = self.receive();
+        assert_eq!(reply["id"], json!(id));
+        reply
+    }
+
+    fn call(&mut self, method: &str, args: Value) -> Value {
+        let reply = self.call_reply(method, args);
+        assert!(reply.get("error").is_none(), "{} failed: {}", method, reply);
+        reply["result"].clone()
+    }
+
+    /// Closes the host's input, as the browser does when the extension disconnects, and
+    /// returns whether it exited cleanly.
+    fn finish(mut self) -> bool {
+        drop(self.stdin.take());
+        self.child.wait().expect("expected the host to exit").success()
+    }
+}
+
+fn label_names(todo: &Value) -> Vec<String> {
+    todo["labels"].as_array().expect("expected labels").iter().map(|label| label["name"].as_str().unwrap().to_string()).collect()
+}
+
+#[test]
+fn test_fake_api_operations() {
+    let mut host = Host::start(&database("operations"));
+
+    assert_eq!(host.call("addLabel", json!(["P0", "rgb(184, 0, 0)"])), json!({"name": "P0", "color": "rgb(184, 0, 0)"}));
+    host.call("addLabel", json!(["Backlog", "rgb(0, 139, 2)"]));
+    assert_eq!(host.call("getLabels", json!([])).as_array().unwrap().len(), 2);
+
+    let todo = host.call("createTodo", json!(["Drink some hot chocolate."]));
+    let uuid = todo["uuid"].clone();
+    assert_eq!(todo["name"], json!("Drink some hot chocolate."));
+    assert_eq!(todo["dueDate"], Value::Null);
+    assert_eq!(todo["labels"], json!([]));
+
+    // Dates are milliseconds, like the rest of the extension's.
+    let todo = host.call("todoChangeDueDate", json!([uuid, 1500000000000i64]));
+    assert_eq!(todo["dueDate"], json!(1500000000000i64));
+    let todo = host.call("todoChangeCompletionDate", json!([uuid, 1500000100000i64]));
+    assert_eq!(todo["completionDate"], json!(1500000100000i64));
+    let todo = host.call("todoChangeCompletionDate", json!([uuid, null]));
+    assert_eq!(todo["completionDate"], Value::Null);
+    assert_eq!(todo["dueDate"], json!(1500000000000i64));
+
+    let todo = host.call("todoChangeName", json!([uuid, "Drink some tea."]));
+    assert_eq!(todo["name"], json!("Drink some tea."));
+
+    host.call("todoAddLabel", json!([uuid, "P0"]));
+    let todo = host.call("todoAddLabel", json!([uuid, "Backlog"]));
+    let mut names = label_names(&todo);
+    names.sort();
+    assert_eq!(names, vec!["Backlog", "P0"]);
+    let todo = host.call("todoRemoveLabel", json!([uuid, "Backlog"]));
+    assert_eq!(label_names(&todo), vec!["P0"]);
+
+    // Removing a label takes it off every todo.
+    assert_eq!(host.call("removeLabel", json!(["P0"])), json!("P0"));
+    let todos = host.call("getTodos", json!([]));
+    assert_eq!(todos.as_array().unwrap().len(), 1);
+    assert_eq!(todos[0]["labels"], json!([]));
+
+    assert_eq!(host.call("removeTodo", json!([uuid])), uuid);
+    assert_eq!(host.call("getTodos", json!([])), json!([]));
+    assert!(host.finish());
+}
+
+#[test]
+fn test_store_outlives_the_host() {
+    let database = database("persistence");
+    let mut host = Host::start(&database);
+    host.call("createTodo", json!(["Make Toodle WebExtension."]));
+    assert!(host.finish());
+
+    let mut host = Host::start(&database);
+    let todos = host.call("getTodos", json!([]));
+    assert_eq!(todos[0]["name"], json!("Make Toodle WebExtension."));
+    assert!(host.finish());
+}
+
+#[test]
+fn test_errors_are_replied_to() {
+    let mut host = Host::start(&database("errors"));
+
+    let reply = host.call_reply("explode", json!([]));
+    assert!(reply["error"]["message"].as_str().unwrap().contains("unknown method"));
+    let reply = host.call_reply("todoChangeName", json!(["not a uuid", "name"]));
+    assert!(reply["error"]["message"].as_str().unwrap().contains("not a uuid"));
+    let reply = host.call_reply("createTodo", json!([]));
+    assert!(reply["error"]["message"].as_str().unwrap().starts_with("invalid argument: name"));
+
+    host.send(&json!("not an object"));
+    assert!(host.receive()["error"].is_object());
+
+    // None of that ended the session.
+    assert_eq!(host.call("getTodos", json!([])), json!([]));
+    assert!(host.finish());
+}
+
+#[test]
+fn test_truncated_input_is_an_error() {
+    let mut host = Host::start(&database("truncated"));
+    host.send_bytes(&[10, 0]);
+    assert!(!host.finish());
+}
+
+#[test]
+fn test_oversized_messages_are_refused() {
+    let mut host = Host::start(&database("oversized"));
+    host.send_bytes(&[0xff, 0xff, 0xff, 0xff]);
+    assert!(!host.finish());
+}
@@ -0,0 +1,927 @@
+// Copyright 2016 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! A JSON request/response API covering everything a `Toodle` can do, so that a platform
+//! only has to bind one function instead of marshalling structs.
+//!
+//! A request looks like
+//!
+//! ```json
+//! {"version": 1, "id": 7, "method": "createItem", "params": {"name": "Buy milk", "labels": ["home"]}}
+//! ```
+//!
+//! and is answered with either `{"version": 1, "id": 7, "result": ...}` or
+//! `{"version": 1, "id": 7, "error": {"code": 2, "message": "..."}}`. `id` is optional
+//! and echoed back untouched. Error codes are those of `ErrorCode`. `params` may be left
+//! out for methods that take none.
+//!
+//! Items are returned as `ItemRecord`s and labels as `LabelRecord`s. Dates are whole
+//! seconds since the epoch. In `updateItem`, a field that's left out is left alone and a
+//! date, notes or priority that's `null` is cleared.
+//!
+//! The methods of version 1 are `createItem`, `getItem`, `updateItem`, `deleteItem`,
+//! `getItems` (with an optional `filter`), `searchItems`, `getLabels`, `createLabel`,
+//! `renameLabel`, `updateLabelColor`, `deleteLabel` and `sync`, and for subtasks
+//! `moveItem`, `completeItem`, `getItemTree` and `getItemTrees`. `createItem` takes an
+//! optional `parent`, and `deleteItem` and `completeItem` a `cascade` flag that applies
+//! them to every subtask too. An item's `recurrence` is an RRULE such as
+//! `FREQ=WEEKLY;BYDAY=MO`; see `recurrence` for what's supported.
+//!
+//! `getLegacyImportReport` returns the `LegacyImport` report of importing the legacy
+//! tables found in the store's own file when it was first opened, or `null` if there
+//! weren't any.
+
+use std::collections::HashMap;
+use std::os::raw::c_char;
+use std::panic::{
+    self,
+    AssertUnwindSafe,
+};
+use std::ptr;
+use std::sync::Mutex;
+
+use serde::{
+    Deserialize,
+    Deserializer,
+};
+use serde_json::{
+    self,
+    Value,
+};
+use mentat_core::Uuid;
+use time::Timespec;
+
+use clock::Clock;
+use encryption::{
+    sync_with_keys,
+    KeyBundle,
+};
+use errors as list_errors;
+use errors::ErrorKind;
+use ffi_errors::{
+    describe,
+    panic_message,
+    ErrorCode,
+};
+use ffi_utils::strings::{
+    c_char_to_string,
+    string_to_c_char,
+};
+use filters::{
+    ItemFilter,
+    SortDirection,
+    SortKey,
+};
+use items::{
+    Item,
+    ItemUpdate,
+};
+use labels::Label;
+use recurrence::Recurrence;
+#[cfg(feature = "remote")]
+use remote::RemoteServer;
+use subtasks::{
+    Cascade,
+    ItemTree,
+    Progress,
+};
+use sync::MemoryServer;
+use {
+    Toodle,
+    parse_uuid_str,
+    with_toodle_handle,
+};
+
+/// The version of the request/response schema this library speaks.
+pub const API_VERSION: u64 = 1;
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LabelRecord {
+    pub name: String,
+    pub color: String,
+}
+
+impl<'a> From<&'a Label> for LabelRecord {
+    fn from(label: &'a Label) -> LabelRecord {
+        LabelRecord {
+            name: label.name.clone(),
+            color: label.color.clone(),
+        }
+    }
+}
+
+impl LabelRecord {
+    /// A label that isn't in any store yet.
+    pub fn to_label(&self) -> Label {
+        Label {
+            id: None,
+            name: self.name.clone(),
+            color: self.color.clone(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ItemRecord {
+    pub uuid: String,
+    pub name: String,
+    pub due_date: Option<i64>,
+    pub completion_date: Option<i64>,
+    pub labels: Vec<LabelRecord>,
+    #[serde(default)]
+    pub notes: Option<String>,
+    #[serde(default)]
+    pub priority: Option<i64>,
+    #[serde(default)]
+    pub starred: bool,
+    #[serde(default)]
+    pub recurrence: Option<String>,
+}
+
+impl<'a> From<&'a Item> for ItemRecord {
+    fn from(item: &'a Item) -> ItemRecord {
+        ItemRecord {
+            uuid: item.uuid.hyphenated().to_string(),
+            name: item.name.clone(),
+            due_date: item.due_date.map(|date| date.sec),
+            completion_date: item.completion_date.map(|date| date.sec),
+            labels: item.labels.iter().map(LabelRecord::from).collect(),
+            notes: item.notes.clone(),
+            priority: item.priority,
+            starred: item.starred,
+            recurrence: item.recurrence.as_ref().map(|recurrence| recurrence.to_string()),
+        }
+    }
+}
+
+impl ItemRecord {
+    /// The item this record describes, as `Toodle::import` takes it: with the record's
+    /// uuid, and labels that are only named.
+    pub fn to_item(&self) -> Result<Item, list_errors::Error> {
+        let mut item = Item::default();
+        item.uuid = parse_item_uuid(&self.uuid)?;
+        item.name = self.name.clone();
+        item.due_date = date(self.due_date);
+        item.completion_date = date(self.completion_date);
+        item.labels = self.labels.iter().map(LabelRecord::to_label).collect();
+        item.notes = self.notes.clone();
+        item.priority = self.priority;
+        item.starred = self.starred;
+        item.recurrence = recurrence(self.recurrence.clone())?;
+        Ok(item)
+    }
+}
+
+/// Parses an item's uuid as records spell it, failing with `InvalidArgument`.
+pub fn parse_item_uuid(uuid: &str) -> Result<Uuid, list_errors::Error> {
+    parse_uuid_str(uuid)
+}
+
+/// An item with its subtasks, and how many of those are complete.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ItemTreeRecord {
+    pub item: ItemRecord,
+    pub progress: Progress,
+    pub children: Vec<ItemTreeRecord>,
+}
+
+impl<'a> From<&'a ItemTree> for ItemTreeRecord {
+    fn from(tree: &'a ItemTree) -> ItemTreeRecord {
+        ItemTreeRecord {
+            item: ItemRecord::from(&tree.item),
+            progress: tree.progress(),
+            children: tree.children.iter().map(ItemTreeRecord::from).collect(),
+        }
+    }
+}
+
+/// Tells a field that was left out (`None`) apart from one that was `null` (`Some(None)`).
+fn present<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+    where D: Deserializer<'de>, T: Deserialize<'de> {
+    Option::deserialize(deserializer).map(Some)
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct NewItem {
+    pub name: String,
+    #[serde(default)]
+    pub due_date: Option<i64>,
+    #[serde(default)]
+    pub completion_date: Option<i64>,
+    /// Label names; every label must already exist.
+    #[serde(default)]
+    pub labels: Vec<String>,
+    #[serde(default)]
+    pub notes: Option<String>,
+    #[serde(default)]
+    pub priority: Option<i64>,
+    #[serde(default)]
+    pub starred: bool,
+    /// The uuid of the item to create this one as a subtask of.
+    #[serde(default)]
+    pub parent: Option<String>,
+    #[serde(default)]
+    pub recurrence: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct ItemChanges {
+    pub uuid: String,
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default, deserialize_with = "present")]
+    pub due_date: Option<Option<i64>>,
+    #[serde(default, deserialize_with = "present")]
+    pub completion_date: Option<Option<i64>>,
+    /// Replaces the item's labels.
+    #[serde(default)]
+    pub labels: Option<Vec<String>>,
+    #[serde(default, deserialize_with = "present")]
+    pub notes: Option<Option<String>>,
+    #[serde(default, deserialize_with = "present")]
+    pub priority: Option<Option<i64>>,
+    #[serde(default)]
+    pub starred: Option<bool>,
+    #[serde(default, deserialize_with = "present")]
+    pub recurrence: Option<Option<String>>,
+}
+
+/// The JSON form of an `ItemFilter`. `overdue` compares due dates with the Toodle's clock.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase", default, deny_unknown_fields)]
+pub struct FilterParams {
+    pub completed: Option<bool>,
+    pub due_before: Option<i64>,
+    pub due_after: Option<i64>,
+    pub overdue: bool,
+    pub any_labels: Vec<String>,
+    pub all_labels: Vec<String>,
+    pub no_labels: Vec<String>,
+    pub starred: Option<bool>,
+    pub min_priority: Option<i64>,
+    pub sort_by: Option<SortKey>,
+    pub descending: bool,
+    pub limit: Option<usize>,
+    pub offset: usize,
+}
+
+impl FilterParams {
+    pub fn to_filter(&self, clock: &Clock) -> ItemFilter {
+        let mut filter = ItemFilter::new();
+        if let Some(completed) = self.completed {
+            filter = filter.completed(completed);
+        }
+        if let Some(date) = self.due_before {
+            filter = filter.due_before(Timespec::new(date, 0));
+        }
+        if let Some(date) = self.due_after {
+            filter = filter.due_after(Timespec::new(date, 0));
+        }
+        if self.overdue {
+            filter = filter.overdue(clock);
+        }
+        for name in self.any_labels.iter() {
+            filter = filter.with_any_label(name.as_str());
+        }
+        for name in self.all_labels.iter() {
+            filter = filter.with_all_label(name.as_str());
+        }
+        for name in self.no_labels.iter() {
+            filter = filter.without_label(name.as_str());
+        }
+        if let Some(starred) = self.starred {
+            filter = filter.starred(starred);
+        }
+        if let Some(priority) = self.min_priority {
+            filter = filter.min_priority(priority);
+        }
+        let direction = if self.descending { SortDirection::Descending } else { SortDirection::Ascending };
+        filter = filter.sort_by(self.sort_by.unwrap_or(SortKey::Name), direction);
+        if let Some(limit) = self.limit {
+            filter = filter.limit(limit);
+        }
+        filter.offset(self.offset)
+    }
+}
+
+/// Where to sync. Memory servers are shared by every `Toodle` in the process, by name;
+/// `http` servers, which need the `remote` feature, are a user's log on a server such as
+/// `toodle-sync-server`. Either kind encrypts what it stores with `keys`, when they're
+/// given.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+pub enum ServerSpec {
+    Memory {
+        name: String,
+        #[serde(default)]
+        keys: Option<KeyBundle>,
+    },
+    #[cfg(feature = "remote")]
+    Http {
+        url: String,
+        #[serde(default)]
+        keys: Option<KeyBundle>,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SyncResult {
+    pub uploaded: usize,
+    pub downloaded: usize,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(tag = "method", content = "params", rename_all = "camelCase")]
+pub enum Request {
+    CreateItem(NewItem),
+    GetItem { uuid: String },
+    UpdateItem(ItemChanges),
+    DeleteItem {
+        uuid: String,
+        #[serde(default)]
+        cascade: bool,
+    },
+    MoveItem {
+        uuid: String,
+        /// Left out or `null` to move the item to the top level.
+        #[serde(default)]
+        parent: Option<String>,
+    },
+    CompleteItem {
+        uuid: String,
+        /// Left out or `null` to mark the item incomplete.
+        #[serde(default, rename = "completionDate")]
+        completion_date: Option<i64>,
+        #[serde(default)]
+        cascade: bool,
+    },
+    GetItemTree { uuid: String },
+    GetItemTrees,
+    GetItems {
+        #[serde(default)]
+        filter: FilterParams,
+    },
+    SearchItems { query: String },
+    GetLabels,
+    CreateLabel { name: String, color: String },
+    RenameLabel {
+        name: String,
+        #[serde(rename = "newName")]
+        new_name: String,
+    },
+    UpdateLabelColor { name: String, color: String },
+    DeleteLabel { name: String },
+    Sync { server: ServerSpec },
+    GetLegacyImportReport,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ErrorRecord {
+    pub code: i32,
+    pub message: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Response {
+    pub version: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<ErrorRecord>,
+}
+
+lazy_static! {
+    static ref MEMORY_SERVERS: Mutex<HashMap<String, MemoryServer>> = Mutex::new(HashMap::new());
+}
+
+fn invalid_request(message: String) -> list_errors::Error {
+    ErrorKind::InvalidArgument(message).into()
+}
+
+/// Splits a request into its id and the request proper, checking its version.
+fn parse_request(request: &str) -> (Option<Value>, Result<Request, list_errors::Error>) {
+    let mut object = match serde_json::from_str::<Value>(request) {
+        Ok(Value::Object(object)) => object,
+        Ok(_) => return (None, Err(invalid_request("a request must be a JSON object".to_string()))),
+        Err(e) => return (None, Err(invalid_request(format!("malformed request: {}", e)))),
+    };
+    let id = object.remove("id");
+    let request = match object.remove("version") {
+        Some(Value::Number(ref version)) if version.as_u64() == Some(API_VERSION) => {
+            serde_json::from_value(Value::Object(object)).map_err(|e| invalid_request(format!("bad request: {}", e)))
+        },
+        Some(version) => Err(invalid_request(format!("unsupported version {}; this library speaks version {}", version, API_VERSION))),
+        None => Err(invalid_request("a request must have a version".to_string())),
+    };
+    (id, request)
+}
+
+fn date(seconds: Option<i64>) -> Option<Timespec> {
+    seconds.map(|seconds| Timespec::new(seconds, 0))
+}
+
+fn existing_item(toodle: &Toodle, uuid: &str) -> Result<Item, list_errors::Error> {
+    let uuid = parse_uuid_str(uuid)?;
+    match toodle.fetch_item(&uuid)? {
+        Some(item) => Ok(item),
+        None => bail!(ErrorKind::NotFound(format!("item {}", uuid.hyphenated()))),
+    }
+}
+
+fn existing_label(toodle: &Toodle, name: &str) -> Result<Label, list_errors::Error> {
+    match toodle.fetch_label(&name.to_string())? {
+        Some(label) => Ok(label),
+        None => bail!(ErrorKind::NotFound(format!("label {:?}", name))),
+    }
+}
+
+fn existing_labels(toodle: &Toodle, names: &[String]) -> Result<Vec<Label>, list_errors::Error> {
+    names.iter().map(|name| existing_label(toodle, name)).collect()
+}
+
+fn recurrence(rule: Option<String>) -> Result<Option<Recurrence>, list_errors::Error> {
+    match rule {
+        Some(rule) => Ok(Some(rule.parse()?)),
+        None => Ok(None),
+    }
+}
+
+fn cascade_for(cascade: bool) -> Cascade {
+    if cascade { Cascade::Descendants } else { Cascade::ItemOnly }
+}
+
+fn item_records(items: &[Item]) -> Vec<ItemRecord> {
+    items.iter().map(ItemRecord::from).collect()
+}
+
+/// Runs a parsed request against `toodle`.
+pub fn execute(toodle: &mut Toodle, request: Request) -> Result<Value, list_errors::Error> {
+    let result = match request {
+        Request::CreateItem(new_item) => {
+            let mut item = Item::default();
+            item.name = new_item.name;
+            item.due_date = date(new_item.due_date);
+            item.completion_date = date(new_item.completion_date);
+            item.labels = existing_labels(toodle, &new_item.labels)?;
+            item.notes = new_item.notes;
+            item.priority = new_item.priority;
+            item.starred = new_item.starred;
+            item.recurrence = recurrence(new_item.recurrence)?;
+            let created = match new_item.parent {
+                Some(parent) => toodle.create_subtask(&parse_uuid_str(&parent)?, &item)?,
+                None => toodle.create_item(&item)?,
+            };
+            match toodle.fetch_item(&created)? {
+                Some(item) => serde_json::to_value(ItemRecord::from(&item))?,
+                None => bail!(ErrorKind::NotFound("the item that was just created".to_string())),
+            }
+        },
+        Request::GetItem { uuid } => {
+            let item = toodle.fetch_item(&parse_uuid_str(&uuid)?)?;
+            serde_json::to_value(item.as_ref().map(ItemRecord::from))?
+        },
+        Request::UpdateItem(changes) => {
+            // Everything is looked up and parsed before anything changes, so that a bad
+            // request leaves the item as it was.
+            let item = existing_item(toodle, &changes.uuid)?;
+            let recurrence = match changes.recurrence {
+                Some(rule) => Some(recurrence(rule)?),
+                None => None,
+            };
+            let labels = match changes.labels {
+                Some(ref names) => Some(existing_labels(toodle, names)?),
+                None => None,
+            };
+            toodle.update_item(&item, ItemUpdate {
+                name: changes.name,
+                due_date: changes.due_date.map(date),
+                completion_date: changes.completion_date.map(date),
+                labels: labels,
+                notes: changes.notes,
+                priority: changes.priority,
+                starred: changes.starred,
+                recurrence: recurrence,
+            })?;
+            let item = existing_item(toodle, &changes.uuid)?;
+            serde_json::to_value(ItemRecord::from(&item))?
+        },
+        Request::DeleteItem { uuid, cascade } => {
+            toodle.delete_item_with(&parse_uuid_str(&uuid)?, cascade_for(cascade))?;
+            Value::Null
+        },
+        Request::MoveItem { uuid, parent } => {
+            let parent = match parent {
+                Some(parent) => Some(parse_uuid_str(&parent)?),
+                None => None,
+            };
+            toodle.move_item(&parse_uuid_str(&uuid)?, parent.as_ref())?;
+            serde_json::to_value(ItemRecord::from(&existing_item(toodle, &uuid)?))?
+        },
+        Request::CompleteItem { uuid, completion_date, cascade } => {
+            toodle.complete_item(&parse_uuid_str(&uuid)?, date(completion_date), cascade_for(cascade))?;
+            serde_json::to_value(ItemRecord::from(&existing_item(toodle, &uuid)?))?
+        },
+        Request::GetItemTree { uuid } => {
+            let tree = toodle.fetch_item_tree(&parse_uuid_str(&uuid)?)?;
+            serde_json::to_value(tree.as_ref().map(ItemTreeRecord::from))?
+        },
+        Request::GetItemTrees => {
+            let trees: Vec<ItemTreeRecord> = toodle.fetch_item_trees()?.iter().map(ItemTreeRecord::from).collect();
+            serde_json::to_value(trees)?
+        },
+        Request::GetItems { filter } => {
+            let filter = filter.to_filter(&*toodle.clock);
+            serde_json::to_value(item_records(&toodle.query_items(&filter)?))?
+        },
+        Request::SearchItems { query } => serde_json::to_value(item_records(&toodle.search_items(&query)?))?,
+        Request::GetLabels => {
+            let labels: Vec<LabelRecord> = toodle.fetch_labels()?.iter().map(LabelRecord::from).collect();
+            serde_json::to_value(labels)?
+        },
+        Request::CreateLabel { name, color } => {
+            match toodle.create_label(name.clone(), color)? {
+                Some(label) => serde_json::to_value(LabelRecord::from(&label))?,
+                None => bail!(ErrorKind::NotFound(format!("label {:?}", name))),
+            }
+        },
+        Request::RenameLabel { name, new_name } => {
+            let label = existing_label(toodle, &name)?;
+            match toodle.rename_label(&label, new_name.clone())? {
+                Some(label) => serde_json::to_value(LabelRecord::from(&label))?,
+                None => bail!(ErrorKind::NotFound(format!("label {:?}", new_name))),
+            }
+        },
+        Request::UpdateLabelColor { name, color } => {
+            let label = existing_label(toodle, &name)?;
+            match toodle.update_label_color(&label, color)? {
+                Some(label) => serde_json::to_value(LabelRecord::from(&label))?,
+                None => bail!(ErrorKind::NotFound(format!("label {:?}", name))),
+            }
+        },
+        Request::DeleteLabel { name } => {
+            let label = existing_label(toodle, &name)?;
+            toodle.delete_label(&label)?;
+            Value::Null
+        },
+        Request::Sync { server } => {
+            let report = match server {
+                ServerSpec::Memory { name, keys } => {
+                    let mut servers = MEMORY_SERVERS.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+                    sync_with_keys(toodle, servers.entry(name).or_insert_with(MemoryServer::new), keys.as_ref())?
+                },
+                #[cfg(feature = "remote")]
+                ServerSpec::Http { url, keys } => sync_with_keys(toodle, RemoteServer::new(&url)?, keys.as_ref())?,
+            };
+            serde_json::to_value(SyncResult { uploaded: report.uploaded, downloaded: report.downloaded })?
+        },
+        Request::GetLegacyImportReport => serde_json::to_value(toodle.legacy_import_report()?)?,
+    };
+    Ok(result)
+}
+
+/// Answers `request` using `run`, reporting failures and panics in the response.
+pub fn respond_with<F>(request: &str, run: F) -> String
+    where F: FnOnce(Request) -> Result<Value, list_errors::Error> {
+    let (id, request) = parse_request(request);
+    let outcome = panic::catch_unwind(AssertUnwindSafe(|| request.and_then(run)));
+    let (result, error) = match outcome {
+        Ok(Ok(result)) => (Some(result), None),
+        Ok(Err(e)) => (None, Some(ErrorRecord { code: ErrorCode::from(&e) as i32, message: describe(&e) })),
+        Err(payload) => (None, Some(ErrorRecord { code: ErrorCode::Panic as i32, message: panic_message(payload) })),
+    };
+    let response = Response {
+        version: API_VERSION,
+        id: id,
+        result: result,
+        error: error,
+    };
+    serde_json::to_string(&response).expect("responses are always serializable")
+}
+
+/// Answers a JSON `request` against `toodle`.
+pub fn respond(toodle: &mut Toodle, request: &str) -> String {
+    respond_with(request, |request| execute(toodle, request))
+}
+
+/// Answers a JSON request against the Toodle for `toodle`. Failures, including a bad
+/// handle, are reported in the response, which must be released with
+/// `toodle_string_destroy`. Returns null only if `request` is null.
+#[no_mangle]
+pub extern "C" fn toodle_call(toodle: u64, request: *const c_char) -> *mut c_char {
+    if request.is_null() {
+        return ptr::null_mut();
+    }
+    let request = c_char_to_string(request);
+    let response = respond_with(&request, |request| with_toodle_handle(toodle, |toodle| execute(toodle, request)));
+    string_to_c_char(response)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn toodle_string_destroy(string: *mut c_char) {
+    if !string.is_null() {
+        let _ = ::std::ffi::CString::from_raw(string);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::ffi::{
+        CStr,
+        CString,
+    };
+
+    use clock::FixedClock;
+    use handles::TOODLES;
+
+    fn toodle() -> Toodle {
+        Toodle::new(String::new(), None).expect("expected a new toodle")
+    }
+
+    /// Sends a request, expecting success, and returns its result.
+    fn call(toodle: &mut Toodle, request: &str) -> Value {
+        let response: Response = serde_json::from_str(&respond(toodle, request)).expect("expected a response");
+        assert_eq!(response.version, API_VERSION);
+        assert_eq!(response.error, None);
+        response.result.expect("expected a result")
+    }
+
+    fn call_error(toodle: &mut Toodle, request: &str) -> ErrorRecord {
+        let response: Response = serde_json::from_str(&respond(toodle, request)).expect("expected a response");
+        assert_eq!(response.result, None);
+        response.error.expect("expected an error")
+    }
+
+    fn item(value: Value) -> ItemRecord {
+        serde_json::from_value(value).expect("expected an item")
+    }
+
+    fn item_names(value: Value) -> Vec<String> {
+        let items: Vec<ItemRecord> = serde_json::from_value(value).expect("expected items");
+        items.into_iter().map(|item| item.name).collect()
+    }
+
+    #[test]
+    fn test_item_crud() {
+        let mut toodle = toodle();
+        call(&mut toodle, r#"{"version": 1, "method": "createLabel", "params": {"name": "home", "color": "#ff0000"}}"#);
+
+        let created = item(call(&mut toodle, r#"{"version": 1, "method": "createItem",
+            "params": {"name": "Buy milk", "dueDate": 1500000000, "labels": ["home"]}}"#));
+        assert_eq!(created.name, "Buy milk");
+        assert_eq!(created.due_date, Some(1500000000));
+        assert_eq!(created.completion_date, None);
+        assert_eq!(created.labels, vec![LabelRecord { name: "home".to_string(), color: "#ff0000".to_string() }]);
+
+        let fetched = item(call(&mut toodle, &format!(r#"{{"version": 1, "method": "getItem", "params": {{"uuid": "{}"}}}}"#, created.uuid)));
+        assert_eq!(fetched, created);
+
+        // Fields that are left out are left alone; null dates are cleared.
+        let updated = item(call(&mut toodle, &format!(r#"{{"version": 1, "method": "updateItem",
+            "params": {{"uuid": "{}", "completionDate": 1500000100, "labels": []}}}}"#, created.uuid)));
+        assert_eq!(updated.name, "Buy milk");
+        assert_eq!(updated.due_date, Some(1500000000));
+        assert_eq!(updated.completion_date, Some(1500000100));
+        assert!(updated.labels.is_empty());
+
+        let updated = item(call(&mut toodle, &format!(r#"{{"version": 1, "method": "updateItem",
+            "params": {{"uuid": "{}", "name": "Buy oat milk", "dueDate": null}}}}"#, created.uuid)));
+        assert_eq!(updated.name, "Buy oat milk");
+        assert_eq!(updated.due_date, None);
+        assert_eq!(updated.completion_date, Some(1500000100));
+
+        let updated = item(call(&mut toodle, &format!(r#"{{"version": 1, "method": "updateItem",
+            "params": {{"uuid": "{}", "notes": "Semi-skimmed", "priority": 2, "starred": true}}}}"#, created.uuid)));
+        assert_eq!(updated.notes, Some("Semi-skimmed".to_string()));
+        assert_eq!(updated.priority, Some(2));
+        assert!(updated.starred);
+
+        let updated = item(call(&mut toodle, &format!(r#"{{"version": 1, "method": "updateItem",
+            "params": {{"uuid": "{}", "notes": null}}}}"#, created.uuid)));
+        assert_eq!(updated.notes, None);
+        assert_eq!(updated.priority, Some(2));
+        assert!(updated.starred);
+
+        call(&mut toodle, &format!(r#"{{"version": 1, "method": "deleteItem", "params": {{"uuid": "{}"}}}}"#, created.uuid));
+        assert_eq!(call(&mut toodle, &format!(r#"{{"version": 1, "method": "getItem", "params": {{"uuid": "{}"}}}}"#, created.uuid)), Value::Null);
+    }
+
+    #[test]
+    fn test_queries() {
+        let mut toodle = toodle();
+        call(&mut toodle, r#"{"version": 1, "method": "createLabel", "params": {"name": "work", "color": "#00ff00"}}"#);
+        call(&mut toodle, r#"{"version": 1, "method": "createItem", "params": {"name": "Write report", "dueDate": 200, "labels": ["work"]}}"#);
+        call(&mut toodle, r#"{"version": 1, "method": "createItem", "params": {"name": "Book flights", "dueDate": 100, "completionDate": 50}}"#);
+        call(&mut toodle, r#"{"version": 1, "method": "createItem", "params": {"name": "Answer email"}}"#);
+
+        assert_eq!(item_names(call(&mut toodle, r#"{"version": 1, "method": "getItems", "params": {}}"#)),
+                   vec!["Answer email", "Book flights", "Write report"]);
+        assert_eq!(item_names(call(&mut toodle, r#"{"version": 1, "method": "getItems",
+            "params": {"filter": {"completed": false, "sortBy": "dueDate", "descending": true}}}"#)),
+                   vec!["Write report", "Answer email"]);
+        assert_eq!(item_names(call(&mut toodle, r#"{"version": 1, "method": "getItems",
+            "params": {"filter": {"anyLabels": ["work"]}}}"#)),
+                   vec!["Write report"]);
+        assert_eq!(item_names(call(&mut toodle, r#"{"version": 1, "method": "getItems",
+            "params": {"filter": {"limit": 1, "offset": 1}}}"#)),
+                   vec!["Book flights"]);
+        assert_eq!(item_names(call(&mut toodle, r#"{"version": 1, "method": "searchItems", "params": {"query": "flights"}}"#)),
+                   vec!["Book flights"]);
+        call(&mut toodle, r#"{"version": 1, "method": "createItem", "params": {"name": "Call plumber", "priority": 3, "starred": true}}"#);
+        assert_eq!(item_names(call(&mut toodle, r#"{"version": 1, "method": "getItems",
+            "params": {"filter": {"starred": true}}}"#)),
+                   vec!["Call plumber"]);
+        assert_eq!(item_names(call(&mut toodle, r#"{"version": 1, "method": "getItems",
+            "params": {"filter": {"minPriority": 1}}}"#)),
+                   vec!["Call plumber"]);
+    }
+
+    #[test]
+    fn test_subtasks() {
+        let mut toodle = toodle();
+        let chores = item(call(&mut toodle, r#"{"version": 1, "method": "createItem", "params": {"name": "Chores"}}"#));
+        let dishes = item(call(&mut toodle, &format!(r#"{{"version": 1, "method": "createItem",
+            "params": {{"name": "Dishes", "parent": "{}"}}}}"#, chores.uuid)));
+        call(&mut toodle, &format!(r#"{{"version": 1, "method": "createItem", "params": {{"name": "Laundry", "parent": "{}"}}}}"#, chores.uuid));
+        call(&mut toodle, &format!(r#"{{"version": 1, "method": "completeItem",
+            "params": {{"uuid": "{}", "completionDate": 1500000000}}}}"#, dishes.uuid));
+
+        let tree: ItemTreeRecord = serde_json::from_value(call(&mut toodle, &format!(r#"{{"version": 1, "method": "getItemTree",
+            "params": {{"uuid": "{}"}}}}"#, chores.uuid))).expect("expected a tree");
+        assert_eq!(tree.progress, Progress { completed: 1, total: 2 });
+        assert_eq!(tree.children.iter().map(|child| child.item.name.clone()).collect::<Vec<_>>(), vec!["Dishes", "Laundry"]);
+
+        // A subtask can't become its parent's parent.
+        let error = call_error(&mut toodle, &format!(r#"{{"version": 1, "method": "moveItem",
+            "params": {{"uuid": "{}", "parent": "{}"}}}}"#, chores.uuid, dishes.uuid));
+        assert_eq!(error.code, ErrorCode::InvalidArgument as i32);
+
+        call(&mut toodle, &format!(r#"{{"version": 1, "method": "deleteItem", "params": {{"uuid": "{}", "cascade": true}}}}"#, chores.uuid));
+        let trees: Vec<ItemTreeRecord> = serde_json::from_value(call(&mut toodle, r#"{"version": 1, "method": "getItemTrees"}"#)).expect("expected trees");
+        assert_eq!(trees, vec![]);
+    }
+
+    #[test]
+    fn test_recurrence() {
+        let mut toodle = toodle();
+        // Due far enough ahead that completing it now is early.
+        let bins = item(call(&mut toodle, r#"{"version": 1, "method": "createItem",
+            "params": {"name": "Bins", "dueDate": 4102444800, "recurrence": "freq=weekly;count=3"}}"#));
+        assert_eq!(bins.recurrence, Some("FREQ=WEEKLY;COUNT=3".to_string()));
+
+        let completed = item(call(&mut toodle, &format!(r#"{{"version": 1, "method": "updateItem",
+            "params": {{"uuid": "{}", "completionDate": 1500000000}}}}"#, bins.uuid)));
+        assert_eq!(completed.recurrence, None);
+        let next: Vec<ItemRecord> = serde_json::from_value(call(&mut toodle, r#"{"version": 1, "method": "getItems",
+            "params": {"filter": {"completed": false}}}"#)).expect("expected items");
+        assert_eq!(next.len(), 1);
+        assert_eq!(next[0].due_date, Some(4102444800 + 7 * 24 * 60 * 60));
+        assert_eq!(next[0].recurrence, Some("FREQ=WEEKLY;COUNT=2".to_string()));
+
+        let updated = item(call(&mut toodle, &format!(r#"{{"version": 1, "method": "updateItem",
+            "params": {{"uuid": "{}", "recurrence": null}}}}"#, next[0].uuid)));
+        assert_eq!(updated.recurrence, None);
+
+        let error = call_error(&mut toodle, &format!(r#"{{"version": 1, "method": "updateItem",
+            "params": {{"uuid": "{}", "recurrence": "FREQ=HOURLY"}}}}"#, next[0].uuid));
+        assert_eq!(error.code, ErrorCode::InvalidArgument as i32);
+
+        // A request that fails changes nothing, not even the parts that came before the
+        // failure.
+        let error = call_error(&mut toodle, &format!(r#"{{"version": 1, "method": "updateItem",
+            "params": {{"uuid": "{}", "recurrence": "FREQ=DAILY", "labels": ["missing"]}}}}"#, next[0].uuid));
+        assert_eq!(error.code, ErrorCode::NotFound as i32);
+        let unchanged = item(call(&mut toodle, &format!(r#"{{"version": 1, "method": "getItem", "params": {{"uuid": "{}"}}}}"#, next[0].uuid)));
+        assert_eq!(unchanged.recurrence, None);
+
+        // A new rule and a completion in one request moves the new rule on.
+        let completed = item(call(&mut toodle, &format!(r#"{{"version": 1, "method": "updateItem",
+            "params": {{"uuid": "{}", "recurrence": "FREQ=DAILY;COUNT=2", "completionDate": 1500000000}}}}"#, next[0].uuid)));
+        assert_eq!(completed.recurrence, None);
+        let next: Vec<ItemRecord> = serde_json::from_value(call(&mut toodle, r#"{"version": 1, "method": "getItems",
+            "params": {"filter": {"completed": false}}}"#)).expect("expected items");
+        assert_eq!(next.len(), 1);
+        assert_eq!(next[0].due_date, Some(4102444800 + 8 * 24 * 60 * 60));
+        assert_eq!(next[0].recurrence, Some("FREQ=DAILY;COUNT=1".to_string()));
+    }
+
+    #[test]
+    fn test_overdue_uses_the_toodle_clock() {
+        let mut toodle = toodle();
+        toodle.set_clock(FixedClock(Timespec::new(150, 0)));
+        call(&mut toodle, r#"{"version": 1, "method": "createItem", "params": {"name": "Late", "dueDate": 100}}"#);
+        call(&mut toodle, r#"{"version": 1, "method": "createItem", "params": {"name": "Soon", "dueDate": 200}}"#);
+        let overdue = r#"{"version": 1, "method": "getItems", "params": {"filter": {"overdue": true}}}"#;
+        assert_eq!(item_names(call(&mut toodle, overdue)), vec!["Late"]);
+
+        toodle.set_clock(FixedClock(Timespec::new(250, 0)));
+        assert_eq!(item_names(call(&mut toodle, overdue)), vec!["Late", "Soon"]);
+    }
+
+    #[test]
+    fn test_labels() {
+        let mut toodle = toodle();
+        call(&mut toodle, r#"{"version": 1, "method": "createLabel", "params": {"name": "home", "color": "#ff0000"}}"#);
+        call(&mut toodle, r#"{"version": 1, "method": "renameLabel", "params": {"name": "home", "newName": "house"}}"#);
+        call(&mut toodle, r#"{"version": 1, "method": "updateLabelColor", "params": {"name": "house", "color": "#0000ff"}}"#);
+
+        let labels: Vec<LabelRecord> = serde_json::from_value(call(&mut toodle, r#"{"version": 1, "method": "getLabels"}"#)).expect("expected labels");
+        assert_eq!(labels, vec![LabelRecord { name: "house".to_string(), color: "#0000ff".to_string() }]);
+
+        call(&mut toodle, r#"{"version": 1, "method": "deleteLabel", "params": {"name": "house"}}"#);
+        assert_eq!(call(&mut toodle, r#"{"version": 1, "method": "getLabels"}"#), Value::Array(vec![]));
+    }
+
+    #[test]
+    fn test_legacy_import_report() {
+        let mut toodle = toodle();
+        assert_eq!(call(&mut toodle, r#"{"version": 1, "method": "getLegacyImportReport"}"#), Value::Null);
+    }
+
+    #[test]
+    fn test_sync_through_a_memory_server() {
+        let mut first = toodle();
+        let mut second = toodle();
+        let sync = r#"{"version": 1, "method": "sync", "params": {"server": {"type": "memory", "name": "test_sync_through_a_memory_server"}}}"#;
+
+        call(&mut first, r#"{"version": 1, "method": "createItem", "params": {"name": "Shared"}}"#);
+        let report: SyncResult = serde_json::from_value(call(&mut first, sync)).expect("expected a report");
+        assert!(report.uploaded > 0);
+        let report: SyncResult = serde_json::from_value(call(&mut second, sync)).expect("expected a report");
+        assert!(report.downloaded > 0);
+        assert_eq!(item_names(call(&mut second, r#"{"version": 1, "method": "getItems", "params": {}}"#)), vec!["Shared"]);
+    }
+
+    #[test]
+    fn test_sync_with_keys() {
+        let mut first = toodle();
+        let mut second = toodle();
+        let keys = serde_json::to_string(&KeyBundle::generate().expect("expected keys")).expect("expected json");
+        let sync = format!(r#"{{"version": 1, "method": "sync", "params": {{"server": {{"type": "memory", "name": "test_sync_with_keys", "keys": {}}}}}}}"#, keys);
+
+        call(&mut first, r#"{"version": 1, "method": "createItem", "params": {"name": "Secret"}}"#);
+        call(&mut first, &sync);
+        call(&mut second, &sync);
+        assert_eq!(item_names(call(&mut second, r#"{"version": 1, "method": "getItems", "params": {}}"#)), vec!["Secret"]);
+
+        // Without the keys, the records can't be read.
+        let error = call_error(&mut toodle(), r#"{"version": 1, "method": "sync", "params": {"server": {"type": "memory", "name": "test_sync_with_keys"}}}"#);
+        assert_eq!(error.code, ErrorCode::Sync as i32);
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut toodle = toodle();
+        let code = |kind: ErrorCode| kind as i32;
+
+        assert_eq!(call_error(&mut toodle, "not json").code, code(ErrorCode::InvalidArgument));
+        assert_eq!(call_error(&mut toodle, r#"{"method": "getLabels"}"#).code, code(ErrorCode::InvalidArgument));
+        let error = call_error(&mut toodle, r#"{"version": 2, "method": "getLabels"}"#);
+        assert_eq!(error.code, code(ErrorCode::InvalidArgument));
+        assert_eq!(error.message, "invalid argument: unsupported version 2; this library speaks version 1");
+        assert_eq!(call_error(&mut toodle, r#"{"version": 1, "method": "explode"}"#).code, code(ErrorCode::InvalidArgument));
+        assert_eq!(call_error(&mut toodle, r#"{"version": 1, "method": "createItem", "params": {"nmae": "typo"}}"#).code, code(ErrorCode::InvalidArgument));
+        assert_eq!(call_error(&mut toodle, r#"{"version": 1, "method": "getItem", "params": {"uuid": "nope"}}"#).code, code(ErrorCode::InvalidArgument));
+        assert_eq!(call_error(&mut toodle, r#"{"version": 1, "method": "createItem", "params": {"name": "x", "labels": ["missing"]}}"#).code, code(ErrorCode::NotFound));
+        assert_eq!(call_error(&mut toodle, r#"{"version": 1, "method": "sync", "params": {"server": {"type": "http", "url": "ftp://example.com"}}}"#).code, code(ErrorCode::InvalidArgument));
+
+        // Ids are echoed back, whether or not the call succeeds.
+        let response: Response = serde_json::from_str(&respond(&mut toodle, r#"{"version": 1, "id": "abc", "method": "deleteLabel", "params": {"name": "missing"}}"#)).unwrap();
+        assert_eq!(response.id, Some(Value::String("abc".to_string())));
+        assert_eq!(response.error.map(|error| error.code), Some(code(ErrorCode::NotFound)));
+    }
+
+    #[test]
+    fn test_toodle_call() {
+        let handle = TOODLES.insert(toodle());
+        let call_handle = |handle: u64, request: &str| -> Response {
+            let request = CString::new(request).unwrap();
+            let response = toodle_call(handle, request.as_ptr());
+            let text = unsafe { CStr::from_ptr(response) }.to_str().expect("expected utf-8").to_string();
+            unsafe { toodle_string_destroy(response) };
+            serde_json::from_str(&text).expect("expected a response")
+        };
+
+        let response = call_handle(handle, r#"{"version": 1, "id": 1, "method": "createItem", "params": {"name": "Over the FFI"}}"#);
+        assert_eq!(response.id, Some(Value::from(1)));
+        assert_eq!(item(response.result.expect("expected a result")).name, "Over the FFI");
+
+        TOODLES.remove(handle).expect("expected a toodle");
+        let response = call_handle(handle, r#"{"version": 1, "method": "getLabels"}"#);
+        assert_eq!(response.error.map(|error| error.code), Some(ErrorCode::InvalidHandle as i32));
+        assert!(toodle_call(handle, ptr::null()).is_null());
+    }
+}
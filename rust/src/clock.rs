@@ -0,0 +1,40 @@
+// Copyright 2016 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+use std::fmt::Debug;
+
+use time::{
+    self,
+    Timespec,
+};
+
+/// A source of the current time, so that time-dependent behaviour can be tested.
+pub trait Clock: Debug {
+    fn now(&self) -> Timespec;
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Timespec {
+        time::get_time()
+    }
+}
+
+/// A clock that is stuck at a single instant.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FixedClock(pub Timespec);
+
+impl Clock for FixedClock {
+    fn now(&self) -> Timespec {
+        self.0
+    }
+}
@@ -0,0 +1,324 @@
+// Copyright 2016 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! Resolving concurrent edits to the same item.
+//!
+//! Edits to different fields never conflict: both are kept. Only when two stores change
+//! the same field, or add and remove the same label, does a `ConflictPolicy` pick a
+//! winner; `Toodle::sync_with_policy` consults it for every such pair of edits.
+//! Conflicting notes, priorities, stars and recurrence rules always go to the local side.
+
+use std::cmp::Ordering;
+
+use time::Timespec;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Winner {
+    Local,
+    Remote,
+}
+
+/// Whether a label that one store added and the other removed ends up on the item.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LabelSemantics {
+    AddWins,
+    RemoveWins,
+}
+
+/// Picks the winner of a conflicting pair of edits. Each method is only called when
+/// both sides changed the field and ended up with different values.
+pub trait ConflictPolicy {
+    fn resolve_name(&self, local: &str, remote: &str) -> Winner;
+    fn resolve_due_date(&self, local: Option<Timespec>, remote: Option<Timespec>) -> Winner;
+    fn resolve_completion_date(&self, local: Option<Timespec>, remote: Option<Timespec>) -> Winner;
+    fn resolve_label(&self, label: &str, local_added: bool, remote_added: bool) -> Winner;
+}
+
+/// Keeps the local name, the earliest deadline and the earliest completion, so that a
+/// deadline or a completion is never lost to a concurrent edit. Labels follow `labels`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DefaultPolicy {
+    pub labels: LabelSemantics,
+}
+
+impl Default for DefaultPolicy {
+    fn default() -> DefaultPolicy {
+        DefaultPolicy {
+            labels: LabelSemantics::AddWins,
+        }
+    }
+}
+
+/// Prefers any date over no date, and the earlier of two dates.
+fn earliest(local: Option<Timespec>, remote: Option<Timespec>) -> Winner {
+    match (local, remote) {
+        (Some(l), Some(r)) => if r.cmp(&l) == Ordering::Less { Winner::Remote } else { Winner::Local },
+        (None, Some(_)) => Winner::Remote,
+        _ => Winner::Local,
+    }
+}
+
+impl ConflictPolicy for DefaultPolicy {
+    fn resolve_name(&self, _local: &str, _remote: &str) -> Winner {
+        Winner::Local
+    }
+
+    fn resolve_due_date(&self, local: Option<Timespec>, remote: Option<Timespec>) -> Winner {
+        earliest(local, remote)
+    }
+
+    fn resolve_completion_date(&self, local: Option<Timespec>, remote: Option<Timespec>) -> Winner {
+        earliest(local, remote)
+    }
+
+    fn resolve_label(&self, _label: &str, local_added: bool, _remote_added: bool) -> Winner {
+        let add_wins = self.labels == LabelSemantics::AddWins;
+        if local_added == add_wins { Winner::Local } else { Winner::Remote }
+    }
+}
+
+/// Lets the remote side win every conflict it's asked about, for tests of custom policies.
+#[cfg(test)]
+pub struct RemoteWins;
+
+#[cfg(test)]
+impl ConflictPolicy for RemoteWins {
+    fn resolve_name(&self, _: &str, _: &str) -> Winner { Winner::Remote }
+    fn resolve_due_date(&self, _: Option<Timespec>, _: Option<Timespec>) -> Winner { Winner::Remote }
+    fn resolve_completion_date(&self, _: Option<Timespec>, _: Option<Timespec>) -> Winner { Winner::Remote }
+    fn resolve_label(&self, _: &str, _: bool, _: bool) -> Winner { Winner::Remote }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use mentat_core::Uuid;
+
+    use items::{
+        Item,
+        ItemUpdate,
+    };
+    use labels::Label;
+    use sync::MemoryServer;
+    use Toodle;
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    enum Field {
+        Name,
+        DueDate,
+        CompletionDate,
+        AddLabel,
+        RemoveLabel,
+        Notes,
+        Priority,
+        Star,
+    }
+
+    const FIELDS: [Field; 8] = [Field::Name, Field::DueDate, Field::CompletionDate, Field::AddLabel, Field::RemoveLabel,
+                                Field::Notes, Field::Priority, Field::Star];
+
+    fn label(name: &str) -> Label {
+        Label {
+            id: None,
+            name: name.to_string(),
+            color: "#000000".to_string(),
+        }
+    }
+
+    fn base() -> Item {
+        Item {
+            id: None,
+            uuid: Uuid::nil(),
+            name: "base".to_string(),
+            due_date: Some(Timespec::new(500, 0)),
+            completion_date: None,
+            notes: None,
+            priority: None,
+            starred: false,
+            recurrence: None,
+            labels: vec![label("shared")],
+        }
+    }
+
+    /// Edits `field` as the local or the remote side would; the two sides pick different values.
+    fn edit_item(item: &mut Item, field: Field, local: bool) {
+        match field {
+            Field::Name => item.name = if local { "local".to_string() } else { "remote".to_string() },
+            Field::DueDate => item.due_date = Some(Timespec::new(if local { 200 } else { 100 }, 0)),
+            Field::CompletionDate => item.completion_date = Some(Timespec::new(if local { 300 } else { 400 }, 0)),
+            Field::AddLabel => item.labels.push(label(if local { "local" } else { "remote" })),
+            Field::RemoveLabel => item.labels.retain(|label| label.name != "shared"),
+            Field::Notes => item.notes = Some(if local { "local".to_string() } else { "remote".to_string() }),
+            Field::Priority => item.priority = Some(if local { 1 } else { 2 }),
+            Field::Star => item.starred = true,
+        }
+    }
+
+    /// Makes the same edit as `edit_item` to the item `uuid` in `toodle`.
+    fn edit(toodle: &mut Toodle, uuid: &Uuid, field: Field, local: bool) {
+        let item = toodle.fetch_item(uuid).expect("expected an item option").expect("expected an item");
+        let mut edited = item.clone();
+        edit_item(&mut edited, field, local);
+        let labels: Vec<Label> = edited.labels.iter().map(|label| {
+            toodle.fetch_label(&label.name).expect("expected a label option").expect("expected a label")
+        }).collect();
+        toodle.update_item(&item, ItemUpdate {
+            name: Some(edited.name.clone()),
+            due_date: Some(edited.due_date),
+            completion_date: Some(edited.completion_date),
+            labels: Some(labels),
+            notes: Some(edited.notes.clone()),
+            priority: Some(edited.priority),
+            starred: Some(edited.starred),
+            recurrence: None,
+        }).expect("expected an update");
+    }
+
+    /// Two stores start out with `base()`; `local` edits are made to one and `remote` edits
+    /// to the other. The remote store syncs first, so the local store is the one that has to
+    /// settle conflicts, with `policy`. Returns the item both stores end up with.
+    fn sync_edits<P>(local: &[(Field, bool)], remote: &[(Field, bool)], policy: &P) -> Item where P: ConflictPolicy {
+        let mut server = MemoryServer::new();
+        let mut a = Toodle::new(String::new(), None).expect("expected a toodle");
+        let mut b = Toodle::new(String::new(), None).expect("expected a toodle");
+
+        let mut labels = vec![];
+        for name in &["shared", "local", "remote"] {
+            labels.push(a.create_label(name.to_string(), "#000000".to_string()).expect("expected a label option").expect("expected a label"));
+        }
+        let mut item = base();
+        item.labels = vec![labels[0].clone()];
+        let uuid = a.create_item(&item).expect("expected a uuid");
+        a.sync(&mut server).expect("expected a sync");
+        b.sync(&mut server).expect("expected a sync");
+
+        for &(field, values) in local.iter() {
+            edit(&mut a, &uuid, field, values);
+        }
+        for &(field, values) in remote.iter() {
+            edit(&mut b, &uuid, field, values);
+        }
+        b.sync(&mut server).expect("expected a sync");
+        a.sync_with_policy(&mut server, policy).expect("expected a sync");
+        b.sync(&mut server).expect("expected a sync");
+
+        let a_item = a.fetch_item(&uuid).expect("expected an item option").expect("expected an item");
+        let b_item = b.fetch_item(&uuid).expect("expected an item option").expect("expected an item");
+        assert_eq!(summary(&a_item), summary(&b_item), "stores diverged");
+        a_item
+    }
+
+    fn summary(item: &Item) -> (String, Option<Timespec>, Option<Timespec>, Option<String>, Option<i64>, bool, Vec<String>) {
+        (item.name.clone(), item.due_date, item.completion_date, item.notes.clone(), item.priority, item.starred, label_names(item))
+    }
+
+    fn label_names(item: &Item) -> Vec<String> {
+        let mut names: Vec<String> = item.labels.iter().map(|label| label.name.clone()).collect();
+        names.sort();
+        names
+    }
+
+    /// What the item should look like after syncing, computed independently of `sync`.
+    fn expected(local_field: Field, remote_field: Field) -> Item {
+        let mut expected = base();
+        edit_item(&mut expected, local_field, true);
+        if remote_field != local_field {
+            edit_item(&mut expected, remote_field, false);
+        } else {
+            match remote_field {
+                // Local names, notes and priorities win; the earlier due date (remote) and
+                // completion (local) win.
+                Field::DueDate => edit_item(&mut expected, remote_field, false),
+                // Both sides added a different label: keep both.
+                Field::AddLabel => edit_item(&mut expected, remote_field, false),
+                // Both sides removed the same label or starred the item: no conflict.
+                _ => (),
+            }
+        }
+        expected
+    }
+
+    #[test]
+    fn test_sync_matrix() {
+        let policy = DefaultPolicy::default();
+        for local_field in FIELDS.iter() {
+            for remote_field in FIELDS.iter() {
+                let synced = sync_edits(&[(*local_field, true)], &[(*remote_field, false)], &policy);
+                let expected = expected(*local_field, *remote_field);
+                let context = format!("local {:?}, remote {:?}", local_field, remote_field);
+                assert_eq!(summary(&synced), summary(&expected), "{}", context);
+
+                // It doesn't matter which store syncs first for any field but those whose
+                // conflicts always go to the local side: the name, notes and priority.
+                let swapped = sync_edits(&[(*remote_field, false)], &[(*local_field, true)], &policy);
+                assert_eq!(swapped.due_date, synced.due_date, "{}", context);
+                assert_eq!(swapped.completion_date, synced.completion_date, "{}", context);
+                assert_eq!(swapped.starred, synced.starred, "{}", context);
+                assert_eq!(label_names(&swapped), label_names(&synced), "{}", context);
+            }
+        }
+    }
+
+    #[test]
+    fn test_sync_unchanged() {
+        let synced = sync_edits(&[], &[], &DefaultPolicy::default());
+        assert_eq!(summary(&synced), summary(&base()));
+    }
+
+    #[test]
+    fn test_sync_notes_priority_and_star() {
+        // Both sides wrote notes, so local's stay; each side's other edit is kept.
+        let synced = sync_edits(&[(Field::Notes, true), (Field::Priority, true)],
+                                &[(Field::Notes, false), (Field::Star, false)],
+                                &RemoteWins);
+        assert_eq!(synced.notes, Some("local".to_string()));
+        assert_eq!(synced.priority, Some(1));
+        assert!(synced.starred);
+    }
+
+    #[test]
+    fn test_date_conflicts() {
+        let policy = DefaultPolicy::default();
+        let early = Some(Timespec::new(100, 0));
+        let late = Some(Timespec::new(200, 0));
+        assert_eq!(policy.resolve_due_date(late, early), Winner::Remote);
+        assert_eq!(policy.resolve_due_date(early, late), Winner::Local);
+        assert_eq!(policy.resolve_due_date(None, late), Winner::Remote);
+        assert_eq!(policy.resolve_due_date(late, None), Winner::Local);
+        assert_eq!(policy.resolve_completion_date(None, early), Winner::Remote);
+        assert_eq!(policy.resolve_completion_date(early, None), Winner::Local);
+    }
+
+    #[test]
+    fn test_label_add_remove_conflicts() {
+        // A label re-added on one side while the other removed it: local has it, remote doesn't.
+        let add_wins = DefaultPolicy { labels: LabelSemantics::AddWins };
+        let remove_wins = DefaultPolicy { labels: LabelSemantics::RemoveWins };
+        assert_eq!(add_wins.resolve_label("contested", true, false), Winner::Local);
+        assert_eq!(add_wins.resolve_label("contested", false, true), Winner::Remote);
+        assert_eq!(remove_wins.resolve_label("contested", true, false), Winner::Remote);
+        assert_eq!(remove_wins.resolve_label("contested", false, true), Winner::Local);
+
+        // With a shared base an add and a remove of the same label can't both happen, so
+        // a one-sided add is always kept.
+        assert_eq!(label_names(&sync_edits(&[(Field::AddLabel, true)], &[], &remove_wins)), vec!["local", "shared"]);
+        assert_eq!(label_names(&sync_edits(&[], &[(Field::AddLabel, true)], &remove_wins)), vec!["local", "shared"]);
+    }
+
+    #[test]
+    fn test_custom_policy() {
+        let synced = sync_edits(&[(Field::Name, true), (Field::CompletionDate, true)],
+                                &[(Field::Name, false), (Field::CompletionDate, false)],
+                                &RemoteWins);
+        assert_eq!(synced.name, "remote");
+        assert_eq!(synced.completion_date, Some(Timespec::new(400, 0)));
+    }
+}
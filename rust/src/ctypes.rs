@@ -12,7 +12,10 @@ use ffi_utils::strings::{
     string_to_c_char,
     c_char_to_string,
 };
-use std::os::raw::c_char;
+use std::os::raw::{
+    c_char,
+    c_int,
+};
 use std::ptr;
 
 use mentat_core::Uuid;
@@ -29,6 +32,9 @@ pub struct ItemC {
     pub name: *mut c_char,
     pub due_date: *mut i64,
     pub completion_date: *mut i64,
+    pub notes: *mut c_char,
+    pub priority: *mut i64,
+    pub starred: c_int,
 }
 
 impl From<Item> for ItemC {
@@ -49,11 +55,22 @@ impl From<Item> for ItemC {
                 ptr::null_mut()
             }
         };
+        let notes = match item.notes {
+            Some(ref notes) => string_to_c_char(notes.clone()),
+            None => ptr::null_mut(),
+        };
+        let priority = match item.priority {
+            Some(priority) => Box::into_raw(Box::new(priority)),
+            None => ptr::null_mut(),
+        };
         ItemC {
             uuid: string_to_c_char(item.uuid.hyphenated().to_string()),
             name: string_to_c_char(item.name.clone()),
             due_date: due,
             completion_date: completion,
+            notes: notes,
+            priority: priority,
+            starred: item.starred as c_int,
         }
     }
 }
@@ -107,14 +124,11 @@ impl From<ItemC> for Item {
             name: c_char_to_string(item_c.name),
             due_date: due,
             completion_date: completion,
+            notes: if item_c.notes.is_null() { None } else { Some(c_char_to_string(item_c.notes)) },
+            priority: if item_c.priority.is_null() { None } else { Some(unsafe { *item_c.priority }) },
+            starred: item_c.starred != 0,
+            recurrence: None,
             labels: vec![]
         }
     }
 }
-
-#[repr(C)]
-#[derive(Debug)]
-pub struct ItemCList {
-    pub items: Box<[ItemC]>,
-    pub len: usize
-}
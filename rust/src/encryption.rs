@@ -0,0 +1,461 @@
+// Copyright 2016 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! End-to-end encryption of sync records.
+//!
+//! An `EncryptedServer` wraps any `SyncServer`, sealing records with ChaCha20-Poly1305
+//! before they're uploaded and opening them once they're downloaded, so the server only
+//! ever sees ciphertext. Every record gets a fresh random nonce.
+//!
+//! Keys come from a `KeyBundle`, which the user shares between their stores. Each key has
+//! an id, and records are sealed with the bundle's current key and carry its id. Rotating
+//! to a new key leaves older records readable for as long as the bundle keeps the keys
+//! they were sealed with. Every store syncing through a log needs the same bundle.
+//!
+//! A sealed record is a format byte, the key id as a big-endian `u32`, the nonce, and
+//! then the ciphertext and its tag. The format byte and key id are authenticated too,
+//! along with the record's version and the name of its log, so that a server can't
+//! reorder records or replay them into another log without opening them failing.
+//! Records sealed in the first format only authenticated the format byte and key id;
+//! they can still be opened.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use ring::aead::{
+    self,
+    OpeningKey,
+    SealingKey,
+    CHACHA20_POLY1305,
+};
+use base64;
+use ring::rand::{
+    SecureRandom,
+    SystemRandom,
+};
+
+use errors as list_errors;
+use errors::ErrorKind;
+use sync::{
+    SyncReport,
+    SyncServer,
+    UploadOutcome,
+};
+
+use Toodle;
+
+/// The length of a key, in bytes.
+pub const KEY_LEN: usize = 32;
+
+/// The format of records that only authenticate their header.
+const FORMAT_V1: u8 = 1;
+const FORMAT: u8 = 2;
+
+/// The format byte and key id.
+const HEADER_LEN: usize = 5;
+
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SyncKey {
+    pub id: u32,
+    /// `KEY_LEN` bytes, base64 encoded.
+    pub key: String,
+}
+
+/// The keys a user syncs with, serialized as JSON to move them between stores.
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyBundle {
+    /// The id of the key that new records are sealed with.
+    pub current: u32,
+    pub keys: Vec<SyncKey>,
+}
+
+impl fmt::Debug for KeyBundle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Keys stay out of logs.
+        let ids: Vec<u32> = self.keys.iter().map(|key| key.id).collect();
+        write!(f, "KeyBundle {{ current: {}, key ids: {:?} }}", self.current, ids)
+    }
+}
+
+fn random_key() -> Result<String, list_errors::Error> {
+    let mut key = [0; KEY_LEN];
+    SystemRandom::new().fill(&mut key).map_err(|_| ErrorKind::SyncFailed("can't generate a key".to_string()))?;
+    Ok(base64::encode(&key))
+}
+
+impl KeyBundle {
+    /// A bundle with one new random key.
+    pub fn generate() -> Result<KeyBundle, list_errors::Error> {
+        Ok(KeyBundle {
+            current: 1,
+            keys: vec![SyncKey { id: 1, key: random_key()? }],
+        })
+    }
+
+    /// Adds a new random key and makes it current, returning its id. The other keys are
+    /// kept, so that records sealed with them can still be opened.
+    pub fn rotate(&mut self) -> Result<u32, list_errors::Error> {
+        let id = self.keys.iter().map(|key| key.id).max().unwrap_or(0) + 1;
+        self.keys.push(SyncKey { id: id, key: random_key()? });
+        self.current = id;
+        Ok(id)
+    }
+}
+
+fn bad_bundle(message: String) -> list_errors::Error {
+    ErrorKind::InvalidArgument(format!("bad key bundle: {}", message)).into()
+}
+
+fn bad_record(version: u64, message: &str) -> list_errors::Error {
+    ErrorKind::BadSyncRecord(format!("version {} {}", version, message)).into()
+}
+
+/// A `SyncServer` that encrypts records on their way to `inner` and decrypts them on the
+/// way back.
+pub struct EncryptedServer<S> {
+    inner: S,
+    current: u32,
+    sealing: SealingKey,
+    opening: BTreeMap<u32, OpeningKey>,
+    random: SystemRandom,
+}
+
+impl<S> EncryptedServer<S> where S: SyncServer {
+    pub fn new(inner: S, keys: &KeyBundle) -> Result<EncryptedServer<S>, list_errors::Error> {
+        let mut sealing = None;
+        let mut opening = BTreeMap::new();
+        for key in keys.keys.iter() {
+            let bytes = match base64::decode(&key.key) {
+                Ok(ref bytes) if bytes.len() == KEY_LEN => bytes.clone(),
+                _ => return Err(bad_bundle(format!("key {} isn't {} bytes of base64", key.id, KEY_LEN))),
+            };
+            let unusable = |_| bad_bundle(format!("key {} can't be used", key.id));
+            if key.id == keys.current {
+                sealing = Some(SealingKey::new(&CHACHA20_POLY1305, &bytes).map_err(&unusable)?);
+            }
+            if opening.insert(key.id, OpeningKey::new(&CHACHA20_POLY1305, &bytes).map_err(&unusable)?).is_some() {
+                return Err(bad_bundle(format!("there's more than one key {}", key.id)));
+            }
+        }
+        Ok(EncryptedServer {
+            inner: inner,
+            current: keys.current,
+            sealing: sealing.ok_or_else(|| bad_bundle(format!("the current key, {}, is missing", keys.current)))?,
+            opening: opening,
+            random: SystemRandom::new(),
+        })
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+
+    /// What a record authenticates besides its ciphertext: its header and, from the
+    /// second format on, its position in the log.
+    fn associated_data(&self, header: &[u8], version: u64) -> Vec<u8> {
+        let mut data = header.to_vec();
+        if header[0] != FORMAT_V1 {
+            data.extend((0..8).rev().map(|byte| (version >> (8 * byte)) as u8));
+            data.extend(self.inner.log_name().into_bytes());
+        }
+        data
+    }
+
+    fn seal(&self, version: u64, record: Vec<u8>) -> Result<Vec<u8>, list_errors::Error> {
+        let nonce_len = CHACHA20_POLY1305.nonce_len();
+        let tag_len = CHACHA20_POLY1305.tag_len();
+        let mut sealed = vec![FORMAT, (self.current >> 24) as u8, (self.current >> 16) as u8, (self.current >> 8) as u8, self.current as u8];
+        sealed.resize(HEADER_LEN + nonce_len, 0);
+        sealed.extend(record);
+        sealed.resize(sealed.len() + tag_len, 0);
+        let sealed_len = {
+            let (header, rest) = sealed.split_at_mut(HEADER_LEN);
+            let (nonce, in_out) = rest.split_at_mut(nonce_len);
+            self.random.fill(nonce).map_err(|_| ErrorKind::SyncFailed("can't generate a nonce".to_string()))?;
+            aead::seal_in_place(&self.sealing, nonce, &self.associated_data(header, version), in_out, tag_len)
+                .map_err(|_| ErrorKind::SyncFailed("can't encrypt a record".to_string()))?
+        };
+        sealed.truncate(HEADER_LEN + nonce_len + sealed_len);
+        Ok(sealed)
+    }
+
+    fn open(&self, version: u64, mut sealed: Vec<u8>) -> Result<Vec<u8>, list_errors::Error> {
+        let nonce_len = CHACHA20_POLY1305.nonce_len();
+        if sealed.len() < HEADER_LEN + nonce_len + CHACHA20_POLY1305.tag_len() || (sealed[0] != FORMAT && sealed[0] != FORMAT_V1) {
+            return Err(bad_record(version, "isn't encrypted"));
+        }
+        let id = sealed[1..HEADER_LEN].iter().fold(0u32, |id, &byte| id << 8 | byte as u32);
+        let key = self.opening.get(&id).ok_or_else(|| bad_record(version, &format!("was sealed with key {}, which isn't in the bundle", id)))?;
+        let opened_len = {
+            let (header, rest) = sealed.split_at_mut(HEADER_LEN);
+            let (nonce, in_out) = rest.split_at_mut(nonce_len);
+            aead::open_in_place(key, nonce, &self.associated_data(header, version), 0, in_out)
+                .map_err(|_| bad_record(version, "can't be decrypted; the key is wrong, or the record was tampered with or moved"))?
+                .len()
+        };
+        Ok(sealed[HEADER_LEN + nonce_len..HEADER_LEN + nonce_len + opened_len].to_vec())
+    }
+}
+
+impl<S> SyncServer for EncryptedServer<S> where S: SyncServer {
+    fn download_since(&mut self, version: u64) -> Result<Vec<(u64, Vec<u8>)>, list_errors::Error> {
+        let sealed = self.inner.download_since(version)?;
+        sealed.into_iter().map(|(version, record)| self.open(version, record).map(|record| (version, record))).collect()
+    }
+
+    /// Seals each record for the version it will have if the upload is accepted. An
+    /// out-of-date upload stores nothing; retrying it at the new head seals the records
+    /// again for their new versions.
+    fn upload(&mut self, base: u64, records: Vec<Vec<u8>>) -> Result<UploadOutcome, list_errors::Error> {
+        let sealed = records.into_iter().enumerate()
+            .map(|(index, record)| self.seal(base + 1 + index as u64, record))
+            .collect::<Result<Vec<_>, _>>()?;
+        self.inner.upload(base, sealed)
+    }
+
+    fn log_name(&self) -> String {
+        self.inner.log_name()
+    }
+}
+
+/// Syncs `toodle` through `server`, encrypting records with `keys` if there are any.
+pub fn sync_with_keys<S>(toodle: &mut Toodle, mut server: S, keys: Option<&KeyBundle>) -> Result<SyncReport, list_errors::Error>
+    where S: SyncServer {
+    match keys {
+        Some(keys) => toodle.sync(&mut EncryptedServer::new(server, keys)?),
+        None => toodle.sync(&mut server),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use serde_json;
+
+    use api::{
+        execute,
+        FilterParams,
+        ItemRecord,
+        NewItem,
+        Request,
+    };
+    use sync::MemoryServer;
+
+    fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+        haystack.windows(needle.len()).any(|window| window == needle)
+    }
+
+    fn expect_bad_record(result: Result<Vec<(u64, Vec<u8>)>, list_errors::Error>) {
+        match result {
+            Err(e) => match e.kind() {
+                &ErrorKind::BadSyncRecord(_) => {},
+                kind => panic!("expected a bad record, not {:?}", kind),
+            },
+            Ok(_) => panic!("expected the records to be refused"),
+        }
+    }
+
+    #[test]
+    fn test_records_round_trip_as_ciphertext() {
+        let keys = KeyBundle::generate().expect("expected keys");
+        let mut server = EncryptedServer::new(MemoryServer::new(), &keys).expect("expected a server");
+        let records = vec![b"Buy milk".to_vec(), b"Buy milk".to_vec(), vec![]];
+        assert_eq!(server.upload(0, records.clone()).expect("expected an upload"), UploadOutcome::Accepted { head: 3 });
+
+        let stored = server.inner().records();
+        assert!(stored.iter().all(|record| !contains(record, b"milk")));
+        // The same plaintext seals differently each time, because nonces aren't reused.
+        assert!(stored[0] != stored[1]);
+
+        let mut other = EncryptedServer::new(server.into_inner(), &keys).expect("expected a server");
+        let downloaded: Vec<Vec<u8>> = other.download_since(0).expect("expected records").into_iter().map(|(_, record)| record).collect();
+        assert_eq!(downloaded, records);
+    }
+
+    #[test]
+    fn test_stores_sync_without_revealing_names() {
+        let keys = KeyBundle::generate().expect("expected keys");
+        let mut memory = MemoryServer::new();
+        let mut a = Toodle::new(String::new(), None).expect("expected a new toodle");
+        let mut b = Toodle::new(String::new(), None).expect("expected a new toodle");
+        execute(&mut a, Request::CreateLabel { name: "groceries".to_string(), color: "#ff0000".to_string() }).expect("expected a label");
+        execute(&mut a, Request::CreateItem(NewItem {
+            name: "Buy oat milk".to_string(),
+            due_date: None,
+            completion_date: None,
+            labels: vec!["groceries".to_string()],
+            notes: None,
+            priority: None,
+            starred: false,
+            parent: None,
+            recurrence: None,
+        })).expect("expected an item");
+
+        a.sync(&mut EncryptedServer::new(&mut memory, &keys).expect("expected a server")).expect("expected a sync");
+        b.sync(&mut EncryptedServer::new(&mut memory, &keys).expect("expected a server")).expect("expected a sync");
+        let items: Vec<ItemRecord> = serde_json::from_value(execute(&mut b, Request::GetItems { filter: FilterParams::default() }).expect("expected items"))
+            .expect("expected item records");
+        assert_eq!(items.len(), 1);
+        assert_eq!((items[0].name.as_str(), items[0].labels[0].name.as_str()), ("Buy oat milk", "groceries"));
+
+        assert!(!memory.records().is_empty());
+        for record in memory.records() {
+            for plaintext in &[&b"oat milk"[..], b"groceries", b"#ff0000", b"item/name"] {
+                assert!(!contains(record, plaintext), "expected {:?} to be hidden", String::from_utf8_lossy(plaintext));
+            }
+        }
+
+        // A store without the keys can't make sense of the log.
+        let mut c = Toodle::new(String::new(), None).expect("expected a new toodle");
+        match c.sync(&mut memory) {
+            Err(e) => match e.kind() {
+                &ErrorKind::BadSyncRecord(_) => {},
+                kind => panic!("expected a bad record, not {:?}", kind),
+            },
+            Ok(_) => panic!("expected the sync to fail"),
+        }
+    }
+
+    #[test]
+    fn test_key_rotation() {
+        let mut keys = KeyBundle::generate().expect("expected keys");
+        let old_keys = keys.clone();
+        let mut memory = MemoryServer::new();
+        EncryptedServer::new(&mut memory, &old_keys).expect("expected a server").upload(0, vec![b"old".to_vec()]).expect("expected an upload");
+
+        assert_eq!(keys.rotate().expect("expected a new key"), 2);
+        EncryptedServer::new(&mut memory, &keys).expect("expected a server").upload(1, vec![b"new".to_vec()]).expect("expected an upload");
+
+        // The rotated bundle opens records sealed with either key...
+        assert_eq!(EncryptedServer::new(&mut memory, &keys).expect("expected a server").download_since(0).expect("expected records"),
+                   vec![(1, b"old".to_vec()), (2, b"new".to_vec())]);
+        // ...but a bundle from before the rotation can't open the new ones.
+        expect_bad_record(EncryptedServer::new(&mut memory, &old_keys).expect("expected a server").download_since(0));
+    }
+
+    #[test]
+    fn test_wrong_keys_and_tampering_are_detected() {
+        let keys = KeyBundle::generate().expect("expected keys");
+        let mut memory = MemoryServer::new();
+        EncryptedServer::new(&mut memory, &keys).expect("expected a server").upload(0, vec![b"secret".to_vec()]).expect("expected an upload");
+
+        let wrong = KeyBundle::generate().expect("expected keys");
+        expect_bad_record(EncryptedServer::new(&mut memory, &wrong).expect("expected a server").download_since(0));
+
+        let mut tampered = memory.records()[0].clone();
+        let last = tampered.len() - 1;
+        tampered[last] ^= 1;
+        let mut tampered_server = MemoryServer::new();
+        tampered_server.upload(0, vec![tampered, b"plaintext".to_vec()]).expect("expected an upload");
+        expect_bad_record(EncryptedServer::new(&mut tampered_server, &keys).expect("expected a server").download_since(0));
+        expect_bad_record(EncryptedServer::new(&mut tampered_server, &keys).expect("expected a server").download_since(1));
+    }
+
+    /// A log with a name, as a remote server's logs have.
+    struct NamedLog {
+        name: &'static str,
+        inner: MemoryServer,
+    }
+
+    impl SyncServer for NamedLog {
+        fn download_since(&mut self, version: u64) -> Result<Vec<(u64, Vec<u8>)>, list_errors::Error> {
+            self.inner.download_since(version)
+        }
+
+        fn upload(&mut self, base: u64, records: Vec<Vec<u8>>) -> Result<UploadOutcome, list_errors::Error> {
+            self.inner.upload(base, records)
+        }
+
+        fn log_name(&self) -> String {
+            self.name.to_string()
+        }
+    }
+
+    #[test]
+    fn test_records_are_bound_to_their_place_in_the_log() {
+        let keys = KeyBundle::generate().expect("expected keys");
+        let mut alice = EncryptedServer::new(NamedLog { name: "/users/alice", inner: MemoryServer::new() }, &keys).expect("expected a server");
+        alice.upload(0, vec![b"first".to_vec(), b"second".to_vec()]).expect("expected an upload");
+        let sealed = alice.inner().inner.records().to_vec();
+
+        // Swapping two records...
+        let mut swapped = NamedLog { name: "/users/alice", inner: MemoryServer::new() };
+        swapped.upload(0, vec![sealed[1].clone(), sealed[0].clone()]).expect("expected an upload");
+        expect_bad_record(EncryptedServer::new(swapped, &keys).expect("expected a server").download_since(0));
+
+        // ...or replaying them into someone else's log is noticed.
+        let mut bob = NamedLog { name: "/users/bob", inner: MemoryServer::new() };
+        bob.upload(0, sealed.clone()).expect("expected an upload");
+        expect_bad_record(EncryptedServer::new(bob, &keys).expect("expected a server").download_since(0));
+
+        let mut copy = NamedLog { name: "/users/alice", inner: MemoryServer::new() };
+        copy.upload(0, sealed).expect("expected an upload");
+        assert_eq!(EncryptedServer::new(copy, &keys).expect("expected a server").download_since(0).expect("expected records"),
+                   vec![(1, b"first".to_vec()), (2, b"second".to_vec())]);
+    }
+
+    #[test]
+    fn test_out_of_date_uploads_are_sealed_again() {
+        let keys = KeyBundle::generate().expect("expected keys");
+        let mut memory = MemoryServer::new();
+        EncryptedServer::new(&mut memory, &keys).expect("expected a server").upload(0, vec![b"first".to_vec()]).expect("expected an upload");
+
+        let mut late = EncryptedServer::new(&mut memory, &keys).expect("expected a server");
+        assert_eq!(late.upload(0, vec![b"second".to_vec()]).expect("expected an answer"), UploadOutcome::OutOfDate { head: 1 });
+        assert_eq!(late.upload(1, vec![b"second".to_vec()]).expect("expected an answer"), UploadOutcome::Accepted { head: 2 });
+        assert_eq!(late.download_since(0).expect("expected records"), vec![(1, b"first".to_vec()), (2, b"second".to_vec())]);
+    }
+
+    #[test]
+    fn test_first_format_records_still_open() {
+        let keys = KeyBundle::generate().expect("expected keys");
+        let bytes = base64::decode(&keys.keys[0].key).expect("expected a key");
+        let key = SealingKey::new(&CHACHA20_POLY1305, &bytes).expect("expected a sealing key");
+        let nonce = vec![7; CHACHA20_POLY1305.nonce_len()];
+        let mut record = vec![FORMAT_V1, 0, 0, 0, keys.current as u8];
+        let mut in_out = b"old".to_vec();
+        in_out.resize(3 + CHACHA20_POLY1305.tag_len(), 0);
+        let sealed_len = aead::seal_in_place(&key, &nonce, &record, &mut in_out, CHACHA20_POLY1305.tag_len()).expect("expected to seal");
+        record.extend(nonce);
+        record.extend(&in_out[..sealed_len]);
+
+        let mut memory = MemoryServer::new();
+        memory.upload(0, vec![record]).expect("expected an upload");
+        assert_eq!(EncryptedServer::new(&mut memory, &keys).expect("expected a server").download_since(0).expect("expected records"),
+                   vec![(1, b"old".to_vec())]);
+    }
+
+    #[test]
+    fn test_bad_bundles_are_refused() {
+        let keys = KeyBundle::generate().expect("expected keys");
+        let mut missing_current = keys.clone();
+        missing_current.current = 7;
+        let mut short_key = keys.clone();
+        short_key.keys[0].key = base64::encode(b"too short");
+        let mut duplicate = keys.clone();
+        duplicate.keys.push(keys.keys[0].clone());
+
+        for bundle in &[missing_current, short_key, duplicate] {
+            match EncryptedServer::new(MemoryServer::new(), bundle) {
+                Err(e) => match e.kind() {
+                    &ErrorKind::InvalidArgument(_) => {},
+                    kind => panic!("expected an invalid argument, not {:?}", kind),
+                },
+                Ok(_) => panic!("expected {:?} to be refused", bundle),
+            }
+        }
+        assert!(!format!("{:?}", keys).contains(&keys.keys[0].key));
+    }
+}
@@ -9,6 +9,8 @@
 // specific language governing permissions and limitations under the License.
 
 use mentat;
+use rusqlite;
+use serde_json;
 
 use store::errors as store_error;
 
@@ -17,6 +19,11 @@ error_chain! {
         Error, ErrorKind, ResultExt, Result;
     }
 
+    foreign_links {
+        Json(serde_json::Error);
+        Rusqlite(rusqlite::Error);
+    }
+
     links {
         StoreError(store_error::Error, store_error::ErrorKind);
         MentatError(mentat::errors::Error, mentat::errors::ErrorKind);
@@ -27,5 +34,35 @@ error_chain! {
             description("An unexpected Result type was encountered")
             display("{}", message)
         }
+
+        BadSyncRecord(message: String) {
+            description("A sync record could not be understood")
+            display("bad sync record: {}", message)
+        }
+
+        SyncFailed(message: String) {
+            description("Sync could not complete")
+            display("sync failed: {}", message)
+        }
+
+        InvalidArgument(message: String) {
+            description("An argument was invalid")
+            display("invalid argument: {}", message)
+        }
+
+        NotFound(message: String) {
+            description("Something that was asked for doesn't exist")
+            display("not found: {}", message)
+        }
+
+        InvalidHandle(message: String) {
+            description("A handle was stale, already released or of the wrong kind")
+            display("invalid handle: {}", message)
+        }
+
+        Java(message: String) {
+            description("A JNI call failed")
+            display("JNI call failed: {}", message)
+        }
     }
 }
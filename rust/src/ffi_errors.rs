@@ -0,0 +1,304 @@
+// Copyright 2016 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! Reporting errors and panics to FFI callers instead of aborting the host app.
+//!
+//! Fallible `extern "C"` functions take a trailing `*mut ExternError`. On success its
+//! `code` is `ErrorCode::Success` and `message` is null; on failure `message` is a
+//! string owned by Rust that must be released with `toodle_error_destroy`. The error
+//! pointer may be null if the caller doesn't care.
+//!
+//! An error must start out zeroed, as `ErrorCode::Success` with a null message. It can
+//! then be passed to any number of calls: each one releases the message left by the one
+//! before, so only the last needs `toodle_error_destroy`.
+//!
+//! Pointer arguments are checked with `check_not_null` or `checked_string` before they're
+//! used, so a null where one isn't allowed fails with `ErrorCode::InvalidArgument`.
+
+use std::any::Any;
+use std::ffi::CString;
+use std::os::raw::{
+    c_char,
+    c_int,
+};
+use std::panic::{
+    self,
+    AssertUnwindSafe,
+};
+use std::ptr;
+
+use ffi_utils::strings::c_char_to_string;
+
+use errors as list_errors;
+use errors::ErrorKind;
+use store::errors::ErrorKind as StoreErrorKind;
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorCode {
+    Success = 0,
+    Panic = 1,
+    InvalidArgument = 2,
+    NotFound = 3,
+    Store = 4,
+    Sqlite = 5,
+    Sync = 6,
+    Other = 7,
+    InvalidHandle = 8,
+    /// An encrypted store was opened with the wrong key, or without one.
+    WrongKey = 9,
+}
+
+impl<'a> From<&'a list_errors::Error> for ErrorCode {
+    fn from(error: &'a list_errors::Error) -> ErrorCode {
+        match error.kind() {
+            &ErrorKind::InvalidArgument(_) => ErrorCode::InvalidArgument,
+            &ErrorKind::NotFound(_) => ErrorCode::NotFound,
+            &ErrorKind::InvalidHandle(_) => ErrorCode::InvalidHandle,
+            &ErrorKind::Rusqlite(_) => ErrorCode::Sqlite,
+            &ErrorKind::StoreError(StoreErrorKind::Rusqlite(_)) => ErrorCode::Sqlite,
+            &ErrorKind::StoreError(StoreErrorKind::WrongKey) => ErrorCode::WrongKey,
+            &ErrorKind::StoreError(StoreErrorKind::EmptyKey) => ErrorCode::InvalidArgument,
+            &ErrorKind::StoreError(_) |
+            &ErrorKind::MentatError(_) => ErrorCode::Store,
+            &ErrorKind::BadSyncRecord(_) |
+            &ErrorKind::SyncFailed(_) |
+            &ErrorKind::Json(_) => ErrorCode::Sync,
+            _ => ErrorCode::Other,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug)]
+pub struct ExternError {
+    pub code: ErrorCode,
+    pub message: *mut c_char,
+}
+
+impl Default for ExternError {
+    fn default() -> ExternError {
+        ExternError {
+            code: ErrorCode::Success,
+            message: ptr::null_mut(),
+        }
+    }
+}
+
+impl ExternError {
+    fn new(code: ErrorCode, message: String) -> ExternError {
+        // Interior nuls would make the message unrepresentable; drop them rather than lose it.
+        let message = CString::new(message.replace('\0', "")).expect("no interior nul bytes");
+        ExternError {
+            code: code,
+            message: message.into_raw(),
+        }
+    }
+}
+
+/// The error and its causes, outermost first.
+pub fn describe(error: &list_errors::Error) -> String {
+    let mut message = error.to_string();
+    for cause in error.iter().skip(1) {
+        message.push_str(": ");
+        message.push_str(&cause.to_string());
+    }
+    message
+}
+
+impl From<list_errors::Error> for ExternError {
+    fn from(error: list_errors::Error) -> ExternError {
+        ExternError::new(ErrorCode::from(&error), describe(&error))
+    }
+}
+
+/// Fails with `InvalidArgument`, naming `argument`, if `pointer` is null.
+pub fn check_not_null<T>(pointer: *const T, argument: &str) -> Result<(), list_errors::Error> {
+    if pointer.is_null() {
+        bail!(ErrorKind::InvalidArgument(format!("{} is null", argument)));
+    }
+    Ok(())
+}
+
+/// The string `pointer` points to, failing like `check_not_null` if it's null.
+pub fn checked_string(pointer: *const c_char, argument: &str) -> Result<String, list_errors::Error> {
+    check_not_null(pointer, argument)?;
+    Ok(c_char_to_string(pointer))
+}
+
+/// What an FFI function returns when it fails.
+pub trait FailureValue {
+    fn failure_value() -> Self;
+}
+
+impl FailureValue for () {
+    fn failure_value() {}
+}
+
+impl FailureValue for c_int {
+    fn failure_value() -> c_int { 0 }
+}
+
+impl FailureValue for usize {
+    fn failure_value() -> usize { 0 }
+}
+
+impl FailureValue for u64 {
+    fn failure_value() -> u64 { 0 }
+}
+
+impl<T> FailureValue for *mut T {
+    fn failure_value() -> *mut T { ptr::null_mut() }
+}
+
+impl<T> FailureValue for *const T {
+    fn failure_value() -> *const T { ptr::null() }
+}
+
+pub fn panic_message(payload: Box<Any + Send>) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown panic".to_string()
+    }
+}
+
+/// Runs `callback`, recording its error or panic in `error`. Callers get
+/// `FailureValue::failure_value()` back on failure.
+pub fn call_with_result<R, F>(error: *mut ExternError, callback: F) -> R
+    where F: FnOnce() -> Result<R, list_errors::Error>, R: FailureValue {
+    let (result, outcome) = match panic::catch_unwind(AssertUnwindSafe(callback)) {
+        Ok(Ok(result)) => (result, ExternError::default()),
+        Ok(Err(e)) => (R::failure_value(), ExternError::from(e)),
+        Err(payload) => (R::failure_value(), ExternError::new(ErrorCode::Panic, panic_message(payload))),
+    };
+    if !error.is_null() {
+        unsafe {
+            if !(*error).message.is_null() {
+                let _ = CString::from_raw((*error).message);
+            }
+            *error = outcome;
+        }
+    } else if !outcome.message.is_null() {
+        unsafe { let _ = CString::from_raw(outcome.message); }
+    }
+    result
+}
+
+/// Releases the message of an error filled in by an FFI function and resets it to success.
+#[no_mangle]
+pub unsafe extern "C" fn toodle_error_destroy(error: *mut ExternError) {
+    if error.is_null() {
+        return;
+    }
+    let error = &mut *error;
+    if !error.message.is_null() {
+        let _ = CString::from_raw(error.message);
+    }
+    *error = ExternError::default();
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::ffi::CStr;
+
+    use store::errors::Error as StoreError;
+
+    fn message(error: &ExternError) -> String {
+        unsafe { CStr::from_ptr(error.message) }.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn test_success() {
+        let mut error = ExternError::default();
+        let result: c_int = call_with_result(&mut error, || Ok(7));
+        assert_eq!(result, 7);
+        assert_eq!(error.code, ErrorCode::Success);
+        assert!(error.message.is_null());
+    }
+
+    #[test]
+    fn test_error() {
+        let mut error = ExternError::default();
+        let result: *mut c_int = call_with_result(&mut error, || {
+            bail!(ErrorKind::InvalidArgument("not a uuid".to_string()))
+        });
+        assert!(result.is_null());
+        assert_eq!(error.code, ErrorCode::InvalidArgument);
+        assert_eq!(message(&error), "invalid argument: not a uuid");
+
+        unsafe { toodle_error_destroy(&mut error) };
+        assert_eq!(error.code, ErrorCode::Success);
+        assert!(error.message.is_null());
+    }
+
+    #[test]
+    fn test_store_key_errors() {
+        let mut error = ExternError::default();
+        let _: u64 = call_with_result(&mut error, || Err(StoreError::from(StoreErrorKind::WrongKey).into()));
+        assert_eq!(error.code, ErrorCode::WrongKey);
+        assert_eq!(message(&error), "the key is wrong, or the store is encrypted and no key was given");
+        unsafe { toodle_error_destroy(&mut error) };
+    }
+
+    #[test]
+    fn test_panic() {
+        let mut error = ExternError::default();
+        let result: u64 = call_with_result(&mut error, || -> Result<u64, list_errors::Error> {
+            panic!("boom")
+        });
+        assert_eq!(result, 0);
+        assert_eq!(error.code, ErrorCode::Panic);
+        assert_eq!(message(&error), "boom");
+        unsafe { toodle_error_destroy(&mut error) };
+    }
+
+    #[test]
+    fn test_reused_error() {
+        let mut error = ExternError::default();
+        let _: c_int = call_with_result(&mut error, || bail!(ErrorKind::NotFound("item".to_string())));
+        assert_eq!(error.code, ErrorCode::NotFound);
+
+        // The first message is released rather than overwritten.
+        let _: c_int = call_with_result(&mut error, || bail!(ErrorKind::InvalidArgument("index".to_string())));
+        assert_eq!(message(&error), "invalid argument: index");
+        let _: c_int = call_with_result(&mut error, || Ok(1));
+        assert_eq!(error.code, ErrorCode::Success);
+        assert!(error.message.is_null());
+    }
+
+    #[test]
+    fn test_null_arguments() {
+        let mut error = ExternError::default();
+        let result: *mut c_char = call_with_result(&mut error, || {
+            let name = checked_string(ptr::null(), "name")?;
+            Ok(CString::new(name).expect("no nul bytes").into_raw())
+        });
+        assert!(result.is_null());
+        assert_eq!(error.code, ErrorCode::InvalidArgument);
+        assert_eq!(message(&error), "invalid argument: name is null");
+        unsafe { toodle_error_destroy(&mut error) };
+
+        let name = CString::new("milk").expect("no nul bytes");
+        assert_eq!(checked_string(name.as_ptr(), "name").expect("expected a string"), "milk");
+        assert!(check_not_null(&7 as *const c_int, "count").is_ok());
+    }
+
+    #[test]
+    fn test_null_error_pointer() {
+        let result: c_int = call_with_result(ptr::null_mut(), || bail!(ErrorKind::NotFound("item".to_string())));
+        assert_eq!(result, 0);
+        unsafe { toodle_error_destroy(ptr::null_mut()) };
+    }
+}
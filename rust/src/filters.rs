@@ -0,0 +1,443 @@
+// Copyright 2016 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+use std::cmp::Ordering;
+
+use mentat::query::Variable;
+use mentat_core::TypedValue;
+use time::Timespec;
+
+use store::ToTypedValue;
+
+use clock::Clock;
+use items::Item;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum SortKey {
+    DueDate,
+    CompletionDate,
+    Name,
+    Priority,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SortDirection {
+    Ascending,
+    Descending,
+}
+
+/// Describes a subset of items, their order and which page of them to return.
+///
+/// Every criterion that is set must hold for an item to match. Label criteria are
+/// expressed in terms of label names. Items without the date or priority being sorted
+/// on always sort last, and ties are broken by name and then uuid so that pages are stable.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ItemFilter {
+    pub completed: Option<bool>,
+    pub due_before: Option<Timespec>,
+    pub due_after: Option<Timespec>,
+    pub overdue_at: Option<Timespec>,
+    pub any_labels: Vec<String>,
+    pub all_labels: Vec<String>,
+    pub no_labels: Vec<String>,
+    pub starred: Option<bool>,
+    pub min_priority: Option<i64>,
+    pub sort_key: SortKey,
+    pub sort_direction: SortDirection,
+    pub limit: Option<usize>,
+    pub offset: usize,
+}
+
+impl Default for ItemFilter {
+    fn default() -> ItemFilter {
+        ItemFilter {
+            completed: None,
+            due_before: None,
+            due_after: None,
+            overdue_at: None,
+            any_labels: vec![],
+            all_labels: vec![],
+            no_labels: vec![],
+            starred: None,
+            min_priority: None,
+            sort_key: SortKey::Name,
+            sort_direction: SortDirection::Ascending,
+            limit: None,
+            offset: 0,
+        }
+    }
+}
+
+impl ItemFilter {
+    pub fn new() -> ItemFilter {
+        ItemFilter::default()
+    }
+
+    pub fn completed(mut self, completed: bool) -> ItemFilter {
+        self.completed = Some(completed);
+        self
+    }
+
+    /// Items due strictly before `date`.
+    pub fn due_before(mut self, date: Timespec) -> ItemFilter {
+        self.due_before = Some(date);
+        self
+    }
+
+    /// Items due strictly after `date`.
+    pub fn due_after(mut self, date: Timespec) -> ItemFilter {
+        self.due_after = Some(date);
+        self
+    }
+
+    /// Incomplete items whose due date has passed according to `clock`.
+    pub fn overdue<C>(mut self, clock: &C) -> ItemFilter where C: Clock + ?Sized {
+        self.overdue_at = Some(clock.now());
+        self
+    }
+
+    pub fn with_any_label<T>(mut self, name: T) -> ItemFilter where T: Into<String> {
+        self.any_labels.push(name.into());
+        self
+    }
+
+    pub fn with_all_label<T>(mut self, name: T) -> ItemFilter where T: Into<String> {
+        self.all_labels.push(name.into());
+        self
+    }
+
+    pub fn without_label<T>(mut self, name: T) -> ItemFilter where T: Into<String> {
+        self.no_labels.push(name.into());
+        self
+    }
+
+    pub fn starred(mut self, starred: bool) -> ItemFilter {
+        self.starred = Some(starred);
+        self
+    }
+
+    /// Items with a priority of at least `priority`.
+    pub fn min_priority(mut self, priority: i64) -> ItemFilter {
+        self.min_priority = Some(priority);
+        self
+    }
+
+    pub fn sort_by(mut self, key: SortKey, direction: SortDirection) -> ItemFilter {
+        self.sort_key = key;
+        self.sort_direction = direction;
+        self
+    }
+
+    pub fn limit(mut self, limit: usize) -> ItemFilter {
+        self.limit = Some(limit);
+        self
+    }
+
+    pub fn offset(mut self, offset: usize) -> ItemFilter {
+        self.offset = offset;
+        self
+    }
+
+    pub fn matches(&self, item: &Item) -> bool {
+        if let Some(completed) = self.completed {
+            if item.completion_date.is_some() != completed {
+                return false;
+            }
+        }
+        if let Some(before) = self.due_before {
+            if !item.due_date.map_or(false, |due| due < before) {
+                return false;
+            }
+        }
+        if let Some(after) = self.due_after {
+            if !item.due_date.map_or(false, |due| due > after) {
+                return false;
+            }
+        }
+        if let Some(now) = self.overdue_at {
+            if item.completion_date.is_some() || !item.due_date.map_or(false, |due| due < now) {
+                return false;
+            }
+        }
+        if let Some(starred) = self.starred {
+            if item.starred != starred {
+                return false;
+            }
+        }
+        if let Some(min) = self.min_priority {
+            if !item.priority.map_or(false, |priority| priority >= min) {
+                return false;
+            }
+        }
+        let has_label = |name: &String| item.labels.iter().any(|label| &label.name == name);
+        if !self.any_labels.is_empty() && !self.any_labels.iter().any(&has_label) {
+            return false;
+        }
+        if !self.all_labels.iter().all(&has_label) {
+            return false;
+        }
+        if self.no_labels.iter().any(&has_label) {
+            return false;
+        }
+        true
+    }
+
+    /// Filters, sorts and paginates `items`.
+    pub fn apply(&self, items: Vec<Item>) -> Vec<Item> {
+        let mut items: Vec<Item> = items.into_iter().filter(|item| self.matches(item)).collect();
+        items.sort_by(|a, b| self.compare(a, b));
+        let limit = self.limit.unwrap_or(items.len());
+        items.into_iter().skip(self.offset).take(limit).collect()
+    }
+
+    /// The Datalog for the `[?eid ?uuid ?name]` rows of matching items, in order, followed
+    /// by the value being sorted on. Datalog can't sort missing values last, so when sorting
+    /// on a date or priority this is the query for the items that have one (`sorted` is true)
+    /// or for those that don't, which follow them ordered by name. At most `limit` rows are
+    /// returned.
+    pub fn to_query(&self, sorted: bool, limit: Option<usize>) -> (String, Vec<(Variable, TypedValue)>) {
+        let mut clauses = vec![
+            "[?eid :item/uuid ?uuid]".to_string(),
+            "[?eid :item/name ?name]".to_string(),
+        ];
+        let mut inputs = vec![];
+
+        match self.completed {
+            Some(true) => clauses.push("[?eid :item/completion_date _]".to_string()),
+            Some(false) => clauses.push("(not [?eid :item/completion_date _])".to_string()),
+            None => {},
+        }
+        if self.due_before.is_some() || self.due_after.is_some() || self.overdue_at.is_some() {
+            clauses.push("[?eid :item/due_date ?due]".to_string());
+        }
+        if let Some(before) = self.due_before {
+            clauses.push("[(< ?due ?due_before)]".to_string());
+            inputs.push(("?due_before".to_string(), before.to_typed_value()));
+        }
+        if let Some(after) = self.due_after {
+            clauses.push("[(> ?due ?due_after)]".to_string());
+            inputs.push(("?due_after".to_string(), after.to_typed_value()));
+        }
+        if let Some(now) = self.overdue_at {
+            clauses.push("(not [?eid :item/completion_date _]) [(< ?due ?overdue_at)]".to_string());
+            inputs.push(("?overdue_at".to_string(), now.to_typed_value()));
+        }
+        match self.starred {
+            Some(true) => clauses.push("[?eid :item/starred true]".to_string()),
+            Some(false) => clauses.push("(not [?eid :item/starred true])".to_string()),
+            None => {},
+        }
+        if let Some(min) = self.min_priority {
+            clauses.push("[?eid :item/priority ?priority] [(>= ?priority ?min_priority)]".to_string());
+            inputs.push(("?min_priority".to_string(), min.to_typed_value()));
+        }
+        if !self.any_labels.is_empty() {
+            let names: Vec<String> = self.any_labels.iter().map(|name| string_literal(name)).collect();
+            clauses.push(format!("[(ground [{}]) [?any_name ...]] [?any :label/name ?any_name] [?eid :item/label ?any]", names.join(" ")));
+        }
+        for (i, name) in self.all_labels.iter().enumerate() {
+            clauses.push(format!("[?all{} :label/name ?all_name{}] [?eid :item/label ?all{}]", i, i, i));
+            inputs.push((format!("?all_name{}", i), name.to_typed_value()));
+        }
+        for (i, name) in self.no_labels.iter().enumerate() {
+            clauses.push(format!("(not-join [?eid ?no_name{}] [?no{} :label/name ?no_name{}] [?eid :item/label ?no{}])", i, i, i, i));
+            inputs.push((format!("?no_name{}", i), name.to_typed_value()));
+        }
+
+        let direction = match self.sort_direction {
+            SortDirection::Ascending => "asc",
+            SortDirection::Descending => "desc",
+        };
+        let sort_attribute = match self.sort_key {
+            SortKey::DueDate => Some("due_date"),
+            SortKey::CompletionDate => Some("completion_date"),
+            SortKey::Priority => Some("priority"),
+            SortKey::Name => None,
+        };
+        let (find, order) = match sort_attribute {
+            Some(attribute) if sorted => {
+                clauses.push(format!("[?eid :item/{} ?sort]", attribute));
+                ("?eid ?uuid ?name ?sort".to_string(), format!("({} ?sort) (asc ?name) (asc ?uuid)", direction))
+            },
+            Some(attribute) => {
+                clauses.push(format!("(not [?eid :item/{} _])", attribute));
+                ("?eid ?uuid ?name".to_string(), "(asc ?name) (asc ?uuid)".to_string())
+            },
+            None => ("?eid ?uuid ?name".to_string(), format!("({} ?name) (asc ?uuid)", direction)),
+        };
+
+        let mut query = format!("[:find {}", find);
+        if !inputs.is_empty() {
+            let names: Vec<&str> = inputs.iter().map(|&(ref name, _)| name.as_str()).collect();
+            query.push_str(&format!(" :in {}", names.join(" ")));
+        }
+        query.push_str(&format!(" :where {} :order {}", clauses.join(" "), order));
+        if let Some(limit) = limit {
+            query.push_str(&format!(" :limit {}", limit));
+        }
+        query.push_str("]");
+        (query, inputs.into_iter().map(|(name, value)| (Variable::from_valid_name(&name), value)).collect())
+    }
+
+    fn compare(&self, a: &Item, b: &Item) -> Ordering {
+        let ordering = match self.sort_key {
+            SortKey::DueDate => compare_present(a.due_date, b.due_date, self.sort_direction),
+            SortKey::CompletionDate => compare_present(a.completion_date, b.completion_date, self.sort_direction),
+            SortKey::Priority => compare_present(a.priority, b.priority, self.sort_direction),
+            SortKey::Name => directed(a.name.cmp(&b.name), self.sort_direction),
+        };
+        ordering.then_with(|| a.name.cmp(&b.name))
+                .then_with(|| a.uuid.as_bytes().cmp(b.uuid.as_bytes()))
+    }
+}
+
+// An EDN string literal for `value`.
+fn string_literal(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+fn directed(ordering: Ordering, direction: SortDirection) -> Ordering {
+    match direction {
+        SortDirection::Ascending => ordering,
+        SortDirection::Descending => ordering.reverse(),
+    }
+}
+
+fn compare_present<T>(a: Option<T>, b: Option<T>, direction: SortDirection) -> Ordering where T: Ord {
+    match (a, b) {
+        (Some(a), Some(b)) => directed(a.cmp(&b), direction),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use mentat_core::Uuid;
+
+    use clock::FixedClock;
+    use labels::Label;
+
+    fn label(name: &str) -> Label {
+        Label {
+            id: None,
+            name: name.to_string(),
+            color: "#000000".to_string(),
+        }
+    }
+
+    fn item(name: &str, due: Option<i64>, completed: Option<i64>, labels: &[&str]) -> Item {
+        Item {
+            id: None,
+            uuid: Uuid::nil(),
+            name: name.to_string(),
+            due_date: due.map(|sec| Timespec::new(sec, 0)),
+            completion_date: completed.map(|sec| Timespec::new(sec, 0)),
+            notes: None,
+            priority: None,
+            starred: false,
+            recurrence: None,
+            labels: labels.iter().map(|name| label(name)).collect(),
+        }
+    }
+
+    fn items() -> Vec<Item> {
+        vec![
+            item("a", Some(100), None, &["home"]),
+            item("b", Some(200), Some(150), &["work"]),
+            item("c", None, None, &["home", "work"]),
+            item("d", Some(300), None, &[]),
+            item("e", None, Some(50), &["home", "urgent"]),
+        ]
+    }
+
+    fn prioritized() -> Vec<Item> {
+        let mut items = items();
+        items[0].priority = Some(1);
+        items[1].priority = Some(3);
+        items[1].starred = true;
+        items[3].priority = Some(2);
+        items[4].starred = true;
+        items
+    }
+
+    fn names(items: Vec<Item>) -> Vec<String> {
+        items.into_iter().map(|item| item.name.clone()).collect()
+    }
+
+    #[test]
+    fn test_default_returns_everything_by_name() {
+        assert_eq!(names(ItemFilter::new().apply(items())), vec!["a", "b", "c", "d", "e"]);
+    }
+
+    #[test]
+    fn test_completion() {
+        assert_eq!(names(ItemFilter::new().completed(true).apply(items())), vec!["b", "e"]);
+        assert_eq!(names(ItemFilter::new().completed(false).apply(items())), vec!["a", "c", "d"]);
+    }
+
+    #[test]
+    fn test_due_ranges() {
+        assert_eq!(names(ItemFilter::new().due_before(Timespec::new(200, 0)).apply(items())), vec!["a"]);
+        assert_eq!(names(ItemFilter::new().due_after(Timespec::new(100, 0)).apply(items())), vec!["b", "d"]);
+        assert_eq!(names(ItemFilter::new().due_after(Timespec::new(100, 0)).due_before(Timespec::new(300, 0)).apply(items())), vec!["b"]);
+    }
+
+    #[test]
+    fn test_overdue() {
+        let clock = FixedClock(Timespec::new(250, 0));
+        assert_eq!(names(ItemFilter::new().overdue(&clock).apply(items())), vec!["a"]);
+        let clock = FixedClock(Timespec::new(1000, 0));
+        assert_eq!(names(ItemFilter::new().overdue(&clock).apply(items())), vec!["a", "d"]);
+    }
+
+    #[test]
+    fn test_labels() {
+        assert_eq!(names(ItemFilter::new().with_any_label("work").with_any_label("urgent").apply(items())), vec!["b", "c", "e"]);
+        assert_eq!(names(ItemFilter::new().with_all_label("home").with_all_label("work").apply(items())), vec!["c"]);
+        assert_eq!(names(ItemFilter::new().without_label("home").apply(items())), vec!["b", "d"]);
+        assert_eq!(names(ItemFilter::new().with_any_label("home").without_label("urgent").apply(items())), vec!["a", "c"]);
+    }
+
+    #[test]
+    fn test_starred_and_priority() {
+        assert_eq!(names(ItemFilter::new().starred(true).apply(prioritized())), vec!["b", "e"]);
+        assert_eq!(names(ItemFilter::new().starred(false).apply(prioritized())), vec!["a", "c", "d"]);
+        assert_eq!(names(ItemFilter::new().min_priority(2).apply(prioritized())), vec!["b", "d"]);
+        assert_eq!(names(ItemFilter::new().min_priority(2).starred(true).apply(prioritized())), vec!["b"]);
+
+        let filter = ItemFilter::new().sort_by(SortKey::Priority, SortDirection::Descending);
+        assert_eq!(names(filter.apply(prioritized())), vec!["b", "d", "a", "c", "e"]);
+    }
+
+    #[test]
+    fn test_sorting() {
+        let filter = ItemFilter::new().sort_by(SortKey::DueDate, SortDirection::Ascending);
+        assert_eq!(names(filter.apply(items())), vec!["a", "b", "d", "c", "e"]);
+        let filter = ItemFilter::new().sort_by(SortKey::DueDate, SortDirection::Descending);
+        assert_eq!(names(filter.apply(items())), vec!["d", "b", "a", "c", "e"]);
+        let filter = ItemFilter::new().sort_by(SortKey::CompletionDate, SortDirection::Ascending);
+        assert_eq!(names(filter.apply(items())), vec!["e", "b", "a", "c", "d"]);
+        let filter = ItemFilter::new().sort_by(SortKey::Name, SortDirection::Descending);
+        assert_eq!(names(filter.apply(items())), vec!["e", "d", "c", "b", "a"]);
+    }
+
+    #[test]
+    fn test_pagination() {
+        assert_eq!(names(ItemFilter::new().limit(2).apply(items())), vec!["a", "b"]);
+        assert_eq!(names(ItemFilter::new().limit(2).offset(2).apply(items())), vec!["c", "d"]);
+        assert_eq!(names(ItemFilter::new().limit(2).offset(4).apply(items())), vec!["e"]);
+        assert_eq!(names(ItemFilter::new().offset(10).apply(items())), Vec::<String>::new());
+    }
+}
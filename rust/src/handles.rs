@@ -0,0 +1,324 @@
+// Copyright 2016 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! Handles for the objects we hand across the FFI.
+//!
+//! Instead of raw pointers, FFI callers get a `u64` that packs the id of the map that
+//! issued it, the generation of its slot and the slot's index. Releasing an object bumps
+//! its slot's generation, so a stale handle, a handle that's released twice or a handle
+//! to the wrong kind of object is reported as `ErrorKind::InvalidHandle` rather than
+//! touching freed memory. No valid handle is 0, which is what failed calls return.
+//!
+//! Each object has a lock of its own, and a map is only locked for long enough to find
+//! one, so calls on different objects don't wait for each other. A call that needs more
+//! than one object must take its Toodle first, so that two calls can never wait on each
+//! other's locks.
+
+use std::sync::{
+    Arc,
+    Mutex,
+    MutexGuard,
+};
+
+use errors as list_errors;
+use errors::ErrorKind;
+use items::Item;
+use labels::Label;
+use Toodle;
+
+const INDEX_BITS: u64 = 32;
+const GENERATION_BITS: u64 = 16;
+
+struct Entry<T> {
+    generation: u16,
+    value: Option<T>,
+}
+
+pub struct HandleMap<T> {
+    id: u16,
+    entries: Vec<Entry<T>>,
+    free: Vec<usize>,
+}
+
+impl<T> HandleMap<T> {
+    /// `id` must be non-zero and distinct from the ids of the other maps handles might be
+    /// confused with.
+    pub fn new(id: u16) -> HandleMap<T> {
+        assert!(id != 0, "handle map ids start at 1");
+        HandleMap {
+            id: id,
+            entries: vec![],
+            free: vec![],
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len() - self.free.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn handle(&self, index: usize) -> u64 {
+        ((self.id as u64) << (INDEX_BITS + GENERATION_BITS)) |
+        ((self.entries[index].generation as u64) << INDEX_BITS) |
+        (index as u64)
+    }
+
+    pub fn insert(&mut self, value: T) -> u64 {
+        let index = match self.free.pop() {
+            Some(index) => {
+                self.entries[index].value = Some(value);
+                index
+            },
+            None => {
+                self.entries.push(Entry { generation: 1, value: Some(value) });
+                self.entries.len() - 1
+            },
+        };
+        self.handle(index)
+    }
+
+    fn index(&self, handle: u64) -> Result<usize, list_errors::Error> {
+        let id = (handle >> (INDEX_BITS + GENERATION_BITS)) as u16;
+        let generation = (handle >> INDEX_BITS) as u16;
+        let index = (handle & ((1 << INDEX_BITS) - 1)) as usize;
+        if id != self.id {
+            bail!(ErrorKind::InvalidHandle(format!("{:#x} is for a different kind of object", handle)));
+        }
+        match self.entries.get(index) {
+            Some(entry) if entry.generation == generation && entry.value.is_some() => Ok(index),
+            Some(_) => bail!(ErrorKind::InvalidHandle(format!("{:#x} has already been released", handle))),
+            None => bail!(ErrorKind::InvalidHandle(format!("{:#x} was never issued", handle))),
+        }
+    }
+
+    pub fn get(&self, handle: u64) -> Result<&T, list_errors::Error> {
+        let index = self.index(handle)?;
+        Ok(self.entries[index].value.as_ref().expect("live entry"))
+    }
+
+    pub fn get_mut(&mut self, handle: u64) -> Result<&mut T, list_errors::Error> {
+        let index = self.index(handle)?;
+        Ok(self.entries[index].value.as_mut().expect("live entry"))
+    }
+
+    pub fn remove(&mut self, handle: u64) -> Result<T, list_errors::Error> {
+        let index = self.index(handle)?;
+        let entry = &mut self.entries[index];
+        // Skip 0 when wrapping so that a recycled slot never reissues a handle of 0.
+        entry.generation = entry.generation.wrapping_add(1).max(1);
+        self.free.push(index);
+        Ok(entry.value.take().expect("live entry"))
+    }
+}
+
+/// A value in a `ConcurrentHandleMap`, which is taken out when it's released.
+type Shared<T> = Arc<Mutex<Option<T>>>;
+
+/// A `HandleMap` that can live in a static and be shared by FFI calls on any thread.
+pub struct ConcurrentHandleMap<T> {
+    map: Mutex<HandleMap<Shared<T>>>,
+}
+
+// The FFI has always let callers use an object from any thread as long as they make one
+// call at a time. Each value's mutex serializes those calls, and whatever the values
+// capture (such as an observer's context pointer) is the caller's to keep valid across
+// threads.
+unsafe impl<T> Sync for ConcurrentHandleMap<T> {}
+
+// A panic while a lock was held has already been reported to that caller; neither the map
+// nor a value is ever left half-updated by a panic, so carry on.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<T> {
+    mutex.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+fn released(handle: u64) -> list_errors::Error {
+    ErrorKind::InvalidHandle(format!("{:#x} has already been released", handle)).into()
+}
+
+impl<T> ConcurrentHandleMap<T> {
+    pub fn new(id: u16) -> ConcurrentHandleMap<T> {
+        ConcurrentHandleMap {
+            map: Mutex::new(HandleMap::new(id)),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        lock(&self.map).len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        lock(&self.map).is_empty()
+    }
+
+    pub fn insert(&self, value: T) -> u64 {
+        lock(&self.map).insert(Arc::new(Mutex::new(Some(value))))
+    }
+
+    /// Releases `handle`, first waiting for any call that's using its value to return.
+    pub fn remove(&self, handle: u64) -> Result<T, list_errors::Error> {
+        let shared = lock(&self.map).remove(handle)?;
+        let value = lock(&*shared).take();
+        value.ok_or_else(|| released(handle))
+    }
+
+    fn shared(&self, handle: u64) -> Result<Shared<T>, list_errors::Error> {
+        lock(&self.map).get(handle).map(|shared| shared.clone())
+    }
+
+    /// Calls `callback` with the value for `handle`, which stays locked until it returns.
+    /// The map itself isn't locked while `callback` runs.
+    pub fn get<R, F>(&self, handle: u64, callback: F) -> Result<R, list_errors::Error>
+        where F: FnOnce(&T) -> Result<R, list_errors::Error> {
+        let shared = self.shared(handle)?;
+        let value = lock(&*shared);
+        let result = match *value {
+            Some(ref value) => callback(value),
+            // Released while this call was waiting for it.
+            None => Err(released(handle)),
+        };
+        result
+    }
+
+    /// Like `get`, with the value borrowed mutably.
+    pub fn get_mut<R, F>(&self, handle: u64, callback: F) -> Result<R, list_errors::Error>
+        where F: FnOnce(&mut T) -> Result<R, list_errors::Error> {
+        let shared = self.shared(handle)?;
+        let mut value = lock(&*shared);
+        let result = match *value {
+            Some(ref mut value) => callback(value),
+            None => Err(released(handle)),
+        };
+        result
+    }
+}
+
+lazy_static! {
+    pub static ref TOODLES: ConcurrentHandleMap<Toodle> = ConcurrentHandleMap::new(1);
+    pub static ref ITEMS: ConcurrentHandleMap<Item> = ConcurrentHandleMap::new(2);
+    pub static ref LABELS: ConcurrentHandleMap<Label> = ConcurrentHandleMap::new(3);
+    pub static ref ITEM_LISTS: ConcurrentHandleMap<Vec<Item>> = ConcurrentHandleMap::new(4);
+    pub static ref LABEL_LISTS: ConcurrentHandleMap<Vec<Label>> = ConcurrentHandleMap::new(5);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn is_invalid_handle(result: Result<&String, list_errors::Error>) -> bool {
+        match result {
+            Err(e) => match e.kind() {
+                &ErrorKind::InvalidHandle(_) => true,
+                _ => false,
+            },
+            Ok(_) => false,
+        }
+    }
+
+    #[test]
+    fn test_insert_get_remove() {
+        let mut map = HandleMap::new(1);
+        let first = map.insert("first".to_string());
+        let second = map.insert("second".to_string());
+        assert!(first != 0 && second != 0 && first != second);
+        assert_eq!(map.len(), 2);
+
+        map.get_mut(first).expect("expected a value").push_str("!");
+        assert_eq!(map.get(first).expect("expected a value"), "first!");
+        assert_eq!(map.remove(second).expect("expected a value"), "second");
+        assert_eq!(map.len(), 1);
+    }
+
+    #[test]
+    fn test_stale_handles() {
+        let mut map = HandleMap::new(1);
+        let handle = map.insert("first".to_string());
+        map.remove(handle).expect("expected a value");
+
+        // Double free.
+        assert!(map.remove(handle).is_err());
+        // Use after free, even once the slot has been reused.
+        let reused = map.insert("second".to_string());
+        assert!(reused != handle);
+        assert!(is_invalid_handle(map.get(handle)));
+        assert_eq!(map.get(reused).expect("expected a value"), "second");
+    }
+
+    #[test]
+    fn test_foreign_and_forged_handles() {
+        let mut strings = HandleMap::new(1);
+        let mut others = HandleMap::new(2);
+        let _ = strings.insert("string".to_string());
+        let other = others.insert("other".to_string());
+
+        assert!(is_invalid_handle(strings.get(other)));
+        assert!(is_invalid_handle(strings.get(0)));
+        assert!(is_invalid_handle(strings.get(u64::max_value())));
+        assert!(is_invalid_handle(strings.get((1 << 48) | (1 << 32) | 7)));
+        assert_eq!(others.get(other).expect("expected a value"), "other");
+    }
+
+    #[test]
+    fn test_concurrent_map_from_threads() {
+        use std::sync::Arc;
+        use std::thread;
+
+        let map = Arc::new(ConcurrentHandleMap::new(1));
+        let threads: Vec<_> = (0..4).map(|n| {
+            let map = map.clone();
+            thread::spawn(move || {
+                let handle = map.insert(n);
+                map.get_mut(handle, |value| { *value += 10; Ok(()) }).expect("expected a value");
+                map.remove(handle).expect("expected a value")
+            })
+        }).collect();
+        let mut values: Vec<i32> = threads.into_iter().map(|thread| thread.join().expect("expected a value")).collect();
+        values.sort();
+        assert_eq!(values, vec![10, 11, 12, 13]);
+        assert!(map.is_empty());
+    }
+
+    #[test]
+    fn test_concurrent_map_locks_each_value() {
+        use std::sync::Arc;
+        use std::sync::mpsc::channel;
+        use std::thread;
+
+        let map = Arc::new(ConcurrentHandleMap::new(1));
+        let busy = map.insert("busy".to_string());
+        let idle = map.insert("idle".to_string());
+
+        let (started, wait_for_start) = channel();
+        let (finish, wait_for_finish) = channel::<()>();
+        let thread = {
+            let map = map.clone();
+            thread::spawn(move || {
+                map.get_mut(busy, |value| {
+                    started.send(()).expect("expected the test to be waiting");
+                    wait_for_finish.recv().expect("expected to be told to finish");
+                    value.push_str("!");
+                    Ok(())
+                }).expect("expected a value");
+            })
+        };
+        wait_for_start.recv().expect("expected the callback to start");
+
+        // Another value, and the map itself, are free while `busy` is in use.
+        assert_eq!(map.get(idle, |value| Ok(value.clone())).expect("expected a value"), "idle");
+        let other = map.insert("other".to_string());
+        assert_eq!(map.remove(other).expect("expected a value"), "other");
+
+        finish.send(()).expect("expected the callback to be waiting");
+        thread.join().expect("expected the thread to finish");
+        assert_eq!(map.remove(busy).expect("expected a value"), "busy!");
+    }
+}
@@ -0,0 +1,196 @@
+// Copyright 2016 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! Creating many labels and items at once, as importing from another list does.
+//!
+//! Everything goes in one transaction, so an import either happens completely or not
+//! at all. Items keep the uuids they had where they came from.
+
+use std::collections::HashMap;
+
+use mentat_core::Uuid;
+
+use errors::{
+    ErrorKind,
+    Result,
+};
+use items::Item;
+use labels::Label;
+use observers::{
+    ChangeKind,
+    Topic,
+};
+use store::{
+    EntityPlace,
+    ToTypedValue,
+    TransactionBuilder,
+};
+use {
+    add_item,
+    kw,
+    Toodle,
+};
+
+/// Labels and items for `Toodle::import` to create.
+#[derive(Clone, Debug, Default)]
+pub struct Import {
+    /// Labels to create. None of them may be in the store already.
+    pub labels: Vec<Label>,
+    /// Items to create with their own uuids, none of which may be in the store already.
+    /// Their labels are found by name, among `labels` and those in the store.
+    pub items: Vec<Item>,
+    /// The parent of each item that has one, by uuid. A parent may be one of `items` or
+    /// an item in the store.
+    pub parents: HashMap<Uuid, Uuid>,
+}
+
+impl Toodle {
+    /// Creates everything in `import` in one transaction. Fails without changing anything
+    /// if a label or item is already here, or if an item's label or parent isn't.
+    pub fn import(&mut self, import: &Import) -> Result<()> {
+        self.import_with(import, TransactionBuilder::new())
+    }
+
+    /// Like `import`, but transacts whatever `builder` already holds along with it, for
+    /// callers that record something about the import which must only stick if it does.
+    pub fn import_with(&mut self, import: &Import, mut builder: TransactionBuilder) -> Result<()> {
+
+        let mut labels: HashMap<String, EntityPlace> = HashMap::new();
+        for label in self.fetch_labels()? {
+            if let Some(ref id) = label.id {
+                labels.insert(label.name.clone(), EntityPlace::from(id));
+            }
+        }
+        for label in import.labels.iter() {
+            if labels.contains_key(&label.name) {
+                bail!(ErrorKind::InvalidArgument(format!("label {:?} is already in the store", label.name)));
+            }
+            let entity = builder.tempid();
+            builder.add(entity.clone(), kw("label", "name"), label.name.to_typed_value())
+                   .add(entity.clone(), kw("label", "color"), label.color.to_typed_value());
+            labels.insert(label.name.clone(), entity);
+        }
+
+        let mut items: HashMap<Uuid, EntityPlace> = HashMap::new();
+        for item in import.items.iter() {
+            if items.contains_key(&item.uuid) || self.fetch_item(&item.uuid)?.is_some() {
+                bail!(ErrorKind::InvalidArgument(format!("item {} is already in the store", item.uuid.hyphenated())));
+            }
+            let entity = builder.tempid();
+            add_item(&mut builder, &entity, item.uuid, item);
+            items.insert(item.uuid, entity);
+        }
+
+        for item in import.items.iter() {
+            let entity = items[&item.uuid].clone();
+            for label in item.labels.iter() {
+                match labels.get(&label.name) {
+                    Some(label) => builder.add(entity.clone(), kw("item", "label"), label.clone()),
+                    None => bail!(ErrorKind::NotFound(format!("label {:?}", label.name))),
+                };
+            }
+            let parent = match import.parents.get(&item.uuid) {
+                Some(parent) => parent,
+                None => continue,
+            };
+            let parent = match items.get(parent) {
+                Some(parent) => parent.clone(),
+                None => match self.fetch_item(parent)?.and_then(|parent| parent.id.clone()) {
+                    Some(id) => EntityPlace::from(id),
+                    None => bail!(ErrorKind::NotFound(format!("item {}", parent.hyphenated()))),
+                },
+            };
+            builder.add(entity, kw("item", "parent"), parent);
+        }
+
+        if builder.is_empty() {
+            return Ok(());
+        }
+        self.connection
+            .transact_builder(&builder)?;
+        if !import.labels.is_empty() {
+            self.notify(Topic::Labels, ChangeKind::Created, import.labels.iter().map(|label| label.name.clone()).collect());
+        }
+        if !import.items.is_empty() {
+            self.notify(Topic::Items, ChangeKind::Created, import.items.iter().map(|item| item.uuid.hyphenated().to_string()).collect());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn toodle() -> Toodle {
+        Toodle::new(String::new(), None).expect("expected a new toodle")
+    }
+
+    fn label(name: &str) -> Label {
+        Label { id: None, name: name.to_string(), color: "#ff0000".to_string() }
+    }
+
+    fn item(uuid: &str, name: &str, labels: Vec<Label>) -> Item {
+        let mut item = Item::default();
+        item.uuid = Uuid::parse_str(uuid).expect("expected a uuid");
+        item.name = name.to_string();
+        item.labels = labels;
+        item
+    }
+
+    const CHORES: &'static str = "2cb9c3a1-62a7-4cc6-8b46-8fcd7e1d7f47";
+    const DISHES: &'static str = "7b3b7e52-6f2e-4d61-9a43-6ad1c8b8a2a0";
+
+    #[test]
+    fn test_import() {
+        let mut toodle = toodle();
+        toodle.create_label("work".to_string(), "#00ff00".to_string()).expect("expected a label");
+
+        let mut import = Import::default();
+        import.labels.push(label("home"));
+        import.items.push(item(DISHES, "Dishes", vec![label("home"), label("work")]));
+        import.items.push(item(CHORES, "Chores", vec![]));
+        import.parents.insert(import.items[0].uuid, import.items[1].uuid);
+        toodle.import(&import).expect("expected an import");
+
+        let dishes = toodle.fetch_item(&import.items[0].uuid).expect("expected to fetch").expect("expected the item");
+        let mut labels: Vec<String> = dishes.labels.iter().map(|label| label.name.clone()).collect();
+        labels.sort();
+        assert_eq!(labels, vec!["home", "work"]);
+        let parent = toodle.fetch_parent(&dishes.uuid).expect("expected to fetch").expect("expected a parent");
+        assert_eq!(parent.uuid, import.items[1].uuid);
+    }
+
+    #[test]
+    fn test_failed_imports_change_nothing() {
+        let mut toodle = toodle();
+        let mut existing = Import::default();
+        existing.items.push(item(CHORES, "Chores", vec![]));
+        toodle.import(&existing).expect("expected an import");
+
+        // The label is new, but the second item names one that doesn't exist.
+        let mut import = Import::default();
+        import.labels.push(label("home"));
+        import.items.push(item(DISHES, "Dishes", vec![label("home")]));
+        import.items.push(item("0f8fad5b-d9cb-469f-a165-70867728950e", "Laundry", vec![label("missing")]));
+        match toodle.import(&import) {
+            Err(e) => match e.kind() {
+                &ErrorKind::NotFound(_) => (),
+                kind => panic!("expected NotFound, got {:?}", kind),
+            },
+            Ok(_) => panic!("expected the import to fail"),
+        }
+        assert!(toodle.fetch_labels().expect("expected labels").is_empty());
+        assert!(toodle.fetch_item(&import.items[0].uuid).expect("expected to fetch").is_none());
+
+        // Nor can an item be imported twice.
+        assert!(toodle.import(&existing).is_err());
+    }
+}
@@ -24,11 +24,24 @@ use mentat_core::{
 };
 use time::Timespec;
 
+use errors::ErrorKind;
+use ffi_errors::{
+    call_with_result,
+    checked_string,
+    ExternError,
+};
+use ffi_utils::log;
 use ffi_utils::strings::{
     string_to_c_char,
     c_char_to_string,
 };
+use handles::{
+    ITEMS,
+    LABELS,
+    LABEL_LISTS,
+};
 use labels::Label;
+use recurrence::Recurrence;
 use store::{
     Entity,
 };
@@ -41,6 +54,26 @@ pub struct Item {
     pub due_date: Option<Timespec>,
     pub completion_date: Option<Timespec>,
     pub labels: Vec<Label>,
+    pub notes: Option<String>,
+    pub priority: Option<i64>,
+    pub starred: bool,
+    pub recurrence: Option<Recurrence>,
+}
+
+/// Changes for `Toodle::update_item` to make to an item. A field that's `None` is left
+/// as it is; `Some(None)` clears a field that can be empty.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ItemUpdate {
+    pub name: Option<String>,
+    pub due_date: Option<Option<Timespec>>,
+    /// Completing an item that recurs creates its next occurrence; see `recurrence`.
+    pub completion_date: Option<Option<Timespec>>,
+    pub labels: Option<Vec<Label>>,
+    pub notes: Option<Option<String>>,
+    pub priority: Option<Option<i64>>,
+    pub starred: Option<bool>,
+    /// A new rule is the one that completing the item in the same update uses.
+    pub recurrence: Option<Option<Recurrence>>,
 }
 
 #[derive(Debug)]
@@ -58,38 +91,42 @@ impl Items {
 
 impl Drop for Item {
     fn drop(&mut self) {
-        println!("{:?} is being deallocated", self);
+        log::d(&format!("{:?} is being deallocated", self));
     }
 }
 
 #[no_mangle]
-pub extern "C" fn item_new() -> *mut Item {
-    let item = Item::default();
-    let boxed_item = Box::new(item);
-    Box::into_raw(boxed_item)
+pub extern "C" fn item_new() -> u64 {
+    ITEMS.insert(Item::default())
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn item_destroy(item: *mut Item) {
-    let _ = Box::from_raw(item);
+pub extern "C" fn item_destroy(item: u64, error: *mut ExternError) {
+    call_with_result(error, || ITEMS.remove(item).map(|_| ()))
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn item_get_name(item: *const Item) -> *mut c_char {
-    let item = &*item;
-    string_to_c_char(item.name.clone())
+pub extern "C" fn item_get_uuid(item: u64, error: *mut ExternError) -> *mut c_char {
+    call_with_result(error, || ITEMS.get(item, |item| Ok(string_to_c_char(item.uuid.hyphenated().to_string()))))
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn item_set_name(item: *mut Item, name: *const c_char) {
-    let item = &mut*item;
-    item.name = c_char_to_string(name);
+pub extern "C" fn item_get_name(item: u64, error: *mut ExternError) -> *mut c_char {
+    call_with_result(error, || ITEMS.get(item, |item| Ok(string_to_c_char(item.name.clone()))))
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn item_get_due_date(item: *const Item) -> *mut i64 {
-    let item = &*item;
-    match item.due_date {
+pub extern "C" fn item_set_name(item: u64, name: *const c_char, error: *mut ExternError) {
+    call_with_result(error, || {
+        ITEMS.get_mut(item, |item| {
+            item.name = checked_string(name, "name")?;
+            Ok(())
+        })
+    })
+}
+
+fn date_to_c(date: Option<Timespec>) -> *mut i64 {
+    match date {
         Some(date) => {
             Box::into_raw(Box::new(date.sec))
         },
@@ -97,60 +134,212 @@ pub unsafe extern "C" fn item_get_due_date(item: *const Item) -> *mut i64 {
             ptr::null_mut()
         }
     }
-
 }
 
-#[no_mangle]
-pub unsafe extern "C" fn item_set_due_date(item: *mut Item, due_date: *const size_t) {
-    let item = &mut*item;
-    if !due_date.is_null() {
-        item.due_date = Some(Timespec::new(due_date as i64, 0));
+unsafe fn date_from_c(date: *const size_t) -> Option<Timespec> {
+    if !date.is_null() {
+        Some(Timespec::new(*date as i64, 0))
     } else {
-        item.due_date = None;
+        None
     }
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn item_get_completion_date(item: *const Item) -> *mut i64 {
-    let item = &*item;
-    match item.completion_date {
-        Some(date) => {
-            Box::into_raw(Box::new(date.sec))
-        },
-        None => {
-            ptr::null_mut()
-        }
-    }
+pub extern "C" fn item_get_due_date(item: u64, error: *mut ExternError) -> *mut i64 {
+    call_with_result(error, || ITEMS.get(item, |item| Ok(date_to_c(item.due_date))))
+}
 
+#[no_mangle]
+pub unsafe extern "C" fn item_set_due_date(item: u64, due_date: *const size_t, error: *mut ExternError) {
+    call_with_result(error, || {
+        ITEMS.get_mut(item, |item| {
+            item.due_date = date_from_c(due_date);
+            Ok(())
+        })
+    })
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn item_set_completion_date(item: *mut Item, completion_date: *const size_t) {
-    let item = &mut*item;
-    if !completion_date.is_null() {
-        item.completion_date = Some(Timespec::new(completion_date as i64, 0));
-    } else {
-        item.completion_date = None;
-    }
+pub extern "C" fn item_get_completion_date(item: u64, error: *mut ExternError) -> *mut i64 {
+    call_with_result(error, || ITEMS.get(item, |item| Ok(date_to_c(item.completion_date))))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn item_set_completion_date(item: u64, completion_date: *const size_t, error: *mut ExternError) {
+    call_with_result(error, || {
+        ITEMS.get_mut(item, |item| {
+            item.completion_date = date_from_c(completion_date);
+            Ok(())
+        })
+    })
+}
+
+/// Returns null if the item has no notes.
+#[no_mangle]
+pub extern "C" fn item_get_notes(item: u64, error: *mut ExternError) -> *mut c_char {
+    call_with_result(error, || ITEMS.get(item, |item| Ok(item.notes.clone().map_or(ptr::null_mut(), string_to_c_char))))
+}
+
+/// A null `notes` clears them.
+#[no_mangle]
+pub extern "C" fn item_set_notes(item: u64, notes: *const c_char, error: *mut ExternError) {
+    call_with_result(error, || {
+        ITEMS.get_mut(item, |item| {
+            item.notes = if notes.is_null() { None } else { Some(c_char_to_string(notes)) };
+            Ok(())
+        })
+    })
+}
+
+/// Returns null if the item has no priority.
+#[no_mangle]
+pub extern "C" fn item_get_priority(item: u64, error: *mut ExternError) -> *mut i64 {
+    call_with_result(error, || ITEMS.get(item, |item| Ok(item.priority.map_or(ptr::null_mut(), |priority| Box::into_raw(Box::new(priority))))))
+}
+
+/// A null `priority` clears it.
+#[no_mangle]
+pub unsafe extern "C" fn item_set_priority(item: u64, priority: *const i64, error: *mut ExternError) {
+    call_with_result(error, || {
+        ITEMS.get_mut(item, |item| {
+            item.priority = if priority.is_null() { None } else { Some(*priority) };
+            Ok(())
+        })
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn item_get_starred(item: u64, error: *mut ExternError) -> c_int {
+    call_with_result(error, || ITEMS.get(item, |item| Ok(item.starred as c_int)))
+}
+
+#[no_mangle]
+pub extern "C" fn item_set_starred(item: u64, starred: c_int, error: *mut ExternError) {
+    call_with_result(error, || {
+        ITEMS.get_mut(item, |item| {
+            item.starred = starred != 0;
+            Ok(())
+        })
+    })
+}
+
+/// Returns the item's recurrence rule, or null if it doesn't recur. Use
+/// `toodle_set_recurrence` to change it.
+#[no_mangle]
+pub extern "C" fn item_get_recurrence(item: u64, error: *mut ExternError) -> *mut c_char {
+    call_with_result(error, || ITEMS.get(item, |item| Ok(item.recurrence.as_ref().map_or(ptr::null_mut(), |recurrence| string_to_c_char(recurrence.to_string())))))
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn item_get_labels(item: *const Item) -> *mut Vec<Label> {
-    let item = &*item;
-    let boxed_labels = Box::new(item.labels.clone());
-    Box::into_raw(boxed_labels)
+pub extern "C" fn item_get_labels(item: u64, error: *mut ExternError) -> u64 {
+    call_with_result(error, || {
+        let labels = ITEMS.get(item, |item| Ok(item.labels.clone()))?;
+        Ok(LABEL_LISTS.insert(labels))
+    })
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn item_labels_count(item: *const Item) -> c_int {
-    let item = &*item;
-    item.labels.len() as c_int
+pub extern "C" fn item_labels_count(item: u64, error: *mut ExternError) -> c_int {
+    call_with_result(error, || ITEMS.get(item, |item| Ok(item.labels.len() as c_int)))
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn item_label_at(label_list: *const Vec<Label>, index: size_t) -> *const Label {
-    let label_list = &*label_list;
-    let index = index as usize;
-    let label = Box::new(label_list[index].clone());
-    Box::into_raw(label)
+pub extern "C" fn item_label_at(label_list: u64, index: size_t, error: *mut ExternError) -> u64 {
+    call_with_result(error, || {
+        let label = LABEL_LISTS.get(label_list, |labels| {
+            match labels.get(index as usize) {
+                Some(label) => Ok(label.clone()),
+                None => bail!(ErrorKind::InvalidArgument(format!("index {} is out of bounds", index))),
+            }
+        })?;
+        Ok(LABELS.insert(label))
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::ffi::CString;
+
+    use ffi_errors::{
+        ErrorCode,
+        toodle_error_destroy,
+    };
+
+    #[test]
+    fn test_item_accessors() {
+        let item = item_new();
+        let name = CString::new("an item").unwrap();
+        let mut error = ExternError::default();
+        item_set_name(item, name.as_ptr(), &mut error);
+        ITEMS.get_mut(item, |item| {
+            item.uuid = Uuid::parse_str("b2bc2a04-d0c5-4a0b-8b3e-3f3d1e6f0c4a").unwrap();
+            Ok(())
+        }).expect("expected an item");
+
+        unsafe {
+            let uuid = CString::from_raw(item_get_uuid(item, &mut error));
+            assert_eq!(uuid.to_str().unwrap(), "b2bc2a04-d0c5-4a0b-8b3e-3f3d1e6f0c4a");
+            let name = CString::from_raw(item_get_name(item, &mut error));
+            assert_eq!(name.to_str().unwrap(), "an item");
+
+            let due_date: size_t = 1_500_000_000;
+            item_set_due_date(item, &due_date, &mut error);
+            let stored = Box::from_raw(item_get_due_date(item, &mut error));
+            assert_eq!(*stored, 1_500_000_000);
+            assert!(item_get_completion_date(item, &mut error).is_null());
+
+            assert!(item_get_notes(item, &mut error).is_null());
+            let notes = CString::new("semi-skimmed").unwrap();
+            item_set_notes(item, notes.as_ptr(), &mut error);
+            let stored = CString::from_raw(item_get_notes(item, &mut error));
+            assert_eq!(stored.to_str().unwrap(), "semi-skimmed");
+            item_set_notes(item, ptr::null(), &mut error);
+            assert!(item_get_notes(item, &mut error).is_null());
+
+            let priority: i64 = 3;
+            item_set_priority(item, &priority, &mut error);
+            assert_eq!(*Box::from_raw(item_get_priority(item, &mut error)), 3);
+            item_set_priority(item, ptr::null(), &mut error);
+            assert!(item_get_priority(item, &mut error).is_null());
+
+            assert_eq!(item_get_starred(item, &mut error), 0);
+            item_set_starred(item, 1, &mut error);
+            assert_eq!(item_get_starred(item, &mut error), 1);
+
+            assert!(item_get_recurrence(item, &mut error).is_null());
+            ITEMS.get_mut(item, |item| {
+                item.recurrence = Some("FREQ=WEEKLY;BYDAY=MO".parse()?);
+                Ok(())
+            }).expect("expected an item");
+            let rule = CString::from_raw(item_get_recurrence(item, &mut error));
+            assert_eq!(rule.to_str().unwrap(), "FREQ=WEEKLY;BYDAY=MO");
+        }
+        assert_eq!(error.code, ErrorCode::Success);
+        item_destroy(item, &mut error);
+        assert_eq!(error.code, ErrorCode::Success);
+    }
+
+    #[test]
+    fn test_destroyed_item_handles_are_rejected() {
+        let item = item_new();
+        let mut error = ExternError::default();
+        item_destroy(item, &mut error);
+        assert_eq!(error.code, ErrorCode::Success);
+
+        assert!(item_get_name(item, &mut error).is_null());
+        assert_eq!(error.code, ErrorCode::InvalidHandle);
+        unsafe { toodle_error_destroy(&mut error) };
+
+        item_destroy(item, &mut error);
+        assert_eq!(error.code, ErrorCode::InvalidHandle);
+        unsafe { toodle_error_destroy(&mut error) };
+
+        // A label list handle isn't an item handle, even if the slot numbers line up.
+        let labels = item_get_labels(item_new(), &mut error);
+        assert_eq!(item_labels_count(labels, &mut error), 0);
+        assert_eq!(error.code, ErrorCode::InvalidHandle);
+        unsafe { toodle_error_destroy(&mut error) };
+    }
 }
@@ -0,0 +1,754 @@
+// Copyright 2016 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! JNI bindings for `com.mozilla.toodle.rust.NativeToodle`.
+//!
+//! These build `ItemRecord` and `LabelRecord` objects directly, so Java never sees an
+//! `ItemC`. Toodles are the same `u64` handles the C API uses, which lets the app mix JNI
+//! calls with JNA ones. Everything is written against `JavaEnv`, the small part of
+//! `JNIEnv` we need, so that it can be tested without a JVM; the `Java_*` entry points at
+//! the bottom are only built for Android, or with the `android` feature so that
+//! `cargo check --features android` catches mistakes in them on any host.
+//!
+//! A native method only gets a few local references to work with, so everything made
+//! while building a record is released as soon as the record, or the array it goes in,
+//! holds on to it.
+
+use std::panic::{
+    self,
+    AssertUnwindSafe,
+};
+
+use mentat_core::Uuid;
+use time::Timespec;
+
+use errors as list_errors;
+use errors::ErrorKind;
+use ffi_errors::{
+    describe,
+    panic_message,
+};
+use handles::TOODLES;
+use items::{
+    Item,
+    ItemUpdate,
+};
+use labels::Label;
+use {
+    Toodle,
+    parse_uuid_str,
+    with_toodle_handle,
+};
+
+pub const ITEM_RECORD_CLASS: &'static str = "com/mozilla/toodle/rust/ItemRecord";
+pub const ITEM_RECORD_CONSTRUCTOR: &'static str = "(Ljava/lang/String;Ljava/lang/String;Ljava/lang/Long;Ljava/lang/Long;[Lcom/mozilla/toodle/rust/LabelRecord;)V";
+pub const LABEL_RECORD_CLASS: &'static str = "com/mozilla/toodle/rust/LabelRecord";
+pub const LABEL_RECORD_CONSTRUCTOR: &'static str = "(Ljava/lang/String;Ljava/lang/String;)V";
+pub const TOODLE_EXCEPTION_CLASS: &'static str = "com/mozilla/toodle/rust/ToodleException";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum JavaValue<O> {
+    Object(O),
+    Long(i64),
+}
+
+/// The parts of `JNIEnv` the bindings use.
+pub trait JavaEnv {
+    /// A local reference to a Java object, which may be null.
+    type Object: Copy;
+
+    fn null(&self) -> Self::Object;
+    fn is_null(&self, object: Self::Object) -> bool;
+    fn get_string(&self, string: Self::Object) -> Result<String, list_errors::Error>;
+    fn new_string(&self, string: &str) -> Result<Self::Object, list_errors::Error>;
+    fn new_object(&self, class: &str, constructor: &str, args: &[JavaValue<Self::Object>]) -> Result<Self::Object, list_errors::Error>;
+    /// An array of `length` nulls.
+    fn new_object_array(&self, class: &str, length: usize) -> Result<Self::Object, list_errors::Error>;
+    fn set_object_array_element(&self, array: Self::Object, index: usize, element: Self::Object) -> Result<(), list_errors::Error>;
+    /// Releases a local reference before the native method returns.
+    fn delete_local_ref(&self, object: Self::Object);
+    /// Unboxes a `java.lang.Long`.
+    fn long_value(&self, boxed: Self::Object) -> Result<i64, list_errors::Error>;
+    fn throw(&self, class: &str, message: &str);
+}
+
+fn exception_class(error: &list_errors::Error) -> &'static str {
+    match error.kind() {
+        &ErrorKind::InvalidArgument(_) => "java/lang/IllegalArgumentException",
+        &ErrorKind::InvalidHandle(_) => "java/lang/IllegalStateException",
+        _ => TOODLE_EXCEPTION_CLASS,
+    }
+}
+
+/// Runs `callback`, turning an error or panic into a pending Java exception and
+/// `fallback`, which Java will never see.
+fn call<E, R, F>(env: &E, fallback: R, callback: F) -> R
+    where E: JavaEnv, F: FnOnce() -> Result<R, list_errors::Error> {
+    match panic::catch_unwind(AssertUnwindSafe(callback)) {
+        Ok(Ok(result)) => result,
+        Ok(Err(e)) => {
+            env.throw(exception_class(&e), &describe(&e));
+            fallback
+        },
+        Err(payload) => {
+            env.throw(TOODLE_EXCEPTION_CLASS, &panic_message(payload));
+            fallback
+        },
+    }
+}
+
+fn optional_string<E: JavaEnv>(env: &E, string: E::Object) -> Result<Option<String>, list_errors::Error> {
+    if env.is_null(string) {
+        Ok(None)
+    } else {
+        env.get_string(string).map(Some)
+    }
+}
+
+fn required_string<E: JavaEnv>(env: &E, string: E::Object, name: &str) -> Result<String, list_errors::Error> {
+    match optional_string(env, string)? {
+        Some(string) => Ok(string),
+        None => bail!(ErrorKind::InvalidArgument(format!("{} must not be null", name))),
+    }
+}
+
+/// Dates cross as nullable `java.lang.Long` seconds.
+fn optional_date<E: JavaEnv>(env: &E, date: E::Object) -> Result<Option<Timespec>, list_errors::Error> {
+    if env.is_null(date) {
+        Ok(None)
+    } else {
+        Ok(Some(Timespec::new(env.long_value(date)?, 0)))
+    }
+}
+
+fn date_object<E: JavaEnv>(env: &E, date: Option<Timespec>) -> Result<E::Object, list_errors::Error> {
+    match date {
+        Some(date) => env.new_object("java/lang/Long", "(J)V", &[JavaValue::Long(date.sec)]),
+        None => Ok(env.null()),
+    }
+}
+
+fn release<E: JavaEnv>(env: &E, objects: &[E::Object]) {
+    for object in objects.iter().filter(|object| !env.is_null(**object)) {
+        env.delete_local_ref(*object);
+    }
+}
+
+/// Constructs `class` from `args`, all of which are released afterwards.
+fn new_record<E: JavaEnv>(env: &E, class: &str, constructor: &str, args: &[E::Object]) -> Result<E::Object, list_errors::Error> {
+    let values: Vec<JavaValue<E::Object>> = args.iter().map(|arg| JavaValue::Object(*arg)).collect();
+    let record = env.new_object(class, constructor, &values);
+    release(env, args);
+    record
+}
+
+/// A `class[]` of `element` of each of `values`. Each element is released once it's in
+/// the array, so only one is alive at a time however many values there are.
+fn object_array<E, T, F>(env: &E, class: &str, values: &[T], element: F) -> Result<E::Object, list_errors::Error>
+    where E: JavaEnv, F: Fn(&T) -> Result<E::Object, list_errors::Error> {
+    let array = env.new_object_array(class, values.len())?;
+    for (index, value) in values.iter().enumerate() {
+        let element = element(value)?;
+        let result = env.set_object_array_element(array, index, element);
+        release(env, &[element]);
+        result?;
+    }
+    Ok(array)
+}
+
+fn label_record<E: JavaEnv>(env: &E, label: &Label) -> Result<E::Object, list_errors::Error> {
+    let name = env.new_string(&label.name)?;
+    let color = env.new_string(&label.color)?;
+    new_record(env, LABEL_RECORD_CLASS, LABEL_RECORD_CONSTRUCTOR, &[name, color])
+}
+
+fn label_records<E: JavaEnv>(env: &E, labels: &[Label]) -> Result<E::Object, list_errors::Error> {
+    object_array(env, LABEL_RECORD_CLASS, labels, |label| label_record(env, label))
+}
+
+fn item_record<E: JavaEnv>(env: &E, item: &Item) -> Result<E::Object, list_errors::Error> {
+    let args = [
+        env.new_string(&item.uuid.hyphenated().to_string())?,
+        env.new_string(&item.name)?,
+        date_object(env, item.due_date)?,
+        date_object(env, item.completion_date)?,
+        label_records(env, &item.labels)?,
+    ];
+    new_record(env, ITEM_RECORD_CLASS, ITEM_RECORD_CONSTRUCTOR, &args)
+}
+
+fn item_records<E: JavaEnv>(env: &E, items: &[Item]) -> Result<E::Object, list_errors::Error> {
+    object_array(env, ITEM_RECORD_CLASS, items, |item| item_record(env, item))
+}
+
+fn fetch_existing_item(toodle: &Toodle, uuid: &Uuid) -> Result<Item, list_errors::Error> {
+    match toodle.fetch_item(uuid)? {
+        Some(item) => Ok(item),
+        None => bail!(ErrorKind::NotFound(format!("item {}", uuid.hyphenated()))),
+    }
+}
+
+fn fetch_existing_label(toodle: &Toodle, name: &str) -> Result<Label, list_errors::Error> {
+    match toodle.fetch_label(&name.to_string())? {
+        Some(label) => Ok(label),
+        None => bail!(ErrorKind::NotFound(format!("label {:?}", name))),
+    }
+}
+
+pub fn new_toodle<E: JavaEnv>(env: &E, path: E::Object) -> i64 {
+    call(env, 0, || {
+        let path = required_string(env, path, "path")?;
+        Ok(TOODLES.insert(Toodle::new(path, None)?) as i64)
+    })
+}
+
+pub fn destroy<E: JavaEnv>(env: &E, toodle: i64) {
+    call(env, (), || TOODLES.remove(toodle as u64).map(|_| ()))
+}
+
+pub fn create_item<E: JavaEnv>(env: &E, toodle: i64, name: E::Object, due_date: E::Object) -> E::Object {
+    call(env, env.null(), || {
+        let mut item = Item::default();
+        item.name = required_string(env, name, "name")?;
+        item.due_date = optional_date(env, due_date)?;
+        let created = with_toodle_handle(toodle as u64, |toodle| toodle.create_and_fetch_item(&item))?;
+        match created {
+            Some(item) => item_record(env, &item),
+            None => bail!(ErrorKind::NotFound("the item that was just created".to_string())),
+        }
+    })
+}
+
+pub fn update_item<E: JavaEnv>(env: &E, toodle: i64, uuid: E::Object, name: E::Object, due_date: E::Object, completion_date: E::Object) {
+    call(env, (), || {
+        let uuid = parse_uuid_str(&required_string(env, uuid, "uuid")?)?;
+        let name = required_string(env, name, "name")?;
+        let due_date = optional_date(env, due_date)?;
+        let completion_date = optional_date(env, completion_date)?;
+        with_toodle_handle(toodle as u64, |toodle| {
+            let item = fetch_existing_item(toodle, &uuid)?;
+            toodle.update_item(&item, ItemUpdate {
+                name: Some(name),
+                due_date: Some(due_date),
+                completion_date: Some(completion_date),
+                ..ItemUpdate::default()
+            })
+        })
+    })
+}
+
+pub fn delete_item<E: JavaEnv>(env: &E, toodle: i64, uuid: E::Object) {
+    call(env, (), || {
+        let uuid = parse_uuid_str(&required_string(env, uuid, "uuid")?)?;
+        with_toodle_handle(toodle as u64, |toodle| toodle.delete_item(&uuid))
+    })
+}
+
+pub fn all_items<E: JavaEnv>(env: &E, toodle: i64) -> E::Object {
+    call(env, env.null(), || {
+        let items = TOODLES.get(toodle as u64, |toodle| toodle.fetch_items())?;
+        item_records(env, &items.vec)
+    })
+}
+
+pub fn search_items<E: JavaEnv>(env: &E, toodle: i64, query: E::Object) -> E::Object {
+    call(env, env.null(), || {
+        let query = required_string(env, query, "query")?;
+        let items = TOODLES.get(toodle as u64, |toodle| toodle.search_items(&query))?;
+        item_records(env, &items)
+    })
+}
+
+pub fn all_labels<E: JavaEnv>(env: &E, toodle: i64) -> E::Object {
+    call(env, env.null(), || {
+        let labels = TOODLES.get(toodle as u64, |toodle| toodle.fetch_labels())?;
+        label_records(env, &labels)
+    })
+}
+
+pub fn create_label<E: JavaEnv>(env: &E, toodle: i64, name: E::Object, color: E::Object) -> E::Object {
+    call(env, env.null(), || {
+        let name = required_string(env, name, "name")?;
+        let color = required_string(env, color, "color")?;
+        let created = with_toodle_handle(toodle as u64, |toodle| toodle.create_label(name.clone(), color))?;
+        match created {
+            Some(label) => label_record(env, &label),
+            None => bail!(ErrorKind::NotFound(format!("label {:?}", name))),
+        }
+    })
+}
+
+pub fn delete_label<E: JavaEnv>(env: &E, toodle: i64, name: E::Object) {
+    call(env, (), || {
+        let name = required_string(env, name, "name")?;
+        with_toodle_handle(toodle as u64, |toodle| {
+            let label = fetch_existing_label(toodle, &name)?;
+            toodle.delete_label(&label)
+        })
+    })
+}
+
+/// Adds (`add` true) or removes the named label from an item.
+pub fn set_item_label<E: JavaEnv>(env: &E, toodle: i64, uuid: E::Object, name: E::Object, add: bool) {
+    call(env, (), || {
+        let uuid = parse_uuid_str(&required_string(env, uuid, "uuid")?)?;
+        let name = required_string(env, name, "name")?;
+        with_toodle_handle(toodle as u64, |toodle| {
+            let item = fetch_existing_item(toodle, &uuid)?;
+            let label = fetch_existing_label(toodle, &name)?;
+            let mut labels: Vec<Label> = item.labels.iter().filter(|existing| existing.name != label.name).cloned().collect();
+            if add {
+                labels.push(label);
+            }
+            toodle.update_item(&item, ItemUpdate { labels: Some(labels), ..ItemUpdate::default() })
+        })
+    })
+}
+
+#[cfg(any(target_os = "android", feature = "android"))]
+mod android {
+    use jni_rs::JNIEnv;
+    use jni_rs::objects::{
+        JClass,
+        JObject,
+        JString,
+        JValue,
+    };
+    use jni_rs::sys::{
+        jboolean,
+        jlong,
+        jobject,
+        jobjectArray,
+        jsize,
+        JNI_FALSE,
+    };
+
+    use errors as list_errors;
+    use errors::ErrorKind;
+
+    use super::*;
+
+    fn java_error<E: ::std::fmt::Display>(error: E) -> list_errors::Error {
+        ErrorKind::Java(error.to_string()).into()
+    }
+
+    fn java_values<'a>(args: &[JavaValue<JObject<'a>>]) -> Vec<JValue<'a>> {
+        args.iter().map(|arg| match arg {
+            &JavaValue::Object(object) => JValue::Object(object),
+            &JavaValue::Long(long) => JValue::Long(long),
+        }).collect()
+    }
+
+    impl<'a> JavaEnv for JNIEnv<'a> {
+        type Object = JObject<'a>;
+
+        fn null(&self) -> JObject<'a> {
+            JObject::null()
+        }
+
+        fn is_null(&self, object: JObject<'a>) -> bool {
+            object.is_null()
+        }
+
+        fn get_string(&self, string: JObject<'a>) -> Result<String, list_errors::Error> {
+            let string = JNIEnv::get_string(self, JString::from(string.into_inner())).map_err(java_error)?;
+            Ok(string.into())
+        }
+
+        fn new_string(&self, string: &str) -> Result<JObject<'a>, list_errors::Error> {
+            JNIEnv::new_string(self, string).map(|string| string.into()).map_err(java_error)
+        }
+
+        fn new_object(&self, class: &str, constructor: &str, args: &[JavaValue<JObject<'a>>]) -> Result<JObject<'a>, list_errors::Error> {
+            JNIEnv::new_object(self, class, constructor, &java_values(args)).map_err(java_error)
+        }
+
+        fn new_object_array(&self, class: &str, length: usize) -> Result<JObject<'a>, list_errors::Error> {
+            let array = JNIEnv::new_object_array(self, length as jsize, class, JObject::null()).map_err(java_error)?;
+            Ok(JObject::from(array))
+        }
+
+        fn set_object_array_element(&self, array: JObject<'a>, index: usize, element: JObject<'a>) -> Result<(), list_errors::Error> {
+            JNIEnv::set_object_array_element(self, array.into_inner(), index as jsize, element).map_err(java_error)
+        }
+
+        fn delete_local_ref(&self, object: JObject<'a>) {
+            // Failing to release a reference early isn't worth failing the call over: the
+            // JVM releases it anyway once the native method returns.
+            let _ = JNIEnv::delete_local_ref(self, object);
+        }
+
+        fn long_value(&self, boxed: JObject<'a>) -> Result<i64, list_errors::Error> {
+            self.call_method(boxed, "longValue", "()J", &[]).and_then(|value| value.j()).map_err(java_error)
+        }
+
+        fn throw(&self, class: &str, message: &str) {
+            // If even this fails there's already an exception pending for Java to see.
+            let _ = self.throw_new(class, message);
+        }
+    }
+
+    #[no_mangle]
+    pub extern "C" fn Java_com_mozilla_toodle_rust_NativeToodle_newToodle(env: JNIEnv, _: JClass, path: JString) -> jlong {
+        new_toodle(&env, path.into())
+    }
+
+    #[no_mangle]
+    pub extern "C" fn Java_com_mozilla_toodle_rust_NativeToodle_destroy(env: JNIEnv, _: JClass, toodle: jlong) {
+        destroy(&env, toodle)
+    }
+
+    #[no_mangle]
+    pub extern "C" fn Java_com_mozilla_toodle_rust_NativeToodle_createItem(env: JNIEnv, _: JClass, toodle: jlong, name: JString, due_date: JObject) -> jobject {
+        create_item(&env, toodle, name.into(), due_date).into_inner()
+    }
+
+    #[no_mangle]
+    pub extern "C" fn Java_com_mozilla_toodle_rust_NativeToodle_updateItem(env: JNIEnv, _: JClass, toodle: jlong, uuid: JString, name: JString, due_date: JObject, completion_date: JObject) {
+        update_item(&env, toodle, uuid.into(), name.into(), due_date, completion_date)
+    }
+
+    #[no_mangle]
+    pub extern "C" fn Java_com_mozilla_toodle_rust_NativeToodle_deleteItem(env: JNIEnv, _: JClass, toodle: jlong, uuid: JString) {
+        delete_item(&env, toodle, uuid.into())
+    }
+
+    #[no_mangle]
+    pub extern "C" fn Java_com_mozilla_toodle_rust_NativeToodle_allItems(env: JNIEnv, _: JClass, toodle: jlong) -> jobjectArray {
+        all_items(&env, toodle).into_inner()
+    }
+
+    #[no_mangle]
+    pub extern "C" fn Java_com_mozilla_toodle_rust_NativeToodle_searchItems(env: JNIEnv, _: JClass, toodle: jlong, query: JString) -> jobjectArray {
+        search_items(&env, toodle, query.into()).into_inner()
+    }
+
+    #[no_mangle]
+    pub extern "C" fn Java_com_mozilla_toodle_rust_NativeToodle_allLabels(env: JNIEnv, _: JClass, toodle: jlong) -> jobjectArray {
+        all_labels(&env, toodle).into_inner()
+    }
+
+    #[no_mangle]
+    pub extern "C" fn Java_com_mozilla_toodle_rust_NativeToodle_createLabel(env: JNIEnv, _: JClass, toodle: jlong, name: JString, color: JString) -> jobject {
+        create_label(&env, toodle, name.into(), color.into()).into_inner()
+    }
+
+    #[no_mangle]
+    pub extern "C" fn Java_com_mozilla_toodle_rust_NativeToodle_deleteLabel(env: JNIEnv, _: JClass, toodle: jlong, name: JString) {
+        delete_label(&env, toodle, name.into())
+    }
+
+    #[no_mangle]
+    pub extern "C" fn Java_com_mozilla_toodle_rust_NativeToodle_setItemLabel(env: JNIEnv, _: JClass, toodle: jlong, uuid: JString, name: JString, add: jboolean) {
+        set_item_label(&env, toodle, uuid.into(), name.into(), add != JNI_FALSE)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::cell::RefCell;
+
+    /// What a mock object reference points at. Reference 0 is null.
+    #[derive(Clone, Debug, PartialEq)]
+    enum MockObject {
+        Null,
+        String(String),
+        Object { class: String, constructor: String, args: Vec<JavaValue<usize>> },
+        Array { class: String, elements: Vec<usize> },
+    }
+
+    /// A JVM-free `JavaEnv` that records the objects it's asked to create.
+    struct MockEnv {
+        objects: RefCell<Vec<MockObject>>,
+        /// References that have been deleted. The objects stay around to be inspected.
+        deleted: RefCell<Vec<usize>>,
+        thrown: RefCell<Option<(String, String)>>,
+    }
+
+    impl MockEnv {
+        fn new() -> MockEnv {
+            MockEnv {
+                objects: RefCell::new(vec![MockObject::Null]),
+                deleted: RefCell::new(vec![]),
+                thrown: RefCell::new(None),
+            }
+        }
+
+        /// How many local references are still alive.
+        fn live_references(&self) -> usize {
+            self.objects.borrow().len() - 1 - self.deleted.borrow().len()
+        }
+
+        fn add(&self, object: MockObject) -> usize {
+            let mut objects = self.objects.borrow_mut();
+            objects.push(object);
+            objects.len() - 1
+        }
+
+        fn object(&self, reference: usize) -> MockObject {
+            self.objects.borrow()[reference].clone()
+        }
+
+        fn string(&self, reference: usize) -> String {
+            match self.object(reference) {
+                MockObject::String(string) => string,
+                other => panic!("expected a string, got {:?}", other),
+            }
+        }
+
+        fn long(&self, value: i64) -> usize {
+            self.add(MockObject::Object {
+                class: "java/lang/Long".to_string(),
+                constructor: "(J)V".to_string(),
+                args: vec![JavaValue::Long(value)],
+            })
+        }
+
+        fn elements(&self, array: usize, expected_class: &str) -> Vec<usize> {
+            match self.object(array) {
+                MockObject::Array { class, elements } => {
+                    assert_eq!(class, expected_class);
+                    elements
+                },
+                other => panic!("expected an array, got {:?}", other),
+            }
+        }
+
+        /// The arguments an object was constructed with.
+        fn args(&self, reference: usize, expected_class: &str) -> Vec<JavaValue<usize>> {
+            match self.object(reference) {
+                MockObject::Object { class, args, .. } => {
+                    assert_eq!(class, expected_class);
+                    args
+                },
+                other => panic!("expected an object, got {:?}", other),
+            }
+        }
+
+        fn arg_object(&self, reference: usize, expected_class: &str, index: usize) -> usize {
+            match self.args(reference, expected_class)[index] {
+                JavaValue::Object(object) => object,
+                other => panic!("expected an object argument, got {:?}", other),
+            }
+        }
+
+        /// `(name, due date)` of every `ItemRecord` in `array`.
+        fn item_summaries(&self, array: usize) -> Vec<(String, Option<i64>)> {
+            self.elements(array, ITEM_RECORD_CLASS).into_iter().map(|item| {
+                let name = self.string(self.arg_object(item, ITEM_RECORD_CLASS, 1));
+                let due = self.arg_object(item, ITEM_RECORD_CLASS, 2);
+                let due = if due == 0 { None } else { self.long_value(due).ok() };
+                (name, due)
+            }).collect()
+        }
+
+        fn take_exception(&self) -> Option<(String, String)> {
+            self.thrown.borrow_mut().take()
+        }
+    }
+
+    impl JavaEnv for MockEnv {
+        type Object = usize;
+
+        fn null(&self) -> usize {
+            0
+        }
+
+        fn is_null(&self, object: usize) -> bool {
+            object == 0
+        }
+
+        fn get_string(&self, string: usize) -> Result<String, list_errors::Error> {
+            Ok(self.string(string))
+        }
+
+        fn new_string(&self, string: &str) -> Result<usize, list_errors::Error> {
+            Ok(self.add(MockObject::String(string.to_string())))
+        }
+
+        fn new_object(&self, class: &str, constructor: &str, args: &[JavaValue<usize>]) -> Result<usize, list_errors::Error> {
+            Ok(self.add(MockObject::Object { class: class.to_string(), constructor: constructor.to_string(), args: args.to_vec() }))
+        }
+
+        fn new_object_array(&self, class: &str, length: usize) -> Result<usize, list_errors::Error> {
+            Ok(self.add(MockObject::Array { class: class.to_string(), elements: vec![0; length] }))
+        }
+
+        fn set_object_array_element(&self, array: usize, index: usize, element: usize) -> Result<(), list_errors::Error> {
+            match self.objects.borrow_mut()[array] {
+                MockObject::Array { ref mut elements, .. } => elements[index] = element,
+                ref other => panic!("expected an array, got {:?}", other),
+            }
+            Ok(())
+        }
+
+        fn delete_local_ref(&self, object: usize) {
+            let mut deleted = self.deleted.borrow_mut();
+            assert!(object != 0, "null isn't a reference");
+            assert!(!deleted.contains(&object), "reference {} was already deleted", object);
+            deleted.push(object);
+        }
+
+        fn long_value(&self, boxed: usize) -> Result<i64, list_errors::Error> {
+            match self.args(boxed, "java/lang/Long")[0] {
+                JavaValue::Long(value) => Ok(value),
+                other => panic!("expected a long, got {:?}", other),
+            }
+        }
+
+        fn throw(&self, class: &str, message: &str) {
+            let mut thrown = self.thrown.borrow_mut();
+            assert!(thrown.is_none(), "an exception is already pending");
+            *thrown = Some((class.to_string(), message.to_string()));
+        }
+    }
+
+    fn toodle(env: &MockEnv) -> i64 {
+        let path = env.new_string("").unwrap();
+        let toodle = new_toodle(env, path);
+        assert_eq!(env.take_exception(), None);
+        toodle
+    }
+
+    #[test]
+    fn test_items_become_item_records() {
+        let env = MockEnv::new();
+        let toodle = toodle(&env);
+
+        let name = env.new_string("Buy milk").unwrap();
+        let due = env.long(1_500_000_000);
+        let record = create_item(&env, toodle, name, due);
+        assert_eq!(env.take_exception(), None);
+
+        match env.object(record) {
+            MockObject::Object { constructor, .. } => assert_eq!(constructor, ITEM_RECORD_CONSTRUCTOR),
+            other => panic!("expected an ItemRecord, got {:?}", other),
+        }
+        let uuid = env.string(env.arg_object(record, ITEM_RECORD_CLASS, 0));
+        assert!(Uuid::parse_str(&uuid).is_ok());
+        assert_eq!(env.arg_object(record, ITEM_RECORD_CLASS, 3), 0);
+        assert!(env.elements(env.arg_object(record, ITEM_RECORD_CLASS, 4), LABEL_RECORD_CLASS).is_empty());
+
+        let name = env.new_string("Walk the dog").unwrap();
+        create_item(&env, toodle, name, env.null());
+        let mut items = env.item_summaries(all_items(&env, toodle));
+        items.sort();
+        assert_eq!(items, vec![("Buy milk".to_string(), Some(1_500_000_000)), ("Walk the dog".to_string(), None)]);
+
+        let query = env.new_string("milk").unwrap();
+        assert_eq!(env.item_summaries(search_items(&env, toodle, query)), vec![("Buy milk".to_string(), Some(1_500_000_000))]);
+
+        let uuid = env.new_string(&uuid).unwrap();
+        let name = env.new_string("Buy oat milk").unwrap();
+        let completed = env.long(1_500_000_100);
+        update_item(&env, toodle, uuid, name, due, completed);
+        let query = env.new_string("oat").unwrap();
+        let updated = env.elements(search_items(&env, toodle, query), ITEM_RECORD_CLASS);
+        assert_eq!(updated.len(), 1);
+        assert_eq!(env.long_value(env.arg_object(updated[0], ITEM_RECORD_CLASS, 3)).unwrap(), 1_500_000_100);
+
+        delete_item(&env, toodle, uuid);
+        assert_eq!(env.item_summaries(all_items(&env, toodle)), vec![("Walk the dog".to_string(), None)]);
+        assert_eq!(env.take_exception(), None);
+        destroy(&env, toodle);
+    }
+
+    #[test]
+    fn test_labels_become_label_records() {
+        let env = MockEnv::new();
+        let toodle = toodle(&env);
+
+        let home = env.new_string("home").unwrap();
+        let red = env.new_string("#ff0000").unwrap();
+        let label = create_label(&env, toodle, home, red);
+        assert_eq!(env.string(env.arg_object(label, LABEL_RECORD_CLASS, 0)), "home");
+        assert_eq!(env.string(env.arg_object(label, LABEL_RECORD_CLASS, 1)), "#ff0000");
+        assert_eq!(env.elements(all_labels(&env, toodle), LABEL_RECORD_CLASS).len(), 1);
+
+        let name = env.new_string("Vacuum").unwrap();
+        let record = create_item(&env, toodle, name, env.null());
+        let uuid = env.arg_object(record, ITEM_RECORD_CLASS, 0);
+        set_item_label(&env, toodle, uuid, home, true);
+        assert_eq!(env.take_exception(), None);
+
+        let items = env.elements(all_items(&env, toodle), ITEM_RECORD_CLASS);
+        let labels = env.elements(env.arg_object(items[0], ITEM_RECORD_CLASS, 4), LABEL_RECORD_CLASS);
+        assert_eq!(labels.len(), 1);
+        assert_eq!(env.string(env.arg_object(labels[0], LABEL_RECORD_CLASS, 0)), "home");
+
+        set_item_label(&env, toodle, uuid, home, false);
+        let items = env.elements(all_items(&env, toodle), ITEM_RECORD_CLASS);
+        assert!(env.elements(env.arg_object(items[0], ITEM_RECORD_CLASS, 4), LABEL_RECORD_CLASS).is_empty());
+
+        delete_label(&env, toodle, home);
+        assert!(env.elements(all_labels(&env, toodle), LABEL_RECORD_CLASS).is_empty());
+        assert_eq!(env.take_exception(), None);
+        destroy(&env, toodle);
+    }
+
+    #[test]
+    fn test_records_release_their_intermediates() {
+        let env = MockEnv::new();
+        let toodle = toodle(&env);
+
+        let home = env.new_string("home").unwrap();
+        let red = env.new_string("#ff0000").unwrap();
+        create_label(&env, toodle, home, red);
+        for index in 0..10 {
+            let name = env.new_string(&format!("Chore {}", index)).unwrap();
+            let due = env.long(1_500_000_000);
+            let record = create_item(&env, toodle, name, due);
+            let uuid = env.arg_object(record, ITEM_RECORD_CLASS, 0);
+            set_item_label(&env, toodle, uuid, home, true);
+        }
+        assert_eq!(env.take_exception(), None);
+
+        // Only the array itself is left for the caller; every record, string, date and
+        // label array that went into it has been released.
+        let live = env.live_references();
+        let items = all_items(&env, toodle);
+        assert_eq!(env.live_references(), live + 1);
+        assert_eq!(env.elements(items, ITEM_RECORD_CLASS).len(), 10);
+
+        let label = create_label(&env, toodle, env.new_string("work").unwrap(), red);
+        let live = env.live_references();
+        assert!(env.deleted.borrow().contains(&env.arg_object(label, LABEL_RECORD_CLASS, 0)));
+        all_labels(&env, toodle);
+        assert_eq!(env.live_references(), live + 1);
+        destroy(&env, toodle);
+    }
+
+    #[test]
+    fn test_errors_become_exceptions() {
+        let env = MockEnv::new();
+        let toodle = toodle(&env);
+
+        assert_eq!(create_item(&env, toodle, env.null(), env.null()), 0);
+        let (class, message) = env.take_exception().expect("expected an exception");
+        assert_eq!(class, "java/lang/IllegalArgumentException");
+        assert_eq!(message, "invalid argument: name must not be null");
+
+        let bad_uuid = env.new_string("not a uuid").unwrap();
+        delete_item(&env, toodle, bad_uuid);
+        assert_eq!(env.take_exception().expect("expected an exception").0, "java/lang/IllegalArgumentException");
+
+        let missing = env.new_string("missing").unwrap();
+        delete_label(&env, toodle, missing);
+        assert_eq!(env.take_exception().expect("expected an exception").0, TOODLE_EXCEPTION_CLASS);
+
+        destroy(&env, toodle);
+        assert_eq!(all_items(&env, toodle), 0);
+        assert_eq!(env.take_exception().expect("expected an exception").0, "java/lang/IllegalStateException");
+        destroy(&env, toodle);
+        assert_eq!(env.take_exception().expect("expected an exception").0, "java/lang/IllegalStateException");
+    }
+}
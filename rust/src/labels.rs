@@ -8,13 +8,22 @@
 // CONDITIONS OF ANY KIND, either express or implied. See the License for the
 // specific language governing permissions and limitations under the License.
 
+use libc::size_t;
 use std::os::raw::c_char;
 
 use mentat_core::TypedValue;
 
-use ffi_utils::strings::{
-    string_to_c_char,
-    c_char_to_string,
+use errors::ErrorKind;
+use ffi_errors::{
+    call_with_result,
+    checked_string,
+    ExternError,
+};
+use ffi_utils::log;
+use ffi_utils::strings::string_to_c_char;
+use handles::{
+    LABELS,
+    LABEL_LISTS,
 };
 use store::{
     Entity,
@@ -30,7 +39,7 @@ pub struct Label {
 
 impl Drop for Label {
     fn drop(&mut self) {
-        println!("{:?} is being deallocated", self);
+        log::d(&format!("{:?} is being deallocated", self));
     }
 }
 
@@ -45,24 +54,166 @@ impl Label {
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn label_destroy(label: *mut Label) {
-    let _ = Box::from_raw(label);
+pub extern "C" fn label_destroy(label: u64, error: *mut ExternError) {
+    call_with_result(error, || LABELS.remove(label).map(|_| ()))
+}
+
+#[no_mangle]
+pub extern "C" fn label_get_name(label: u64, error: *mut ExternError) -> *mut c_char {
+    call_with_result(error, || LABELS.get(label, |label| Ok(string_to_c_char(label.name.clone()))))
+}
+
+#[no_mangle]
+pub extern "C" fn label_get_color(label: u64, error: *mut ExternError) -> *mut c_char {
+    call_with_result(error, || LABELS.get(label, |label| Ok(string_to_c_char(label.color.clone()))))
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn label_get_name(label: *const Label) -> *mut c_char {
-    let label = &*label;
-    string_to_c_char(label.name.clone())
+pub extern "C" fn label_set_color(label: u64, color: *const c_char, error: *mut ExternError) {
+    call_with_result(error, || {
+        LABELS.get_mut(label, |label| {
+            label.color = checked_string(color, "color")?;
+            Ok(())
+        })
+    })
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn label_get_color(label: *const Label) -> *mut c_char {
-    let label = &*label;
-    string_to_c_char(label.color.clone())
+pub extern "C" fn label_list_new() -> u64 {
+    LABEL_LISTS.insert(vec![])
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn label_set_color(label: *mut Label, color: *const c_char) {
-    let label = &mut*label;
-    label.color = c_char_to_string(color);
+pub extern "C" fn label_list_destroy(label_list: u64, error: *mut ExternError) {
+    call_with_result(error, || LABEL_LISTS.remove(label_list).map(|_| ()))
+}
+
+#[no_mangle]
+pub extern "C" fn label_list_count(label_list: u64, error: *mut ExternError) -> size_t {
+    call_with_result(error, || LABEL_LISTS.get(label_list, |labels| Ok(labels.len() as size_t)))
+}
+
+/// Returns a handle to a copy of the label at `index`, to be released with `label_destroy`.
+#[no_mangle]
+pub extern "C" fn label_list_entry_at(label_list: u64, index: size_t, error: *mut ExternError) -> u64 {
+    call_with_result(error, || {
+        let label = LABEL_LISTS.get(label_list, |labels| {
+            match labels.get(index as usize) {
+                Some(label) => Ok(label.clone()),
+                None => bail!(ErrorKind::InvalidArgument(format!("index {} is out of bounds", index))),
+            }
+        })?;
+        Ok(LABELS.insert(label))
+    })
+}
+
+/// Appends a copy of `label`; the caller keeps ownership of `label`.
+#[no_mangle]
+pub extern "C" fn add_label(label_list: u64, label: u64, error: *mut ExternError) {
+    call_with_result(error, || {
+        let label = LABELS.get(label, |label| Ok(label.clone()))?;
+        LABEL_LISTS.get_mut(label_list, |labels| {
+            labels.push(label);
+            Ok(())
+        })
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::ffi::CString;
+    use std::ptr;
+
+    use ffi_errors::{
+        ErrorCode,
+        toodle_error_destroy,
+    };
+    use handles::{
+        ITEMS,
+        TOODLES,
+    };
+    use items::Item;
+    use {
+        new_toodle,
+        toodle_create_label,
+        toodle_destroy,
+        toodle_get_all_labels,
+        toodle_update_item,
+    };
+
+    fn c_string_to_string(s: *mut c_char) -> String {
+        unsafe { CString::from_raw(s) }.into_string().expect("expected utf-8")
+    }
+
+    #[test]
+    fn test_label_list_lifecycle() {
+        let mut error = ExternError::default();
+        let uri = CString::new("").unwrap();
+        let toodle = new_toodle(uri.as_ptr(), ptr::null(), &mut error);
+        for &(name, color) in [("home", "#ff0000"), ("work", "#00ff00")].iter() {
+            let name = CString::new(name).unwrap();
+            let color = CString::new(color).unwrap();
+            label_destroy(toodle_create_label(toodle, name.as_ptr(), color.as_ptr(), &mut error), &mut error);
+        }
+
+        let all = toodle_get_all_labels(toodle, &mut error);
+        assert_eq!(label_list_count(all, &mut error), 2);
+
+        let work = label_list_entry_at(all, 1, &mut error);
+        assert_eq!(c_string_to_string(label_get_name(work, &mut error)), "work");
+        assert_eq!(c_string_to_string(label_get_color(work, &mut error)), "#00ff00");
+
+        // Build a new list from entries of the old one and tag an item with it.
+        let chosen = label_list_new();
+        assert_eq!(label_list_count(chosen, &mut error), 0);
+        add_label(chosen, work, &mut error);
+        label_destroy(work, &mut error);
+        label_list_destroy(all, &mut error);
+        assert_eq!(label_list_count(chosen, &mut error), 1);
+        assert_eq!(error.code, ErrorCode::Success);
+
+        let uuid = TOODLES.get_mut(toodle, |toodle| toodle.create_item(&Item::default())).expect("expected a uuid");
+        let item = TOODLES.get(toodle, |toodle| toodle.fetch_item(&uuid)).expect("expected an item option").expect("expected an item");
+        let item = ITEMS.insert(item);
+        let name = CString::new("tagged").unwrap();
+        toodle_update_item(toodle, item, name.as_ptr(), ptr::null(), ptr::null(), chosen, &mut error);
+        label_list_destroy(chosen, &mut error);
+        assert_eq!(error.code, ErrorCode::Success);
+
+        let item = TOODLES.get(toodle, |toodle| toodle.fetch_item(&uuid)).expect("expected an item option").expect("expected an item");
+        assert_eq!(item.name, "tagged");
+        assert_eq!(item.labels.iter().map(|label| label.name.clone()).collect::<Vec<_>>(), vec!["work".to_string()]);
+        toodle_destroy(toodle, &mut error);
+    }
+
+    #[test]
+    fn test_label_handle_misuse() {
+        let mut error = ExternError::default();
+        let labels = label_list_new();
+
+        assert_eq!(label_list_entry_at(labels, 0, &mut error), 0);
+        assert_eq!(error.code, ErrorCode::InvalidArgument);
+        unsafe { toodle_error_destroy(&mut error) };
+
+        // A list handle passed where a label is expected.
+        assert!(label_get_name(labels, &mut error).is_null());
+        assert_eq!(error.code, ErrorCode::InvalidHandle);
+        unsafe { toodle_error_destroy(&mut error) };
+
+        label_list_destroy(labels, &mut error);
+        assert_eq!(error.code, ErrorCode::Success);
+        label_list_destroy(labels, &mut error);
+        assert_eq!(error.code, ErrorCode::InvalidHandle);
+        unsafe { toodle_error_destroy(&mut error) };
+
+        // The slot is reused, but the old handle doesn't alias the new list.
+        let reused = label_list_new();
+        assert!(reused != labels);
+        assert_eq!(label_list_count(labels, &mut error), 0);
+        assert_eq!(error.code, ErrorCode::InvalidHandle);
+        unsafe { toodle_error_destroy(&mut error) };
+        label_list_destroy(reused, &mut error);
+    }
 }
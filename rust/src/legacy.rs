@@ -0,0 +1,431 @@
+// Copyright 2016 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! Importing lists kept in the plain SQL schema that Toodle used before Mentat:
+//!
+//! ```sql
+//! items(uuid, name, due_date, completion_date)
+//! labels(name, color)
+//! item_labels(item_uuid, label_name)
+//! ```
+//!
+//! Uuids and dates are kept. Anything that can't be imported is left out and listed in
+//! the report: items whose uuid is unreadable or already in the store, labels whose name
+//! is taken, unreadable dates, and links to items or labels that don't exist.
+//!
+//! Everything that is imported goes in one transaction.
+//!
+//! `Toodle::new` imports these tables when it finds them in the store's own file, as
+//! they are when an early adopter's app is upgraded, and then renames them with a
+//! `legacy_` prefix. The report is kept in the store, in the same transaction as the
+//! import, for `Toodle::legacy_import_report`; finding it is what stops an open that
+//! was interrupted before the rename from importing everything a second time.
+
+use std::collections::{
+    HashMap,
+    HashSet,
+};
+use std::path::Path;
+
+use mentat::query::IntoResult;
+use mentat_core::{
+    TypedValue,
+    Uuid,
+};
+use rusqlite::{
+    self,
+    Connection,
+};
+use rusqlite::types::Value;
+use time::{
+    self,
+    Timespec,
+};
+
+use serde_json;
+use store::{
+    ToTypedValue,
+    TransactionBuilder,
+};
+
+use errors::{
+    ErrorKind,
+    Result,
+};
+use import::Import;
+use items::Item;
+use labels::Label;
+use {
+    kw,
+    Toodle,
+};
+
+const TABLES: [&'static str; 3] = ["items", "labels", "item_labels"];
+
+/// Something that wasn't imported, and why.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Skipped {
+    pub what: String,
+    pub reason: String,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LegacyImport {
+    pub labels: usize,
+    pub items: usize,
+    /// Labels attached to imported items.
+    pub links: usize,
+    pub skipped: Vec<Skipped>,
+}
+
+impl LegacyImport {
+    fn skip<W, R>(&mut self, what: W, reason: R) where W: Into<String>, R: Into<String> {
+        self.skipped.push(Skipped { what: what.into(), reason: reason.into() });
+    }
+}
+
+struct LegacyItem {
+    uuid: String,
+    name: String,
+    due_date: Value,
+    completion_date: Value,
+}
+
+/// Every row of the legacy tables, read up front so that importing from the store's own
+/// file doesn't read and write through the same connection at once.
+struct LegacyData {
+    labels: Vec<(String, String)>,
+    items: Vec<LegacyItem>,
+    links: Vec<(String, String)>,
+}
+
+impl LegacyData {
+    fn read(handle: &Connection) -> Result<LegacyData> {
+        let mut stmt = handle.prepare("SELECT name, color FROM labels ORDER BY rowid")?;
+        let labels = stmt.query_map(&[], |row| (row.get(0), row.get(1)))?.collect::<rusqlite::Result<Vec<_>>>()?;
+        let mut stmt = handle.prepare("SELECT uuid, name, due_date, completion_date FROM items ORDER BY rowid")?;
+        let items = stmt.query_map(&[], |row| LegacyItem {
+            uuid: row.get(0),
+            name: row.get(1),
+            due_date: row.get(2),
+            completion_date: row.get(3),
+        })?.collect::<rusqlite::Result<Vec<_>>>()?;
+        let mut stmt = handle.prepare("SELECT item_uuid, label_name FROM item_labels ORDER BY rowid")?;
+        let links = stmt.query_map(&[], |row| (row.get(0), row.get(1)))?.collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(LegacyData {
+            labels: labels,
+            items: items,
+            links: links,
+        })
+    }
+}
+
+/// Whether `handle`'s database has all of the legacy tables.
+pub fn is_legacy_database(handle: &Connection) -> Result<bool> {
+    let mut found = 0;
+    for table in TABLES.iter() {
+        let count: i64 = handle.query_row("SELECT count(*) FROM sqlite_master WHERE type = 'table' AND name = ?",
+                                          &[table], |row| row.get(0))?;
+        found += count;
+    }
+    Ok(found == TABLES.len() as i64)
+}
+
+/// Legacy dates are either seconds since the epoch or UTC text like `2017-11-27 10:37:26`.
+fn parse_date(value: &Value) -> ::std::result::Result<Option<Timespec>, String> {
+    match value {
+        &Value::Null => Ok(None),
+        &Value::Integer(seconds) => Ok(Some(Timespec::new(seconds, 0))),
+        &Value::Text(ref text) => {
+            // Fractional seconds and zones aren't worth keeping: dates are whole UTC seconds.
+            let text = text.trim().trim_end_matches('Z').trim_end_matches(" UTC");
+            let text = text.splitn(2, '.').next().unwrap_or(text);
+            ["%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M:%S", "%Y-%m-%d"].iter()
+                .filter_map(|format| time::strptime(text, format).ok())
+                .next()
+                .map(|tm| Some(tm.to_timespec()))
+                .ok_or_else(|| format!("{:?} isn't a date", text))
+        },
+        other => Err(format!("{:?} isn't a date", other)),
+    }
+}
+
+/// What to import from `data`, and the report of doing so.
+fn prepare(toodle: &Toodle, data: LegacyData) -> Result<(Import, LegacyImport)> {
+    let mut import = Import::default();
+    let mut report = LegacyImport::default();
+
+    let mut labels: HashMap<String, Label> = toodle.fetch_labels()?.into_iter().map(|label| (label.name.clone(), label)).collect();
+    for (name, color) in data.labels {
+        if labels.contains_key(&name) {
+            report.skip(format!("label {:?}", name), "a label with that name is already in the store");
+            continue;
+        }
+        let label = Label { id: None, name: name.clone(), color: color };
+        import.labels.push(label.clone());
+        labels.insert(name, label);
+        report.labels += 1;
+    }
+
+    let mut links: HashMap<String, Vec<String>> = HashMap::new();
+    for (item_uuid, label_name) in data.links {
+        links.entry(item_uuid).or_insert(vec![]).push(label_name);
+    }
+    let mut known: HashSet<String> = HashSet::new();
+    let mut imported: HashSet<Uuid> = HashSet::new();
+    for legacy in data.items {
+        known.insert(legacy.uuid.clone());
+        let what = format!("item {} ({:?})", legacy.uuid, legacy.name);
+        let uuid = match Uuid::parse_str(&legacy.uuid) {
+            Ok(uuid) => uuid,
+            Err(_) => {
+                report.skip(what, "its uuid can't be read");
+                continue;
+            },
+        };
+        if imported.contains(&uuid) || toodle.fetch_item(&uuid)?.is_some() {
+            report.skip(what, "an item with that uuid is already in the store");
+            continue;
+        }
+        let due_date = parse_date(&legacy.due_date).unwrap_or_else(|reason| {
+            report.skip(format!("due date of {}", what), reason);
+            None
+        });
+        let completion_date = parse_date(&legacy.completion_date).unwrap_or_else(|reason| {
+            report.skip(format!("completion date of {}", what), reason);
+            None
+        });
+        let mut item_labels = vec![];
+        for name in links.remove(&legacy.uuid).unwrap_or(vec![]) {
+            match labels.get(&name) {
+                Some(label) => item_labels.push(label.clone()),
+                None => report.skip(format!("label {:?} of {}", name, what), "there's no label with that name"),
+            }
+        }
+        report.links += item_labels.len();
+        import.items.push(Item {
+            id: None,
+            uuid: uuid,
+            name: legacy.name,
+            due_date: due_date,
+            completion_date: completion_date,
+            notes: None,
+            priority: None,
+            starred: false,
+            recurrence: None,
+            labels: item_labels,
+        });
+        imported.insert(uuid);
+        report.items += 1;
+    }
+
+    // Links of skipped items go with them; the rest name items that don't exist.
+    for (item_uuid, names) in links {
+        if known.contains(&item_uuid) {
+            continue;
+        }
+        for name in names {
+            report.skip(format!("label {:?} of item {}", name, item_uuid), "there's no item with that uuid");
+        }
+    }
+    report.skipped.sort_by(|a, b| a.what.cmp(&b.what));
+    Ok((import, report))
+}
+
+/// Imports the legacy list at `path` into `toodle`. The legacy file isn't changed.
+pub fn import_file(toodle: &mut Toodle, path: &str) -> Result<LegacyImport> {
+    if !Path::new(path).is_file() {
+        bail!(ErrorKind::NotFound(format!("there's no file at {}", path)));
+    }
+    let handle = Connection::open(path)?;
+    if !is_legacy_database(&handle)? {
+        bail!(ErrorKind::InvalidArgument(format!("{} isn't a legacy Toodle list", path)));
+    }
+    let (import, report) = prepare(toodle, LegacyData::read(&handle)?)?;
+    toodle.import(&import)?;
+    Ok(report)
+}
+
+/// Imports the legacy tables in `toodle`'s own file, if there are any, and renames them
+/// so that they aren't imported again.
+pub fn import_in_place(toodle: &mut Toodle) -> Result<Option<LegacyImport>> {
+    if !is_legacy_database(&toodle.connection.handle)? {
+        return Ok(None);
+    }
+    // A report means the tables were imported, but renaming them didn't happen.
+    let report = match toodle.legacy_import_report()? {
+        Some(report) => report,
+        None => {
+            let (import, report) = prepare(toodle, LegacyData::read(&toodle.connection.handle)?)?;
+            let mut builder = TransactionBuilder::new();
+            let entity = builder.tempid();
+            builder.add(entity, kw("legacy", "report"), serde_json::to_string(&report)?.to_typed_value());
+            toodle.import_with(&import, builder)?;
+            report
+        },
+    };
+    for table in TABLES.iter() {
+        toodle.connection.handle.execute_batch(&format!("ALTER TABLE {} RENAME TO legacy_{}", table, table))?;
+    }
+    Ok(Some(report))
+}
+
+impl Toodle {
+    /// What was imported from legacy tables found in the store's own file when it was
+    /// first opened, or `None` if there weren't any.
+    pub fn legacy_import_report(&self) -> Result<Option<LegacyImport>> {
+        let report = self.connection
+                         .query("[:find ?report . :where [_ :legacy/report ?report]]")
+                         .into_scalar_result()?;
+        match report {
+            Some(TypedValue::String(report)) => Ok(Some(serde_json::from_str(&report)?)),
+            _ => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::fs;
+    use std::path::PathBuf;
+
+    use test::temp_path;
+
+    /// The checked-in list made by the legacy schema.
+    fn sql_test() -> String {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("sql_test").to_string_lossy().into_owned()
+    }
+
+    fn new_toodle() -> Toodle {
+        Toodle::new(String::new(), None).expect("expected a new toodle")
+    }
+
+    fn test_uuid() -> Uuid {
+        Uuid::parse_str("f102e78af68c49c1a08e16d8cc9ec446").unwrap()
+    }
+
+    fn label_names(item: &Item) -> Vec<String> {
+        let mut names: Vec<String> = item.labels.iter().map(|label| label.name.clone()).collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn test_parse_date() {
+        assert_eq!(parse_date(&Value::Null), Ok(None));
+        assert_eq!(parse_date(&Value::Integer(1511779046)), Ok(Some(Timespec::new(1511779046, 0))));
+        assert_eq!(parse_date(&Value::Text("2017-11-27 10:37:26".to_string())), Ok(Some(Timespec::new(1511779046, 0))));
+        assert_eq!(parse_date(&Value::Text("2017-11-27T10:37:26.250Z".to_string())), Ok(Some(Timespec::new(1511779046, 0))));
+        assert!(parse_date(&Value::Text("tomorrow".to_string())).is_err());
+    }
+
+    #[test]
+    fn test_import_sql_test() {
+        let mut toodle = new_toodle();
+        let report = import_file(&mut toodle, &sql_test()).expect("expected an import");
+        assert_eq!(report, LegacyImport { labels: 2, items: 1, links: 2, skipped: vec![] });
+
+        let item = toodle.fetch_item(&test_uuid()).expect("expected a fetch").expect("expected the item");
+        assert_eq!(item.name, "test item");
+        assert_eq!(item.due_date, Some(Timespec::new(1511779046, 0)));
+        assert_eq!(item.completion_date, Some(Timespec::new(1511779046, 0)));
+        assert_eq!(label_names(&item), vec!["label1".to_string(), "label2".to_string()]);
+        let colors: Vec<String> = toodle.fetch_labels().expect("expected labels").into_iter().map(|label| label.color.clone()).collect();
+        assert_eq!(colors, vec!["#000000".to_string(), "#000000".to_string()]);
+
+        // A second import finds everything already there.
+        let again = import_file(&mut toodle, &sql_test()).expect("expected an import");
+        assert_eq!((again.labels, again.items, again.links), (0, 0, 0));
+        assert_eq!(again.skipped.len(), 3);
+    }
+
+    #[test]
+    fn test_skipped_rows_are_reported() {
+        let path = temp_path("legacy-skips.db");
+        {
+            let handle = Connection::open(&path).expect("expected a database");
+            handle.execute_batch(r#"
+                CREATE TABLE labels (name TEXT NOT NULL PRIMARY KEY, color TEXT NOT NULL);
+                CREATE TABLE items (uuid TEXT NOT NULL PRIMARY KEY, name TEXT NOT NULL, due_date DATETIME, completion_date DATETIME);
+                CREATE TABLE item_labels (item_uuid TEXT NOT NULL, label_name TEXT NOT NULL, PRIMARY KEY (item_uuid, label_name));
+                INSERT INTO labels VALUES ('home', '#ff0000');
+                INSERT INTO items VALUES ('not-a-uuid', 'Broken', NULL, NULL);
+                INSERT INTO items VALUES ('2cb9c3a162a74cc68b468fcd7e1d7f47', 'Buy milk', 'soon', NULL);
+                INSERT INTO item_labels VALUES ('2cb9c3a162a74cc68b468fcd7e1d7f47', 'home');
+                INSERT INTO item_labels VALUES ('2cb9c3a162a74cc68b468fcd7e1d7f47', 'work');
+                INSERT INTO item_labels VALUES ('ffffffffffffffffffffffffffffffff', 'home');
+            "#).expect("expected legacy tables");
+        }
+
+        let mut toodle = new_toodle();
+        let report = import_file(&mut toodle, &path.to_string_lossy()).expect("expected an import");
+        assert_eq!((report.labels, report.items, report.links), (1, 1, 1));
+        let whats: Vec<&str> = report.skipped.iter().map(|skipped| skipped.what.as_str()).collect();
+        assert_eq!(whats, vec![
+            "due date of item 2cb9c3a162a74cc68b468fcd7e1d7f47 (\"Buy milk\")",
+            "item not-a-uuid (\"Broken\")",
+            "label \"home\" of item ffffffffffffffffffffffffffffffff",
+            "label \"work\" of item 2cb9c3a162a74cc68b468fcd7e1d7f47 (\"Buy milk\")",
+        ]);
+    }
+
+    #[test]
+    fn test_other_databases_are_refused() {
+        let mut toodle = new_toodle();
+        let path = temp_path("not-legacy.db");
+        Connection::open(&path).and_then(|handle| handle.execute_batch("CREATE TABLE items (id INTEGER)")).expect("expected a database");
+        match import_file(&mut toodle, &path.to_string_lossy()) {
+            Err(e) => match e.kind() {
+                &ErrorKind::InvalidArgument(_) => (),
+                kind => panic!("expected InvalidArgument, got {:?}", kind),
+            },
+            Ok(_) => panic!("expected a database without the legacy tables to be refused"),
+        }
+    }
+
+    #[test]
+    fn test_opening_a_legacy_file_imports_it() {
+        let path = temp_path("legacy-in-place.db");
+        fs::copy(sql_test(), &path).expect("expected a copy of sql_test");
+
+        let uri = path.to_string_lossy().into_owned();
+        let expected = LegacyImport { labels: 2, items: 1, links: 2, skipped: vec![] };
+        {
+            let toodle = Toodle::new(uri.clone(), None).expect("expected the legacy file to open");
+            assert!(!is_legacy_database(&toodle.connection.handle).expect("expected a check"));
+            let item = toodle.fetch_item(&test_uuid()).expect("expected a fetch").expect("expected the item");
+            assert_eq!(label_names(&item), vec!["label1".to_string(), "label2".to_string()]);
+            assert_eq!(toodle.legacy_import_report().expect("expected a report"), Some(expected.clone()));
+        }
+
+        // Reopening doesn't import anything twice, and the report is kept.
+        let mut toodle = Toodle::new(uri, None).expect("expected the store to reopen");
+        assert_eq!(toodle.fetch_items().expect("expected items").vec.len(), 1);
+        assert_eq!(toodle.fetch_labels().expect("expected labels").len(), 2);
+        assert_eq!(toodle.legacy_import_report().expect("expected a report"), Some(expected.clone()));
+
+        // Nor does an open that imported the tables but stopped before renaming them.
+        for table in TABLES.iter() {
+            toodle.connection.handle.execute_batch(&format!("ALTER TABLE legacy_{} RENAME TO {}", table, table)).expect("expected a rename");
+        }
+        assert_eq!(import_in_place(&mut toodle).expect("expected an import"), Some(expected));
+        assert!(!is_legacy_database(&toodle.connection.handle).expect("expected a check"));
+        let reports = toodle.connection.query("[:find ?e :where [?e :legacy/report _]]").into_rel_result().expect("expected reports");
+        assert_eq!(reports.len(), 1);
+    }
+
+    #[test]
+    fn test_stores_without_legacy_tables_have_no_report() {
+        let toodle = new_toodle();
+        assert_eq!(toodle.legacy_import_report().expect("expected a query"), None);
+    }
+}
@@ -9,161 +9,463 @@
 // specific language governing permissions and limitations under the License.
 
 #[macro_use] extern crate error_chain;
+#[macro_use] extern crate lazy_static;
+#[macro_use] extern crate serde_derive;
 
+extern crate base64;
 extern crate libc;
 extern crate edn;
 extern crate mentat;
 extern crate mentat_core;
+extern crate ring;
 extern crate rusqlite;
+extern crate serde;
+extern crate serde_json;
 extern crate store;
 extern crate time;
 extern crate uuid;
 
 extern crate ffi_utils;
+// Renamed so that it doesn't collide with our own `jni` module.
+#[cfg(any(target_os = "android", feature = "android"))]
+extern crate jni as jni_rs;
 
 use libc::{ c_int, time_t };
-use std::os::raw::c_char;
+use std::os::raw::{
+    c_char,
+    c_void,
+};
 use std::ffi::CString;
+use edn::NamespacedKeyword;
 use mentat::query::{
     IntoResult,
-    QueryExecutionResult,
     Variable,
 };
 use mentat_core::{
+    Entid,
     TypedValue,
     Uuid,
 };
 use time::Timespec;
 
+pub mod api;
 pub mod labels;
 pub mod items;
+pub mod legacy;
 pub mod errors;
+pub mod ffi_errors;
 pub mod ctypes;
-
+pub mod handles;
+pub mod jni;
+pub mod clock;
+pub mod filters;
+pub mod conflicts;
+pub mod encryption;
+pub mod import;
+pub mod observers;
+pub mod recurrence;
+#[cfg(feature = "remote")]
+pub mod remote;
+pub mod subtasks;
+pub mod sync;
+pub mod vocabulary;
+
+use clock::{
+    Clock,
+    SystemClock,
+};
 use errors as list_errors;
+use errors::ErrorKind;
+use ffi_errors::{
+    call_with_result,
+    checked_string,
+    ExternError,
+    FailureValue,
+};
 use ffi_utils::strings::{
     c_char_to_string,
     optional_timespec
 };
 use ffi_utils::log;
+use filters::{
+    ItemFilter,
+    SortKey,
+};
+use handles::{
+    ITEMS,
+    ITEM_LISTS,
+    LABELS,
+    LABEL_LISTS,
+    TOODLES,
+};
 use labels::Label;
 use items::{
     Item,
+    ItemUpdate,
     Items
 };
-use ctypes::{
-    ItemC,
-    ItemsC,
-    ItemCList
+use observers::{
+    Change,
+    ChangeKind,
+    ObserverToken,
+    Observers,
+    Topic,
 };
+use ctypes::ItemC;
+use recurrence::Recurrence;
+use subtasks::Cascade;
 use store::{
+    Entity,
+    EntityPlace,
     Store,
     StoreConnection,
     ToInner,
     ToTypedValue,
+    TransactionBuilder,
 };
+use std::cell::RefCell;
+use std::cmp::{
+    self,
+    Ordering,
+};
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io;
+use std::mem;
+use std::path::PathBuf;
 use std::str::FromStr;
 
-// TODO this is pretty horrible and rather crafty, but I couldn't get this to live
-// inside a Toodle struct and be able to mutate it...
-static mut CHANGED_CALLBACK: Option<extern fn()> = None;
-
 #[derive(Debug)]
 #[repr(C)]
 pub struct Toodle {
     connection: StoreConnection,
+    observers: Observers,
+    clock: Box<Clock>,
 }
 
 impl Toodle {
-    fn new(uri: String) -> Result<Toodle, errors::Error> {
-        let store_result = Store::new_store(uri)?;
+    /// Opens the list at `uri`, or an in-memory one if `uri` is empty. A `key` encrypts
+    /// the store, which needs the `sqlcipher` feature; opening an encrypted store with the
+    /// wrong key, or without one, fails with `WrongKey`.
+    pub fn new(uri: String, key: Option<&str>) -> Result<Toodle, errors::Error> {
+        let store_result = Store::new_store(uri, key)?;
         let mut toodle = Toodle {
             connection: store_result,
+            observers: Observers::new(),
+            clock: Box::new(SystemClock),
         };
 
-        // TODO proper error handling at the FFI boundary
-        toodle.transact_labels_vocabulary().expect("transacted");
-        toodle.transact_items_vocabulary().expect("transacted");
+        for upgrade in vocabulary::registry().install(&mut toodle.connection)? {
+            log::d(&format!("upgraded the {} vocabulary from version {} to {}", upgrade.name, upgrade.from, upgrade.to));
+        }
+        if let Some(report) = legacy::import_in_place(&mut toodle)? {
+            log::d(&format!("imported {} labels and {} items from legacy tables; skipped {:?}", report.labels, report.items, report.skipped));
+        }
 
         Ok(toodle)
     }
+
+    /// Where the command-line tools keep their store: `$TOODLE_DB` if that's set, or
+    /// `~/.toodle/toodle.db`, creating its directory if need be.
+    pub fn default_path() -> io::Result<PathBuf> {
+        if let Some(path) = env::var_os("TOODLE_DB") {
+            return Ok(PathBuf::from(path));
+        }
+        let mut path = env::var_os("HOME").map(PathBuf::from).unwrap_or_else(|| PathBuf::from("."));
+        path.push(".toodle");
+        fs::create_dir_all(&path)?;
+        path.push("toodle.db");
+        Ok(path)
+    }
+
+    /// Re-encrypts a store that was opened with a key, so that it must be opened with
+    /// `key` from now on.
+    pub fn rekey(&mut self, key: &str) -> Result<(), errors::Error> {
+        Ok(self.connection.rekey(key)?)
+    }
+
+    /// Calls `callback` with every change made through this `Toodle` to any of `topics`,
+    /// until the returned token is unregistered.
+    pub fn register_observer<F>(&mut self, topics: &[Topic], callback: F) -> ObserverToken where F: Fn(&Change) + 'static {
+        self.observers.register(topics, callback)
+    }
+
+    /// Returns false if `token` wasn't registered with this `Toodle`.
+    pub fn unregister_observer(&mut self, token: ObserverToken) -> bool {
+        self.observers.unregister(token)
+    }
+
+    /// Replaces the system clock, which decides when recurring items are next due.
+    pub fn set_clock<C>(&mut self, clock: C) where C: Clock + 'static {
+        self.clock = Box::new(clock);
+    }
+
+    fn notify<I, T>(&self, topic: Topic, kind: ChangeKind, ids: I) where I: IntoIterator<Item=T>, T: Into<String> {
+        self.observers.notify(&Change::new(topic, kind, ids));
+    }
 }
 
 fn create_uuid() -> Uuid {
     uuid::Uuid::new_v4()
 }
 
-fn return_date_field(results: QueryExecutionResult) -> Result<Option<Timespec>, list_errors::Error> {
-    results.into_scalar_result()
-            .map(|o| o.and_then(|ts| ts.to_inner()))
-            .map_err(|e| e.into())
+/// The uuid of the occurrence of recurring item `item_uuid` that's due at `due_date`,
+/// which is the same in every store that works it out.
+fn occurrence_uuid(item_uuid: &Uuid, due_date: Timespec) -> Uuid {
+    uuid::Uuid::new_v5(item_uuid, &due_date.sec.to_string())
 }
 
+fn kw(namespace: &str, name: &str) -> NamespacedKeyword {
+    NamespacedKeyword::new(namespace, name)
+}
+
+// Dates are stored to whole-second precision; see `ToInner<Option<Timespec>>`.
+fn date_value(date: &Timespec) -> TypedValue {
+    Timespec::new(date.sec, 0).to_typed_value()
+}
+
+struct SearchHit {
+    rank: usize,
+    score: f64,
+    name: String,
+    row: Vec<TypedValue>,
+}
+
+impl SearchHit {
+    fn cmp_rank(&self, other: &SearchHit) -> Ordering {
+        self.rank.cmp(&other.rank)
+            .then(other.score.partial_cmp(&self.score).unwrap_or(Ordering::Equal))
+            .then_with(|| self.name.cmp(&other.name))
+    }
+}
+
+/// Turns free text into a full-text match expression in which every word must appear.
+/// Each word is quoted so that user input can't be interpreted as match syntax.
+fn fulltext_term(query: &str) -> Option<String> {
+    let words: Vec<String> = query.split_whitespace()
+                                  .map(|word| format!("\"{}\"", word.replace("\"", "\"\"")))
+                                  .collect();
+    if words.is_empty() {
+        None
+    } else {
+        Some(words.join(" "))
+    }
+}
+
+// Labels are ordered by creation (and so entid) to give callers a stable order.
+fn sort_labels(labels: &mut Vec<Label>) {
+    labels.sort_by_key(|label| label.id.as_ref().map(|e| e.id));
+}
+
+fn notes_value(value: Option<TypedValue>) -> Option<String> {
+    value.map(|v| v.to_inner())
+}
+
+fn priority_value(value: Option<TypedValue>) -> Option<i64> {
+    value.and_then(|v| v.to_inner())
+}
+
+fn starred_value(value: Option<TypedValue>) -> bool {
+    match value {
+        Some(TypedValue::Boolean(starred)) => starred,
+        _ => false,
+    }
+}
+
+// A rule this version can't parse is dropped rather than failing the whole fetch.
+fn recurrence_value(value: Option<TypedValue>) -> Option<Recurrence> {
+    match value {
+        Some(TypedValue::String(ref rule)) => rule.parse().ok(),
+        _ => None,
+    }
+}
+
+// Hydrating a batch of items names them in the query text, so batches are kept small
+// enough for the SQL that Mentat generates to stay within SQLite's limits.
+const HYDRATION_BATCH: usize = 256;
+
 impl Toodle {
-    fn item_row_to_item(&self, row: Vec<TypedValue>) -> Item {
-        let uuid = row[1].clone().to_inner();
-        Item {
-            id: row[0].clone().to_inner(),
-            uuid: uuid,
-            name: row[2].clone().to_inner(),
-            due_date: self.fetch_due_date_for_item( &uuid).unwrap_or(None),
-            completion_date: self.fetch_completion_date_for_item(&uuid).unwrap_or(None),
-            labels: self.fetch_labels_for_item(&uuid).unwrap_or(vec![]),
-        }
-    }
-
-    pub fn transact_items_vocabulary(&mut self) -> Result<(), list_errors::Error> {
-        let schema = r#"[
-            {   :db/ident       :item/uuid
-                :db/valueType   :db.type/uuid
-                :db/cardinality :db.cardinality/one
-                :db/unique      :db.unique/value
-                :db/index true },
-            {   :db/ident       :item/name
-                :db/valueType   :db.type/string
-                :db/cardinality :db.cardinality/one
-                :db/index       true
-                :db/fulltext    true  },
-            {   :db/ident       :item/due_date
-                :db/valueType   :db.type/instant
-                :db/cardinality :db.cardinality/one  },
-            {   :db/ident       :item/completion_date
-                :db/valueType   :db.type/instant
-                :db/cardinality :db.cardinality/one  },
-            {  :db/ident     :item/label
-                :db/valueType :db.type/ref
-                :db/cardinality :db.cardinality/many }]"#;
+    /// Hydrates a batch of `[?eid ?uuid ?name]` rows. Only the requested items are looked
+    /// at, with a fixed number of queries for every `HYDRATION_BATCH` rows.
+    fn item_rows_to_items(&self, mut rows: Vec<Vec<TypedValue>>) -> Result<Vec<Item>, list_errors::Error> {
+        let mut items = Vec::with_capacity(rows.len());
+        while !rows.is_empty() {
+            let rest = rows.split_off(cmp::min(HYDRATION_BATCH, rows.len()));
+            let batch = mem::replace(&mut rows, rest);
+            let uuids: Vec<String> = batch.iter().map(|row| {
+                let uuid: Uuid = row[1].clone().to_inner();
+                format!("#uuid \"{}\"", uuid.hyphenated())
+            }).collect();
+            let scope = format!("[(ground [{}]) [?uuid ...]] [?eid :item/uuid ?uuid]", uuids.join(" "));
+            items.extend(self.hydrate_item_rows(batch, &scope)?);
+        }
+        Ok(items)
+    }
+
+    /// Hydrates rows with the values of the items matched by the `scope` clauses, which
+    /// bind `?eid`; an empty scope covers the whole store.
+    fn hydrate_item_rows(&self, rows: Vec<Vec<TypedValue>>, scope: &str) -> Result<Vec<Item>, list_errors::Error> {
+        if rows.is_empty() {
+            return Ok(vec![]);
+        }
+        let due_dates = self.fetch_item_dates("due_date", scope)?;
+        let completion_dates = self.fetch_item_dates("completion_date", scope)?;
+        let mut notes = self.fetch_item_values("notes", scope)?;
+        let mut priorities = self.fetch_item_values("priority", scope)?;
+        let mut starred = self.fetch_item_values("starred", scope)?;
+        let mut recurrences = self.fetch_item_values("recurrence", scope)?;
+        let mut labels = self.fetch_labels_by_item(scope)?;
+
+        Ok(rows.into_iter().map(|row| {
+            let id: Option<Entity> = row[0].clone().to_inner();
+            let entid = id.as_ref().map(|e| e.id);
+            Item {
+                id: id,
+                uuid: row[1].clone().to_inner(),
+                name: row[2].clone().to_inner(),
+                due_date: entid.and_then(|e| due_dates.get(&e).cloned()),
+                completion_date: entid.and_then(|e| completion_dates.get(&e).cloned()),
+                notes: notes_value(entid.and_then(|e| notes.remove(&e))),
+                priority: priority_value(entid.and_then(|e| priorities.remove(&e))),
+                starred: starred_value(entid.and_then(|e| starred.remove(&e))),
+                recurrence: recurrence_value(entid.and_then(|e| recurrences.remove(&e))),
+                labels: entid.and_then(|e| labels.remove(&e)).unwrap_or(vec![]),
+            }
+        }).collect())
+    }
+
+    fn fetch_item_dates(&self, attribute: &str, scope: &str) -> Result<HashMap<Entid, Timespec>, list_errors::Error> {
+        let query = format!(r#"[:find ?eid ?date
+                                :where
+                                {}
+                                [?eid :item/{} ?date]
+        ]"#, scope, attribute);
         self.connection
-            .transact(schema)
+            .query(&query)
+            .into_rel_result()
+            .map(|rows| rows.into_iter().filter_map(|row| {
+                let id: Option<Entity> = row[0].clone().to_inner();
+                let date: Option<Timespec> = row[1].clone().to_inner();
+                match (id, date) {
+                    (Some(id), Some(date)) => Some((id.id, date)),
+                    _ => None,
+                }
+            }).collect())
             .map_err(|e| e.into())
-            .map(|_| ())
-    }
-
-    pub fn transact_labels_vocabulary(&mut self) -> Result<(), list_errors::Error> {
-        let schema = r#"[
-            {  :db/ident       :label/name
-               :db/valueType   :db.type/string
-               :db/cardinality :db.cardinality/one
-               :db/unique      :db.unique/identity
-               :db/index       true
-               :db/fulltext    true },
-            {  :db/ident       :label/color
-               :db/valueType   :db.type/string
-               :db/cardinality :db.cardinality/one }]"#;
+    }
+
+    /// The value of the cardinality-one `attribute` of every item in `scope`, by entid.
+    fn fetch_item_values(&self, attribute: &str, scope: &str) -> Result<HashMap<Entid, TypedValue>, list_errors::Error> {
+        let query = format!(r#"[:find ?eid ?value
+                                :where
+                                {}
+                                [?eid :item/{} ?value]
+        ]"#, scope, attribute);
         self.connection
-            .transact(schema)
+            .query(&query)
+            .into_rel_result()
+            .map(|rows| rows.into_iter().filter_map(|row| {
+                let id: Option<Entity> = row[0].clone().to_inner();
+                id.map(|id| (id.id, row[1].clone()))
+            }).collect())
             .map_err(|e| e.into())
-            .map(|_| ())
+    }
+
+    fn fetch_labels_by_item(&self, scope: &str) -> Result<HashMap<Entid, Vec<Label>>, list_errors::Error> {
+        let query = format!(r#"[:find ?eid ?l ?name ?color
+                                :where
+                                {}
+                                [?eid :item/label ?l]
+                                [?l :label/name ?name]
+                                [?l :label/color ?color]
+        ]"#, scope);
+        let rows = self.connection
+                       .query(&query)
+                       .into_rel_result()?;
+        let mut labels_by_item: HashMap<Entid, Vec<Label>> = HashMap::new();
+        for row in rows.into_iter() {
+            let item: Option<Entity> = row[0].clone().to_inner();
+            if let (Some(item), Some(label)) = (item, Label::from_row(&row[1..].to_vec())) {
+                labels_by_item.entry(item.id).or_insert(vec![]).push(label);
+            }
+        }
+        for labels in labels_by_item.values_mut() {
+            sort_labels(labels);
+        }
+        Ok(labels_by_item)
     }
 
     pub fn create_label(&mut self, name: String, color: String) -> Result<Option<Label>, list_errors::Error> {
-        // TODO: better transact API.
-        let query = format!("[{{ :label/name \"{0}\" :label/color \"{1}\" }}]", &name, &color);
+        let mut builder = TransactionBuilder::new();
+        let label = builder.tempid();
+        builder.add(label.clone(), kw("label", "name"), name.to_typed_value())
+               .add(label, kw("label", "color"), color.to_typed_value());
         self.connection
-            .transact(&query)?;
+            .transact_builder(&builder)?;
+        self.notify(Topic::Labels, ChangeKind::Created, vec![name.clone()]);
         self.fetch_label(&name)
     }
 
+    pub fn rename_label(&mut self, label: &Label, new_name: String) -> Result<Option<Label>, list_errors::Error> {
+        let existing = match self.fetch_label(&label.name)? {
+            Some(existing) => existing,
+            None => return Ok(None),
+        };
+        if existing.name != new_name {
+            let label_id = existing.id.expect("fetched label must have an ID");
+            // Items refer to the label entity, not to its name, so tagged items follow the rename.
+            let mut builder = TransactionBuilder::new();
+            builder.add(label_id, kw("label", "name"), new_name.to_typed_value());
+            self.connection
+                .transact_builder(&builder)?;
+            self.notify(Topic::Labels, ChangeKind::Updated, vec![existing.name.clone(), new_name.clone()]);
+        }
+        self.fetch_label(&new_name)
+    }
+
+    pub fn update_label_color(&mut self, label: &Label, color: String) -> Result<Option<Label>, list_errors::Error> {
+        let existing = match self.fetch_label(&label.name)? {
+            Some(existing) => existing,
+            None => return Ok(None),
+        };
+        if existing.color != color {
+            let label_id = existing.id.expect("fetched label must have an ID");
+            let mut builder = TransactionBuilder::new();
+            builder.add(label_id, kw("label", "color"), color.to_typed_value());
+            self.connection
+                .transact_builder(&builder)?;
+            self.notify(Topic::Labels, ChangeKind::Updated, vec![existing.name.clone()]);
+        }
+        self.fetch_label(&label.name)
+    }
+
+    pub fn delete_label(&mut self, label: &Label) -> Result<(), list_errors::Error> {
+        let existing = match self.fetch_label(&label.name)? {
+            Some(existing) => existing,
+            None => return Ok(()),
+        };
+        let label_id = existing.id.clone().expect("fetched label must have an ID");
+        let mut builder = TransactionBuilder::new();
+
+        // Untag every item first so that no `:item/label` refers to a label without a name.
+        let mut untagged = vec![];
+        for item in self.fetch_items_with_label(&existing)? {
+            if let Some(ref item_id) = item.id {
+                builder.retract(item_id.clone(), kw("item", "label"), label_id.to_typed_value());
+                untagged.push(item.uuid.hyphenated().to_string());
+            }
+        }
+        builder.retract(label_id.clone(), kw("label", "name"), existing.name.to_typed_value())
+               .retract(label_id, kw("label", "color"), existing.color.to_typed_value());
+
+        self.connection
+            .transact_builder(&builder)?;
+        self.notify(Topic::Labels, ChangeKind::Deleted, vec![existing.name.clone()]);
+        self.notify(Topic::Items, ChangeKind::Updated, untagged);
+        Ok(())
+    }
+
     pub fn fetch_label(&self, name: &String) -> Result<Option<Label>, list_errors::Error> {
         let query = r#"[:find [?eid ?name ?color]
                         :in ?name
@@ -203,7 +505,11 @@ impl Toodle {
         self.connection
             .query_args(query, vec![(Variable::from_valid_name("?item_uuid"), item_uuid.to_typed_value())])
             .into_rel_result()
-            .map(|rows| rows.iter().filter_map(|row| Label::from_row(&row)).collect())
+            .map(|rows| {
+                let mut labels = rows.iter().filter_map(|row| Label::from_row(&row)).collect();
+                sort_labels(&mut labels);
+                labels
+            })
             .map_err(|e| e.into())
     }
 
@@ -217,11 +523,10 @@ impl Toodle {
                         [?eid :item/uuid ?uuid]
                         [?eid :item/name ?name]
         ]"#;
-        self.connection
-            .query_args(query, vec![(Variable::from_valid_name("?label"), label.name.to_typed_value())])
-            .into_rel_result()
-            .map(|rows| rows.into_iter().map(|r| self.item_row_to_item(r)).collect())
-            .map_err(|e| e.into())
+        let rows = self.connection
+                       .query_args(query, vec![(Variable::from_valid_name("?label"), label.name.to_typed_value())])
+                       .into_rel_result()?;
+        self.item_rows_to_items(rows)
     }
 
     pub fn fetch_items(&self) -> Result<Items, list_errors::Error> {
@@ -230,318 +535,716 @@ impl Toodle {
                         [?eid :item/uuid ?uuid]
                         [?eid :item/name ?name]
         ]"#;
-        
-        self.connection
-            .query(query)
-            .into_rel_result()
-            .map(|rows| Items::new(rows.into_iter().map(|r| self.item_row_to_item(r)).collect()))
-            .map_err(|e| e.into())
+
+        let rows = self.connection
+                       .query(query)
+                       .into_rel_result()?;
+        // Every item is wanted, so there's nothing to restrict the hydration queries to.
+        self.hydrate_item_rows(rows, "").map(Items::new)
     }
 
+    /// Fetches one item with two queries: one for every attribute of the item, and one
+    /// for its labels.
     pub fn fetch_item(&self, uuid: &Uuid) -> Result<Option<Item> , list_errors::Error>{
-        let query = r#"[:find [?eid ?uuid ?name]
+        let query = r#"[:find ?eid ?a ?value
                         :in ?uuid
                         :where
                         [?eid :item/uuid ?uuid]
-                        [?eid :item/name ?name]
+                        [?eid ?a ?value]
         ]"#;
-        self.connection
-            .query_args(query, vec![(Variable::from_valid_name("?uuid"), uuid.to_typed_value())])
-            .into_tuple_result()
-            .map(|o| o.map(|r| self.item_row_to_item(r)))
-            .map_err(|e| e.into())
+        let rows = self.connection
+                       .query_args(query, vec![(Variable::from_valid_name("?uuid"), uuid.to_typed_value())])
+                       .into_rel_result()?;
+        let mut id: Option<Entity> = None;
+        let mut values: HashMap<String, TypedValue> = HashMap::new();
+        for row in rows.into_iter() {
+            id = row[0].clone().to_inner();
+            let attribute = match row[1] {
+                TypedValue::Ref(a) => self.connection.ident_for_entid(a),
+                _ => None,
+            };
+            // Labels are many-valued, and are fetched with their names and colors below.
+            if let Some(attribute) = attribute {
+                if attribute.namespace == "item" && attribute.name != "label" {
+                    values.insert(attribute.name, row[2].clone());
+                }
+            }
+        }
+        let name: String = match values.remove("name") {
+            Some(name) => name.to_inner(),
+            None => return Ok(None),
+        };
+        Ok(Some(Item {
+            id: id,
+            uuid: *uuid,
+            name: name,
+            due_date: values.remove("due_date").and_then(|date| date.to_inner()),
+            completion_date: values.remove("completion_date").and_then(|date| date.to_inner()),
+            notes: notes_value(values.remove("notes")),
+            priority: priority_value(values.remove("priority")),
+            starred: starred_value(values.remove("starred")),
+            recurrence: recurrence_value(values.remove("recurrence")),
+            labels: self.fetch_labels_for_item(uuid)?,
+        }))
     }
 
-    fn fetch_completion_date_for_item(&self, item_id: &Uuid) -> Result<Option<Timespec>, list_errors::Error> {
-        let query = r#"[:find ?date .
-            :in ?uuid
-            :where
-            [?eid :item/uuid ?uuid]
-            [?eid :item/completion_date ?date]
-        ]"#;
+    /// The page of items that `filter` describes. Only the rows of that page, and of the
+    /// items before it, are fetched, and only the page is hydrated.
+    pub fn query_items(&self, filter: &ItemFilter) -> Result<Vec<Item>, list_errors::Error> {
+        // Datalog can't skip rows, so the rows before the page are fetched and dropped.
+        let wanted = filter.limit.map(|limit| filter.offset + limit);
+        if wanted == Some(0) {
+            return Ok(vec![]);
+        }
+        let mut rows = self.query_item_rows(filter, true, wanted)?;
+        // Items without the date or priority being sorted on follow the others.
+        if filter.sort_key != SortKey::Name && wanted.map_or(true, |wanted| rows.len() < wanted) {
+            let remaining = wanted.map(|wanted| wanted - rows.len());
+            rows.extend(self.query_item_rows(filter, false, remaining)?);
+        }
+        let rows = rows.into_iter().skip(filter.offset).map(|mut row| {
+            row.truncate(3);
+            row
+        }).collect();
+        self.item_rows_to_items(rows)
+    }
 
-        return_date_field(
-            self.connection
-                .query_args(&query, vec![(Variable::from_valid_name("?uuid"), item_id.to_typed_value())]))
+    fn query_item_rows(&self, filter: &ItemFilter, sorted: bool, limit: Option<usize>) -> Result<Vec<Vec<TypedValue>>, list_errors::Error> {
+        let (query, inputs) = filter.to_query(sorted, limit);
+        Ok(self.connection
+               .query_args(&query, inputs)
+               .into_rel_result()?)
     }
 
-    fn fetch_due_date_for_item(&self, item_id: &Uuid) -> Result<Option<Timespec>, list_errors::Error> {
-        let query = r#"[:find ?date .
-            :in ?uuid
-            :where
-            [?eid :item/uuid ?uuid]
-            [?eid :item/due_date ?date]
+    /// Full-text search over item names, item notes and the names of the labels attached
+    /// to items. Items whose own name matches rank first, then items matching on their
+    /// notes, then items that only match through a label; within each group, results are
+    /// ordered by descending score and then by name.
+    pub fn search_items(&self, query: &str) -> Result<Vec<Item>, list_errors::Error> {
+        let term = match fulltext_term(query) {
+            Some(term) => term,
+            None => return Ok(vec![]),
+        };
+        let by_item_name = r#"[:find ?eid ?uuid ?name ?score
+                               :in ?term
+                               :where
+                               [(fulltext $ :item/name ?term) [[?eid ?name _ ?score]]]
+                               [?eid :item/uuid ?uuid]
+        ]"#;
+        let by_notes = r#"[:find ?eid ?uuid ?name ?score
+                           :in ?term
+                           :where
+                           [(fulltext $ :item/notes ?term) [[?eid _ _ ?score]]]
+                           [?eid :item/uuid ?uuid]
+                           [?eid :item/name ?name]
+        ]"#;
+        let by_label_name = r#"[:find ?eid ?uuid ?name ?score
+                                :in ?term
+                                :where
+                                [(fulltext $ :label/name ?term) [[?l _ _ ?score]]]
+                                [?eid :item/label ?l]
+                                [?eid :item/uuid ?uuid]
+                                [?eid :item/name ?name]
         ]"#;
 
-        let date = return_date_field(
-            self.connection
-                .query_args(&query, vec![(Variable::from_valid_name("?uuid"), item_id.to_typed_value())]));
-        date
+        // Rank 0 is a match on the item's own name, rank 1 on its notes and rank 2 on one
+        // of its labels.
+        let mut hits: HashMap<Uuid, SearchHit> = HashMap::new();
+        for (rank, query) in [by_item_name, by_notes, by_label_name].iter().enumerate() {
+            let rows = self.connection
+                           .query_args(query, vec![(Variable::from_valid_name("?term"), term.to_typed_value())])
+                           .into_rel_result()?;
+            for mut row in rows.into_iter() {
+                let score = match row.pop() {
+                    Some(TypedValue::Double(score)) => score.into_inner(),
+                    _ => 0.0,
+                };
+                let hit = SearchHit {
+                    rank: rank,
+                    score: score,
+                    name: row[2].clone().to_inner(),
+                    row: row,
+                };
+                let uuid: Uuid = hit.row[1].clone().to_inner();
+                let replace = match hits.get(&uuid) {
+                    Some(existing) => hit.cmp_rank(existing) == Ordering::Less,
+                    None => true,
+                };
+                if replace {
+                    hits.insert(uuid, hit);
+                }
+            }
+        }
+
+        let mut hits: Vec<SearchHit> = hits.into_iter().map(|(_, hit)| hit).collect();
+        hits.sort_by(|a, b| a.cmp_rank(b));
+        self.item_rows_to_items(hits.into_iter().map(|hit| hit.row).collect())
     }
 
     pub fn create_item(&mut self, item: &Item) -> Result<Uuid, list_errors::Error> {
-        // TODO: make this mapping better!
-        let label_str = item.labels
-                            .iter()
-                            .filter(|label| label.id.is_some() )
-                            .map(|label|  format!("{}", label.id.clone().map::<i64, _>(|e| e.into()).unwrap()) )
-                            .collect::<Vec<String>>()
-                            .join(", ");
         let item_uuid = create_uuid();
-        let uuid_string = item_uuid.hyphenated().to_string();
-        let mut query = format!(r#"[{{
-            :item/uuid #uuid {:?}
-            :item/name {:?}
-            "#, &uuid_string, &(item.name));
-        if let Some(due_date) = item.due_date {
-            let micro_seconds = due_date.sec * 1000000;
-            query = format!(r#"{}:item/due_date #instmicros {}
-                "#, &query, &micro_seconds);
-        }
-        if let Some(completion_date) = item.completion_date {
-            let micro_seconds = completion_date.sec * 1000000;
-            query = format!(r#"{}:item/completion_date #instmicros {}
-                "#, &query, &micro_seconds);
-        }
-        if !label_str.is_empty() {
-            query = format!(r#"{0}:item/label [{1}]
-                "#, &query, &label_str);
-        }
-        query = format!("{0}}}]", &query);
-        let _ = self.connection.transact(&query)?;
+        self.create_item_with_uuid(item_uuid, item, None)?;
         Ok(item_uuid)
     }
 
+    /// Like `create_item`, for items that already have a uuid somewhere else or that are
+    /// created as a subtask of the item `parent`.
+    fn create_item_with_uuid(&mut self, item_uuid: Uuid, item: &Item, parent: Option<Entid>) -> Result<(), list_errors::Error> {
+        let mut builder = TransactionBuilder::new();
+        add_new_item(&mut builder, item_uuid, item, parent);
+        let _ = self.connection.transact_builder(&builder)?;
+        self.notify(Topic::Items, ChangeKind::Created, vec![item_uuid.hyphenated().to_string()]);
+        Ok(())
+    }
+
     pub fn create_and_fetch_item(&mut self, item: &Item) -> Result<Option<Item>, list_errors::Error> {
         let item_uuid = self.create_item(&item)?;
         self.fetch_item(&item_uuid)
     }
 
-    pub fn update_item(&mut self, item: &Item, name: Option<String>, due_date: Option<Timespec>, completion_date: Option<Timespec>, labels: Option<&Vec<Label>>) -> Result<(), list_errors::Error> {
+    /// Makes the changes in `update` to `item` in one transaction.
+    pub fn update_item(&mut self, item: &Item, update: ItemUpdate) -> Result<(), list_errors::Error> {
         let item_id = item.id.to_owned().expect("item must have ID to be updated");
-        let mut transaction = vec![];
+        let mut builder = TransactionBuilder::new();
+        let mut next = None;
+
+        let ItemUpdate { name, due_date, completion_date, labels, notes, priority, starred, recurrence } = update;
+        let due_date = due_date.unwrap_or(item.due_date);
+        let completion_date = completion_date.unwrap_or(item.completion_date);
+        let notes = notes.unwrap_or_else(|| item.notes.clone());
+        let priority = priority.unwrap_or(item.priority);
+        let starred = starred.unwrap_or(item.starred);
+
+        // The item as it's scheduled once this update applies. Completing it moves its
+        // rule on to the next occurrence, leaving the item itself without one.
+        let mut scheduled = item.clone();
+        if let Some(recurrence) = recurrence {
+            scheduled.recurrence = recurrence;
+        }
+        let mut rule = scheduled.recurrence.clone();
 
         if let Some(name) = name {
             if item.name != name {
-                transaction.push(format!("[:db/add {0} :item/name \"{1}\"]", &item_id.id, name));
+                builder.add(item_id.clone(), kw("item", "name"), name.to_typed_value());
             }
         }
         if item.due_date != due_date {
             if let Some(date) = due_date {
-                let micro_seconds = date.sec * 1000000;
-                transaction.push(format!("[:db/add {:?} :item/due_date #instmicros {}]", &item_id.id, &micro_seconds));
+                builder.add(item_id.clone(), kw("item", "due_date"), date_value(&date));
             } else {
-                let micro_seconds = item.due_date.unwrap().sec * 1000000;
-                transaction.push(format!("[:db/retract {:?} :item/due_date #instmicros {}]", &item_id.id, &micro_seconds));
+                builder.retract(item_id.clone(), kw("item", "due_date"), date_value(&item.due_date.unwrap()));
             }
         }
 
         if item.completion_date != completion_date {
             if let Some(date) = completion_date {
-                let micro_seconds = date.sec * 1000000;
-                transaction.push(format!("[:db/add {:?} :item/completion_date #instmicros {}]", &item_id.id, &micro_seconds));
+                builder.add(item_id.clone(), kw("item", "completion_date"), date_value(&date));
+                if item.completion_date.is_none() {
+                    next = self.next_occurrence(&mut builder, &scheduled, date)?;
+                    rule = None;
+                }
+            } else {
+                builder.retract(item_id.clone(), kw("item", "completion_date"), date_value(&item.completion_date.unwrap()));
+            }
+        }
+
+        if item.notes != notes {
+            if let Some(notes) = notes {
+                builder.add(item_id.clone(), kw("item", "notes"), notes.to_typed_value());
+            } else if let Some(ref old) = item.notes {
+                builder.retract(item_id.clone(), kw("item", "notes"), old.to_typed_value());
+            }
+        }
+
+        if item.priority != priority {
+            if let Some(priority) = priority {
+                builder.add(item_id.clone(), kw("item", "priority"), priority.to_typed_value());
+            } else {
+                builder.retract(item_id.clone(), kw("item", "priority"), item.priority.unwrap().to_typed_value());
+            }
+        }
+
+        // Unstarred items have no `:item/starred` datom at all, as with a fresh item.
+        if item.starred != starred {
+            if starred {
+                builder.add(item_id.clone(), kw("item", "starred"), true.to_typed_value());
             } else {
-                let micro_seconds = item.completion_date.unwrap().sec * 1000000;
-                transaction.push(format!("[:db/retract {:?} :item/completion_date #instmicros {}]", &item_id.id, &micro_seconds));
+                builder.retract(item_id.clone(), kw("item", "starred"), true.to_typed_value());
             }
         }
 
-        if let Some(new_labels) = labels {
+        change_recurrence(&mut builder, item_id.clone(), item.recurrence.as_ref(), rule.as_ref());
+
+        if let Some(ref new_labels) = labels {
             let existing_labels = self.fetch_labels_for_item(&(item.uuid)).unwrap_or(vec![]);
 
-            let labels_to_add = new_labels.iter()
-                                        .filter(|label| !existing_labels.contains(label) && label.id.is_some() )
-                                        .map(|label|  format!("{}", label.id.clone().map::<i64, _>(|e| e.into()).unwrap()) )
-                                        .collect::<Vec<String>>()
-                                        .join(", ");
-            if !labels_to_add.is_empty() {
-                transaction.push(format!("[:db/add {0} :item/label [{1}]]", &item_id.id, labels_to_add));
+            for label in new_labels.iter().filter(|label| !existing_labels.contains(label)) {
+                if let Some(ref label_id) = label.id {
+                    builder.add(item_id.clone(), kw("item", "label"), label_id.to_typed_value());
+                }
             }
-            let labels_to_remove = existing_labels.iter()
-                                        .filter(|label| !new_labels.contains(label) && label.id.is_some() )
-                                        .map(|label|  format!("{}", label.id.clone().map::<i64, _>(|e| e.into()).unwrap()) )
-                                        .collect::<Vec<String>>()
-                                        .join(", ");
-            if !labels_to_remove.is_empty() {
-                transaction.push(format!("[:db/retract {0} :item/label [{1}]]", &item_id.id, labels_to_remove));
+            for label in existing_labels.iter().filter(|label| !new_labels.contains(label)) {
+                if let Some(ref label_id) = label.id {
+                    builder.retract(item_id.clone(), kw("item", "label"), label_id.to_typed_value());
+                }
             }
         }
 
-        // TODO: better transact API.
-        let query = format!("[{0}]", transaction.join(""));
+        if builder.is_empty() {
+            return Ok(());
+        }
         self.connection
-            .transact( &query)
-            .map(|_| ())
-            .map_err(|e| e.into())
+            .transact_builder(&builder)?;
+        self.notify(Topic::Items, ChangeKind::Updated, vec![item.uuid.hyphenated().to_string()]);
+        self.notify_next_occurrences(next);
+        Ok(())
+    }
+
+    /// Deletes an item. Its subtasks move up to its own parent; use `delete_item_with` to
+    /// delete them too.
+    pub fn delete_item(&mut self, uuid: &Uuid) -> Result<(), list_errors::Error> {
+        self.delete_item_with(uuid, Cascade::ItemOnly)
+    }
+
+    /// Adds the retractions that delete all of `item` but its parent to `builder`.
+    fn retract_item(&self, builder: &mut TransactionBuilder, item: &Item) {
+        let item_id = item.id.to_owned().expect("fetched item must have an ID");
+        builder.retract(item_id.clone(), kw("item", "uuid"), item.uuid.to_typed_value())
+               .retract(item_id.clone(), kw("item", "name"), item.name.to_typed_value());
+        if let Some(date) = item.due_date {
+            builder.retract(item_id.clone(), kw("item", "due_date"), date_value(&date));
+        }
+        if let Some(date) = item.completion_date {
+            builder.retract(item_id.clone(), kw("item", "completion_date"), date_value(&date));
+        }
+        if let Some(ref notes) = item.notes {
+            builder.retract(item_id.clone(), kw("item", "notes"), notes.to_typed_value());
+        }
+        if let Some(priority) = item.priority {
+            builder.retract(item_id.clone(), kw("item", "priority"), priority.to_typed_value());
+        }
+        if item.starred {
+            builder.retract(item_id.clone(), kw("item", "starred"), true.to_typed_value());
+        }
+        if let Some(ref recurrence) = item.recurrence {
+            builder.retract(item_id.clone(), kw("item", "recurrence"), recurrence.to_string().to_typed_value());
+        }
+        for label_id in item.labels.iter().filter_map(|label| label.id.as_ref()) {
+            builder.retract(item_id.clone(), kw("item", "label"), label_id.to_typed_value());
+        }
+    }
+
+    /// For an item that's being completed at `completion_date`: if it's the current
+    /// occurrence of a recurring item, adds the retraction of its schedule and the
+    /// occurrence that takes the schedule over to `builder`, and returns that occurrence's
+    /// uuid.
+    fn complete_recurrence(&self, builder: &mut TransactionBuilder, item: &Item, completion_date: Timespec) -> Result<Option<Uuid>, list_errors::Error> {
+        if item.completion_date.is_some() {
+            return Ok(None);
+        }
+        let item_id = item.id.to_owned().expect("fetched item must have an ID");
+        change_recurrence(builder, item_id, item.recurrence.as_ref(), None);
+        self.next_occurrence(builder, item, completion_date)
+    }
+
+    /// Adds the occurrence that follows `item` when it's completed at `completion_date` to
+    /// `builder`, and returns its uuid. Nothing is added if another store completed the
+    /// same occurrence and its successor has synced here already.
+    fn next_occurrence(&self, builder: &mut TransactionBuilder, item: &Item, completion_date: Timespec) -> Result<Option<Uuid>, list_errors::Error> {
+        let recurrence = match item.recurrence.as_ref() {
+            Some(recurrence) => recurrence,
+            None => return Ok(None),
+        };
+        let start = item.due_date.unwrap_or(completion_date);
+        let (due_date, recurrence) = match recurrence.next_after(start, cmp::max(start, self.clock.now())) {
+            Some(next) => next,
+            None => return Ok(None),
+        };
+        let uuid = occurrence_uuid(&item.uuid, due_date);
+        if self.fetch_item(&uuid)?.is_some() {
+            return Ok(None);
+        }
+        let parent = self.fetch_parent(&item.uuid)?.and_then(|parent| parent.id).map(|parent| parent.id);
+        add_new_item(builder, uuid, &Item {
+            id: None,
+            uuid: uuid,
+            name: item.name.clone(),
+            due_date: Some(due_date),
+            completion_date: None,
+            labels: item.labels.clone(),
+            notes: item.notes.clone(),
+            priority: item.priority,
+            starred: item.starred,
+            recurrence: Some(recurrence),
+        }, parent);
+        Ok(Some(uuid))
+    }
+
+    fn notify_next_occurrences<I>(&self, next: I) where I: IntoIterator<Item=Uuid> {
+        let created: Vec<String> = next.into_iter().map(|uuid| uuid.hyphenated().to_string()).collect();
+        if !created.is_empty() {
+            self.notify(Topic::Items, ChangeKind::Created, created);
+        }
     }
 }
 
-#[no_mangle]
-pub extern "C" fn new_toodle(uri: *const c_char) -> *mut Toodle {
-    let uri = c_char_to_string(uri);
-    let toodle = Toodle::new(uri).expect("expected a toodle");
-    Box::into_raw(Box::new(toodle))
+/// Adds `item` to `builder` as the new item `item_uuid`, with its labels, and under
+/// `parent` if it has one.
+fn add_new_item(builder: &mut TransactionBuilder, item_uuid: Uuid, item: &Item, parent: Option<Entid>) {
+    let entity = builder.tempid();
+    add_item(builder, &entity, item_uuid, item);
+    if let Some(parent) = parent {
+        builder.add(entity.clone(), kw("item", "parent"), TypedValue::Ref(parent));
+    }
+    for label_id in item.labels.iter().filter_map(|label| label.id.as_ref()) {
+        builder.add(entity.clone(), kw("item", "label"), label_id.to_typed_value());
+    }
+}
+
+/// Adds everything about `item` but its labels and parent to `builder`, as the item
+/// `item_uuid`.
+fn add_item(builder: &mut TransactionBuilder, entity: &EntityPlace, item_uuid: Uuid, item: &Item) {
+    builder.add(entity.clone(), kw("item", "uuid"), item_uuid.to_typed_value())
+           .add(entity.clone(), kw("item", "name"), item.name.to_typed_value());
+    if let Some(due_date) = item.due_date {
+        builder.add(entity.clone(), kw("item", "due_date"), date_value(&due_date));
+    }
+    if let Some(completion_date) = item.completion_date {
+        builder.add(entity.clone(), kw("item", "completion_date"), date_value(&completion_date));
+    }
+    if let Some(ref notes) = item.notes {
+        builder.add(entity.clone(), kw("item", "notes"), notes.to_typed_value());
+    }
+    if let Some(priority) = item.priority {
+        builder.add(entity.clone(), kw("item", "priority"), priority.to_typed_value());
+    }
+    if item.starred {
+        builder.add(entity.clone(), kw("item", "starred"), true.to_typed_value());
+    }
+    if let Some(ref recurrence) = item.recurrence {
+        builder.add(entity.clone(), kw("item", "recurrence"), recurrence.to_string().to_typed_value());
+    }
+}
+
+/// Adds what replaces the recurrence rule `old` with `new` to `builder`.
+fn change_recurrence(builder: &mut TransactionBuilder, item_id: Entity, old: Option<&Recurrence>, new: Option<&Recurrence>) {
+    if old == new {
+        return;
+    }
+    match (new, old) {
+        (Some(new), _) => builder.add(item_id, kw("item", "recurrence"), new.to_string().to_typed_value()),
+        (None, Some(old)) => builder.retract(item_id, kw("item", "recurrence"), old.to_string().to_typed_value()),
+        (None, None) => return,
+    };
+}
+
+fn parse_uuid_str(uuid: &str) -> Result<Uuid, list_errors::Error> {
+    Uuid::from_str(uuid).map_err(|_| ErrorKind::InvalidArgument(format!("{:?} is not a uuid", uuid)).into())
+}
+
+fn parse_uuid(uuid: *const c_char) -> Result<Uuid, list_errors::Error> {
+    parse_uuid_str(&checked_string(uuid, "uuid")?)
+}
+
+thread_local! {
+    // Changes for observers registered over the FFI. They're queued while the Toodle that
+    // made them is locked and delivered once it's been released, so that callbacks are
+    // free to call back into the library.
+    static PENDING_CHANGES: RefCell<Vec<Box<Fn()>>> = RefCell::new(vec![]);
+}
+
+fn deliver_pending_changes() {
+    loop {
+        let pending = PENDING_CHANGES.with(|pending| mem::replace(&mut *pending.borrow_mut(), vec![]));
+        if pending.is_empty() {
+            return;
+        }
+        for deliver in pending {
+            deliver();
+        }
+    }
+}
+
+/// Runs `callback` against the Toodle for `handle`, then tells FFI observers about
+/// whatever it changed.
+fn with_toodle_handle<R, F>(handle: u64, callback: F) -> Result<R, list_errors::Error>
+    where F: FnOnce(&mut Toodle) -> Result<R, list_errors::Error> {
+    let result = TOODLES.get_mut(handle, callback);
+    deliver_pending_changes();
+    result
+}
+
+/// Like `with_toodle_handle`, reporting failures in `error`.
+fn with_toodle<R, F>(handle: u64, error: *mut ExternError, callback: F) -> R
+    where F: FnOnce(&mut Toodle) -> Result<R, list_errors::Error>, R: FailureValue {
+    call_with_result(error, || with_toodle_handle(handle, callback))
 }
 
+/// `key` may be null, for a store that isn't encrypted.
 #[no_mangle]
-pub unsafe extern "C" fn toodle_destroy(toodle: *mut Toodle) {
-    let _ = Box::from_raw(toodle);
+pub extern "C" fn new_toodle(uri: *const c_char, key: *const c_char, error: *mut ExternError) -> u64 {
+    call_with_result(error, || {
+        let uri = checked_string(uri, "uri")?;
+        let key = if key.is_null() { None } else { Some(c_char_to_string(key)) };
+        let toodle = Toodle::new(uri, key.as_ref().map(|key| key.as_str()))?;
+        Ok(TOODLES.insert(toodle))
+    })
 }
 
+/// Only stores that were opened with a key can be re-keyed.
 #[no_mangle]
-pub unsafe extern "C" fn toodle_get_all_labels(manager: *const Toodle) -> *mut Vec<Label> {
-    let manager = &*manager;
-    let label_list = Box::new(manager.fetch_labels().unwrap_or(vec![]));
-    Box::into_raw(label_list)
+pub extern "C" fn toodle_rekey(toodle: u64, key: *const c_char, error: *mut ExternError) {
+    with_toodle(toodle, error, |toodle| {
+        let key = checked_string(key, "key")?;
+        toodle.rekey(&key)
+    })
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn toodle_create_item(manager: *mut Toodle, name: *const c_char, due_date: *const time_t) -> *mut ItemC {
-    let name = c_char_to_string(name);
-    log::d(&format!("Creating item: {:?}, {:?}, {:?}", name, due_date, manager)[..]);
-
-    let manager = &mut*manager;
-    let mut item = Item::default();
-
-    item.name = name;
-    let due: Option<Timespec>;
-    if !due_date.is_null() {
-        let due_date = *due_date as i64;
-        due = Some(Timespec::new(due_date, 0));
-    } else {
-        due = None;
-    }
-    item.due_date = due;
-    let item = manager.create_and_fetch_item(&item).expect("expected an item");
-    if let Some(callback) = CHANGED_CALLBACK {
-        callback();
-    }
-    if let Some(i) = item {
-        return Box::into_raw(Box::new(i.into()));
-    }
-    return std::ptr::null_mut();
+pub extern "C" fn toodle_destroy(toodle: u64, error: *mut ExternError) {
+    call_with_result(error, || TOODLES.remove(toodle).map(|_| ()))
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn toodle_on_items_changed(callback: extern fn()) {
-    CHANGED_CALLBACK = Some(callback);
-    callback();
+pub extern "C" fn toodle_get_all_labels(toodle: u64, error: *mut ExternError) -> u64 {
+    with_toodle(toodle, error, |toodle| Ok(LABEL_LISTS.insert(toodle.fetch_labels()?)))
 }
 
-// TODO: figure out callbacks in swift such that we can use `toodle_all_items` instead.
+/// Returns a handle to the new item, to be released with `item_destroy`.
 #[no_mangle]
-pub unsafe extern "C" fn toodle_get_all_items(manager: *mut Toodle) -> *mut ItemCList {
-    let manager = &mut *manager;
-    let items: ItemsC = manager.fetch_items().map(|item| item.into()).expect("all items");
-    let count = items.vec.len();
-    let item_list = ItemCList {
-        items: items.vec.into_boxed_slice(),
-        len: count,
-    };
+pub extern "C" fn toodle_create_item(toodle: u64, name: *const c_char, due_date: *const time_t, error: *mut ExternError) -> u64 {
+    with_toodle(toodle, error, |toodle| {
+        let name = checked_string(name, "name")?;
+        log::d(&format!("Creating item: {:?}, {:?}", name, due_date)[..]);
+        let mut item = Item::default();
+        item.name = name;
+        item.due_date = optional_timespec(due_date);
+        match toodle.create_and_fetch_item(&item)? {
+            Some(item) => Ok(ITEMS.insert(item)),
+            None => bail!(ErrorKind::NotFound("the item that was just created".to_string())),
+        }
+    })
+}
+
+/// Called with the topic, the kind of change and the ids of the changed items (uuids)
+/// or labels (names). `ids` is only valid until the callback returns.
+pub type ChangedCallback = extern "C" fn(context: *mut c_void, topic: c_int, kind: c_int, ids: *const *const c_char, count: c_int);
 
-    Box::into_raw(Box::new(item_list))
+/// `topics` is a bitmask of `Topic`s. `context` is passed back to `callback` untouched.
+/// `callback` is called once the call that made the change is about to return.
+#[no_mangle]
+pub extern "C" fn toodle_register_observer(toodle: u64, topics: c_int, context: *mut c_void, callback: ChangedCallback, error: *mut ExternError) -> u64 {
+    with_toodle(toodle, error, |toodle| {
+        let topics: Vec<Topic> = [Topic::Items, Topic::Labels].iter()
+                                                               .filter(|topic| topics & (**topic as c_int) != 0)
+                                                               .cloned()
+                                                               .collect();
+        let token = toodle.register_observer(&topics, move |change| {
+            let change = change.clone();
+            PENDING_CHANGES.with(|pending| pending.borrow_mut().push(Box::new(move || {
+                let ids: Vec<CString> = change.ids.iter().filter_map(|id| CString::new(id.as_str()).ok()).collect();
+                let pointers: Vec<*const c_char> = ids.iter().map(|id| id.as_ptr()).collect();
+                callback(context, change.topic as c_int, change.kind as c_int, pointers.as_ptr(), pointers.len() as c_int);
+            })));
+        });
+        Ok(token.0)
+    })
 }
 
+/// Returns 1 if `token` was registered with `toodle`, and 0 otherwise.
 #[no_mangle]
-pub unsafe extern "C" fn item_list_entry_at(item_c_list: *mut ItemCList, index: c_int) -> *const ItemC {
-    let item_c_list = &*item_c_list;
-    let index = index as usize;
-    let item = Box::new(item_c_list.items[index].clone());
-    Box::into_raw(item)
+pub extern "C" fn toodle_unregister_observer(toodle: u64, token: u64, error: *mut ExternError) -> c_int {
+    with_toodle(toodle, error, |toodle| Ok(toodle.unregister_observer(ObserverToken(token)) as c_int))
 }
 
+// TODO: figure out callbacks in swift such that we can use `toodle_all_items` instead.
 #[no_mangle]
-pub unsafe extern "C" fn item_list_count(item_list: *mut ItemCList) -> c_int {
-    let item_list = &*item_list;
-    item_list.len as c_int
+pub extern "C" fn toodle_get_all_items(toodle: u64, error: *mut ExternError) -> u64 {
+    with_toodle(toodle, error, |toodle| Ok(ITEM_LISTS.insert(toodle.fetch_items()?.vec)))
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn toodle_all_items(manager: *mut Toodle, callback: extern "C" fn(Option<&ItemCList>)) {
-    let manager = &*manager;
-    let items: ItemsC = manager.fetch_items().map(|item| item.into()).expect("all items");
+pub extern "C" fn toodle_search_items(toodle: u64, query: *const c_char, error: *mut ExternError) -> u64 {
+    with_toodle(toodle, error, |toodle| {
+        let query = checked_string(query, "query")?;
+        Ok(ITEM_LISTS.insert(toodle.search_items(&query)?))
+    })
+}
 
-    // TODO there's bound to be a better way. Ideally this should just return an empty set,
-    // but I ran into problems while doing that.
-    let count = items.vec.len();
+/// Returns a copy of the item at `index`, to be freed with `item_c_destroy`.
+#[no_mangle]
+pub extern "C" fn item_list_entry_at(item_list: u64, index: c_int, error: *mut ExternError) -> *mut ItemC {
+    call_with_result(error, || {
+        ITEM_LISTS.get(item_list, |items| {
+            match items.get(index as usize) {
+                Some(item) => Ok(Box::into_raw(Box::new(item.clone().into()))),
+                None => bail!(ErrorKind::InvalidArgument(format!("index {} is out of bounds", index))),
+            }
+        })
+    })
+}
 
-    let set = ItemCList {
-        items: items.vec.into_boxed_slice(),
-        len: count,
-    };
+/// Returns a handle to a copy of the item at `index`, to be released with `item_destroy`.
+#[no_mangle]
+pub extern "C" fn item_list_item_at(item_list: u64, index: c_int, error: *mut ExternError) -> u64 {
+    call_with_result(error, || {
+        let item = ITEM_LISTS.get(item_list, |items| {
+            match items.get(index as usize) {
+                Some(item) => Ok(item.clone()),
+                None => bail!(ErrorKind::InvalidArgument(format!("index {} is out of bounds", index))),
+            }
+        })?;
+        Ok(ITEMS.insert(item))
+    })
+}
 
-    let res = match count > 0 {
-        // NB: we're lending a set, it will be cleaned up automatically once 'callback' returns
-        true => Some(&set),
-        false => None
-    };
+#[no_mangle]
+pub extern "C" fn item_list_count(item_list: u64, error: *mut ExternError) -> c_int {
+    call_with_result(error, || ITEM_LISTS.get(item_list, |items| Ok(items.len() as c_int)))
+}
 
-    callback(res);
+#[no_mangle]
+pub extern "C" fn item_list_destroy(item_list: u64, error: *mut ExternError) {
+    call_with_result(error, || ITEM_LISTS.remove(item_list).map(|_| ()))
 }
 
+/// Lends `callback` a list of every item. The list is released once `callback` returns.
+#[no_mangle]
+pub extern "C" fn toodle_all_items(toodle: u64, callback: extern "C" fn(u64), error: *mut ExternError) {
+    call_with_result(error, || {
+        let items = TOODLES.get(toodle, |toodle| Ok(toodle.fetch_items()?.vec))?;
+        let item_list = ITEM_LISTS.insert(items);
+        callback(item_list);
+        ITEM_LISTS.remove(item_list).map(|_| ())
+    })
+}
 
-// TODO this is pretty crafty... Currently this setup means that ItemJNA could only be used
-// together with something like toodle_all_items - a function that will clear up ItemJNA itself.
 #[no_mangle]
-pub unsafe extern "C" fn item_c_destroy(item: *mut ItemC) -> *mut ItemC {
+pub unsafe extern "C" fn item_c_destroy(item: *mut ItemC) {
+    if item.is_null() {
+        return;
+    }
     let item = Box::from_raw(item);
-
-    // Reclaim our strings and let Rust clear up their memory.
     let _ = CString::from_raw(item.uuid);
     let _ = CString::from_raw(item.name);
+    if !item.due_date.is_null() {
+        let _ = Box::from_raw(item.due_date);
+    }
+    if !item.completion_date.is_null() {
+        let _ = Box::from_raw(item.completion_date);
+    }
+    if !item.notes.is_null() {
+        let _ = CString::from_raw(item.notes);
+    }
+    if !item.priority.is_null() {
+        let _ = Box::from_raw(item.priority);
+    }
+}
 
-    // Prevent Rust from clearing out item itself. It's already managed by toodle_all_items.
-    // If we'll let Rust clean up entirely here, we'll get an NPE in toodle_all_items.
-    Box::into_raw(item)
+#[no_mangle]
+pub extern "C" fn toodle_update_item(toodle: u64, item: u64, name: *const c_char, due_date: *const time_t, completion_date: *const time_t, labels: u64, error: *mut ExternError) {
+    with_toodle(toodle, error, |toodle| {
+        let name = checked_string(name, "name")?;
+        let item = ITEMS.get(item, |item| Ok(item.clone()))?;
+        let labels = LABEL_LISTS.get(labels, |labels| Ok(labels.clone()))?;
+        // The handle carries the new notes, priority and starred flag (see `item_set_notes`
+        // and friends), so changes are worked out against what's stored.
+        let stored = match toodle.fetch_item(&item.uuid)? {
+            Some(stored) => stored,
+            None => bail!(ErrorKind::NotFound(format!("item {}", item.uuid.hyphenated()))),
+        };
+        toodle.update_item(&stored, ItemUpdate {
+            name: Some(name),
+            due_date: Some(optional_timespec(due_date)),
+            completion_date: Some(optional_timespec(completion_date)),
+            labels: Some(labels),
+            notes: Some(item.notes.clone()),
+            priority: Some(item.priority),
+            starred: Some(item.starred),
+            recurrence: None,
+        })
+    })
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn toodle_update_item(manager: *mut Toodle, item: *const Item, name: *const c_char, due_date: *const time_t, completion_date: *const time_t, labels: *const Vec<Label>) {
-    let manager = &mut*manager;
-    let item = &*item;
-    let labels = &*labels;
-    let _ = manager.update_item(
-        &item,
-        Some(c_char_to_string(name)),
-        optional_timespec(due_date),
-        optional_timespec(completion_date),
-        Some(&labels)
-    );
+pub extern "C" fn toodle_update_item_by_uuid(toodle: u64, uuid: *const c_char, name: *const c_char, due_date: *const time_t, completion_date: *const time_t, error: *mut ExternError) {
+    with_toodle(toodle, error, |toodle| {
+        let uuid = parse_uuid(uuid)?;
+        let name = checked_string(name, "name")?;
+        let item = match toodle.fetch_item(&uuid)? {
+            Some(item) => item,
+            None => bail!(ErrorKind::NotFound(format!("item {}", uuid.hyphenated()))),
+        };
+        toodle.update_item(&item, ItemUpdate {
+            name: Some(name),
+            due_date: Some(optional_timespec(due_date)),
+            completion_date: Some(optional_timespec(completion_date)),
+            ..ItemUpdate::default()
+        })
+    })
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn toodle_update_item_by_uuid(manager: *mut Toodle, uuid: *const c_char, name: *const c_char, due_date: *const time_t, completion_date: *const time_t) {
-    let manager = &mut*manager;
-    // TODO proper error handling, see https://github.com/mozilla-prototypes/sync-storage-prototype/pull/6
-    let item = manager.fetch_item(
-        &Uuid::from_str(c_char_to_string(uuid).as_str()).expect("parsed uuid")
-    ).expect("item from uuid").unwrap();
-    let _ = manager.update_item(
-        &item,
-        Some(c_char_to_string(name)),
-        optional_timespec(due_date),
-        optional_timespec(completion_date),
-        Some(&item.labels)
-    );
+pub extern "C" fn toodle_delete_item_by_uuid(toodle: u64, uuid: *const c_char, error: *mut ExternError) {
+    with_toodle(toodle, error, |toodle| {
+        let uuid = parse_uuid(uuid)?;
+        toodle.delete_item(&uuid)
+    })
+}
 
-    if let Some(callback) = CHANGED_CALLBACK {
-        callback();
-    }
+#[no_mangle]
+pub extern "C" fn toodle_create_label(toodle: u64, name: *const c_char, color: *const c_char, error: *mut ExternError) -> u64 {
+    with_toodle(toodle, error, |toodle| {
+        let name = checked_string(name, "name")?;
+        let color = checked_string(color, "color")?;
+        match toodle.create_label(name.clone(), color)? {
+            Some(label) => Ok(LABELS.insert(label)),
+            None => bail!(ErrorKind::NotFound(format!("label {:?}", name))),
+        }
+    })
 }
 
+/// Deletes the label from the store. `label` stays valid until it's destroyed.
 #[no_mangle]
-pub unsafe extern "C" fn toodle_create_label(manager: *mut Toodle, name: *const c_char, color: *const c_char) -> *mut Option<Label> {
-    let manager = &mut*manager;
-    let name = c_char_to_string(name);
-    let color = c_char_to_string(color);
-    let label = Box::new(manager.create_label(name, color).unwrap_or(None));
-    Box::into_raw(label)
+pub extern "C" fn toodle_delete_label(toodle: u64, label: u64, error: *mut ExternError) {
+    with_toodle(toodle, error, |toodle| {
+        let label = LABELS.get(label, |label| Ok(label.clone()))?;
+        toodle.delete_label(&label)
+    })
 }
 
+#[no_mangle]
+pub extern "C" fn toodle_rename_label(toodle: u64, label: u64, new_name: *const c_char, error: *mut ExternError) {
+    with_toodle(toodle, error, |toodle| {
+        let new_name = checked_string(new_name, "new_name")?;
+        LABELS.get_mut(label, |label| {
+            match toodle.rename_label(&label, new_name)? {
+                Some(renamed) => label.name = renamed.name.clone(),
+                None => bail!(ErrorKind::NotFound(format!("label {:?}", label.name))),
+            }
+            Ok(())
+        })
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn toodle_update_label_color(toodle: u64, label: u64, color: *const c_char, error: *mut ExternError) {
+    with_toodle(toodle, error, |toodle| {
+        let color = checked_string(color, "color")?;
+        LABELS.get_mut(label, |label| {
+            match toodle.update_label_color(&label, color)? {
+                Some(updated) => label.color = updated.color.clone(),
+                None => bail!(ErrorKind::NotFound(format!("label {:?}", label.name))),
+            }
+            Ok(())
+        })
+    })
+}
 
 #[cfg(test)]
 mod test {
     extern crate edn;
+    extern crate header_gen;
 
     use super::{
         Store,
@@ -549,16 +1252,90 @@ mod test {
         Toodle,
         Label,
         Item,
+        ItemUpdate,
         create_uuid,
+        item_c_destroy,
+        item_list_count,
+        item_list_destroy,
+        item_list_entry_at,
+        item_list_item_at,
+        toodle_all_items,
+        toodle_create_item,
+        toodle_create_label,
+        toodle_delete_item_by_uuid,
+        toodle_destroy,
+        toodle_get_all_items,
+        toodle_register_observer,
+        toodle_rekey,
+        toodle_unregister_observer,
+        toodle_update_item_by_uuid,
     };
 
+    use std::cell::{
+        Cell,
+        RefCell,
+    };
+    use std::env;
+    use std::ffi::{
+        CStr,
+        CString,
+    };
+    use std::fs;
+    use std::os::raw::{
+        c_char,
+        c_int,
+        c_void,
+    };
+    use std::path::PathBuf;
+    use std::process;
+    use std::ptr;
+    use std::rc::Rc;
     use std::sync::Arc;
+    use std::sync::atomic::{
+        AtomicUsize,
+        Ordering,
+    };
 
     use mentat_core::Uuid;
-    use time::now_utc;
+    use time::{
+        now_utc,
+        Timespec,
+    };
+
+    use clock::FixedClock;
+    use ffi_errors::{
+        ErrorCode,
+        ExternError,
+        toodle_error_destroy,
+    };
+    use observers::{
+        Change,
+        ChangeKind,
+        Topic,
+    };
+    use filters::{
+        ItemFilter,
+        SortDirection,
+        SortKey,
+    };
+    use handles::TOODLES;
+    use items::{
+        item_destroy,
+        item_get_name,
+    };
 
     fn toodle() -> Toodle {
-        Toodle::new(String::new()).expect("Expected a Toodle")
+        Toodle::new(String::new(), None).expect("Expected a Toodle")
+    }
+
+    /// A path in a directory of its own, for tests that need a store on disk: tests running
+    /// at once, here or in another checkout, can't share one.
+    pub fn temp_path(name: &str) -> PathBuf {
+        static NEXT_DIRECTORY: AtomicUsize = AtomicUsize::new(0);
+        let directory = env::temp_dir().join(format!("toodle-{}-{}", process::id(), NEXT_DIRECTORY.fetch_add(1, Ordering::SeqCst)));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).expect("expected a temporary directory");
+        directory.join(name)
     }
 
     fn assert_ident_present(edn: edn::Value, namespace: &str, name: &str) -> bool {
@@ -653,6 +1430,10 @@ mod test {
             name: "test item".to_string(),
             due_date: Some(date.clone()),
             completion_date: Some(date.clone()),
+            notes: None,
+            priority: None,
+            starred: false,
+            recurrence: None,
             labels: vec![label, label2]
         };
 
@@ -690,6 +1471,10 @@ mod test {
             name: "test item".to_string(),
             due_date: None,
             completion_date: Some(date.clone()),
+            notes: None,
+            priority: None,
+            starred: false,
+            recurrence: None,
             labels: vec![label, label2]
         };
 
@@ -726,6 +1511,10 @@ mod test {
             name: "test item".to_string(),
             due_date: Some(date.clone()),
             completion_date: None,
+            notes: None,
+            priority: None,
+            starred: false,
+            recurrence: None,
             labels: vec![label, label2]
         };
 
@@ -748,6 +1537,10 @@ mod test {
             name: "test item".to_string(),
             due_date: None,
             completion_date: None,
+            notes: None,
+            priority: None,
+            starred: false,
+            recurrence: None,
             labels: vec![label]
         };
 
@@ -778,6 +1571,10 @@ mod test {
             name: "test item 1".to_string(),
             due_date: None,
             completion_date: None,
+            notes: None,
+            priority: None,
+            starred: false,
+            recurrence: None,
             labels: vec![label, label2, label3]
         };
 
@@ -799,6 +1596,10 @@ mod test {
             name: "test item 1".to_string(),
             due_date: None,
             completion_date: None,
+            notes: None,
+            priority: None,
+            starred: false,
+            recurrence: None,
             labels: vec![label.clone()]
         };
         let item2 = Item {
@@ -807,6 +1608,10 @@ mod test {
             name: "test item 2".to_string(),
             due_date: None,
             completion_date: None,
+            notes: None,
+            priority: None,
+            starred: false,
+            recurrence: None,
             labels: vec![label.clone()]
         };
         let item3 = Item {
@@ -815,6 +1620,10 @@ mod test {
             name: "test item 3".to_string(),
             due_date: None,
             completion_date: None,
+            notes: None,
+            priority: None,
+            starred: false,
+            recurrence: None,
             labels: vec![label.clone(), label2.clone()]
         };
 
@@ -824,6 +1633,10 @@ mod test {
             name: "test item 4".to_string(),
             due_date: None,
             completion_date: None,
+            notes: None,
+            priority: None,
+            starred: false,
+            recurrence: None,
             labels: vec![label2.clone()]
         };
 
@@ -851,6 +1664,10 @@ mod test {
             name: "test item 1".to_string(),
             due_date: None,
             completion_date: None,
+            notes: None,
+            priority: None,
+            starred: false,
+            recurrence: None,
             labels: vec![label, label2]
         };
 
@@ -858,7 +1675,7 @@ mod test {
         let mut new_labels = item1.labels.clone();
         new_labels.push(label3);
 
-        match manager.update_item(&created_item, None, None, None, Some(&new_labels)) {
+        match manager.update_item(&created_item, ItemUpdate { labels: Some(new_labels.clone()), ..ItemUpdate::default() }) {
             Ok(()) => (),
             Err(e) => {
                 println!("e {:?}", e);
@@ -885,6 +1702,10 @@ mod test {
             name: "test item 1".to_string(),
             due_date: None,
             completion_date: None,
+            notes: None,
+            priority: None,
+            starred: false,
+            recurrence: None,
             labels: vec![label, label2, label3]
         };
 
@@ -892,7 +1713,7 @@ mod test {
         let mut new_labels = created_item.labels.clone();
         new_labels.remove(2);
 
-        match manager.update_item(&created_item, None, None, None, Some(&new_labels)) {
+        match manager.update_item(&created_item, ItemUpdate { labels: Some(new_labels.clone()), ..ItemUpdate::default() }) {
             Ok(()) => (),
             Err(e) => {
                 println!("e {:?}", e);
@@ -920,12 +1741,16 @@ mod test {
             name: "test item 1".to_string(),
             due_date: None,
             completion_date: None,
+            notes: None,
+            priority: None,
+            starred: false,
+            recurrence: None,
             labels: vec![label, label2, label3]
         };
 
         let created_item = manager.create_and_fetch_item(&item1).expect("expected an item option").expect("expected an item");
 
-        match manager.update_item(&created_item, None, Some(date), None, None) {
+        match manager.update_item(&created_item, ItemUpdate { due_date: Some(Some(date)), ..ItemUpdate::default() }) {
             Ok(()) => (),
             Err(e) => {
                 println!("e {:?}", e);
@@ -952,11 +1777,15 @@ mod test {
             name: "test item 1".to_string(),
             due_date: Some(date),
             completion_date: None,
+            notes: None,
+            priority: None,
+            starred: false,
+            recurrence: None,
             labels: vec![label, label2, label3]
         };
 
         let mut created_item = manager.create_and_fetch_item(&item1).expect("expected an item option").expect("expected an item");
-        match manager.update_item(&created_item, Some("new name".to_string()), None, None, None) {
+        match manager.update_item(&created_item, ItemUpdate { name: Some("new name".to_string()), ..ItemUpdate::default() }) {
             Ok(()) => (),
             Err(e) => {
                 println!("e {:?}", e);
@@ -984,11 +1813,15 @@ mod test {
             name: "test item 1".to_string(),
             due_date: None,
             completion_date: None,
+            notes: None,
+            priority: None,
+            starred: false,
+            recurrence: None,
             labels: vec![label, label2, label3]
         };
 
         let created_item = manager.create_and_fetch_item(&item1).expect("expected an item option").expect("expected an item");
-        match manager.update_item(&created_item, None, None, Some(date), None) {
+        match manager.update_item(&created_item, ItemUpdate { completion_date: Some(Some(date)), ..ItemUpdate::default() }) {
             Ok(()) => (),
             Err(e) => {
                 println!("e {:?}", e);
@@ -1000,4 +1833,688 @@ mod test {
         let completion_date = fetched_item.completion_date.expect("expected a completion_date");
         assert_eq!(completion_date.sec, date.sec);
     }
+
+    #[test]
+    fn test_update_item_notes_priority_and_star() {
+        let mut manager = toodle();
+        let item = Item {
+            id: None,
+            uuid: Uuid::nil(),
+            name: "test item".to_string(),
+            due_date: None,
+            completion_date: None,
+            notes: Some("first draft".to_string()),
+            priority: None,
+            starred: false,
+            recurrence: None,
+            labels: vec![]
+        };
+        let created_item = manager.create_and_fetch_item(&item).expect("expected an item option").expect("expected an item");
+        assert_eq!(created_item.notes, Some("first draft".to_string()));
+
+        manager.update_item(&created_item, ItemUpdate {
+            notes: Some(Some("second draft".to_string())),
+            priority: Some(Some(3)),
+            starred: Some(true),
+            ..ItemUpdate::default()
+        }).expect("expected an update");
+        let fetched_item = manager.fetch_item(&created_item.uuid).expect("expected an item option").expect("expected an item");
+        assert_eq!(fetched_item.notes, Some("second draft".to_string()));
+        assert_eq!(fetched_item.priority, Some(3));
+        assert!(fetched_item.starred);
+
+        // Fields that aren't in the update are left alone.
+        manager.update_item(&fetched_item, ItemUpdate { name: Some("renamed".to_string()), ..ItemUpdate::default() }).expect("expected an update");
+        let fetched_item = manager.fetch_item(&created_item.uuid).expect("expected an item option").expect("expected an item");
+        assert_eq!(fetched_item.name, "renamed");
+        assert_eq!(fetched_item.notes, Some("second draft".to_string()));
+        assert_eq!(fetched_item.priority, Some(3));
+        assert!(fetched_item.starred);
+
+        manager.update_item(&fetched_item, ItemUpdate {
+            notes: Some(None),
+            priority: Some(None),
+            starred: Some(false),
+            ..ItemUpdate::default()
+        }).expect("expected an update");
+        let fetched_item = manager.fetch_item(&created_item.uuid).expect("expected an item option").expect("expected an item");
+        assert_eq!(fetched_item.notes, None);
+        assert_eq!(fetched_item.priority, None);
+        assert!(!fetched_item.starred);
+
+        manager.delete_item(&created_item.uuid).expect("expected a deletion");
+        assert_eq!(manager.fetch_items().expect("expected items").vec, vec![]);
+    }
+
+    #[test]
+    fn test_delete_item() {
+        let mut manager = toodle();
+        let label = manager.create_label("label1".to_string(), "#000000".to_string()).expect("expected a label option").unwrap();
+
+        let date = now_utc().to_timespec();
+        let item1 = Item {
+            id: None,
+            uuid: Uuid::nil(),
+            name: "test item 1".to_string(),
+            due_date: Some(date),
+            completion_date: Some(date),
+            notes: None,
+            priority: None,
+            starred: false,
+            recurrence: None,
+            labels: vec![label.clone()]
+        };
+        let item2 = Item {
+            id: None,
+            uuid: Uuid::nil(),
+            name: "test item 2".to_string(),
+            due_date: None,
+            completion_date: None,
+            notes: None,
+            priority: None,
+            starred: false,
+            recurrence: None,
+            labels: vec![label.clone()]
+        };
+
+        let item1 = manager.create_and_fetch_item(&item1).expect("expected an item option").expect("expected item1");
+        let item2 = manager.create_and_fetch_item(&item2).expect("expected an item option").expect("expected item2");
+
+        match manager.delete_item(&item1.uuid) {
+            Ok(()) => (),
+            Err(e) => {
+                println!("e {:?}", e);
+                assert!(false)
+            }
+        }
+
+        let fetched_item = manager.fetch_item(&item1.uuid).expect("expected an item option");
+        assert_eq!(fetched_item, None);
+        let fetched_items = manager.fetch_items().expect("expected items");
+        assert_eq!(fetched_items.vec, vec![item2.clone()]);
+        let fetched_label_items = manager.fetch_items_with_label(&label).expect("expected a vector of items");
+        assert_eq!(fetched_label_items, vec![item2]);
+        let fetched_labels = manager.fetch_labels_for_item(&item1.uuid).expect("expected a vector of labels");
+        assert_eq!(fetched_labels, vec![]);
+    }
+
+    #[test]
+    fn test_delete_item_not_present() {
+        let mut manager = toodle();
+        assert!(manager.delete_item(&create_uuid()).is_ok());
+    }
+
+    #[test]
+    fn test_rename_label() {
+        let mut manager = toodle();
+        let label = manager.create_label("label1".to_string(), "#000000".to_string()).expect("expected a label option").unwrap();
+        let item1 = Item {
+            id: None,
+            uuid: Uuid::nil(),
+            name: "test item 1".to_string(),
+            due_date: None,
+            completion_date: None,
+            notes: None,
+            priority: None,
+            starred: false,
+            recurrence: None,
+            labels: vec![label.clone()]
+        };
+        let item1 = manager.create_and_fetch_item(&item1).expect("expected an item option").expect("expected item1");
+
+        let renamed = manager.rename_label(&label, "renamed".to_string()).expect("expected a label option").expect("expected a label");
+        assert_eq!(renamed.id, label.id);
+        assert_eq!(renamed.name, "renamed");
+        assert_eq!(renamed.color, label.color);
+
+        assert_eq!(manager.fetch_label(&label.name).expect("expected a label option"), None);
+        let fetched_labels = manager.fetch_labels_for_item(&item1.uuid).expect("expected a vector of labels");
+        assert_eq!(fetched_labels, vec![renamed.clone()]);
+        let fetched_items = manager.fetch_items_with_label(&renamed).expect("expected a vector of items");
+        assert_eq!(fetched_items.len(), 1);
+        assert_eq!(fetched_items[0].uuid, item1.uuid);
+    }
+
+    #[test]
+    fn test_rename_label_to_existing_name() {
+        let mut manager = toodle();
+        let label = manager.create_label("label1".to_string(), "#000000".to_string()).expect("expected a label option").unwrap();
+        let _ = manager.create_label("label2".to_string(), "#000000".to_string()).expect("expected a label option").unwrap();
+
+        assert!(manager.rename_label(&label, "label2".to_string()).is_err());
+        assert_eq!(manager.fetch_labels().expect("expected a vector of labels").len(), 2);
+    }
+
+    #[test]
+    fn test_update_label_color() {
+        let mut manager = toodle();
+        let label = manager.create_label("label1".to_string(), "#000000".to_string()).expect("expected a label option").unwrap();
+
+        let updated = manager.update_label_color(&label, "#ffffff".to_string()).expect("expected a label option").expect("expected a label");
+        assert_eq!(updated.color, "#ffffff");
+        let fetched_label = manager.fetch_label(&label.name).expect("expected a label option").expect("expected a label");
+        assert_eq!(fetched_label, updated);
+    }
+
+    #[test]
+    fn test_delete_label() {
+        let mut manager = toodle();
+        let label = manager.create_label("label1".to_string(), "#000000".to_string()).expect("expected a label option").unwrap();
+        let label2 = manager.create_label("label2".to_string(), "#000000".to_string()).expect("expected a label option").unwrap();
+
+        let item1 = Item {
+            id: None,
+            uuid: Uuid::nil(),
+            name: "test item 1".to_string(),
+            due_date: None,
+            completion_date: None,
+            notes: None,
+            priority: None,
+            starred: false,
+            recurrence: None,
+            labels: vec![label.clone(), label2.clone()]
+        };
+        let item2 = Item {
+            id: None,
+            uuid: Uuid::nil(),
+            name: "test item 2".to_string(),
+            due_date: None,
+            completion_date: None,
+            notes: None,
+            priority: None,
+            starred: false,
+            recurrence: None,
+            labels: vec![label.clone()]
+        };
+        let item1 = manager.create_and_fetch_item(&item1).expect("expected an item option").expect("expected item1");
+        let item2 = manager.create_and_fetch_item(&item2).expect("expected an item option").expect("expected item2");
+
+        match manager.delete_label(&label) {
+            Ok(()) => (),
+            Err(e) => {
+                println!("e {:?}", e);
+                assert!(false)
+            }
+        }
+
+        assert_eq!(manager.fetch_label(&label.name).expect("expected a label option"), None);
+        assert_eq!(manager.fetch_labels().expect("expected a vector of labels"), vec![label2.clone()]);
+        assert_eq!(manager.fetch_items_with_label(&label).expect("expected a vector of items"), vec![]);
+        assert_eq!(manager.fetch_labels_for_item(&item1.uuid).expect("expected a vector of labels"), vec![label2]);
+        assert_eq!(manager.fetch_labels_for_item(&item2.uuid).expect("expected a vector of labels"), vec![]);
+        assert_eq!(manager.fetch_items().expect("expected items").vec.len(), 2);
+    }
+
+    #[test]
+    fn test_create_label_hostile_name() {
+        let mut manager = toodle();
+        let name = r#"quote" ]}] [:db/retract 1 :label/name "x"] back\slash\"#.to_string();
+        let color = "#000000\"\n".to_string();
+
+        let label = manager.create_label(name.clone(), color.clone()).expect("expected a label option").expect("expected a label");
+        assert_eq!(label.name, name);
+        assert_eq!(label.color, color);
+        assert_eq!(manager.fetch_labels().expect("expected a vector of labels"), vec![label]);
+    }
+
+    #[test]
+    fn test_create_and_update_item_hostile_name() {
+        let mut manager = toodle();
+        let label = manager.create_label("\\\"".to_string(), "#000000".to_string()).expect("expected a label option").unwrap();
+        let i = Item {
+            id: None,
+            uuid: Uuid::nil(),
+            name: "\"}] [:db/add \"x\" :item/name \"injected\"] \\".to_string(),
+            due_date: None,
+            completion_date: None,
+            notes: None,
+            priority: None,
+            starred: false,
+            recurrence: None,
+            labels: vec![label.clone()]
+        };
+
+        let item = manager.create_and_fetch_item(&i).expect("expected an item option").expect("expected an item");
+        assert_eq!(item.name, i.name);
+        assert_eq!(item.labels, vec![label]);
+        assert_eq!(manager.fetch_items().expect("expected items").vec.len(), 1);
+
+        let new_name = "tab\tnewline\n\"quote\" \\backslash".to_string();
+        manager.update_item(&item, ItemUpdate { name: Some(new_name.clone()), ..ItemUpdate::default() }).expect("expected an update");
+        let fetched_item = manager.fetch_item(&item.uuid).expect("expected an item option").expect("expected an item");
+        assert_eq!(fetched_item.name, new_name);
+
+        manager.delete_item(&item.uuid).expect("expected a deletion");
+        assert_eq!(manager.fetch_items().expect("expected items").vec, vec![]);
+    }
+
+    #[test]
+    fn test_fetch_items_hydrates_all_fields() {
+        let mut manager = toodle();
+        let label = manager.create_label("label1".to_string(), "#000000".to_string()).expect("expected a label option").unwrap();
+        let label2 = manager.create_label("label2".to_string(), "#000000".to_string()).expect("expected a label option").unwrap();
+
+        let date = now_utc().to_timespec();
+        let mut created = vec![];
+        for i in 0..10 {
+            let item = Item {
+                id: None,
+                uuid: Uuid::nil(),
+                name: format!("test item {}", i),
+                due_date: if i % 2 == 0 { Some(date) } else { None },
+                completion_date: if i % 3 == 0 { Some(date) } else { None },
+                notes: if i % 2 == 1 { Some(format!("notes {}", i)) } else { None },
+                priority: if i % 3 == 1 { Some(i) } else { None },
+                starred: i % 5 == 0,
+                recurrence: if i % 3 == 0 { Some("FREQ=WEEKLY".parse().expect("expected a rule")) } else { None },
+                labels: match i % 4 {
+                    0 => vec![],
+                    1 => vec![label.clone()],
+                    2 => vec![label2.clone()],
+                    _ => vec![label.clone(), label2.clone()],
+                }
+            };
+            created.push(manager.create_and_fetch_item(&item).expect("expected an item option").expect("expected an item"));
+        }
+
+        let mut fetched = manager.fetch_items().expect("expected items").vec;
+        fetched.sort_by(|a, b| a.name.cmp(&b.name));
+        created.sort_by(|a, b| a.name.cmp(&b.name));
+        assert_eq!(fetched, created);
+    }
+
+    #[test]
+    fn test_search_items() {
+        let mut manager = toodle();
+        let groceries = manager.create_label("groceries".to_string(), "#000000".to_string()).expect("expected a label option").unwrap();
+        let chores = manager.create_label("chores".to_string(), "#000000".to_string()).expect("expected a label option").unwrap();
+
+        let milk = Item {
+            id: None,
+            uuid: Uuid::nil(),
+            name: "buy milk".to_string(),
+            due_date: None,
+            completion_date: None,
+            notes: None,
+            priority: None,
+            starred: false,
+            recurrence: None,
+            labels: vec![groceries.clone()]
+        };
+        let bread = Item {
+            id: None,
+            uuid: Uuid::nil(),
+            name: "buy bread".to_string(),
+            due_date: None,
+            completion_date: None,
+            notes: Some("from the bakery, with the groceries".to_string()),
+            priority: None,
+            starred: false,
+            recurrence: None,
+            labels: vec![groceries.clone()]
+        };
+        let groceries_item = Item {
+            id: None,
+            uuid: Uuid::nil(),
+            name: "put the groceries away".to_string(),
+            due_date: None,
+            completion_date: None,
+            notes: None,
+            priority: None,
+            starred: false,
+            recurrence: None,
+            labels: vec![chores.clone()]
+        };
+        let milk = manager.create_and_fetch_item(&milk).expect("expected an item option").expect("expected milk");
+        let bread = manager.create_and_fetch_item(&bread).expect("expected an item option").expect("expected bread");
+        let groceries_item = manager.create_and_fetch_item(&groceries_item).expect("expected an item option").expect("expected groceries");
+
+        assert_eq!(manager.search_items("milk").expect("expected search results"), vec![milk.clone()]);
+        assert_eq!(manager.search_items("buy").expect("expected search results"), vec![bread.clone(), milk.clone()]);
+        assert_eq!(manager.search_items("chores").expect("expected search results"), vec![groceries_item.clone()]);
+        assert_eq!(manager.search_items("bakery").expect("expected search results"), vec![bread.clone()]);
+
+        // Items named after the term rank ahead of items whose notes mention it, which rank
+        // ahead of items that only carry a matching label.
+        assert_eq!(manager.search_items("groceries").expect("expected search results"), vec![groceries_item, bread, milk]);
+
+        assert_eq!(manager.search_items("nothing matches").expect("expected search results"), vec![]);
+        assert_eq!(manager.search_items("   ").expect("expected search results"), vec![]);
+    }
+
+    #[test]
+    fn test_search_items_hostile_query() {
+        let mut manager = toodle();
+        let item = Item {
+            id: None,
+            uuid: Uuid::nil(),
+            name: "buy milk".to_string(),
+            due_date: None,
+            completion_date: None,
+            notes: None,
+            priority: None,
+            starred: false,
+            recurrence: None,
+            labels: vec![]
+        };
+        let _ = manager.create_and_fetch_item(&item).expect("expected an item option").expect("expected an item");
+
+        for query in ["\"", "milk\" OR \"", "NEAR(", "*", "milk -", "a:b"].iter() {
+            assert!(manager.search_items(query).is_ok(), "query {:?} failed", query);
+        }
+    }
+
+    #[test]
+    fn test_query_items() {
+        let mut manager = toodle();
+        let home = manager.create_label("home".to_string(), "#000000".to_string()).expect("expected a label option").unwrap();
+        let work = manager.create_label("work".to_string(), "#000000".to_string()).expect("expected a label option").unwrap();
+
+        let now = now_utc().to_timespec();
+        let yesterday = Timespec::new(now.sec - 86400, 0);
+        let tomorrow = Timespec::new(now.sec + 86400, 0);
+        let items = vec![
+            ("overdue", Some(yesterday), None, vec![home.clone()]),
+            ("upcoming", Some(tomorrow), None, vec![work.clone()]),
+            ("done", Some(yesterday), Some(now), vec![home.clone(), work.clone()]),
+            ("someday", None, None, vec![]),
+        ];
+        for (name, due_date, completion_date, labels) in items.into_iter() {
+            let item = Item {
+                id: None,
+                uuid: Uuid::nil(),
+                name: name.to_string(),
+                due_date: due_date,
+                completion_date: completion_date,
+                notes: None,
+                priority: None,
+                starred: false,
+                recurrence: None,
+                labels: labels
+            };
+            let _ = manager.create_item(&item).expect("expected a uuid");
+        }
+
+        let names = |filter: ItemFilter| -> Vec<String> {
+            manager.query_items(&filter).expect("expected items").into_iter().map(|item| item.name.clone()).collect()
+        };
+
+        assert_eq!(names(ItemFilter::new()), vec!["done", "overdue", "someday", "upcoming"]);
+        assert_eq!(names(ItemFilter::new().overdue(&FixedClock(now))), vec!["overdue"]);
+        assert_eq!(names(ItemFilter::new().completed(false).with_any_label("home").with_any_label("work")), vec!["overdue", "upcoming"]);
+        assert_eq!(names(ItemFilter::new().with_all_label("home").with_all_label("work")), vec!["done"]);
+        assert_eq!(names(ItemFilter::new().without_label("home").sort_by(SortKey::DueDate, SortDirection::Descending)), vec!["upcoming", "someday"]);
+        assert_eq!(names(ItemFilter::new().due_before(now).sort_by(SortKey::DueDate, SortDirection::Ascending).limit(1).offset(1)), vec!["overdue"]);
+        assert_eq!(names(ItemFilter::new().sort_by(SortKey::DueDate, SortDirection::Ascending).limit(2).offset(2)), vec!["upcoming", "someday"]);
+        assert_eq!(names(ItemFilter::new().sort_by(SortKey::Name, SortDirection::Descending).limit(3)), vec!["upcoming", "someday", "overdue"]);
+    }
+
+    fn record_changes(manager: &mut Toodle, topics: &[Topic]) -> Rc<RefCell<Vec<Change>>> {
+        let seen = Rc::new(RefCell::new(vec![]));
+        let sink = seen.clone();
+        manager.register_observer(topics, move |change| sink.borrow_mut().push(change.clone()));
+        seen
+    }
+
+    #[test]
+    fn test_observers_see_item_changes() {
+        let mut manager = toodle();
+        let items = record_changes(&mut manager, &[Topic::Items]);
+        let labels = record_changes(&mut manager, &[Topic::Labels]);
+
+        let home = manager.create_label("home".to_string(), "#ff0000".to_string()).expect("expected a label option").unwrap();
+        let uuid = manager.create_item(&Item::default()).expect("expected a uuid");
+        let item = manager.fetch_item(&uuid).expect("expected an item option").unwrap();
+        manager.update_item(&item, ItemUpdate { name: Some("renamed".to_string()), labels: Some(vec![home.clone()]), ..ItemUpdate::default() }).expect("expected an update");
+        // Nothing changed, so nobody hears about it.
+        let item = manager.fetch_item(&uuid).expect("expected an item option").unwrap();
+        manager.update_item(&item, ItemUpdate::default()).expect("expected an update");
+        manager.delete_label(&home).expect("expected a deletion");
+        manager.delete_item(&uuid).expect("expected a deletion");
+
+        let id = uuid.hyphenated().to_string();
+        assert_eq!(*items.borrow(), vec![
+            Change::new(Topic::Items, ChangeKind::Created, vec![id.clone()]),
+            Change::new(Topic::Items, ChangeKind::Updated, vec![id.clone()]),
+            Change::new(Topic::Items, ChangeKind::Updated, vec![id.clone()]),
+            Change::new(Topic::Items, ChangeKind::Deleted, vec![id.clone()]),
+        ]);
+        assert_eq!(*labels.borrow(), vec![
+            Change::new(Topic::Labels, ChangeKind::Created, vec!["home"]),
+            Change::new(Topic::Labels, ChangeKind::Deleted, vec!["home"]),
+        ]);
+    }
+
+    #[test]
+    fn test_observers_see_label_changes() {
+        let mut manager = toodle();
+        let labels = record_changes(&mut manager, &[Topic::Labels]);
+
+        let home = manager.create_label("home".to_string(), "#ff0000".to_string()).expect("expected a label option").unwrap();
+        let house = manager.rename_label(&home, "house".to_string()).expect("expected a label option").unwrap();
+        manager.update_label_color(&house, "#00ff00".to_string()).expect("expected a label option");
+
+        assert_eq!(*labels.borrow(), vec![
+            Change::new(Topic::Labels, ChangeKind::Created, vec!["home"]),
+            Change::new(Topic::Labels, ChangeKind::Updated, vec!["home", "house"]),
+            Change::new(Topic::Labels, ChangeKind::Updated, vec!["house"]),
+        ]);
+    }
+
+    #[test]
+    fn test_observers_belong_to_one_toodle() {
+        let mut first = toodle();
+        let mut second = toodle();
+        let first_seen = record_changes(&mut first, &[Topic::Items]);
+        let second_seen = record_changes(&mut second, &[Topic::Items]);
+
+        first.create_item(&Item::default()).expect("expected a uuid");
+        assert_eq!(first_seen.borrow().len(), 1);
+        assert!(second_seen.borrow().is_empty());
+    }
+
+    #[test]
+    fn test_unregister_observer() {
+        let mut manager = toodle();
+        let seen = Rc::new(RefCell::new(0));
+        let sink = seen.clone();
+        let token = manager.register_observer(&[Topic::Items], move |_| *sink.borrow_mut() += 1);
+
+        manager.create_item(&Item::default()).expect("expected a uuid");
+        assert!(manager.unregister_observer(token));
+        assert!(!manager.unregister_observer(token));
+        manager.create_item(&Item::default()).expect("expected a uuid");
+        assert_eq!(*seen.borrow(), 1);
+    }
+
+    extern "C" fn record_ffi_change(context: *mut c_void, topic: c_int, kind: c_int, ids: *const *const c_char, count: c_int) {
+        let seen = unsafe { &mut *(context as *mut Vec<(c_int, c_int, Vec<String>)>) };
+        let ids = (0..count as isize).map(|i| unsafe {
+            CStr::from_ptr(*ids.offset(i)).to_string_lossy().into_owned()
+        }).collect();
+        seen.push((topic, kind, ids));
+    }
+
+    #[test]
+    fn test_ffi_observer_context() {
+        let manager = TOODLES.insert(toodle());
+        let mut seen: Vec<(c_int, c_int, Vec<String>)> = vec![];
+        let context = &mut seen as *mut _ as *mut c_void;
+        let token = toodle_register_observer(manager, Topic::Labels as c_int, context, record_ffi_change, ptr::null_mut());
+
+        TOODLES.get_mut(manager, |manager| {
+            manager.create_item(&Item::default()).expect("expected a uuid");
+            manager.create_label("home".to_string(), "#ff0000".to_string())
+        }).expect("expected a label option");
+        assert_eq!(toodle_unregister_observer(manager, token, ptr::null_mut()), 1);
+        assert_eq!(toodle_unregister_observer(manager, token, ptr::null_mut()), 0);
+        TOODLES.get_mut(manager, |manager| manager.create_label("work".to_string(), "#00ff00".to_string())).expect("expected a label option");
+        toodle_destroy(manager, ptr::null_mut());
+
+        assert_eq!(seen, vec![(Topic::Labels as c_int, ChangeKind::Created as c_int, vec!["home".to_string()])]);
+    }
+
+    #[test]
+    fn test_ffi_reports_errors() {
+        let manager = TOODLES.insert(toodle());
+        let mut error = ExternError::default();
+        let name = CString::new("renamed").unwrap();
+
+        let bad_uuid = CString::new("not a uuid").unwrap();
+        toodle_update_item_by_uuid(manager, bad_uuid.as_ptr(), name.as_ptr(), ptr::null(), ptr::null(), &mut error);
+        assert_eq!(error.code, ErrorCode::InvalidArgument);
+        assert!(!error.message.is_null());
+        unsafe { toodle_error_destroy(&mut error) };
+
+        let missing = CString::new(create_uuid().hyphenated().to_string()).unwrap();
+        toodle_update_item_by_uuid(manager, missing.as_ptr(), name.as_ptr(), ptr::null(), ptr::null(), &mut error);
+        assert_eq!(error.code, ErrorCode::NotFound);
+        unsafe { toodle_error_destroy(&mut error) };
+
+        toodle_delete_item_by_uuid(manager, bad_uuid.as_ptr(), &mut error);
+        assert_eq!(error.code, ErrorCode::InvalidArgument);
+        unsafe { toodle_error_destroy(&mut error) };
+
+        let uuid = TOODLES.get_mut(manager, |manager| manager.create_item(&Item::default())).expect("expected a uuid");
+        let existing = CString::new(uuid.hyphenated().to_string()).unwrap();
+        toodle_update_item_by_uuid(manager, existing.as_ptr(), name.as_ptr(), ptr::null(), ptr::null(), &mut error);
+        assert_eq!(error.code, ErrorCode::Success);
+        assert!(error.message.is_null());
+        let item = TOODLES.get(manager, |manager| manager.fetch_item(&uuid)).expect("expected an item option").unwrap();
+        assert_eq!(item.name, "renamed");
+
+        // Null pointers are refused rather than dereferenced.
+        toodle_update_item_by_uuid(manager, ptr::null(), name.as_ptr(), ptr::null(), ptr::null(), &mut error);
+        assert_eq!(error.code, ErrorCode::InvalidArgument);
+        toodle_update_item_by_uuid(manager, existing.as_ptr(), ptr::null(), ptr::null(), ptr::null(), &mut error);
+        assert_eq!(error.code, ErrorCode::InvalidArgument);
+        assert_eq!(toodle_create_item(manager, ptr::null(), ptr::null(), &mut error), 0);
+        assert_eq!(error.code, ErrorCode::InvalidArgument);
+        toodle_create_label(manager, ptr::null(), name.as_ptr(), &mut error);
+        assert_eq!(error.code, ErrorCode::InvalidArgument);
+        toodle_rekey(manager, ptr::null(), &mut error);
+        assert_eq!(error.code, ErrorCode::InvalidArgument);
+        unsafe { toodle_error_destroy(&mut error) };
+        toodle_destroy(manager, ptr::null_mut());
+    }
+
+    #[test]
+    fn test_ffi_rejects_stale_and_foreign_handles() {
+        let manager = TOODLES.insert(toodle());
+        let mut error = ExternError::default();
+        let name = CString::new("an item").unwrap();
+
+        let created = toodle_create_item(manager, name.as_ptr(), ptr::null(), &mut error);
+        assert_eq!(error.code, ErrorCode::Success);
+        item_destroy(created, &mut error);
+
+        let items = toodle_get_all_items(manager, &mut error);
+        assert_eq!(item_list_count(items, &mut error), 1);
+        let entry = item_list_entry_at(items, 0, &mut error);
+        assert_eq!(unsafe { CStr::from_ptr((*entry).name) }.to_str().unwrap(), "an item");
+        unsafe { item_c_destroy(entry) };
+        assert!(item_list_entry_at(items, 1, &mut error).is_null());
+        assert_eq!(error.code, ErrorCode::InvalidArgument);
+        unsafe { toodle_error_destroy(&mut error) };
+
+        // Item handles from a list outlive it, and aren't list handles.
+        let item = item_list_item_at(items, 0, &mut error);
+        assert_eq!(error.code, ErrorCode::Success);
+        assert_eq!(item_list_count(item, &mut error), 0);
+        assert_eq!(error.code, ErrorCode::InvalidHandle);
+        unsafe { toodle_error_destroy(&mut error) };
+
+        // An item list isn't a Toodle.
+        assert_eq!(toodle_get_all_items(items, &mut error), 0);
+        assert_eq!(error.code, ErrorCode::InvalidHandle);
+        unsafe { toodle_error_destroy(&mut error) };
+
+        // Nor is a forged handle.
+        toodle_delete_item_by_uuid(manager ^ (0xff << 32), name.as_ptr(), &mut error);
+        assert_eq!(error.code, ErrorCode::InvalidHandle);
+        unsafe { toodle_error_destroy(&mut error) };
+
+        item_list_destroy(items, &mut error);
+        assert_eq!(error.code, ErrorCode::Success);
+        assert_eq!(item_list_count(items, &mut error), 0);
+        assert_eq!(error.code, ErrorCode::InvalidHandle);
+        unsafe { toodle_error_destroy(&mut error) };
+        let name = unsafe { CString::from_raw(item_get_name(item, &mut error)) };
+        assert_eq!(name.to_str().unwrap(), "an item");
+        item_destroy(item, &mut error);
+
+        toodle_destroy(manager, &mut error);
+        assert_eq!(error.code, ErrorCode::Success);
+        assert_eq!(toodle_get_all_items(manager, &mut error), 0);
+        assert_eq!(error.code, ErrorCode::InvalidHandle);
+        assert!(!error.message.is_null());
+        unsafe { toodle_error_destroy(&mut error) };
+        toodle_destroy(manager, &mut error);
+        assert_eq!(error.code, ErrorCode::InvalidHandle);
+        unsafe { toodle_error_destroy(&mut error) };
+    }
+
+    struct Reentrant {
+        toodle: u64,
+        counts: Vec<c_int>,
+    }
+
+    extern "C" fn count_items_on_change(context: *mut c_void, _: c_int, _: c_int, _: *const *const c_char, _: c_int) {
+        let reentrant = unsafe { &mut *(context as *mut Reentrant) };
+        let items = toodle_get_all_items(reentrant.toodle, ptr::null_mut());
+        reentrant.counts.push(item_list_count(items, ptr::null_mut()));
+        item_list_destroy(items, ptr::null_mut());
+    }
+
+    #[test]
+    fn test_ffi_observers_can_call_back_in() {
+        let manager = TOODLES.insert(toodle());
+        let mut reentrant = Reentrant { toodle: manager, counts: vec![] };
+        let context = &mut reentrant as *mut _ as *mut c_void;
+        toodle_register_observer(manager, Topic::Items as c_int, context, count_items_on_change, ptr::null_mut());
+
+        let name = CString::new("an item").unwrap();
+        for _ in 0..2 {
+            item_destroy(toodle_create_item(manager, name.as_ptr(), ptr::null(), ptr::null_mut()), ptr::null_mut());
+        }
+        toodle_destroy(manager, ptr::null_mut());
+        assert_eq!(reentrant.counts, vec![1, 2]);
+    }
+
+    extern "C" fn count_lent_items(items: u64) {
+        assert_eq!(item_list_count(items, ptr::null_mut()), 1);
+        LENT_ITEMS.with(|lent| lent.set(items));
+    }
+
+    thread_local! {
+        static LENT_ITEMS: Cell<u64> = Cell::new(0);
+    }
+
+    #[test]
+    fn test_ffi_lent_item_lists_are_released() {
+        let manager = TOODLES.insert(toodle());
+        let mut error = ExternError::default();
+        TOODLES.get_mut(manager, |manager| manager.create_item(&Item::default())).expect("expected a uuid");
+
+        toodle_all_items(manager, count_lent_items, &mut error);
+        assert_eq!(error.code, ErrorCode::Success);
+        let lent = LENT_ITEMS.with(|lent| lent.get());
+        assert_eq!(item_list_count(lent, &mut error), 0);
+        assert_eq!(error.code, ErrorCode::InvalidHandle);
+        unsafe { toodle_error_destroy(&mut error) };
+        toodle_destroy(manager, ptr::null_mut());
+    }
+
+    #[test]
+    fn test_header_is_up_to_date() {
+        use std::fs::File;
+        use std::io::Read;
+        use std::path::Path;
+
+        let src = Path::new(env!("CARGO_MANIFEST_DIR")).join("src");
+        let generated = header_gen::generate_for_dir(&src, "toodle_h").expect("expected a header");
+        let mut checked_in = String::new();
+        File::open(src.join("toodle.h")).and_then(|mut file| file.read_to_string(&mut checked_in)).expect("expected src/toodle.h");
+        assert!(generated == checked_in, "src/toodle.h is out of date; run `cargo run -p header-gen` to regenerate it");
+    }
 }
@@ -0,0 +1,171 @@
+// Copyright 2016 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! Observers that are told about changes made through a single `Toodle`.
+
+use std::collections::BTreeSet;
+use std::fmt;
+
+/// What an observer can subscribe to. The discriminants are the bits used at the FFI
+/// boundary.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Topic {
+    Items = 1,
+    Labels = 2,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ChangeKind {
+    Created = 0,
+    Updated = 1,
+    Deleted = 2,
+}
+
+/// A change to one or more entities of the same kind. Items are identified by their
+/// hyphenated uuid and labels by their name.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Change {
+    pub topic: Topic,
+    pub kind: ChangeKind,
+    pub ids: BTreeSet<String>,
+}
+
+impl Change {
+    pub fn new<I, T>(topic: Topic, kind: ChangeKind, ids: I) -> Change where I: IntoIterator<Item=T>, T: Into<String> {
+        Change {
+            topic: topic,
+            kind: kind,
+            ids: ids.into_iter().map(|id| id.into()).collect(),
+        }
+    }
+}
+
+/// Returned by `Observers::register`; hand it back to `Observers::unregister`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ObserverToken(pub u64);
+
+struct Registration {
+    token: ObserverToken,
+    topics: Vec<Topic>,
+    callback: Box<Fn(&Change)>,
+}
+
+#[derive(Default)]
+pub struct Observers {
+    next_token: u64,
+    registrations: Vec<Registration>,
+}
+
+impl fmt::Debug for Observers {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Observers({} registered)", self.registrations.len())
+    }
+}
+
+impl Observers {
+    pub fn new() -> Observers {
+        Observers::default()
+    }
+
+    pub fn register<F>(&mut self, topics: &[Topic], callback: F) -> ObserverToken where F: Fn(&Change) + 'static {
+        self.next_token += 1;
+        let token = ObserverToken(self.next_token);
+        self.registrations.push(Registration {
+            token: token,
+            topics: topics.to_vec(),
+            callback: Box::new(callback),
+        });
+        token
+    }
+
+    /// Returns false if `token` wasn't registered.
+    pub fn unregister(&mut self, token: ObserverToken) -> bool {
+        let before = self.registrations.len();
+        self.registrations.retain(|registration| registration.token != token);
+        self.registrations.len() != before
+    }
+
+    pub fn len(&self) -> usize {
+        self.registrations.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.registrations.is_empty()
+    }
+
+    /// Calls every observer subscribed to `change.topic`. Empty changes are dropped.
+    pub fn notify(&self, change: &Change) {
+        if change.ids.is_empty() {
+            return;
+        }
+        for registration in self.registrations.iter().filter(|registration| registration.topics.contains(&change.topic)) {
+            (registration.callback)(change);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    fn recorder(observers: &mut Observers, topics: &[Topic]) -> (ObserverToken, Rc<RefCell<Vec<Change>>>) {
+        let seen = Rc::new(RefCell::new(vec![]));
+        let sink = seen.clone();
+        let token = observers.register(topics, move |change| sink.borrow_mut().push(change.clone()));
+        (token, seen)
+    }
+
+    #[test]
+    fn test_topics() {
+        let mut observers = Observers::new();
+        let (_, items) = recorder(&mut observers, &[Topic::Items]);
+        let (_, both) = recorder(&mut observers, &[Topic::Items, Topic::Labels]);
+
+        let item_change = Change::new(Topic::Items, ChangeKind::Created, vec!["a"]);
+        let label_change = Change::new(Topic::Labels, ChangeKind::Deleted, vec!["home"]);
+        observers.notify(&item_change);
+        observers.notify(&label_change);
+
+        assert_eq!(*items.borrow(), vec![item_change.clone()]);
+        assert_eq!(*both.borrow(), vec![item_change, label_change]);
+    }
+
+    #[test]
+    fn test_unregister() {
+        let mut observers = Observers::new();
+        let (first, first_seen) = recorder(&mut observers, &[Topic::Items]);
+        let (second, second_seen) = recorder(&mut observers, &[Topic::Items]);
+        assert_ne!(first, second);
+
+        assert!(observers.unregister(first));
+        assert!(!observers.unregister(first));
+        assert_eq!(observers.len(), 1);
+
+        observers.notify(&Change::new(Topic::Items, ChangeKind::Updated, vec!["a"]));
+        assert!(first_seen.borrow().is_empty());
+        assert_eq!(second_seen.borrow().len(), 1);
+
+        assert!(observers.unregister(second));
+        assert!(observers.is_empty());
+    }
+
+    #[test]
+    fn test_empty_changes_are_dropped() {
+        let mut observers = Observers::new();
+        let (_, seen) = recorder(&mut observers, &[Topic::Items]);
+        observers.notify(&Change::new(Topic::Items, ChangeKind::Updated, Vec::<String>::new()));
+        assert!(seen.borrow().is_empty());
+    }
+}
@@ -0,0 +1,658 @@
+// Copyright 2016 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! Recurring items, scheduled with a subset of iCalendar's RRULE syntax.
+//!
+//! A schedule such as `FREQ=WEEKLY;INTERVAL=2;BYDAY=MO,TH` is stored on the item as
+//! `:item/recurrence`, which is also how it syncs and how `api` exchanges it. `FREQ` is
+//! required; `INTERVAL`, `BYDAY` (with daily and weekly rules only) and one of `UNTIL` or
+//! `COUNT` are optional. Dates are worked out in UTC, weeks start on Monday, and every
+//! occurrence keeps the time of day of the first.
+//!
+//! Completing a recurring item hands its schedule on to a new item, due at the next
+//! occurrence after both the old due date and the current time, so that an item that's
+//! completed late doesn't leave a backlog of overdue copies. `COUNT` is the number of
+//! occurrences left, counting the item that has it, and skipped occurrences use it up.
+//!
+//! The new item is created in the same transaction as the completion. Its uuid is
+//! derived from the completed item's and its own due date, so stores that complete the
+//! same occurrence create the same item.
+
+use std::fmt;
+use std::os::raw::c_char;
+use std::str::FromStr;
+
+use mentat_core::Uuid;
+use time::Timespec;
+
+use errors as list_errors;
+use errors::ErrorKind;
+use ffi_errors::ExternError;
+use ffi_utils::strings::c_char_to_string;
+use observers::{
+    ChangeKind,
+    Topic,
+};
+use store::TransactionBuilder;
+use {
+    change_recurrence,
+    parse_uuid,
+    with_toodle,
+    Toodle,
+};
+
+const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+
+// Schedules that can never produce another occurrence, such as every seventh day on a
+// weekday the first one isn't, end after this many periods in a row without one.
+const MAX_EMPTY_PERIODS: usize = 1000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Frequency {
+    Daily,
+    Weekly,
+    Monthly,
+    Yearly,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Weekday {
+    Monday,
+    Tuesday,
+    Wednesday,
+    Thursday,
+    Friday,
+    Saturday,
+    Sunday,
+}
+
+const FREQUENCIES: [(Frequency, &'static str); 4] = [
+    (Frequency::Daily, "DAILY"),
+    (Frequency::Weekly, "WEEKLY"),
+    (Frequency::Monthly, "MONTHLY"),
+    (Frequency::Yearly, "YEARLY"),
+];
+
+const WEEKDAYS: [(Weekday, &'static str); 7] = [
+    (Weekday::Monday, "MO"),
+    (Weekday::Tuesday, "TU"),
+    (Weekday::Wednesday, "WE"),
+    (Weekday::Thursday, "TH"),
+    (Weekday::Friday, "FR"),
+    (Weekday::Saturday, "SA"),
+    (Weekday::Sunday, "SU"),
+];
+
+fn div_floor(a: i64, b: i64) -> i64 {
+    let quotient = a / b;
+    if a % b != 0 && (a < 0) != (b < 0) { quotient - 1 } else { quotient }
+}
+
+fn mod_floor(a: i64, b: i64) -> i64 {
+    a - div_floor(a, b) * b
+}
+
+fn lookup<T>(table: &[(T, &'static str)], code: &str) -> Option<T> where T: Copy {
+    table.iter().find(|&&(_, c)| c == code).map(|&(value, _)| value)
+}
+
+fn code<T>(table: &[(T, &'static str)], value: T) -> &'static str where T: Copy + PartialEq {
+    table.iter().find(|&&(v, _)| v == value).map_or("", |&(_, code)| code)
+}
+
+impl Weekday {
+    /// Days after Monday.
+    fn index(self) -> i64 {
+        self as i64
+    }
+
+    fn of_day(day: i64) -> Weekday {
+        // Day 0, 1970-01-01, was a Thursday.
+        WEEKDAYS[(mod_floor(day, 7) as usize + 3) % 7].0
+    }
+}
+
+// Civil dates from day numbers and back, after Howard Hinnant's `days_from_civil` and
+// `civil_from_days`. Months run from 1 to 12.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = div_floor(year, 400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = div_floor(days, 146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+    (if month <= 2 { year_of_era + era * 400 + 1 } else { year_of_era + era * 400 }, month, day)
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// The day number of a date, if the month has that day.
+fn day_of(year: i64, month: i64, day: i64) -> Option<i64> {
+    if day <= days_in_month(year, month) {
+        Some(days_from_civil(year, month, day))
+    } else {
+        None
+    }
+}
+
+fn number(value: &str, start: usize, len: usize) -> Option<i64> {
+    match value.get(start..start + len) {
+        Some(digits) if digits.bytes().all(|b| b.is_ascii_digit()) => digits.parse().ok(),
+        _ => None,
+    }
+}
+
+/// Parses `YYYYMMDD` or `YYYYMMDDTHHMMSS`, optionally ending in `Z`, as UTC. A date on its
+/// own lasts until the end of that day.
+fn parse_until(value: &str) -> Option<Timespec> {
+    if !value.is_ascii() {
+        return None;
+    }
+    let time = match value.len() {
+        8 => None,
+        15 | 16 if &value[8..9] == "T" && (value.len() == 15 || value.ends_with('Z')) => Some(&value[9..15]),
+        _ => return None,
+    };
+    let (year, month, day) = match (number(value, 0, 4), number(value, 4, 2), number(value, 6, 2)) {
+        (Some(year), Some(month), Some(day)) if month >= 1 && month <= 12 && day >= 1 => (year, month, day),
+        _ => return None,
+    };
+    let seconds = match time {
+        None => SECONDS_PER_DAY - 1,
+        Some(time) => match (number(time, 0, 2), number(time, 2, 2), number(time, 4, 2)) {
+            (Some(hours), Some(minutes), Some(seconds)) if hours < 24 && minutes < 60 && seconds < 60 => {
+                hours * 3600 + minutes * 60 + seconds
+            },
+            _ => return None,
+        },
+    };
+    day_of(year, month, day).map(|day| Timespec::new(day * SECONDS_PER_DAY + seconds, 0))
+}
+
+fn positive(value: &str) -> Option<u32> {
+    match value.parse() {
+        Ok(0) | Err(_) => None,
+        Ok(n) => Some(n),
+    }
+}
+
+/// How an item repeats. Build one with `Recurrence::new` and the builder methods, or parse
+/// one from a rule; `to_string` gives the rule back.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Recurrence {
+    pub frequency: Frequency,
+    pub interval: u32,
+    /// Sorted, and only used by daily and weekly schedules.
+    pub weekdays: Vec<Weekday>,
+    pub until: Option<Timespec>,
+    pub count: Option<u32>,
+}
+
+impl Recurrence {
+    pub fn new(frequency: Frequency) -> Recurrence {
+        Recurrence {
+            frequency: frequency,
+            interval: 1,
+            weekdays: vec![],
+            until: None,
+            count: None,
+        }
+    }
+
+    /// Repeats every `interval` days, weeks, months or years rather than every one.
+    pub fn every(mut self, interval: u32) -> Recurrence {
+        self.interval = interval;
+        self
+    }
+
+    /// Restricts a daily schedule to, or spreads a weekly one over, the given weekdays.
+    pub fn on(mut self, weekday: Weekday) -> Recurrence {
+        if let Err(index) = self.weekdays.binary_search(&weekday) {
+            self.weekdays.insert(index, weekday);
+        }
+        self
+    }
+
+    /// Ends the schedule at `date`, inclusive. Clears any `count`.
+    pub fn until(mut self, date: Timespec) -> Recurrence {
+        self.until = Some(date);
+        self.count = None;
+        self
+    }
+
+    /// Ends the schedule after `count` occurrences. Clears any `until`.
+    pub fn count(mut self, count: u32) -> Recurrence {
+        self.count = Some(count);
+        self.until = None;
+        self
+    }
+
+    /// The occurrences that follow `start`, the first one, in order.
+    pub fn occurrences(&self, start: Timespec) -> Occurrences {
+        let start_day = div_floor(start.sec, SECONDS_PER_DAY);
+        Occurrences {
+            recurrence: self,
+            start_day: start_day,
+            time_of_day: start.sec - start_day * SECONDS_PER_DAY,
+            period: 0,
+            pending: vec![],
+            remaining: self.count.map(|count| count.saturating_sub(1)),
+        }
+    }
+
+    /// The first occurrence after `after` of the schedule that started at `start`, with the
+    /// schedule that carries on from it.
+    pub fn next_after(&self, start: Timespec, after: Timespec) -> Option<(Timespec, Recurrence)> {
+        for (index, occurrence) in self.occurrences(start).enumerate() {
+            if occurrence > after {
+                let mut next = self.clone();
+                next.count = self.count.map(|count| count - 1 - index as u32);
+                return Some((occurrence, next));
+            }
+        }
+        None
+    }
+
+    /// The days on which occurrences fall in the `period`th day, week, month or year of a
+    /// schedule that started on `start_day`, in order.
+    fn days_in_period(&self, start_day: i64, period: i64) -> Vec<i64> {
+        let step = period * self.interval as i64;
+        match self.frequency {
+            Frequency::Daily => {
+                let day = start_day + step;
+                if self.weekdays.is_empty() || self.weekdays.contains(&Weekday::of_day(day)) { vec![day] } else { vec![] }
+            },
+            Frequency::Weekly if self.weekdays.is_empty() => vec![start_day + 7 * step],
+            Frequency::Weekly => {
+                let monday = start_day - Weekday::of_day(start_day).index() + 7 * step;
+                self.weekdays.iter().map(|weekday| monday + weekday.index()).collect()
+            },
+            Frequency::Monthly => {
+                let (year, month, day) = civil_from_days(start_day);
+                let months = year * 12 + month - 1 + step;
+                day_of(div_floor(months, 12), mod_floor(months, 12) + 1, day).into_iter().collect()
+            },
+            Frequency::Yearly => {
+                let (year, month, day) = civil_from_days(start_day);
+                day_of(year + step, month, day).into_iter().collect()
+            },
+        }
+    }
+}
+
+impl FromStr for Recurrence {
+    type Err = list_errors::Error;
+
+    fn from_str(rule: &str) -> Result<Recurrence, list_errors::Error> {
+        let invalid = |reason: String| -> list_errors::Error {
+            ErrorKind::InvalidArgument(format!("{:?} isn't a supported recurrence rule: {}", rule, reason)).into()
+        };
+        let upper = rule.trim().to_uppercase();
+        let body = if upper.starts_with("RRULE:") { &upper[6..] } else { &upper[..] };
+
+        let mut frequency = None;
+        let mut recurrence = Recurrence::new(Frequency::Daily);
+        for part in body.split(';').filter(|part| !part.is_empty()) {
+            let mut pair = part.splitn(2, '=');
+            let (key, value) = match (pair.next(), pair.next()) {
+                (Some(key), Some(value)) => (key, value),
+                _ => return Err(invalid(format!("{} has no value", part))),
+            };
+            match key {
+                "FREQ" => frequency = Some(lookup(&FREQUENCIES, value).ok_or_else(|| invalid(format!("unknown frequency {}", value)))?),
+                "INTERVAL" => recurrence.interval = positive(value).ok_or_else(|| invalid(format!("bad interval {}", value)))?,
+                "BYDAY" => for day in value.split(',') {
+                    recurrence = recurrence.on(lookup(&WEEKDAYS, day).ok_or_else(|| invalid(format!("unknown weekday {}", day)))?);
+                },
+                "UNTIL" => recurrence.until = Some(parse_until(value).ok_or_else(|| invalid(format!("bad date {}", value)))?),
+                "COUNT" => recurrence.count = Some(positive(value).ok_or_else(|| invalid(format!("bad count {}", value)))?),
+                _ => return Err(invalid(format!("{} isn't supported", key))),
+            }
+        }
+
+        recurrence.frequency = match frequency {
+            Some(frequency) => frequency,
+            None => return Err(invalid("FREQ is missing".to_string())),
+        };
+        if recurrence.until.is_some() && recurrence.count.is_some() {
+            return Err(invalid("UNTIL and COUNT can't both be given".to_string()));
+        }
+        if !recurrence.weekdays.is_empty() && (recurrence.frequency == Frequency::Monthly || recurrence.frequency == Frequency::Yearly) {
+            return Err(invalid("BYDAY only goes with daily and weekly rules".to_string()));
+        }
+        Ok(recurrence)
+    }
+}
+
+impl fmt::Display for Recurrence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "FREQ={}", code(&FREQUENCIES, self.frequency))?;
+        if self.interval != 1 {
+            write!(f, ";INTERVAL={}", self.interval)?;
+        }
+        if !self.weekdays.is_empty() {
+            let days: Vec<&str> = self.weekdays.iter().map(|weekday| code(&WEEKDAYS, *weekday)).collect();
+            write!(f, ";BYDAY={}", days.join(","))?;
+        }
+        if let Some(until) = self.until {
+            let day = div_floor(until.sec, SECONDS_PER_DAY);
+            let seconds = until.sec - day * SECONDS_PER_DAY;
+            let (year, month, day) = civil_from_days(day);
+            write!(f, ";UNTIL={:04}{:02}{:02}T{:02}{:02}{:02}Z", year, month, day, seconds / 3600, seconds / 60 % 60, seconds % 60)?;
+        }
+        if let Some(count) = self.count {
+            write!(f, ";COUNT={}", count)?;
+        }
+        Ok(())
+    }
+}
+
+/// The occurrences of a schedule after its first; see `Recurrence::occurrences`.
+pub struct Occurrences<'a> {
+    recurrence: &'a Recurrence,
+    start_day: i64,
+    time_of_day: i64,
+    period: i64,
+    /// Days still to come in the current period, last first.
+    pending: Vec<i64>,
+    remaining: Option<u32>,
+}
+
+impl<'a> Iterator for Occurrences<'a> {
+    type Item = Timespec;
+
+    fn next(&mut self) -> Option<Timespec> {
+        if self.remaining == Some(0) {
+            return None;
+        }
+        let mut empty_periods = 0;
+        while self.pending.is_empty() {
+            if empty_periods == MAX_EMPTY_PERIODS {
+                return None;
+            }
+            let start_day = self.start_day;
+            self.pending = self.recurrence.days_in_period(start_day, self.period)
+                                          .into_iter()
+                                          .filter(|day| *day > start_day)
+                                          .rev()
+                                          .collect();
+            self.period += 1;
+            empty_periods += 1;
+        }
+        let day = match self.pending.pop() {
+            Some(day) => day,
+            None => return None,
+        };
+        let occurrence = Timespec::new(day * SECONDS_PER_DAY + self.time_of_day, 0);
+        if self.recurrence.until.map_or(false, |until| occurrence > until) {
+            self.remaining = Some(0);
+            return None;
+        }
+        self.remaining = self.remaining.map(|remaining| remaining - 1);
+        Some(occurrence)
+    }
+}
+
+impl Toodle {
+    /// Sets, or with `None` clears, how an item repeats.
+    pub fn set_recurrence(&mut self, uuid: &Uuid, recurrence: Option<&Recurrence>) -> Result<(), list_errors::Error> {
+        let item = match self.fetch_item(uuid)? {
+            Some(item) => item,
+            None => bail!(ErrorKind::NotFound(format!("item {}", uuid.hyphenated()))),
+        };
+        if item.recurrence.as_ref() == recurrence {
+            return Ok(());
+        }
+        let item_id = item.id.to_owned().expect("fetched item must have an ID");
+        let mut builder = TransactionBuilder::new();
+        change_recurrence(&mut builder, item_id, item.recurrence.as_ref(), recurrence);
+        self.connection
+            .transact_builder(&builder)?;
+        self.notify(Topic::Items, ChangeKind::Updated, vec![uuid.hyphenated().to_string()]);
+        Ok(())
+    }
+}
+
+/// Sets the recurrence rule of the item `uuid`, or clears it if `rule` is null. A rule this
+/// library doesn't support fails with `InvalidArgument`.
+#[no_mangle]
+pub extern "C" fn toodle_set_recurrence(toodle: u64, uuid: *const c_char, rule: *const c_char, error: *mut ExternError) {
+    with_toodle(toodle, error, |toodle| {
+        let recurrence = if rule.is_null() { None } else { Some(c_char_to_string(rule).parse::<Recurrence>()?) };
+        toodle.set_recurrence(&parse_uuid(uuid)?, recurrence.as_ref())
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use clock::FixedClock;
+    use import::Import;
+    use items::{
+        Item,
+        ItemUpdate,
+    };
+    use subtasks::Cascade;
+
+    fn date(year: i64, month: i64, day: i64, hours: i64) -> Timespec {
+        Timespec::new(days_from_civil(year, month, day) * SECONDS_PER_DAY + hours * 3600, 0)
+    }
+
+    fn rule(rule: &str) -> Recurrence {
+        rule.parse().expect("expected a rule")
+    }
+
+    fn dates(recurrence: &Recurrence, start: Timespec, n: usize) -> Vec<Timespec> {
+        recurrence.occurrences(start).take(n).collect()
+    }
+
+    #[test]
+    fn test_civil_dates() {
+        assert_eq!(days_from_civil(1970, 1, 1), 0);
+        assert_eq!(days_from_civil(2000, 3, 1), 11017);
+        for day in -1000..30000 {
+            let (year, month, day_of_month) = civil_from_days(day);
+            assert_eq!(days_from_civil(year, month, day_of_month), day);
+        }
+        assert_eq!(Weekday::of_day(0), Weekday::Thursday);
+        assert_eq!(Weekday::of_day(-1), Weekday::Wednesday);
+        assert_eq!(Weekday::of_day(days_from_civil(2017, 7, 17)), Weekday::Monday);
+    }
+
+    #[test]
+    fn test_rules_round_trip() {
+        for text in &["FREQ=DAILY", "FREQ=WEEKLY;INTERVAL=2;BYDAY=MO,TH", "FREQ=MONTHLY;COUNT=3", "FREQ=YEARLY;UNTIL=20271231T090000Z"] {
+            assert_eq!(rule(text).to_string(), *text);
+        }
+        assert_eq!(rule("rrule:freq=weekly;byday=th,mo,th"), Recurrence::new(Frequency::Weekly).on(Weekday::Monday).on(Weekday::Thursday));
+        assert_eq!(rule("FREQ=DAILY;UNTIL=20170720").until, Some(Timespec::new(date(2017, 7, 21, 0).sec - 1, 0)));
+
+        for bad in &["", "INTERVAL=2", "FREQ=HOURLY", "FREQ=DAILY;INTERVAL=0", "FREQ=DAILY;BYDAY=XX", "FREQ=MONTHLY;BYDAY=MO",
+                     "FREQ=DAILY;COUNT=2;UNTIL=20170720", "FREQ=DAILY;UNTIL=20170231", "FREQ=DAILY;BYSETPOS=1", "FREQ"] {
+            match bad.parse::<Recurrence>() {
+                Err(e) => match e.kind() {
+                    &ErrorKind::InvalidArgument(_) => {},
+                    kind => panic!("expected InvalidArgument for {:?}, got {:?}", bad, kind),
+                },
+                Ok(recurrence) => panic!("expected {:?} to be rejected, got {:?}", bad, recurrence),
+            }
+        }
+    }
+
+    #[test]
+    fn test_occurrences() {
+        // Monday 17 July 2017, 9am.
+        let start = date(2017, 7, 17, 9);
+        assert_eq!(dates(&rule("FREQ=DAILY;INTERVAL=3"), start, 2), vec![date(2017, 7, 20, 9), date(2017, 7, 23, 9)]);
+        assert_eq!(dates(&rule("FREQ=DAILY;BYDAY=SA,SU"), start, 3), vec![date(2017, 7, 22, 9), date(2017, 7, 23, 9), date(2017, 7, 29, 9)]);
+        assert_eq!(dates(&rule("FREQ=WEEKLY"), start, 1), vec![date(2017, 7, 24, 9)]);
+        assert_eq!(dates(&rule("FREQ=WEEKLY;INTERVAL=2;BYDAY=MO,TH"), start, 3),
+                   vec![date(2017, 7, 20, 9), date(2017, 7, 31, 9), date(2017, 8, 3, 9)]);
+        assert_eq!(dates(&rule("FREQ=MONTHLY"), date(2017, 1, 31, 9), 3), vec![date(2017, 3, 31, 9), date(2017, 5, 31, 9), date(2017, 7, 31, 9)]);
+        assert_eq!(dates(&rule("FREQ=MONTHLY;INTERVAL=5"), date(2017, 11, 15, 9), 1), vec![date(2018, 4, 15, 9)]);
+        assert_eq!(dates(&rule("FREQ=YEARLY"), date(2016, 2, 29, 9), 2), vec![date(2020, 2, 29, 9), date(2024, 2, 29, 9)]);
+
+        assert_eq!(dates(&rule("FREQ=DAILY;COUNT=3"), start, 10).len(), 2);
+        assert_eq!(dates(&rule("FREQ=WEEKLY;UNTIL=20170731T090000Z"), start, 10), vec![date(2017, 7, 24, 9), date(2017, 7, 31, 9)]);
+        assert_eq!(dates(&rule("FREQ=DAILY;INTERVAL=7;BYDAY=TU"), start, 1), vec![]);
+    }
+
+    #[test]
+    fn test_next_after() {
+        let start = date(2017, 7, 17, 9);
+        let weekly = rule("FREQ=WEEKLY;COUNT=5");
+        assert_eq!(weekly.next_after(start, start), Some((date(2017, 7, 24, 9), rule("FREQ=WEEKLY;COUNT=4"))));
+        // Two missed weeks are skipped, and count as occurrences.
+        assert_eq!(weekly.next_after(start, date(2017, 8, 1, 0)), Some((date(2017, 8, 7, 9), rule("FREQ=WEEKLY;COUNT=2"))));
+        assert_eq!(weekly.next_after(start, date(2017, 8, 14, 9)), None);
+        assert_eq!(rule("FREQ=WEEKLY;COUNT=1").next_after(start, start), None);
+    }
+
+    fn toodle(now: Timespec) -> Toodle {
+        let mut toodle = Toodle::new(String::new(), None).expect("Expected a Toodle");
+        toodle.set_clock(FixedClock(now));
+        toodle
+    }
+
+    fn recurring(toodle: &mut Toodle, name: &str, due_date: Option<Timespec>, recurrence: &str) -> Uuid {
+        let mut item = Item::default();
+        item.name = name.to_string();
+        item.due_date = due_date;
+        item.priority = Some(2);
+        item.recurrence = Some(rule(recurrence));
+        toodle.create_item(&item).expect("expected an item")
+    }
+
+    fn fetch(toodle: &Toodle, uuid: &Uuid) -> Item {
+        toodle.fetch_item(uuid).expect("expected an item option").expect("expected an item")
+    }
+
+    fn by_name(toodle: &Toodle, name: &str) -> Vec<Item> {
+        toodle.fetch_items().expect("expected items").vec.into_iter().filter(|item| item.name == name).collect()
+    }
+
+    fn complete(toodle: &mut Toodle, uuid: &Uuid, date: Timespec) {
+        let item = fetch(toodle, uuid);
+        toodle.update_item(&item, ItemUpdate { completion_date: Some(Some(date)), ..ItemUpdate::default() }).expect("expected an update");
+    }
+
+    #[test]
+    fn test_completing_spawns_the_next_occurrence() {
+        let start = date(2017, 7, 17, 9);
+        let mut toodle = toodle(date(2017, 7, 17, 12));
+        let bins = recurring(&mut toodle, "bins", Some(start), "FREQ=WEEKLY;COUNT=2");
+        complete(&mut toodle, &bins, date(2017, 7, 17, 12));
+
+        let completed = fetch(&toodle, &bins);
+        assert_eq!(completed.recurrence, None);
+        let next: Vec<Item> = by_name(&toodle, "bins").into_iter().filter(|item| item.uuid != bins).collect();
+        assert_eq!(next.len(), 1);
+        assert_eq!(next[0].due_date, Some(date(2017, 7, 24, 9)));
+        assert_eq!(next[0].completion_date, None);
+        assert_eq!(next[0].priority, Some(2));
+        assert_eq!(next[0].recurrence, Some(rule("FREQ=WEEKLY;COUNT=1")));
+
+        // That was the last one.
+        let last = next[0].uuid;
+        complete(&mut toodle, &last, date(2017, 7, 24, 12));
+        assert_eq!(by_name(&toodle, "bins").len(), 2);
+
+        // Editing a completed item again spawns nothing more.
+        complete(&mut toodle, &bins, date(2017, 7, 18, 12));
+        assert_eq!(by_name(&toodle, "bins").len(), 2);
+    }
+
+    #[test]
+    fn test_occurrences_have_the_same_uuid_in_every_store() {
+        let mut bins = Item::default();
+        bins.uuid = Uuid::parse_str("2cb9c3a1-62a7-4cc6-8b46-8fcd7e1d7f47").expect("expected a uuid");
+        bins.name = "bins".to_string();
+        bins.due_date = Some(date(2017, 7, 17, 9));
+        bins.recurrence = Some(rule("FREQ=WEEKLY"));
+        let mut import = Import::default();
+        import.items.push(bins.clone());
+
+        let mut successors = vec![];
+        for _ in 0..2 {
+            let mut toodle = toodle(date(2017, 7, 17, 12));
+            toodle.import(&import).expect("expected an import");
+            complete(&mut toodle, &bins.uuid, date(2017, 7, 17, 12));
+            let next: Vec<Uuid> = by_name(&toodle, "bins").into_iter().map(|item| item.uuid).filter(|uuid| *uuid != bins.uuid).collect();
+            assert_eq!(next.len(), 1);
+            successors.push(next[0]);
+        }
+        assert_eq!(successors[0], successors[1]);
+    }
+
+    #[test]
+    fn test_late_completion_skips_missed_occurrences() {
+        let mut toodle = toodle(date(2017, 7, 20, 12));
+        let standup = recurring(&mut toodle, "standup", Some(date(2017, 7, 17, 9)), "FREQ=DAILY;BYDAY=MO,TU,WE,TH,FR");
+        complete(&mut toodle, &standup, date(2017, 7, 20, 12));
+        let due: Vec<Option<Timespec>> = by_name(&toodle, "standup").into_iter().filter(|item| item.uuid != standup).map(|item| item.due_date).collect();
+        assert_eq!(due, vec![Some(date(2017, 7, 21, 9))]);
+
+        // Without a due date, the schedule starts from the completion.
+        let water = recurring(&mut toodle, "water plants", None, "FREQ=DAILY;INTERVAL=2");
+        complete(&mut toodle, &water, date(2017, 7, 20, 8));
+        let due: Vec<Option<Timespec>> = by_name(&toodle, "water plants").into_iter().filter(|item| item.uuid != water).map(|item| item.due_date).collect();
+        assert_eq!(due, vec![Some(date(2017, 7, 22, 8))]);
+    }
+
+    #[test]
+    fn test_complete_item_spawns_subtask_occurrences() {
+        let now = date(2017, 7, 17, 12);
+        let mut toodle = toodle(now);
+        let mut chores = Item::default();
+        chores.name = "chores".to_string();
+        let chores = toodle.create_item(&chores).expect("expected an item");
+        let mut dishes = Item::default();
+        dishes.name = "dishes".to_string();
+        dishes.due_date = Some(date(2017, 7, 17, 20));
+        dishes.recurrence = Some(rule("FREQ=DAILY"));
+        toodle.create_subtask(&chores, &dishes).expect("expected a subtask");
+
+        toodle.complete_item(&chores, Some(now), Cascade::Descendants).expect("expected a completion");
+        let children = toodle.fetch_children(&chores).expect("expected children");
+        let due: Vec<(Option<Timespec>, bool)> = children.iter().map(|child| (child.due_date, child.completion_date.is_some())).collect();
+        assert_eq!(due, vec![(Some(date(2017, 7, 17, 20)), true), (Some(date(2017, 7, 18, 20)), false)]);
+    }
+
+    #[test]
+    fn test_set_recurrence() {
+        let mut toodle = toodle(date(2017, 7, 17, 12));
+        let uuid = toodle.create_item(&Item::default()).expect("expected an item");
+        toodle.set_recurrence(&uuid, Some(&rule("FREQ=MONTHLY"))).expect("expected a recurrence");
+        assert_eq!(fetch(&toodle, &uuid).recurrence, Some(rule("FREQ=MONTHLY")));
+        toodle.set_recurrence(&uuid, Some(&rule("FREQ=YEARLY"))).expect("expected a recurrence");
+        assert_eq!(fetch(&toodle, &uuid).recurrence, Some(rule("FREQ=YEARLY")));
+        toodle.set_recurrence(&uuid, None).expect("expected no recurrence");
+        assert_eq!(fetch(&toodle, &uuid).recurrence, None);
+    }
+}
@@ -0,0 +1,232 @@
+// Copyright 2016 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! Syncing through a server over HTTP.
+//!
+//! Each user's log lives at a URL such as `http://localhost:8088/users/alice`, and its
+//! records at that URL plus `/records`:
+//!
+//! * `GET .../records?since=VERSION` answers `200 OK` with a `Download`.
+//! * `POST .../records` with an `Upload` answers `200 OK` with a `Head` once the records
+//!   are appended, or `409 Conflict` with a `Head` if `base` is out of date.
+//!
+//! Bodies are JSON. Records are base64, so servers never need to understand them.
+//! Anything else that goes wrong answers with a `Failure`. Only plain `http://` is
+//! spoken: this is for talking to a server on a development machine.
+//!
+//! Only built with the `remote` feature, so that the mobile libraries don't carry an
+//! HTTP client they have no use for.
+
+use std::fmt::Display;
+use std::io::{
+    self,
+    Read,
+    Write,
+};
+use std::net::TcpStream;
+use std::time::Duration;
+
+use base64;
+use serde::de::DeserializeOwned;
+use serde_json;
+
+use errors as list_errors;
+use errors::ErrorKind;
+use sync::{
+    SyncServer,
+    UploadOutcome,
+};
+
+/// How long to wait for a server before giving up on a request.
+const TIMEOUT_SECONDS: u64 = 30;
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Record {
+    pub version: u64,
+    /// The record, base64 encoded.
+    pub payload: String,
+}
+
+/// The answer to a download: `head` is the latest version in the log.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Download {
+    pub head: u64,
+    pub records: Vec<Record>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Upload {
+    pub base: u64,
+    /// The records, base64 encoded, in order.
+    pub records: Vec<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Head {
+    pub head: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Failure {
+    pub error: String,
+}
+
+fn sync_failed<M>(message: M) -> list_errors::Error where M: Display {
+    ErrorKind::SyncFailed(message.to_string()).into()
+}
+
+/// Splits an HTTP response into its status and body.
+fn parse_response(response: &[u8]) -> io::Result<(u16, Vec<u8>)> {
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+    let end = response.windows(4).position(|window| window == b"\r\n\r\n").ok_or_else(|| invalid("truncated response"))?;
+    let head = String::from_utf8_lossy(&response[..end]);
+    let mut lines = head.split("\r\n");
+    let status = lines.next()
+        .and_then(|line| line.split_whitespace().nth(1))
+        .and_then(|status| status.parse().ok())
+        .ok_or_else(|| invalid("malformed status line"))?;
+    let mut body = response[end + 4..].to_vec();
+    for line in lines {
+        let mut parts = line.splitn(2, ':');
+        if parts.next().map(|name| name.trim().to_lowercase()) == Some("content-length".to_string()) {
+            let length = parts.next().and_then(|length| length.trim().parse().ok()).ok_or_else(|| invalid("bad Content-Length"))?;
+            if body.len() < length {
+                return Err(invalid("truncated body"));
+            }
+            body.truncate(length);
+        }
+    }
+    Ok((status, body))
+}
+
+/// A `SyncServer` that's a user's log on an HTTP server, such as `toodle-sync-server`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RemoteServer {
+    url: String,
+    /// The host and port, as `TcpStream::connect` wants them.
+    address: String,
+    host: String,
+    /// The log's path, without a trailing slash.
+    path: String,
+}
+
+impl RemoteServer {
+    /// The server for the log at `url`, e.g. `http://localhost:8088/users/alice`.
+    pub fn new(url: &str) -> Result<RemoteServer, list_errors::Error> {
+        if !url.starts_with("http://") {
+            return Err(ErrorKind::InvalidArgument(format!("{} isn't an http:// URL", url)).into());
+        }
+        let rest = &url["http://".len()..];
+        let (host, path) = match rest.find('/') {
+            Some(slash) => (&rest[..slash], &rest[slash..]),
+            None => (rest, ""),
+        };
+        if host.is_empty() {
+            return Err(ErrorKind::InvalidArgument(format!("{} has no host", url)).into());
+        }
+        let has_port = host.rfind(':').map_or(false, |colon| !host[colon..].contains(']'));
+        Ok(RemoteServer {
+            url: url.to_string(),
+            address: if has_port { host.to_string() } else { format!("{}:80", host) },
+            host: host.to_string(),
+            path: path.trim_end_matches('/').to_string(),
+        })
+    }
+
+    fn request(&self, method: &str, target: &str, body: &[u8]) -> io::Result<(u16, Vec<u8>)> {
+        let mut stream = TcpStream::connect(self.address.as_str())?;
+        stream.set_read_timeout(Some(Duration::from_secs(TIMEOUT_SECONDS)))?;
+        stream.set_write_timeout(Some(Duration::from_secs(TIMEOUT_SECONDS)))?;
+        write!(stream, "{} {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+               method, target, self.host, body.len())?;
+        stream.write_all(body)?;
+        let mut response = vec![];
+        stream.read_to_end(&mut response)?;
+        parse_response(&response)
+    }
+
+    /// Makes a request, returning the status and body when it's one of `expected`.
+    fn call(&self, method: &str, target: &str, body: &[u8], expected: &[u16]) -> Result<(u16, Vec<u8>), list_errors::Error> {
+        let (status, body) = self.request(method, target, body).map_err(|e| sync_failed(format!("{}: {}", self.url, e)))?;
+        if expected.contains(&status) {
+            return Ok((status, body));
+        }
+        let message = serde_json::from_slice::<Failure>(&body).map(|failure| failure.error).unwrap_or_default();
+        Err(sync_failed(format!("{} answered {} {}", self.url, status, message)))
+    }
+
+    fn parse<T>(&self, body: &[u8]) -> Result<T, list_errors::Error> where T: DeserializeOwned {
+        serde_json::from_slice(body).map_err(|e| sync_failed(format!("{} sent a bad reply: {}", self.url, e)))
+    }
+}
+
+impl SyncServer for RemoteServer {
+    fn download_since(&mut self, version: u64) -> Result<Vec<(u64, Vec<u8>)>, list_errors::Error> {
+        let (_, body) = self.call("GET", &format!("{}/records?since={}", self.path, version), &[], &[200])?;
+        let download: Download = self.parse(&body)?;
+        download.records.into_iter().map(|record| {
+            base64::decode(&record.payload)
+                .map(|payload| (record.version, payload))
+                .map_err(|_| ErrorKind::BadSyncRecord(format!("version {} isn't base64", record.version)).into())
+        }).collect()
+    }
+
+    fn upload(&mut self, base: u64, records: Vec<Vec<u8>>) -> Result<UploadOutcome, list_errors::Error> {
+        let upload = Upload {
+            base: base,
+            records: records.iter().map(base64::encode).collect(),
+        };
+        let (status, body) = self.call("POST", &format!("{}/records", self.path), &serde_json::to_vec(&upload)?, &[200, 409])?;
+        let head: Head = self.parse(&body)?;
+        Ok(if status == 409 {
+            UploadOutcome::OutOfDate { head: head.head }
+        } else {
+            UploadOutcome::Accepted { head: head.head }
+        })
+    }
+
+    /// The log's path, which names the user whatever the server's address is spelled as.
+    fn log_name(&self) -> String {
+        self.path.clone()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_urls() {
+        let server = RemoteServer::new("http://localhost:8088/users/alice/").expect("expected a server");
+        assert_eq!((server.address.as_str(), server.host.as_str(), server.path.as_str()), ("localhost:8088", "localhost:8088", "/users/alice"));
+        let server = RemoteServer::new("http://example.com").expect("expected a server");
+        assert_eq!((server.address.as_str(), server.path.as_str()), ("example.com:80", ""));
+        let server = RemoteServer::new("http://[::1]/users/bob").expect("expected a server");
+        assert_eq!(server.address, "[::1]:80");
+
+        match RemoteServer::new("https://example.com/users/alice") {
+            Err(e) => match e.kind() {
+                &ErrorKind::InvalidArgument(_) => {},
+                kind => panic!("expected an invalid argument, not {:?}", kind),
+            },
+            Ok(_) => panic!("expected https to be refused"),
+        }
+        assert!(RemoteServer::new("http:///users/alice").is_err());
+    }
+
+    #[test]
+    fn test_parse_responses() {
+        let response = b"HTTP/1.1 409 Conflict\r\nContent-Type: application/json\r\nContent-Length: 10\r\n\r\n{\"head\":3}";
+        assert_eq!(parse_response(response).expect("expected a response"), (409, b"{\"head\":3}".to_vec()));
+        assert!(parse_response(b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\n{}").is_err());
+        assert!(parse_response(b"HTTP/1.1 200 OK\r\n").is_err());
+        assert!(parse_response(b"garbage\r\n\r\n").is_err());
+    }
+}
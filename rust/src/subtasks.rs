@@ -0,0 +1,638 @@
+// Copyright 2016 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! Subtasks: items nested under other items through `:item/parent`.
+//!
+//! An item has at most one parent, and moving an item never makes it its own ancestor.
+//! Two stores moving items concurrently can still sync into a cycle; `fetch_item_trees`
+//! then lists the items in it at the top level rather than losing them. Children are
+//! always in the order they were created.
+
+use std::collections::HashMap;
+use std::os::raw::c_char;
+
+use libc::{ c_int, time_t };
+use mentat_core::{
+    Entid,
+    TypedValue,
+    Uuid,
+};
+use mentat::query::{
+    IntoResult,
+    Variable,
+};
+use time::Timespec;
+
+use errors as list_errors;
+use errors::ErrorKind;
+use ffi_errors::{
+    checked_string,
+    ExternError,
+};
+use ffi_utils::strings::optional_timespec;
+use handles::{
+    ITEMS,
+    ITEM_LISTS,
+};
+use items::Item;
+use observers::{
+    ChangeKind,
+    Topic,
+};
+use store::{
+    Entity,
+    ToInner,
+    TransactionBuilder,
+};
+use {
+    HYDRATION_BATCH,
+    create_uuid,
+    date_value,
+    kw,
+    parse_uuid,
+    with_toodle,
+    Toodle,
+};
+
+/// Which items deleting or completing an item applies to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Cascade {
+    /// Just the item. A deleted item's subtasks move up to its parent.
+    ItemOnly,
+    /// The item and all of its subtasks, however deeply nested.
+    Descendants,
+}
+
+/// How many of an item's direct subtasks are complete.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Progress {
+    pub completed: usize,
+    pub total: usize,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ItemTree {
+    pub item: Item,
+    pub children: Vec<ItemTree>,
+}
+
+impl ItemTree {
+    pub fn progress(&self) -> Progress {
+        Progress {
+            completed: self.children.iter().filter(|child| child.item.completion_date.is_some()).count(),
+            total: self.children.len(),
+        }
+    }
+}
+
+fn entid(item: &Item) -> Entid {
+    item.id.as_ref().expect("fetched item must have an ID").id
+}
+
+/// Children of each parent, in creation order.
+fn children_by_parent(parents: &HashMap<Entid, Entid>) -> HashMap<Entid, Vec<Entid>> {
+    let mut children: HashMap<Entid, Vec<Entid>> = HashMap::new();
+    for (child, parent) in parents.iter() {
+        children.entry(*parent).or_insert_with(Vec::new).push(*child);
+    }
+    for siblings in children.values_mut() {
+        siblings.sort();
+    }
+    children
+}
+
+/// `id` and everything below it, depth first.
+fn subtree(id: Entid, children: &HashMap<Entid, Vec<Entid>>) -> Vec<Entid> {
+    let mut found = vec![];
+    let mut pending = vec![id];
+    while let Some(next) = pending.pop() {
+        if found.contains(&next) {
+            continue;
+        }
+        found.push(next);
+        if let Some(below) = children.get(&next) {
+            pending.extend(below.iter().rev());
+        }
+    }
+    found
+}
+
+/// Takes `id` and its descendants out of `items`, so that each item lands in one tree only.
+fn build_tree(id: Entid, items: &mut HashMap<Entid, Item>, children: &HashMap<Entid, Vec<Entid>>) -> Option<ItemTree> {
+    let item = match items.remove(&id) {
+        Some(item) => item,
+        None => return None,
+    };
+    let subtasks = children.get(&id)
+                           .map(|ids| ids.iter().filter_map(|child| build_tree(*child, items, children)).collect())
+                           .unwrap_or(vec![]);
+    Some(ItemTree { item: item, children: subtasks })
+}
+
+impl Toodle {
+    /// The parent of every item that has one, by entid. Links to deleted items are left out.
+    fn fetch_parents(&self) -> Result<HashMap<Entid, Entid>, list_errors::Error> {
+        let query = r#"[:find ?child ?parent
+                        :where
+                        [?child :item/parent ?parent]
+                        [?parent :item/uuid _]
+        ]"#;
+        self.connection
+            .query(query)
+            .into_rel_result()
+            .map(|rows| rows.into_iter().filter_map(|row| {
+                let child: Option<Entity> = row[0].clone().to_inner();
+                let parent: Option<Entity> = row[1].clone().to_inner();
+                match (child, parent) {
+                    (Some(child), Some(parent)) => Some((child.id, parent.id)),
+                    _ => None,
+                }
+            }).collect())
+            .map_err(|e| e.into())
+    }
+
+    fn fetch_all_items_by_entid(&self) -> Result<HashMap<Entid, Item>, list_errors::Error> {
+        Ok(self.fetch_items()?.vec.into_iter().map(|item| (entid(&item), item)).collect())
+    }
+
+    /// The items among `ids`, by entid. Only these items are looked at, with a fixed
+    /// number of queries for every `HYDRATION_BATCH` of them.
+    fn fetch_items_by_entid(&self, ids: &[Entid]) -> Result<HashMap<Entid, Item>, list_errors::Error> {
+        let mut items = HashMap::with_capacity(ids.len());
+        for batch in ids.chunks(HYDRATION_BATCH) {
+            let ids: Vec<String> = batch.iter().map(|id| id.to_string()).collect();
+            let scope = format!("[(ground [{}]) [?eid ...]]", ids.join(" "));
+            let query = format!(r#"[:find ?eid ?uuid ?name
+                                    :where
+                                    {}
+                                    [?eid :item/uuid ?uuid]
+                                    [?eid :item/name ?name]
+            ]"#, scope);
+            let rows = self.connection
+                           .query(&query)
+                           .into_rel_result()?;
+            items.extend(self.hydrate_item_rows(rows, &scope)?.into_iter().map(|item| (entid(&item), item)));
+        }
+        Ok(items)
+    }
+
+    fn fetch_existing_item(&self, uuid: &Uuid) -> Result<Item, list_errors::Error> {
+        match self.fetch_item(uuid)? {
+            Some(item) => Ok(item),
+            None => bail!(ErrorKind::NotFound(format!("item {}", uuid.hyphenated()))),
+        }
+    }
+
+    /// Creates `item` as the last subtask of `parent`.
+    pub fn create_subtask(&mut self, parent: &Uuid, item: &Item) -> Result<Uuid, list_errors::Error> {
+        let parent = self.fetch_existing_item(parent)?;
+        let item_uuid = create_uuid();
+        self.create_item_with_uuid(item_uuid, item, Some(entid(&parent)))?;
+        Ok(item_uuid)
+    }
+
+    pub fn fetch_parent(&self, uuid: &Uuid) -> Result<Option<Item>, list_errors::Error> {
+        let item = self.fetch_existing_item(uuid)?;
+        let query = r#"[:find [?parent ?uuid ?name]
+                        :in ?child
+                        :where
+                        [?child :item/parent ?parent]
+                        [?parent :item/uuid ?uuid]
+                        [?parent :item/name ?name]
+        ]"#;
+        let row = self.connection
+                      .query_args(query, vec![(Variable::from_valid_name("?child"), TypedValue::Ref(entid(&item)))])
+                      .into_tuple_result()?;
+        match row {
+            Some(row) => Ok(self.item_rows_to_items(vec![row])?.pop()),
+            None => Ok(None),
+        }
+    }
+
+    pub fn fetch_children(&self, uuid: &Uuid) -> Result<Vec<Item>, list_errors::Error> {
+        let item = self.fetch_existing_item(uuid)?;
+        let query = r#"[:find ?child ?uuid ?name
+                        :in ?parent
+                        :where
+                        [?child :item/parent ?parent]
+                        [?child :item/uuid ?uuid]
+                        [?child :item/name ?name]
+        ]"#;
+        let mut rows = self.connection
+                           .query_args(query, vec![(Variable::from_valid_name("?parent"), TypedValue::Ref(entid(&item)))])
+                           .into_rel_result()?;
+        // Entids grow as items are created, so this is creation order.
+        rows.sort_by_key(|row| match row[0] {
+            TypedValue::Ref(id) => id,
+            _ => 0,
+        });
+        self.item_rows_to_items(rows)
+    }
+
+    /// Moves an item under `parent`, or to the top level if `parent` is `None`. Fails if
+    /// `parent` is the item itself or one of its subtasks.
+    pub fn move_item(&mut self, uuid: &Uuid, parent: Option<&Uuid>) -> Result<(), list_errors::Error> {
+        let item_id = entid(&self.fetch_existing_item(uuid)?);
+        let parents = self.fetch_parents()?;
+        let new_parent = match parent {
+            Some(parent) => Some(entid(&self.fetch_existing_item(parent)?)),
+            None => None,
+        };
+        let old_parent = parents.get(&item_id).cloned();
+        if new_parent == old_parent {
+            return Ok(());
+        }
+        if let Some(new_parent) = new_parent {
+            // A cycle that sync left behind ends the walk after visiting every parent once.
+            let mut ancestor = Some(new_parent);
+            for _ in 0..parents.len() + 1 {
+                let id = match ancestor {
+                    Some(id) => id,
+                    None => break,
+                };
+                if id == item_id {
+                    bail!(ErrorKind::InvalidArgument(format!("item {} can't be moved under itself or one of its subtasks", uuid.hyphenated())));
+                }
+                ancestor = parents.get(&id).cloned();
+            }
+        }
+
+        let mut builder = TransactionBuilder::new();
+        if let Some(old_parent) = old_parent {
+            builder.retract(item_id, kw("item", "parent"), TypedValue::Ref(old_parent));
+        }
+        if let Some(new_parent) = new_parent {
+            builder.add(item_id, kw("item", "parent"), TypedValue::Ref(new_parent));
+        }
+        self.connection
+            .transact_builder(&builder)?;
+        self.notify(Topic::Items, ChangeKind::Updated, vec![uuid.hyphenated().to_string()]);
+        Ok(())
+    }
+
+    /// The item and all of its subtasks, or `None` if there's no such item.
+    pub fn fetch_item_tree(&self, uuid: &Uuid) -> Result<Option<ItemTree>, list_errors::Error> {
+        let id = match self.fetch_item(uuid)? {
+            Some(item) => entid(&item),
+            None => return Ok(None),
+        };
+        let children = children_by_parent(&self.fetch_parents()?);
+        let mut items = self.fetch_items_by_entid(&subtree(id, &children))?;
+        Ok(build_tree(id, &mut items, &children))
+    }
+
+    /// Every top-level item with its subtasks, in creation order.
+    pub fn fetch_item_trees(&self) -> Result<Vec<ItemTree>, list_errors::Error> {
+        let mut items = self.fetch_all_items_by_entid()?;
+        let parents = self.fetch_parents()?;
+        let children = children_by_parent(&parents);
+        let mut ids: Vec<Entid> = items.keys().cloned().collect();
+        ids.sort();
+
+        let mut trees: Vec<ItemTree> = ids.iter()
+                                          .filter(|id| !parents.contains_key(*id))
+                                          .filter_map(|id| build_tree(*id, &mut items, &children))
+                                          .collect();
+        // Whatever is left is in a cycle, or below one.
+        for id in ids.iter() {
+            if let Some(tree) = build_tree(*id, &mut items, &children) {
+                trees.push(tree);
+            }
+        }
+        Ok(trees)
+    }
+
+    /// How many of an item's direct subtasks are complete.
+    pub fn fetch_progress(&self, uuid: &Uuid) -> Result<Progress, list_errors::Error> {
+        let children = self.fetch_children(uuid)?;
+        Ok(Progress {
+            completed: children.iter().filter(|child| child.completion_date.is_some()).count(),
+            total: children.len(),
+        })
+    }
+
+    /// Sets, or with `None` clears, the completion date of an item and, if `cascade` says
+    /// so, of all of its subtasks. Recurring items that this completes spawn their next
+    /// occurrence, as with `update_item`.
+    pub fn complete_item(&mut self, uuid: &Uuid, completion_date: Option<Timespec>, cascade: Cascade) -> Result<(), list_errors::Error> {
+        let item = self.fetch_existing_item(uuid)?;
+        let targets = match cascade {
+            Cascade::ItemOnly => vec![entid(&item)],
+            Cascade::Descendants => subtree(entid(&item), &children_by_parent(&self.fetch_parents()?)),
+        };
+        let mut items = match cascade {
+            Cascade::ItemOnly => vec![(entid(&item), item)].into_iter().collect(),
+            Cascade::Descendants => self.fetch_items_by_entid(&targets)?,
+        };
+
+        let mut builder = TransactionBuilder::new();
+        let mut changed = vec![];
+        let mut next = vec![];
+        for item in targets.iter().filter_map(|id| items.remove(id)) {
+            // Dates are compared as they're stored, to whole seconds.
+            if item.completion_date.map(|date| date.sec) == completion_date.map(|date| date.sec) {
+                continue;
+            }
+            let item_id = entid(&item);
+            if let Some(date) = completion_date {
+                next.extend(self.complete_recurrence(&mut builder, &item, date)?);
+            }
+            match (completion_date, item.completion_date) {
+                (Some(date), _) => builder.add(item_id, kw("item", "completion_date"), date_value(&date)),
+                (None, Some(old)) => builder.retract(item_id, kw("item", "completion_date"), date_value(&old)),
+                (None, None) => continue,
+            };
+            changed.push(item.uuid.hyphenated().to_string());
+        }
+        if builder.is_empty() {
+            return Ok(());
+        }
+        self.connection
+            .transact_builder(&builder)?;
+        self.notify(Topic::Items, ChangeKind::Updated, changed);
+        self.notify_next_occurrences(next);
+        Ok(())
+    }
+
+    /// Deletes an item. With `Cascade::ItemOnly` its subtasks move up to its parent, or to
+    /// the top level; with `Cascade::Descendants` they're deleted with it.
+    pub fn delete_item_with(&mut self, uuid: &Uuid, cascade: Cascade) -> Result<(), list_errors::Error> {
+        let item = match self.fetch_item(uuid)? {
+            Some(item) => item,
+            None => return Ok(()),
+        };
+        let item_id = entid(&item);
+        let parents = self.fetch_parents()?;
+        let children = children_by_parent(&parents);
+
+        let mut builder = TransactionBuilder::new();
+        let doomed = match cascade {
+            Cascade::ItemOnly => {
+                let grandparent = parents.get(&item_id).cloned();
+                for child in children.get(&item_id).map(|ids| ids.as_slice()).unwrap_or(&[]) {
+                    builder.retract(*child, kw("item", "parent"), TypedValue::Ref(item_id));
+                    // In a cycle synced from two stores the grandparent can be the child
+                    // itself, which mustn't become its own parent.
+                    if let Some(grandparent) = grandparent {
+                        if grandparent != *child {
+                            builder.add(*child, kw("item", "parent"), TypedValue::Ref(grandparent));
+                        }
+                    }
+                }
+                vec![item_id]
+            },
+            Cascade::Descendants => subtree(item_id, &children),
+        };
+        let mut items = self.fetch_items_by_entid(&doomed)?;
+
+        let mut deleted = vec![];
+        for item in doomed.iter().filter_map(|id| items.remove(id)) {
+            let id = entid(&item);
+            self.retract_item(&mut builder, &item);
+            if let Some(parent) = parents.get(&id) {
+                builder.retract(id, kw("item", "parent"), TypedValue::Ref(*parent));
+            }
+            deleted.push(item.uuid.hyphenated().to_string());
+        }
+        self.connection
+            .transact_builder(&builder)?;
+        self.notify(Topic::Items, ChangeKind::Deleted, deleted);
+        Ok(())
+    }
+}
+
+/// Returns a handle to the new item, which is added as the last subtask of `parent`.
+#[no_mangle]
+pub extern "C" fn toodle_create_subtask(toodle: u64, parent: *const c_char, name: *const c_char, due_date: *const time_t, error: *mut ExternError) -> u64 {
+    with_toodle(toodle, error, |toodle| {
+        let parent = parse_uuid(parent)?;
+        let mut item = Item::default();
+        item.name = checked_string(name, "name")?;
+        item.due_date = optional_timespec(due_date);
+        let uuid = toodle.create_subtask(&parent, &item)?;
+        match toodle.fetch_item(&uuid)? {
+            Some(item) => Ok(ITEMS.insert(item)),
+            None => bail!(ErrorKind::NotFound("the item that was just created".to_string())),
+        }
+    })
+}
+
+/// A null `parent` moves the item to the top level.
+#[no_mangle]
+pub extern "C" fn toodle_move_item(toodle: u64, uuid: *const c_char, parent: *const c_char, error: *mut ExternError) {
+    with_toodle(toodle, error, |toodle| {
+        let uuid = parse_uuid(uuid)?;
+        let parent = if parent.is_null() { None } else { Some(parse_uuid(parent)?) };
+        toodle.move_item(&uuid, parent.as_ref())
+    })
+}
+
+/// Returns a handle to a list of the item's direct subtasks.
+#[no_mangle]
+pub extern "C" fn toodle_get_children(toodle: u64, uuid: *const c_char, error: *mut ExternError) -> u64 {
+    with_toodle(toodle, error, |toodle| {
+        let uuid = parse_uuid(uuid)?;
+        Ok(ITEM_LISTS.insert(toodle.fetch_children(&uuid)?))
+    })
+}
+
+/// Returns how many direct subtasks the item has, and stores how many of them are
+/// complete in `completed`.
+#[no_mangle]
+pub unsafe extern "C" fn toodle_get_progress(toodle: u64, uuid: *const c_char, completed: *mut c_int, error: *mut ExternError) -> c_int {
+    with_toodle(toodle, error, |toodle| {
+        let progress = toodle.fetch_progress(&parse_uuid(uuid)?)?;
+        if !completed.is_null() {
+            *completed = progress.completed as c_int;
+        }
+        Ok(progress.total as c_int)
+    })
+}
+
+/// A null `completion_date` marks the items incomplete. A non-zero `cascade` applies the
+/// change to all of the item's subtasks too.
+#[no_mangle]
+pub extern "C" fn toodle_complete_item(toodle: u64, uuid: *const c_char, completion_date: *const time_t, cascade: c_int, error: *mut ExternError) {
+    with_toodle(toodle, error, |toodle| {
+        let uuid = parse_uuid(uuid)?;
+        let cascade = if cascade != 0 { Cascade::Descendants } else { Cascade::ItemOnly };
+        toodle.complete_item(&uuid, optional_timespec(completion_date), cascade)
+    })
+}
+
+/// Deletes an item along with all of its subtasks.
+#[no_mangle]
+pub extern "C" fn toodle_delete_item_with_subtasks(toodle: u64, uuid: *const c_char, error: *mut ExternError) {
+    with_toodle(toodle, error, |toodle| {
+        let uuid = parse_uuid(uuid)?;
+        toodle.delete_item_with(&uuid, Cascade::Descendants)
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn toodle() -> Toodle {
+        Toodle::new(String::new(), None).expect("expected a new toodle")
+    }
+
+    fn add(toodle: &mut Toodle, name: &str, parent: Option<&Uuid>) -> Uuid {
+        let mut item = Item::default();
+        item.name = name.to_string();
+        match parent {
+            Some(parent) => toodle.create_subtask(parent, &item).expect("expected a subtask"),
+            None => toodle.create_item(&item).expect("expected an item"),
+        }
+    }
+
+    fn names(items: &[Item]) -> Vec<String> {
+        items.iter().map(|item| item.name.clone()).collect()
+    }
+
+    /// Each tree as `name(child, child(grandchild))`.
+    fn outline(trees: &[ItemTree]) -> Vec<String> {
+        trees.iter().map(|tree| {
+            if tree.children.is_empty() {
+                tree.item.name.clone()
+            } else {
+                format!("{}({})", tree.item.name, outline(&tree.children).join(", "))
+            }
+        }).collect()
+    }
+
+    #[test]
+    fn test_subtasks() {
+        let mut toodle = toodle();
+        let move_house = add(&mut toodle, "move house", None);
+        let pack = add(&mut toodle, "pack", Some(&move_house));
+        add(&mut toodle, "books", Some(&pack));
+        add(&mut toodle, "clean", Some(&move_house));
+        add(&mut toodle, "buy milk", None);
+
+        assert_eq!(names(&toodle.fetch_children(&move_house).expect("expected children")), vec!["pack", "clean"]);
+        assert_eq!(toodle.fetch_parent(&pack).expect("expected a parent option").map(|item| item.uuid), Some(move_house));
+        assert_eq!(toodle.fetch_parent(&move_house).expect("expected a parent option"), None);
+        assert_eq!(outline(&toodle.fetch_item_trees().expect("expected trees")), vec!["move house(pack(books), clean)", "buy milk"]);
+        assert_eq!(outline(&[toodle.fetch_item_tree(&pack).expect("expected a tree option").expect("expected a tree")]), vec!["pack(books)"]);
+
+        // Every item is still listed on its own.
+        assert_eq!(toodle.fetch_items().expect("expected items").vec.len(), 5);
+
+        match toodle.create_subtask(&Uuid::nil(), &Item::default()) {
+            Err(e) => match e.kind() {
+                &ErrorKind::NotFound(_) => (),
+                kind => panic!("expected NotFound, got {:?}", kind),
+            },
+            Ok(_) => panic!("expected a missing parent to be refused"),
+        }
+    }
+
+    #[test]
+    fn test_move_item() {
+        let mut toodle = toodle();
+        let a = add(&mut toodle, "a", None);
+        let b = add(&mut toodle, "b", Some(&a));
+        let c = add(&mut toodle, "c", Some(&b));
+
+        toodle.move_item(&c, Some(&a)).expect("expected a move");
+        assert_eq!(outline(&toodle.fetch_item_trees().expect("expected trees")), vec!["a(b, c)"]);
+        toodle.move_item(&b, None).expect("expected a move");
+        assert_eq!(outline(&toodle.fetch_item_trees().expect("expected trees")), vec!["a(c)", "b"]);
+
+        // Neither an item nor its subtasks can become its parent.
+        toodle.move_item(&b, Some(&a)).expect("expected a move");
+        for parent in &[a, b, c] {
+            match toodle.move_item(&a, Some(parent)) {
+                Err(e) => match e.kind() {
+                    &ErrorKind::InvalidArgument(_) => (),
+                    kind => panic!("expected InvalidArgument, got {:?}", kind),
+                },
+                Ok(_) => panic!("expected a cycle to be refused"),
+            }
+        }
+        // Subtasks stay in creation order, however they were moved.
+        assert_eq!(outline(&toodle.fetch_item_trees().expect("expected trees")), vec!["a(b, c)"]);
+    }
+
+    #[test]
+    fn test_cycles_from_sync_are_listed() {
+        let mut toodle = toodle();
+        let a = add(&mut toodle, "a", None);
+        let b = add(&mut toodle, "b", Some(&a));
+        add(&mut toodle, "c", None);
+
+        // As if another store had concurrently moved `a` under `b`.
+        let (a_id, b_id) = (toodle.fetch_item(&a).unwrap().unwrap().id.unwrap().id, toodle.fetch_item(&b).unwrap().unwrap().id.unwrap().id);
+        let mut builder = TransactionBuilder::new();
+        builder.add(a_id, kw("item", "parent"), TypedValue::Ref(b_id));
+        toodle.connection.transact_builder(&builder).expect("expected a transaction");
+
+        assert_eq!(outline(&toodle.fetch_item_trees().expect("expected trees")), vec!["c", "a(b)"]);
+    }
+
+    #[test]
+    fn test_deleting_from_a_cycle() {
+        let mut toodle = toodle();
+        let a = add(&mut toodle, "a", None);
+        let b = add(&mut toodle, "b", Some(&a));
+
+        let (a_id, b_id) = (toodle.fetch_item(&a).unwrap().unwrap().id.unwrap().id, toodle.fetch_item(&b).unwrap().unwrap().id.unwrap().id);
+        let mut builder = TransactionBuilder::new();
+        builder.add(a_id, kw("item", "parent"), TypedValue::Ref(b_id));
+        toodle.connection.transact_builder(&builder).expect("expected a transaction");
+
+        // `b` is both `a`'s child and its parent, so it's left without one.
+        toodle.delete_item_with(&a, Cascade::ItemOnly).expect("expected a deletion");
+        assert_eq!(outline(&toodle.fetch_item_trees().expect("expected trees")), vec!["b"]);
+    }
+
+    #[test]
+    fn test_progress_and_complete() {
+        let mut toodle = toodle();
+        let chores = add(&mut toodle, "chores", None);
+        let dishes = add(&mut toodle, "dishes", Some(&chores));
+        let laundry = add(&mut toodle, "laundry", Some(&chores));
+        let fold = add(&mut toodle, "fold", Some(&laundry));
+
+        assert_eq!(toodle.fetch_progress(&chores).expect("expected progress"), Progress { completed: 0, total: 2 });
+        let now = Timespec::new(1_500_000_000, 0);
+        toodle.complete_item(&dishes, Some(now), Cascade::ItemOnly).expect("expected a completion");
+        assert_eq!(toodle.fetch_progress(&chores).expect("expected progress"), Progress { completed: 1, total: 2 });
+        let tree = toodle.fetch_item_tree(&chores).expect("expected a tree option").expect("expected a tree");
+        assert_eq!(tree.progress(), Progress { completed: 1, total: 2 });
+
+        toodle.complete_item(&chores, Some(now), Cascade::Descendants).expect("expected a completion");
+        for uuid in &[chores, dishes, laundry, fold] {
+            assert_eq!(toodle.fetch_item(uuid).unwrap().unwrap().completion_date, Some(now));
+        }
+        toodle.complete_item(&laundry, None, Cascade::Descendants).expect("expected an undo");
+        assert_eq!(toodle.fetch_item(&fold).unwrap().unwrap().completion_date, None);
+        assert_eq!(toodle.fetch_item(&chores).unwrap().unwrap().completion_date, Some(now));
+        assert_eq!(toodle.fetch_progress(&chores).expect("expected progress"), Progress { completed: 1, total: 2 });
+    }
+
+    #[test]
+    fn test_delete_cascades() {
+        let mut toodle = toodle();
+        let a = add(&mut toodle, "a", None);
+        let b = add(&mut toodle, "b", Some(&a));
+        add(&mut toodle, "c", Some(&b));
+        add(&mut toodle, "d", Some(&b));
+
+        // Deleting just `b` hands its subtasks to `a`.
+        toodle.delete_item(&b).expect("expected a deletion");
+        assert_eq!(outline(&toodle.fetch_item_trees().expect("expected trees")), vec!["a(c, d)"]);
+
+        toodle.delete_item_with(&a, Cascade::Descendants).expect("expected a deletion");
+        assert_eq!(toodle.fetch_items().expect("expected items").vec, vec![]);
+        assert_eq!(toodle.fetch_item_trees().expect("expected trees"), vec![]);
+    }
+}
@@ -0,0 +1,1137 @@
+// Copyright 2016 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! Syncing a `Toodle` through a `SyncServer`.
+//!
+//! Every local Mentat transaction that touches the `:item` or `:label` vocabulary is
+//! turned into a `SyncTransaction`. Entities are named by their `:item/uuid` or
+//! `:label/name` rather than by local entids, which mean nothing to other stores. The
+//! server keeps an ordered log of opaque records; each one is a serialized
+//! `SyncTransaction` and its position in the log is its version.
+//!
+//! A sync first collects local transactions since the last upload, then downloads and
+//! applies remote records since the last download, and finally uploads the local
+//! transactions on top of the server's latest version.
+//!
+//! When a remote change touches a field that also has a pending local change, a
+//! `ConflictPolicy` picks one. If the local change wins, the remote one is skipped: the
+//! local change is about to land after it in the server's log, so every store ends up
+//! agreeing with it. If the remote change wins, it's applied here and the local change
+//! is left out of the upload.
+
+use std::collections::{
+    BTreeMap,
+    HashMap,
+    HashSet,
+};
+use std::str::FromStr;
+
+use edn::{
+    DateTime,
+    FromMicros,
+    NamespacedKeyword,
+    Utc,
+};
+use mentat::query::{
+    IntoResult,
+    Variable,
+};
+use mentat_core::{
+    Entid,
+    TypedValue,
+    Uuid,
+};
+use serde_json;
+use time::Timespec;
+
+use conflicts::{
+    ConflictPolicy,
+    DefaultPolicy,
+    Winner,
+};
+use errors as list_errors;
+use observers::{
+    ChangeKind,
+    Topic,
+};
+use errors::ErrorKind;
+use store::{
+    EntityPlace,
+    ToTypedValue,
+    TransactionBuilder,
+    ValuePlace,
+};
+
+use {
+    kw,
+    Toodle,
+};
+
+/// How many times to download and retry when an upload is rejected as out of date.
+const MAX_SYNC_ATTEMPTS: usize = 5;
+
+/// The identity of an entity, valid across stores.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum EntityId {
+    Item { uuid: String },
+    Label { name: String },
+}
+
+impl EntityId {
+    fn tempid(&self) -> String {
+        match self {
+            &EntityId::Item { ref uuid } => format!("item {}", uuid),
+            &EntityId::Label { ref name } => format!("label {}", name),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(tag = "type", content = "value", rename_all = "lowercase")]
+pub enum SyncValue {
+    String(String),
+    Long(i64),
+    Boolean(bool),
+    /// Microseconds since the epoch.
+    Instant(i64),
+    Uuid(String),
+    Ref(EntityId),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Change {
+    pub entity: EntityId,
+    /// The attribute without its leading colon, e.g. `item/name`.
+    pub attribute: String,
+    pub value: SyncValue,
+    pub added: bool,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SyncTransaction {
+    pub changes: Vec<Change>,
+}
+
+impl SyncTransaction {
+    pub fn to_bytes(&self) -> Result<Vec<u8>, list_errors::Error> {
+        Ok(serde_json::to_vec(self)?)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<SyncTransaction, list_errors::Error> {
+        serde_json::from_slice(bytes).map_err(|e| ErrorKind::BadSyncRecord(e.to_string()).into())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UploadOutcome {
+    /// The records were appended; `head` is the version of the last of them.
+    Accepted { head: u64 },
+    /// The server has moved on since `base`; download before trying again.
+    OutOfDate { head: u64 },
+}
+
+/// An ordered log of opaque records. Versions start at 1; version 0 is the empty log.
+pub trait SyncServer {
+    /// Every record after `version`, paired with its version, in order.
+    fn download_since(&mut self, version: u64) -> Result<Vec<(u64, Vec<u8>)>, list_errors::Error>;
+
+    /// Appends `records` if and only if `base` is the server's latest version.
+    fn upload(&mut self, base: u64, records: Vec<Vec<u8>>) -> Result<UploadOutcome, list_errors::Error>;
+
+    /// Names the log, so that `EncryptedServer` can bind records to it. Logs without a
+    /// name of their own have an empty one.
+    fn log_name(&self) -> String {
+        String::new()
+    }
+}
+
+impl<'a, S> SyncServer for &'a mut S where S: SyncServer + ?Sized {
+    fn download_since(&mut self, version: u64) -> Result<Vec<(u64, Vec<u8>)>, list_errors::Error> {
+        (**self).download_since(version)
+    }
+
+    fn upload(&mut self, base: u64, records: Vec<Vec<u8>>) -> Result<UploadOutcome, list_errors::Error> {
+        (**self).upload(base, records)
+    }
+
+    fn log_name(&self) -> String {
+        (**self).log_name()
+    }
+}
+
+/// A `SyncServer` that lives in memory, for tests and for syncing stores in one process.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryServer {
+    records: Vec<Vec<u8>>,
+}
+
+impl MemoryServer {
+    pub fn new() -> MemoryServer {
+        MemoryServer::default()
+    }
+
+    pub fn records(&self) -> &[Vec<u8>] {
+        &self.records
+    }
+}
+
+impl SyncServer for MemoryServer {
+    fn download_since(&mut self, version: u64) -> Result<Vec<(u64, Vec<u8>)>, list_errors::Error> {
+        Ok(self.records
+               .iter()
+               .enumerate()
+               .skip(version as usize)
+               .map(|(index, record)| (index as u64 + 1, record.clone()))
+               .collect())
+    }
+
+    fn upload(&mut self, base: u64, records: Vec<Vec<u8>>) -> Result<UploadOutcome, list_errors::Error> {
+        let head = self.records.len() as u64;
+        if base != head {
+            return Ok(UploadOutcome::OutOfDate { head: head });
+        }
+        self.records.extend(records);
+        Ok(UploadOutcome::Accepted { head: self.records.len() as u64 })
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SyncReport {
+    pub uploaded: usize,
+    pub downloaded: usize,
+}
+
+/// Identifies the field a change touches: the value is only part of the key for
+/// cardinality-many attributes, where each value is independent.
+type FieldKey = (EntityId, String, Option<SyncValue>);
+
+/// Where this store is up to, kept as `:sync` datoms so that it's always written in the
+/// same transaction as the changes it accounts for.
+struct SyncMetadata {
+    /// Every local transaction up to and including this one has been uploaded.
+    local_tx: Entid,
+    /// Every remote record up to and including this version has been applied.
+    remote_version: u64,
+}
+
+impl SyncMetadata {
+    fn read(toodle: &Toodle) -> Result<SyncMetadata, list_errors::Error> {
+        Ok(SyncMetadata {
+            local_tx: read_metadata(toodle, "local_tx")?.unwrap_or(0),
+            remote_version: read_metadata(toodle, "remote_version")?.unwrap_or(0) as u64,
+        })
+    }
+
+    /// Adds this metadata to `builder`, to be written along with whatever else it holds.
+    fn write(&self, builder: &mut TransactionBuilder) {
+        let entity = builder.named_tempid("sync".to_string());
+        builder.add(entity.clone(), kw("sync", "store"), "local".to_typed_value())
+               .add(entity.clone(), kw("sync", "local_tx"), TypedValue::Long(self.local_tx))
+               .add(entity, kw("sync", "remote_version"), TypedValue::Long(self.remote_version as i64));
+    }
+}
+
+fn read_metadata(toodle: &Toodle, attribute: &str) -> Result<Option<i64>, list_errors::Error> {
+    let query = format!(r#"[:find ?value .
+                            :where
+                            [?s :sync/store "local"]
+                            [?s :sync/{} ?value]
+    ]"#, attribute);
+    Ok(match toodle.connection.query(&query).into_scalar_result()? {
+        Some(TypedValue::Long(value)) => Some(value),
+        _ => None,
+    })
+}
+
+fn is_synced_attribute(attribute: &NamespacedKeyword) -> bool {
+    attribute.namespace == "item" || attribute.namespace == "label"
+}
+
+fn parse_attribute(attribute: &str) -> Result<NamespacedKeyword, list_errors::Error> {
+    let mut parts = attribute.splitn(2, '/');
+    match (parts.next(), parts.next()) {
+        (Some(namespace), Some(name)) if !namespace.is_empty() && !name.is_empty() => {
+            Ok(NamespacedKeyword::new(namespace, name))
+        },
+        _ => bail!(ErrorKind::BadSyncRecord(format!("invalid attribute {:?}", attribute))),
+    }
+}
+
+fn attribute_name(attribute: &NamespacedKeyword) -> String {
+    format!("{}/{}", attribute.namespace, attribute.name)
+}
+
+impl Toodle {
+    /// Uploads local changes to `server` and applies changes made elsewhere, resolving
+    /// conflicting edits with the `DefaultPolicy`.
+    pub fn sync<S>(&mut self, server: &mut S) -> Result<SyncReport, list_errors::Error> where S: SyncServer {
+        self.sync_with_policy(server, &DefaultPolicy::default())
+    }
+
+    /// Like `sync`, but lets `policy` pick the winner when a remote change conflicts with
+    /// a pending local one.
+    pub fn sync_with_policy<S, P>(&mut self, server: &mut S, policy: &P) -> Result<SyncReport, list_errors::Error>
+        where S: SyncServer, P: ConflictPolicy {
+        let mut metadata = SyncMetadata::read(self)?;
+        let (local, last_tx) = self.local_transactions_since(metadata.local_tx)?;
+        let mut pending = self.pending_fields(&local);
+        let mut lost = HashSet::new();
+        let mut report = SyncReport::default();
+
+        for _ in 0..MAX_SYNC_ATTEMPTS {
+            for (version, record) in server.download_since(metadata.remote_version)? {
+                let transaction = SyncTransaction::from_bytes(&record)?;
+                metadata.remote_version = version;
+                self.apply_remote_transaction(&transaction, &metadata, &mut pending, &mut lost, policy)?;
+                report.downloaded += 1;
+            }
+
+            let records = self.outgoing_records(&local, &lost)?;
+            if records.is_empty() {
+                metadata.local_tx = last_tx;
+                self.write_metadata(&metadata)?;
+                return Ok(report);
+            }
+
+            let uploaded = records.len();
+            match server.upload(metadata.remote_version, records)? {
+                UploadOutcome::Accepted { head } => {
+                    metadata.local_tx = last_tx;
+                    metadata.remote_version = head;
+                    self.write_metadata(&metadata)?;
+                    report.uploaded = uploaded;
+                    return Ok(report);
+                },
+                UploadOutcome::OutOfDate { .. } => continue,
+            }
+        }
+        bail!(ErrorKind::SyncFailed(format!("server kept moving on after {} attempts", MAX_SYNC_ATTEMPTS)))
+    }
+
+    fn write_metadata(&mut self, metadata: &SyncMetadata) -> Result<(), list_errors::Error> {
+        let mut builder = TransactionBuilder::new();
+        metadata.write(&mut builder);
+        self.connection.transact_builder(&builder)?;
+        Ok(())
+    }
+
+    /// Serializes the local transactions, leaving out changes to fields that lost a
+    /// conflict: this store has already taken on the remote value.
+    fn outgoing_records(&self, local: &[SyncTransaction], lost: &HashSet<FieldKey>) -> Result<Vec<Vec<u8>>, list_errors::Error> {
+        let mut records = vec![];
+        for transaction in local.iter() {
+            let changes: Vec<Change> = transaction.changes
+                                                  .iter()
+                                                  .filter(|change| !lost.contains(&self.field_key(change)))
+                                                  .cloned()
+                                                  .collect();
+            if !changes.is_empty() {
+                records.push(SyncTransaction { changes: changes }.to_bytes()?);
+            }
+        }
+        Ok(records)
+    }
+
+    /// Local transactions after `after` that haven't come from another store, and the id of
+    /// the last transaction looked at. Transactions that wrote sync metadata either applied
+    /// remote records or only recorded progress, so none of them are local changes.
+    fn local_transactions_since(&self, after: Entid) -> Result<(Vec<SyncTransaction>, Entid), list_errors::Error> {
+        let datoms = self.connection.transactions_after(after)?;
+        let mut applied = HashSet::new();
+        for datom in datoms.iter() {
+            match self.connection.ident_for_entid(datom.a) {
+                Some(ref attribute) if attribute.namespace == "sync" => { applied.insert(datom.tx); },
+                _ => {},
+            }
+        }
+
+        let mut last_tx = after;
+        let mut transactions: BTreeMap<Entid, SyncTransaction> = BTreeMap::new();
+        for datom in datoms {
+            last_tx = datom.tx;
+            if applied.contains(&datom.tx) {
+                continue;
+            }
+            let attribute = match self.connection.ident_for_entid(datom.a) {
+                Some(ref attribute) if is_synced_attribute(attribute) => attribute.clone(),
+                _ => continue,
+            };
+            let entity = match self.identity_of(datom.e, datom.tx)? {
+                Some(entity) => entity,
+                None => continue,
+            };
+            let value = match self.to_sync_value(datom.v, datom.tx)? {
+                Some(value) => value,
+                None => continue,
+            };
+            transactions.entry(datom.tx).or_insert_with(SyncTransaction::default).changes.push(Change {
+                entity: entity,
+                attribute: attribute_name(&attribute),
+                value: value,
+                added: datom.added,
+            });
+        }
+        Ok((transactions.into_iter().map(|(_, transaction)| transaction).collect(), last_tx))
+    }
+
+    /// The stable identity of `e` in transaction `tx`. The identity it had before `tx` wins,
+    /// so that a label renamed in `tx` is addressed by the name other stores still know.
+    fn identity_of(&self, e: Entid, tx: Entid) -> Result<Option<EntityId>, list_errors::Error> {
+        let item_uuid = NamespacedKeyword::new("item", "uuid");
+        let label_name = NamespacedKeyword::new("label", "name");
+        for before in [tx, tx + 1].iter() {
+            if let Some(TypedValue::Uuid(uuid)) = self.connection.value_before(e, &item_uuid, *before)? {
+                return Ok(Some(EntityId::Item { uuid: uuid.hyphenated().to_string() }));
+            }
+            if let Some(TypedValue::String(name)) = self.connection.value_before(e, &label_name, *before)? {
+                return Ok(Some(EntityId::Label { name: name.to_string() }));
+            }
+        }
+        Ok(None)
+    }
+
+    fn to_sync_value(&self, value: TypedValue, tx: Entid) -> Result<Option<SyncValue>, list_errors::Error> {
+        Ok(match value {
+            TypedValue::String(s) => Some(SyncValue::String(s.to_string())),
+            TypedValue::Long(l) => Some(SyncValue::Long(l)),
+            TypedValue::Boolean(b) => Some(SyncValue::Boolean(b)),
+            TypedValue::Instant(instant) => {
+                Some(SyncValue::Instant(instant.timestamp() * 1_000_000 + i64::from(instant.timestamp_subsec_micros())))
+            },
+            TypedValue::Uuid(uuid) => Some(SyncValue::Uuid(uuid.hyphenated().to_string())),
+            TypedValue::Ref(e) => self.identity_of(e, tx)?.map(SyncValue::Ref),
+            _ => None,
+        })
+    }
+
+    fn field_key(&self, change: &Change) -> FieldKey {
+        let multival = parse_attribute(&change.attribute)
+                           .map(|attribute| self.connection.is_multival(&attribute))
+                           .unwrap_or(false);
+        let value = if multival { Some(change.value.clone()) } else { None };
+        (change.entity.clone(), change.attribute.clone(), value)
+    }
+
+    /// Every field with a pending local change, and whether the last such change was an
+    /// assertion.
+    fn pending_fields(&self, local: &[SyncTransaction]) -> HashMap<FieldKey, bool> {
+        local.iter()
+             .flat_map(|transaction| transaction.changes.iter())
+             .map(|change| (self.field_key(change), change.added))
+             .collect()
+    }
+
+    /// Decides whether the remote changes in `transaction` to the pending field `key` should
+    /// be applied (`Winner::Remote`) or dropped in favour of the local change.
+    fn resolve_conflict<P>(&self, key: &FieldKey, local_added: bool, transaction: &SyncTransaction, policy: &P) -> Result<Winner, list_errors::Error>
+        where P: ConflictPolicy {
+        let &(ref entity, ref attribute, _) = key;
+        let remote: Vec<&Change> = transaction.changes.iter().filter(|change| &self.field_key(change) == key).collect();
+        let remote_value = remote_result(&remote);
+
+        if attribute == "item/label" {
+            let remote_added = remote.last().map_or(local_added, |change| change.added);
+            if remote_added == local_added {
+                return Ok(Winner::Local);
+            }
+            return Ok(match remote.last().map(|change| &change.value) {
+                Some(&SyncValue::Ref(EntityId::Label { ref name })) => policy.resolve_label(name, local_added, remote_added),
+                _ => Winner::Local,
+            });
+        }
+
+        let local_value = self.current_value(entity, attribute)?;
+        if local_value == remote_value {
+            return Ok(Winner::Local);
+        }
+        Ok(match attribute.as_str() {
+            "item/name" => match (local_value, remote_value) {
+                (Some(SyncValue::String(ref local)), Some(SyncValue::String(ref remote))) => policy.resolve_name(local, remote),
+                _ => Winner::Local,
+            },
+            "item/due_date" => policy.resolve_due_date(to_timespec(local_value), to_timespec(remote_value)),
+            "item/completion_date" => policy.resolve_completion_date(to_timespec(local_value), to_timespec(remote_value)),
+            _ => Winner::Local,
+        })
+    }
+
+    /// The value `attribute` of the item `entity` has in this store right now.
+    fn current_value(&self, entity: &EntityId, attribute: &str) -> Result<Option<SyncValue>, list_errors::Error> {
+        let uuid = match entity {
+            &EntityId::Item { ref uuid } => Uuid::from_str(uuid).map_err(|e| ErrorKind::BadSyncRecord(e.to_string()))?,
+            _ => return Ok(None),
+        };
+        let entid = match self.entid_for_item(&uuid)? {
+            Some(entid) => entid,
+            None => return Ok(None),
+        };
+        let now = self.connection.last_tx()? + 1;
+        match self.connection.value_before(entid, &parse_attribute(attribute)?, now)? {
+            Some(value) => self.to_sync_value(value, now),
+            None => Ok(None),
+        }
+    }
+
+    /// Finds the local entid for `id`, or allocates a tempid if `id` is being created.
+    fn resolve_entity(&self, id: &EntityId, created: &HashSet<EntityId>, builder: &mut TransactionBuilder, resolved: &mut HashMap<EntityId, EntityPlace>) -> Result<Option<EntityPlace>, list_errors::Error> {
+        if let Some(place) = resolved.get(id) {
+            return Ok(Some(place.clone()));
+        }
+        let local = match id {
+            &EntityId::Item { ref uuid } => {
+                let uuid = Uuid::from_str(uuid).map_err(|e| ErrorKind::BadSyncRecord(e.to_string()))?;
+                self.entid_for_item(&uuid)?
+            },
+            &EntityId::Label { ref name } => {
+                self.fetch_label(name)?.and_then(|label| label.id).map(|entity| entity.id)
+            },
+        };
+        let place = match local {
+            Some(entid) => EntityPlace::Entid(entid),
+            None if created.contains(id) => builder.named_tempid(id.tempid()),
+            None => return Ok(None),
+        };
+        resolved.insert(id.clone(), place.clone());
+        Ok(Some(place))
+    }
+
+    fn entid_for_item(&self, uuid: &Uuid) -> Result<Option<Entid>, list_errors::Error> {
+        let query = r#"[:find ?eid .
+                        :in ?uuid
+                        :where
+                        [?eid :item/uuid ?uuid]
+        ]"#;
+        let result = self.connection
+                         .query_args(query, vec![(Variable::from_valid_name("?uuid"), uuid.to_typed_value())])
+                         .into_scalar_result()?;
+        Ok(match result {
+            Some(TypedValue::Ref(entid)) => Some(entid),
+            _ => None,
+        })
+    }
+
+    /// Applies `transaction` and records `metadata`, which accounts for it, in the same
+    /// Mentat transaction.
+    fn apply_remote_transaction<P>(&mut self, transaction: &SyncTransaction, metadata: &SyncMetadata, pending: &mut HashMap<FieldKey, bool>, lost: &mut HashSet<FieldKey>, policy: &P) -> Result<(), list_errors::Error>
+        where P: ConflictPolicy {
+        // Entities that don't exist here may only be created by a transaction that names them.
+        let created: HashSet<EntityId> = transaction.changes
+                                                    .iter()
+                                                    .filter(|change| change.added && (change.attribute == "item/uuid" || change.attribute == "label/name"))
+                                                    .map(|change| change.entity.clone())
+                                                    .collect();
+        let mut builder = TransactionBuilder::new();
+        let mut resolved = HashMap::new();
+
+        // Settle every conflict up front. A field the remote store wins is no longer pending
+        // here, and its local changes are kept out of the upload.
+        let mut overwritten: HashMap<FieldKey, Option<SyncValue>> = HashMap::new();
+        let mut touched: HashMap<EntityId, ChangeKind> = HashMap::new();
+        for change in transaction.changes.iter() {
+            let key = self.field_key(change);
+            let local_added = match pending.get(&key) {
+                Some(added) => *added,
+                None => continue,
+            };
+            if !is_mergeable(&key) || self.resolve_conflict(&key, local_added, transaction, policy)? == Winner::Local {
+                continue;
+            }
+            pending.remove(&key);
+            lost.insert(key.clone());
+            if key.2.is_none() {
+                // The local value may differ from the one the remote store replaced, so
+                // assert or retract outright rather than replaying the remote changes.
+                let current = self.current_value(&key.0, &key.1)?;
+                overwritten.insert(key, current);
+            }
+        }
+        for (key, current) in overwritten.iter() {
+            let entity = match self.resolve_entity(&key.0, &created, &mut builder, &mut resolved)? {
+                Some(entity) => entity,
+                None => continue,
+            };
+            let attribute = parse_attribute(&key.1)?;
+            let remote: Vec<&Change> = transaction.changes.iter().filter(|change| &self.field_key(change) == key).collect();
+            match (remote_result(&remote), current) {
+                (Some(value), _) => builder.add(entity, attribute, to_typed_value(&value)?),
+                (None, &Some(ref value)) => builder.retract(entity, attribute, to_typed_value(value)?),
+                (None, &None) => continue,
+            };
+            touched.entry(key.0.clone()).or_insert(ChangeKind::Updated);
+        }
+
+        for change in transaction.changes.iter() {
+            let key = self.field_key(change);
+            if pending.contains_key(&key) || overwritten.contains_key(&key) {
+                continue;
+            }
+            let attribute = parse_attribute(&change.attribute)?;
+            let entity = match self.resolve_entity(&change.entity, &created, &mut builder, &mut resolved)? {
+                Some(entity) => entity,
+                None => continue,
+            };
+            let value = match change.value {
+                SyncValue::Ref(ref id) => match self.resolve_entity(id, &created, &mut builder, &mut resolved)? {
+                    Some(place) => ValuePlace::Entity(place),
+                    None => continue,
+                },
+                ref value => ValuePlace::Value(to_typed_value(value)?),
+            };
+            // There's nothing to retract from an entity that's only just being created here.
+            if !change.added && (is_tempid(&entity) || is_tempid_value(&value)) {
+                continue;
+            }
+            let is_identity = change.attribute == "item/uuid" || change.attribute == "label/name";
+            let kind = if is_tempid(&entity) {
+                ChangeKind::Created
+            } else if is_identity && !change.added && !created.contains(&change.entity) {
+                ChangeKind::Deleted
+            } else {
+                ChangeKind::Updated
+            };
+            let entry = touched.entry(change.entity.clone()).or_insert(kind);
+            if *entry == ChangeKind::Updated {
+                *entry = kind;
+            }
+            if change.added {
+                builder.add(entity, attribute, value);
+            } else {
+                builder.retract(entity, attribute, value);
+            }
+        }
+
+        metadata.write(&mut builder);
+        self.connection.transact_builder(&builder)?;
+        self.notify_remote_changes(touched);
+        Ok(())
+    }
+
+    fn notify_remote_changes(&self, touched: HashMap<EntityId, ChangeKind>) {
+        let mut changes: HashMap<(Topic, ChangeKind), Vec<String>> = HashMap::new();
+        for (entity, kind) in touched.into_iter() {
+            let (topic, id) = match entity {
+                EntityId::Item { uuid } => (Topic::Items, uuid),
+                EntityId::Label { name } => (Topic::Labels, name),
+            };
+            changes.entry((topic, kind)).or_insert_with(Vec::new).push(id);
+        }
+        for ((topic, kind), ids) in changes.into_iter() {
+            self.notify(topic, kind, ids);
+        }
+    }
+}
+
+/// Whether a conflict over `key` is one for a `ConflictPolicy` to settle. Anything else is
+/// left to the pending local change.
+fn is_mergeable(key: &FieldKey) -> bool {
+    match key.0 {
+        EntityId::Item { .. } => {
+            ["item/name", "item/due_date", "item/completion_date", "item/label"].contains(&key.1.as_str())
+        },
+        EntityId::Label { .. } => false,
+    }
+}
+
+/// The value a field is left with after `changes`, all of which are to that field.
+fn remote_result(changes: &[&Change]) -> Option<SyncValue> {
+    changes.last().and_then(|change| if change.added { Some(change.value.clone()) } else { None })
+}
+
+fn to_timespec(value: Option<SyncValue>) -> Option<Timespec> {
+    match value {
+        Some(SyncValue::Instant(micros)) => {
+            Some(Timespec::new(micros / 1_000_000, ((micros % 1_000_000) * 1_000) as i32))
+        },
+        _ => None,
+    }
+}
+
+fn is_tempid(place: &EntityPlace) -> bool {
+    match place {
+        &EntityPlace::TempId(_) => true,
+        _ => false,
+    }
+}
+
+fn is_tempid_value(value: &ValuePlace) -> bool {
+    match value {
+        &ValuePlace::Entity(ref place) => is_tempid(place),
+        _ => false,
+    }
+}
+
+fn to_typed_value(value: &SyncValue) -> Result<TypedValue, list_errors::Error> {
+    Ok(match value {
+        &SyncValue::String(ref s) => s.to_typed_value(),
+        &SyncValue::Long(l) => TypedValue::Long(l),
+        &SyncValue::Boolean(b) => TypedValue::Boolean(b),
+        &SyncValue::Instant(micros) => TypedValue::Instant(DateTime::<Utc>::from_micros(micros)),
+        &SyncValue::Uuid(ref uuid) => {
+            Uuid::from_str(uuid).map_err(|e| ErrorKind::BadSyncRecord(e.to_string()))?.to_typed_value()
+        },
+        &SyncValue::Ref(_) => bail!(ErrorKind::BadSyncRecord("refs must be resolved to entities".to_string())),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use clock::FixedClock;
+    use conflicts::{
+        LabelSemantics,
+        RemoteWins,
+    };
+    use items::{
+        Item,
+        ItemUpdate,
+    };
+    use observers::Change;
+    use labels::Label;
+    use recurrence::Recurrence;
+
+    fn toodle() -> Toodle {
+        Toodle::new(String::new(), None).expect("Expected a Toodle")
+    }
+
+    /// Everything about an item that should be the same in every store.
+    fn summary(toodle: &Toodle) -> Vec<(Uuid, String, Option<Timespec>, Option<Timespec>, Vec<String>)> {
+        let mut items: Vec<_> = toodle.fetch_items().expect("expected items").vec.iter().map(|item| {
+            let mut labels: Vec<String> = item.labels.iter().map(|label| label.name.clone()).collect();
+            labels.sort();
+            (item.uuid, item.name.clone(), item.due_date, item.completion_date, labels)
+        }).collect();
+        items.sort_by(|a, b| a.0.as_bytes().cmp(b.0.as_bytes()));
+        items
+    }
+
+    fn label_summary(toodle: &Toodle) -> Vec<(String, String)> {
+        let mut labels: Vec<_> = toodle.fetch_labels().expect("expected labels").iter().map(|label| (label.name.clone(), label.color.clone())).collect();
+        labels.sort();
+        labels
+    }
+
+    fn item(name: &str, due_date: Option<Timespec>, labels: Vec<Label>) -> Item {
+        Item {
+            id: None,
+            uuid: Uuid::nil(),
+            name: name.to_string(),
+            due_date: due_date,
+            completion_date: None,
+            notes: None,
+            priority: None,
+            starred: false,
+            recurrence: None,
+            labels: labels
+        }
+    }
+
+    fn fetch(toodle: &Toodle, uuid: &Uuid) -> Item {
+        toodle.fetch_item(uuid).expect("expected an item option").expect("expected an item")
+    }
+
+    #[test]
+    fn test_sync_new_items_and_labels() {
+        let mut server = MemoryServer::new();
+        let mut a = toodle();
+        let mut b = toodle();
+
+        let home = a.create_label("home".to_string(), "#ff0000".to_string()).expect("expected a label option").unwrap();
+        let work = a.create_label("work".to_string(), "#00ff00".to_string()).expect("expected a label option").unwrap();
+        a.create_item(&item("one", Some(Timespec::new(1000, 0)), vec![home.clone()])).expect("expected a uuid");
+        a.create_item(&item("two", None, vec![home, work])).expect("expected a uuid");
+
+        let report = a.sync(&mut server).expect("expected a sync");
+        assert!(report.uploaded > 0);
+        assert_eq!(report.downloaded, 0);
+
+        let report = b.sync(&mut server).expect("expected a sync");
+        assert_eq!(report.uploaded, 0);
+        assert_eq!(report.downloaded, server.records().len());
+
+        assert_eq!(summary(&a), summary(&b));
+        assert_eq!(label_summary(&a), label_summary(&b));
+        assert_eq!(summary(&b).len(), 2);
+    }
+
+    #[test]
+    fn test_sync_is_idempotent() {
+        let mut server = MemoryServer::new();
+        let mut a = toodle();
+        let mut b = toodle();
+
+        a.create_item(&item("one", None, vec![])).expect("expected a uuid");
+        a.sync(&mut server).expect("expected a sync");
+        b.sync(&mut server).expect("expected a sync");
+        let records = server.records().len();
+
+        assert_eq!(a.sync(&mut server).expect("expected a sync"), SyncReport::default());
+        assert_eq!(b.sync(&mut server).expect("expected a sync"), SyncReport::default());
+        assert_eq!(server.records().len(), records);
+        assert_eq!(summary(&a), summary(&b));
+    }
+
+    #[test]
+    fn test_sync_progress_is_written_with_remote_changes() {
+        let mut server = MemoryServer::new();
+        let mut a = toodle();
+        let mut b = toodle();
+
+        let uuid = a.create_item(&item("one", None, vec![])).expect("expected a uuid");
+        a.sync(&mut server).expect("expected a sync");
+        b.sync(&mut server).expect("expected a sync");
+
+        // The transaction that created the item here also moved the remote version on, so
+        // there's no moment at which one is stored without the other.
+        let entid = b.entid_for_item(&uuid).expect("expected an entid option").expect("expected an entid");
+        let datoms = b.connection.transactions_after(0).expect("expected datoms");
+        let tx = datoms.iter().find(|datom| datom.e == entid).map(|datom| datom.tx).expect("expected the item's transaction");
+        let remote_version = b.connection.entid_for_ident(&kw("sync", "remote_version")).expect("expected the attribute");
+        assert!(datoms.iter().any(|datom| datom.tx == tx && datom.a == remote_version && datom.v == TypedValue::Long(1)));
+        assert_eq!(SyncMetadata::read(&b).expect("expected metadata").remote_version, 1);
+    }
+
+    #[test]
+    fn test_sync_recurring_items() {
+        let mut server = MemoryServer::new();
+        let mut a = toodle();
+        let mut b = toodle();
+        b.set_clock(FixedClock(Timespec::new(1000, 0)));
+
+        let daily: Recurrence = "FREQ=DAILY;COUNT=3".parse().expect("expected a rule");
+        let mut bins = item("bins", Some(Timespec::new(0, 0)), vec![]);
+        bins.recurrence = Some(daily);
+        let uuid = a.create_item(&bins).expect("expected a uuid");
+        a.sync(&mut server).expect("expected a sync");
+        b.sync(&mut server).expect("expected a sync");
+
+        let synced = fetch(&b, &uuid);
+        assert_eq!(synced.recurrence, bins.recurrence);
+        b.update_item(&synced, ItemUpdate { completion_date: Some(Some(Timespec::new(1000, 0))), ..ItemUpdate::default() }).expect("expected an update");
+        b.sync(&mut server).expect("expected a sync");
+        a.sync(&mut server).expect("expected a sync");
+
+        assert_eq!(summary(&a), summary(&b));
+        let recurrences: Vec<Option<String>> = summary(&a).iter().map(|&(ref uuid, _, _, _, _)| fetch(&a, uuid).recurrence.map(|r| r.to_string())).collect();
+        assert_eq!(recurrences.iter().filter(|r| r.is_none()).count(), 1);
+        assert!(recurrences.contains(&Some("FREQ=DAILY;COUNT=2".to_string())));
+        assert_eq!(fetch(&a, &uuid).recurrence, None);
+    }
+
+    #[test]
+    fn test_sync_updates_and_deletions() {
+        let mut server = MemoryServer::new();
+        let mut a = toodle();
+        let mut b = toodle();
+
+        let home = a.create_label("home".to_string(), "#ff0000".to_string()).expect("expected a label option").unwrap();
+        let one = a.create_item(&item("one", Some(Timespec::new(1000, 0)), vec![home.clone()])).expect("expected a uuid");
+        let two = a.create_item(&item("two", None, vec![])).expect("expected a uuid");
+        a.sync(&mut server).expect("expected a sync");
+        b.sync(&mut server).expect("expected a sync");
+
+        // Rename, complete and relabel on b.
+        let b_home = b.fetch_label(&"home".to_string()).expect("expected a label option").expect("expected a label");
+        let b_one = fetch(&b, &one);
+        b.update_item(&b_one, ItemUpdate {
+            name: Some("one, renamed".to_string()),
+            due_date: Some(None),
+            completion_date: Some(Some(Timespec::new(2000, 0))),
+            labels: Some(vec![]),
+            ..ItemUpdate::default()
+        }).expect("expected an update");
+        let b_two = fetch(&b, &two);
+        b.update_item(&b_two, ItemUpdate { labels: Some(vec![b_home]), ..ItemUpdate::default() }).expect("expected an update");
+        b.sync(&mut server).expect("expected a sync");
+        a.sync(&mut server).expect("expected a sync");
+        assert_eq!(summary(&a), summary(&b));
+
+        let a_one = fetch(&a, &one);
+        assert_eq!(a_one.name, "one, renamed");
+        assert_eq!(a_one.due_date, None);
+        assert_eq!(a_one.completion_date, Some(Timespec::new(2000, 0)));
+        assert_eq!(a_one.labels, vec![]);
+        assert_eq!(fetch(&a, &two).labels.len(), 1);
+
+        // Delete on a.
+        a.delete_item(&one).expect("expected a deletion");
+        a.sync(&mut server).expect("expected a sync");
+        b.sync(&mut server).expect("expected a sync");
+        assert_eq!(b.fetch_item(&one).expect("expected an item option"), None);
+        assert_eq!(summary(&a), summary(&b));
+    }
+
+    #[test]
+    fn test_sync_label_rename_recolor_and_delete() {
+        let mut server = MemoryServer::new();
+        let mut a = toodle();
+        let mut b = toodle();
+
+        let home = a.create_label("home".to_string(), "#ff0000".to_string()).expect("expected a label option").unwrap();
+        let one = a.create_item(&item("one", None, vec![home.clone()])).expect("expected a uuid");
+        a.sync(&mut server).expect("expected a sync");
+        b.sync(&mut server).expect("expected a sync");
+
+        let house = a.rename_label(&home, "house".to_string()).expect("expected a label option").expect("expected a label");
+        a.update_label_color(&house, "#0000ff".to_string()).expect("expected a label option");
+        a.sync(&mut server).expect("expected a sync");
+        b.sync(&mut server).expect("expected a sync");
+        assert_eq!(label_summary(&b), vec![("house".to_string(), "#0000ff".to_string())]);
+        assert_eq!(fetch(&b, &one).labels.iter().map(|label| label.name.clone()).collect::<Vec<_>>(), vec!["house".to_string()]);
+
+        let b_house = b.fetch_label(&"house".to_string()).expect("expected a label option").expect("expected a label");
+        b.delete_label(&b_house).expect("expected a deletion");
+        b.sync(&mut server).expect("expected a sync");
+        a.sync(&mut server).expect("expected a sync");
+        assert_eq!(label_summary(&a), vec![]);
+        assert_eq!(fetch(&a, &one).labels, vec![]);
+        assert_eq!(summary(&a), summary(&b));
+    }
+
+    #[test]
+    fn test_sync_labels_with_the_same_name_merge() {
+        let mut server = MemoryServer::new();
+        let mut a = toodle();
+        let mut b = toodle();
+
+        let a_home = a.create_label("home".to_string(), "#ff0000".to_string()).expect("expected a label option").unwrap();
+        let b_home = b.create_label("home".to_string(), "#ff0000".to_string()).expect("expected a label option").unwrap();
+        a.create_item(&item("from a", None, vec![a_home])).expect("expected a uuid");
+        b.create_item(&item("from b", None, vec![b_home])).expect("expected a uuid");
+
+        a.sync(&mut server).expect("expected a sync");
+        b.sync(&mut server).expect("expected a sync");
+        a.sync(&mut server).expect("expected a sync");
+
+        assert_eq!(label_summary(&a), vec![("home".to_string(), "#ff0000".to_string())]);
+        assert_eq!(summary(&a), summary(&b));
+        assert_eq!(summary(&a).len(), 2);
+    }
+
+    #[test]
+    fn test_sync_concurrent_edits_converge() {
+        let mut server = MemoryServer::new();
+        let mut a = toodle();
+        let mut b = toodle();
+
+        let one = a.create_item(&item("one", None, vec![])).expect("expected a uuid");
+        a.sync(&mut server).expect("expected a sync");
+        b.sync(&mut server).expect("expected a sync");
+
+        let a_one = fetch(&a, &one);
+        a.update_item(&a_one, ItemUpdate { name: Some("renamed by a".to_string()), ..ItemUpdate::default() }).expect("expected an update");
+        let b_one = fetch(&b, &one);
+        b.update_item(&b_one, ItemUpdate { name: Some("renamed by b".to_string()), ..ItemUpdate::default() }).expect("expected an update");
+
+        a.sync(&mut server).expect("expected a sync");
+        b.sync(&mut server).expect("expected a sync");
+        a.sync(&mut server).expect("expected a sync");
+
+        // b's rename landed on the server last, so it wins everywhere.
+        assert_eq!(fetch(&a, &one).name, "renamed by b");
+        assert_eq!(summary(&a), summary(&b));
+    }
+
+    #[test]
+    fn test_sync_keeps_edits_to_different_fields() {
+        let mut server = MemoryServer::new();
+        let mut a = toodle();
+        let mut b = toodle();
+
+        let one = a.create_item(&item("one", Some(Timespec::new(1000, 0)), vec![])).expect("expected a uuid");
+        a.sync(&mut server).expect("expected a sync");
+        b.sync(&mut server).expect("expected a sync");
+
+        let a_one = fetch(&a, &one);
+        a.update_item(&a_one, ItemUpdate { name: Some("renamed by a".to_string()), ..ItemUpdate::default() }).expect("expected an update");
+        let b_one = fetch(&b, &one);
+        b.update_item(&b_one, ItemUpdate { completion_date: Some(Some(Timespec::new(2000, 0))), ..ItemUpdate::default() }).expect("expected an update");
+
+        a.sync(&mut server).expect("expected a sync");
+        b.sync(&mut server).expect("expected a sync");
+        a.sync(&mut server).expect("expected a sync");
+
+        let a_one = fetch(&a, &one);
+        assert_eq!(a_one.name, "renamed by a");
+        assert_eq!(a_one.due_date, Some(Timespec::new(1000, 0)));
+        assert_eq!(a_one.completion_date, Some(Timespec::new(2000, 0)));
+        assert_eq!(summary(&a), summary(&b));
+    }
+
+    #[test]
+    fn test_sync_keeps_earliest_dates() {
+        let mut server = MemoryServer::new();
+        let mut a = toodle();
+        let mut b = toodle();
+
+        let one = a.create_item(&item("one", Some(Timespec::new(1000, 0)), vec![])).expect("expected a uuid");
+        a.sync(&mut server).expect("expected a sync");
+        b.sync(&mut server).expect("expected a sync");
+
+        // a's dates are earlier, but a uploads first; b has to give its own up.
+        let a_one = fetch(&a, &one);
+        a.update_item(&a_one, ItemUpdate {
+            due_date: Some(Some(Timespec::new(500, 0))),
+            completion_date: Some(Some(Timespec::new(3000, 0))),
+            ..ItemUpdate::default()
+        }).expect("expected an update");
+        let b_one = fetch(&b, &one);
+        b.update_item(&b_one, ItemUpdate {
+            due_date: Some(Some(Timespec::new(800, 0))),
+            completion_date: Some(Some(Timespec::new(4000, 0))),
+            ..ItemUpdate::default()
+        }).expect("expected an update");
+        a.sync(&mut server).expect("expected a sync");
+        let records = server.records().len();
+        b.sync(&mut server).expect("expected a sync");
+        assert_eq!(server.records().len(), records);
+
+        let b_one = fetch(&b, &one);
+        assert_eq!(b_one.due_date, Some(Timespec::new(500, 0)));
+        assert_eq!(b_one.completion_date, Some(Timespec::new(3000, 0)));
+
+        // Now the later uploader has the earlier completion, and keeps it.
+        let a_one = fetch(&a, &one);
+        a.update_item(&a_one, ItemUpdate { completion_date: Some(Some(Timespec::new(2500, 0))), ..ItemUpdate::default() }).expect("expected an update");
+        let b_one = fetch(&b, &one);
+        b.update_item(&b_one, ItemUpdate { completion_date: Some(Some(Timespec::new(2000, 0))), ..ItemUpdate::default() }).expect("expected an update");
+        a.sync(&mut server).expect("expected a sync");
+        b.sync(&mut server).expect("expected a sync");
+        a.sync(&mut server).expect("expected a sync");
+
+        assert_eq!(fetch(&a, &one).completion_date, Some(Timespec::new(2000, 0)));
+        assert_eq!(summary(&a), summary(&b));
+    }
+
+    /// a removes and re-adds `home` on an item while b removes it, then a syncs first.
+    fn sync_label_add_remove_conflict<P>(policy: &P) -> (Toodle, Toodle, Uuid) where P: ConflictPolicy {
+        let mut server = MemoryServer::new();
+        let mut a = toodle();
+        let mut b = toodle();
+
+        let home = a.create_label("home".to_string(), "#ff0000".to_string()).expect("expected a label option").unwrap();
+        let one = a.create_item(&item("one", None, vec![home.clone()])).expect("expected a uuid");
+        a.sync_with_policy(&mut server, policy).expect("expected a sync");
+        b.sync_with_policy(&mut server, policy).expect("expected a sync");
+
+        a.update_item(&fetch(&a, &one), ItemUpdate { labels: Some(vec![]), ..ItemUpdate::default() }).expect("expected an update");
+        a.update_item(&fetch(&a, &one), ItemUpdate { labels: Some(vec![home]), ..ItemUpdate::default() }).expect("expected an update");
+        b.update_item(&fetch(&b, &one), ItemUpdate { labels: Some(vec![]), ..ItemUpdate::default() }).expect("expected an update");
+
+        a.sync_with_policy(&mut server, policy).expect("expected a sync");
+        b.sync_with_policy(&mut server, policy).expect("expected a sync");
+        a.sync_with_policy(&mut server, policy).expect("expected a sync");
+        (a, b, one)
+    }
+
+    #[test]
+    fn test_sync_label_add_wins() {
+        let (a, b, one) = sync_label_add_remove_conflict(&DefaultPolicy { labels: LabelSemantics::AddWins });
+        assert_eq!(fetch(&a, &one).labels.iter().map(|label| label.name.clone()).collect::<Vec<_>>(), vec!["home".to_string()]);
+        assert_eq!(summary(&a), summary(&b));
+    }
+
+    #[test]
+    fn test_sync_label_remove_wins() {
+        let (a, b, one) = sync_label_add_remove_conflict(&DefaultPolicy { labels: LabelSemantics::RemoveWins });
+        assert_eq!(fetch(&a, &one).labels, vec![]);
+        assert_eq!(summary(&a), summary(&b));
+    }
+
+    #[test]
+    fn test_sync_with_custom_policy() {
+        let mut server = MemoryServer::new();
+        let mut a = toodle();
+        let mut b = toodle();
+
+        let one = a.create_item(&item("one", None, vec![])).expect("expected a uuid");
+        a.sync(&mut server).expect("expected a sync");
+        b.sync(&mut server).expect("expected a sync");
+
+        a.update_item(&fetch(&a, &one), ItemUpdate { name: Some("renamed by a".to_string()), ..ItemUpdate::default() }).expect("expected an update");
+        b.update_item(&fetch(&b, &one), ItemUpdate { name: Some("renamed by b".to_string()), ..ItemUpdate::default() }).expect("expected an update");
+        a.sync_with_policy(&mut server, &RemoteWins).expect("expected a sync");
+        b.sync_with_policy(&mut server, &RemoteWins).expect("expected a sync");
+        a.sync_with_policy(&mut server, &RemoteWins).expect("expected a sync");
+
+        // The first upload wins, since b defers to it.
+        assert_eq!(fetch(&b, &one).name, "renamed by a");
+        assert_eq!(summary(&a), summary(&b));
+    }
+
+    #[test]
+    fn test_sync_notifies_observers() {
+        let mut server = MemoryServer::new();
+        let mut a = toodle();
+        let mut b = toodle();
+        let seen = Rc::new(RefCell::new(vec![]));
+        let sink = seen.clone();
+        b.register_observer(&[Topic::Items, Topic::Labels], move |change| sink.borrow_mut().push(change.clone()));
+
+        let home = a.create_label("home".to_string(), "#ff0000".to_string()).expect("expected a label option").unwrap();
+        let one = a.create_item(&item("one", None, vec![home])).expect("expected a uuid");
+        a.sync(&mut server).expect("expected a sync");
+        b.sync(&mut server).expect("expected a sync");
+
+        let one_id = one.hyphenated().to_string();
+        {
+            let seen = seen.borrow();
+            assert!(seen.contains(&Change::new(Topic::Labels, ChangeKind::Created, vec!["home"])));
+            assert!(seen.contains(&Change::new(Topic::Items, ChangeKind::Created, vec![one_id.clone()])));
+        }
+        seen.borrow_mut().clear();
+
+        let a_one = fetch(&a, &one);
+        a.update_item(&a_one, ItemUpdate { name: Some("renamed".to_string()), ..ItemUpdate::default() }).expect("expected an update");
+        a.delete_item(&one).expect("expected a deletion");
+        a.sync(&mut server).expect("expected a sync");
+        b.sync(&mut server).expect("expected a sync");
+        assert_eq!(*seen.borrow(), vec![
+            Change::new(Topic::Items, ChangeKind::Updated, vec![one_id.clone()]),
+            Change::new(Topic::Items, ChangeKind::Deleted, vec![one_id]),
+        ]);
+    }
+
+    #[test]
+    fn test_memory_server_rejects_out_of_date_uploads() {
+        let mut server = MemoryServer::new();
+        assert_eq!(server.upload(0, vec![vec![1]]).expect("expected an upload"), UploadOutcome::Accepted { head: 1 });
+        assert_eq!(server.upload(0, vec![vec![2]]).expect("expected an upload"), UploadOutcome::OutOfDate { head: 1 });
+        assert_eq!(server.upload(1, vec![vec![2], vec![3]]).expect("expected an upload"), UploadOutcome::Accepted { head: 3 });
+        assert_eq!(server.download_since(1).expect("expected records"), vec![(2, vec![2]), (3, vec![3])]);
+    }
+
+    #[test]
+    fn test_sync_transaction_round_trip() {
+        let transaction = SyncTransaction {
+            changes: vec![
+                Change {
+                    entity: EntityId::Item { uuid: "b2bc2a04-d0c5-4a0b-8b3e-3f3d1e6f0c4a".to_string() },
+                    attribute: "item/label".to_string(),
+                    value: SyncValue::Ref(EntityId::Label { name: "\"quoted\"".to_string() }),
+                    added: true,
+                },
+                Change {
+                    entity: EntityId::Label { name: "home".to_string() },
+                    attribute: "label/color".to_string(),
+                    value: SyncValue::String("#000000".to_string()),
+                    added: false,
+                },
+            ],
+        };
+        let bytes = transaction.to_bytes().expect("expected bytes");
+        assert_eq!(SyncTransaction::from_bytes(&bytes).expect("expected a transaction"), transaction);
+        assert!(SyncTransaction::from_bytes(b"not json").is_err());
+    }
+}
@@ -1,7 +1,126 @@
+// Generated by header-gen from the toodle crate's sources; don't edit it by hand.
+// Run `cargo run -p header-gen` in the rust directory after changing an export.
+
+#ifndef toodle_h
+#define toodle_h
+
+#include <stddef.h>
 #include <stdint.h>
-#include "labels.h"
+#include <time.h>
+
+enum ErrorCode {
+    ErrorCodeSuccess = 0,
+    ErrorCodePanic = 1,
+    ErrorCodeInvalidArgument = 2,
+    ErrorCodeNotFound = 3,
+    ErrorCodeStore = 4,
+    ErrorCodeSqlite = 5,
+    ErrorCodeSync = 6,
+    ErrorCodeOther = 7,
+    ErrorCodeInvalidHandle = 8,
+    ErrorCodeWrongKey = 9,
+};
+
+enum Topic {
+    TopicItems = 1,
+    TopicLabels = 2,
+};
+
+enum ChangeKind {
+    ChangeKindCreated = 0,
+    ChangeKindUpdated = 1,
+    ChangeKindDeleted = 2,
+};
+
+struct CItem {
+    char* uuid;
+    char* name;
+    int64_t* due_date;
+    int64_t* completion_date;
+    char* notes;
+    int64_t* priority;
+    int starred;
+};
+
+struct ExternError {
+    enum ErrorCode code;
+    char* message;
+};
+
+typedef void (*ChangedCallback)(void* context, int topic, int kind, const char* const* ids, int count);
+
+// api.rs
+char* toodle_call(uint64_t toodle, const char* request);
+void toodle_string_destroy(char* string);
+
+// ffi_errors.rs
+void toodle_error_destroy(struct ExternError* error);
+
+// items.rs
+uint64_t item_new(void);
+void item_destroy(uint64_t item, struct ExternError* error);
+char* item_get_uuid(uint64_t item, struct ExternError* error);
+char* item_get_name(uint64_t item, struct ExternError* error);
+void item_set_name(uint64_t item, const char* name, struct ExternError* error);
+int64_t* item_get_due_date(uint64_t item, struct ExternError* error);
+void item_set_due_date(uint64_t item, const size_t* due_date, struct ExternError* error);
+int64_t* item_get_completion_date(uint64_t item, struct ExternError* error);
+void item_set_completion_date(uint64_t item, const size_t* completion_date, struct ExternError* error);
+char* item_get_notes(uint64_t item, struct ExternError* error);
+void item_set_notes(uint64_t item, const char* notes, struct ExternError* error);
+int64_t* item_get_priority(uint64_t item, struct ExternError* error);
+void item_set_priority(uint64_t item, const int64_t* priority, struct ExternError* error);
+int item_get_starred(uint64_t item, struct ExternError* error);
+void item_set_starred(uint64_t item, int starred, struct ExternError* error);
+char* item_get_recurrence(uint64_t item, struct ExternError* error);
+uint64_t item_get_labels(uint64_t item, struct ExternError* error);
+int item_labels_count(uint64_t item, struct ExternError* error);
+uint64_t item_label_at(uint64_t label_list, size_t index, struct ExternError* error);
+
+// labels.rs
+void label_destroy(uint64_t label, struct ExternError* error);
+char* label_get_name(uint64_t label, struct ExternError* error);
+char* label_get_color(uint64_t label, struct ExternError* error);
+void label_set_color(uint64_t label, const char* color, struct ExternError* error);
+uint64_t label_list_new(void);
+void label_list_destroy(uint64_t label_list, struct ExternError* error);
+size_t label_list_count(uint64_t label_list, struct ExternError* error);
+uint64_t label_list_entry_at(uint64_t label_list, size_t index, struct ExternError* error);
+void add_label(uint64_t label_list, uint64_t label, struct ExternError* error);
+
+// lib.rs
+uint64_t new_toodle(const char* uri, const char* key, struct ExternError* error);
+void toodle_rekey(uint64_t toodle, const char* key, struct ExternError* error);
+void toodle_destroy(uint64_t toodle, struct ExternError* error);
+uint64_t toodle_get_all_labels(uint64_t toodle, struct ExternError* error);
+uint64_t toodle_create_item(uint64_t toodle, const char* name, const time_t* due_date, struct ExternError* error);
+uint64_t toodle_register_observer(uint64_t toodle, int topics, void* context, ChangedCallback callback, struct ExternError* error);
+int toodle_unregister_observer(uint64_t toodle, uint64_t token, struct ExternError* error);
+uint64_t toodle_get_all_items(uint64_t toodle, struct ExternError* error);
+uint64_t toodle_search_items(uint64_t toodle, const char* query, struct ExternError* error);
+struct CItem* item_list_entry_at(uint64_t item_list, int index, struct ExternError* error);
+uint64_t item_list_item_at(uint64_t item_list, int index, struct ExternError* error);
+int item_list_count(uint64_t item_list, struct ExternError* error);
+void item_list_destroy(uint64_t item_list, struct ExternError* error);
+void toodle_all_items(uint64_t toodle, void (*callback)(uint64_t), struct ExternError* error);
+void item_c_destroy(struct CItem* item);
+void toodle_update_item(uint64_t toodle, uint64_t item, const char* name, const time_t* due_date, const time_t* completion_date, uint64_t labels, struct ExternError* error);
+void toodle_update_item_by_uuid(uint64_t toodle, const char* uuid, const char* name, const time_t* due_date, const time_t* completion_date, struct ExternError* error);
+void toodle_delete_item_by_uuid(uint64_t toodle, const char* uuid, struct ExternError* error);
+uint64_t toodle_create_label(uint64_t toodle, const char* name, const char* color, struct ExternError* error);
+void toodle_delete_label(uint64_t toodle, uint64_t label, struct ExternError* error);
+void toodle_rename_label(uint64_t toodle, uint64_t label, const char* new_name, struct ExternError* error);
+void toodle_update_label_color(uint64_t toodle, uint64_t label, const char* color, struct ExternError* error);
+
+// recurrence.rs
+void toodle_set_recurrence(uint64_t toodle, const char* uuid, const char* rule, struct ExternError* error);
 
-struct toodle;
+// subtasks.rs
+uint64_t toodle_create_subtask(uint64_t toodle, const char* parent, const char* name, const time_t* due_date, struct ExternError* error);
+void toodle_move_item(uint64_t toodle, const char* uuid, const char* parent, struct ExternError* error);
+uint64_t toodle_get_children(uint64_t toodle, const char* uuid, struct ExternError* error);
+int toodle_get_progress(uint64_t toodle, const char* uuid, int* completed, struct ExternError* error);
+void toodle_complete_item(uint64_t toodle, const char* uuid, const time_t* completion_date, int cascade, struct ExternError* error);
+void toodle_delete_item_with_subtasks(uint64_t toodle, const char* uuid, struct ExternError* error);
 
-struct toodle* new_toodle(const char* uri);
-void toodle_destroy(struct toodle* toodle);
+#endif /* toodle_h */
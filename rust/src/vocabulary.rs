@@ -0,0 +1,157 @@
+// Copyright 2016 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! The `:label`, `:item`, `:sync` and `:legacy` vocabularies, and every version they've been through.
+//!
+//! Changing any of them means adding a step to the end of its list, never editing a
+//! step that has shipped: stores that already ran it won't run it again.
+
+use store::{
+    Vocabulary,
+    VocabularyRegistry,
+};
+
+pub const LABELS: &'static str = "labels";
+pub const ITEMS: &'static str = "items";
+pub const SYNC: &'static str = "sync";
+pub const LEGACY: &'static str = "legacy";
+
+const LABELS_V1: &'static str = r#"[
+    {  :db/ident       :label/name
+       :db/valueType   :db.type/string
+       :db/cardinality :db.cardinality/one
+       :db/unique      :db.unique/identity
+       :db/index       true
+       :db/fulltext    true },
+    {  :db/ident       :label/color
+       :db/valueType   :db.type/string
+       :db/cardinality :db.cardinality/one }]"#;
+
+const ITEMS_V1: &'static str = r#"[
+    {   :db/ident       :item/uuid
+        :db/valueType   :db.type/uuid
+        :db/cardinality :db.cardinality/one
+        :db/unique      :db.unique/value
+        :db/index true },
+    {   :db/ident       :item/name
+        :db/valueType   :db.type/string
+        :db/cardinality :db.cardinality/one
+        :db/index       true
+        :db/fulltext    true  },
+    {   :db/ident       :item/due_date
+        :db/valueType   :db.type/instant
+        :db/cardinality :db.cardinality/one  },
+    {   :db/ident       :item/completion_date
+        :db/valueType   :db.type/instant
+        :db/cardinality :db.cardinality/one  },
+    {  :db/ident     :item/label
+        :db/valueType :db.type/ref
+        :db/cardinality :db.cardinality/many }]"#;
+
+const ITEMS_V2: &'static str = r#"[
+    {   :db/ident       :item/notes
+        :db/valueType   :db.type/string
+        :db/cardinality :db.cardinality/one
+        :db/fulltext    true },
+    {   :db/ident       :item/priority
+        :db/valueType   :db.type/long
+        :db/cardinality :db.cardinality/one },
+    {   :db/ident       :item/starred
+        :db/valueType   :db.type/boolean
+        :db/cardinality :db.cardinality/one }]"#;
+
+const ITEMS_V3: &'static str = r#"[
+    {   :db/ident       :item/parent
+        :db/valueType   :db.type/ref
+        :db/cardinality :db.cardinality/one
+        :db/index       true }]"#;
+
+const ITEMS_V4: &'static str = r#"[
+    {   :db/ident       :item/recurrence
+        :db/valueType   :db.type/string
+        :db/cardinality :db.cardinality/one }]"#;
+
+/// Where this store is up to with its sync server; see `sync::SyncMetadata`.
+const SYNC_V1: &'static str = r#"[
+    {   :db/ident       :sync/store
+        :db/valueType   :db.type/string
+        :db/cardinality :db.cardinality/one
+        :db/unique      :db.unique/identity },
+    {   :db/ident       :sync/local_tx
+        :db/valueType   :db.type/long
+        :db/cardinality :db.cardinality/one },
+    {   :db/ident       :sync/remote_version
+        :db/valueType   :db.type/long
+        :db/cardinality :db.cardinality/one }]"#;
+
+/// The report of importing the legacy tables in a store's own file; see `legacy`.
+const LEGACY_V1: &'static str = r#"[
+    {   :db/ident       :legacy/report
+        :db/valueType   :db.type/string
+        :db/cardinality :db.cardinality/one }]"#;
+
+/// Stores made before versions were recorded have the first version of both
+/// vocabularies, which is safe to transact again; so they start at version 1 like
+/// any new store.
+pub fn registry() -> VocabularyRegistry {
+    VocabularyRegistry::new()
+        .register(Vocabulary::new(LABELS).step(LABELS_V1))
+        .register(Vocabulary::new(ITEMS).step(ITEMS_V1).step(ITEMS_V2).step(ITEMS_V3).step(ITEMS_V4))
+        .register(Vocabulary::new(SYNC).step(SYNC_V1))
+        .register(Vocabulary::new(LEGACY).step(LEGACY_V1))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use store::Store;
+    use store::vocabulary::installed_version;
+
+    use Toodle;
+    use test::temp_path;
+
+    #[test]
+    fn test_new_stores_are_current() {
+        let toodle = Toodle::new(String::new(), None).expect("expected a new toodle");
+        let registry = registry();
+        for name in &[LABELS, ITEMS, SYNC, LEGACY] {
+            let current = registry.get(name).expect("expected a vocabulary").version();
+            assert_eq!(installed_version(&toodle.connection, name).expect("expected a version"), current);
+        }
+    }
+
+    #[test]
+    fn test_unversioned_stores_are_upgraded() {
+        let path = temp_path("unversioned.db");
+        let uri = path.to_string_lossy().into_owned();
+
+        // What `Toodle::new` used to leave behind: the vocabularies, but no versions.
+        {
+            let mut connection = Store::new_store(uri.clone(), None).expect("expected a store");
+            connection.transact(LABELS_V1).expect("expected the labels vocabulary");
+            connection.transact(ITEMS_V1).expect("expected the items vocabulary");
+            connection.transact(r#"[{:label/name "home" :label/color "#ff0000"}]"#).expect("expected a label");
+            connection.transact(r#"[{:item/uuid #uuid "2cb9c3a1-62a7-4cc6-8b46-8fcd7e1d7f47"
+                                     :item/name "Buy milk"
+                                     :item/label (lookup-ref :label/name "home")}]"#).expect("expected an item");
+            assert_eq!(installed_version(&connection, ITEMS).expect("expected a version"), 0);
+        }
+
+        let toodle = Toodle::new(uri, None).expect("expected the old store to open");
+        assert_eq!(installed_version(&toodle.connection, LABELS).expect("expected a version"), 1);
+        assert_eq!(installed_version(&toodle.connection, ITEMS).expect("expected a version"), 4);
+        let items = toodle.fetch_items().expect("expected items");
+        assert_eq!(items.vec.len(), 1);
+        assert_eq!(items.vec[0].name, "Buy milk");
+        assert_eq!(items.vec[0].notes, None);
+        assert_eq!(items.vec[0].labels.iter().map(|label| label.name.clone()).collect::<Vec<_>>(), vec!["home".to_string()]);
+    }
+}
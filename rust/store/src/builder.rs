@@ -0,0 +1,268 @@
+// Copyright 2016 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! A typed alternative to hand-formatting EDN transactions.
+//!
+//! Callers describe assertions and retractions in terms of entities, attributes and
+//! `TypedValue`s; the builder takes care of rendering them as valid EDN, including
+//! escaping any user supplied strings.
+
+use std::fmt::Write;
+
+use edn::NamespacedKeyword;
+
+use mentat_core::{
+    Entid,
+    TypedValue,
+};
+
+use Entity;
+
+/// The entity position of an assertion or retraction.
+#[derive(Clone, Debug, PartialEq)]
+pub enum EntityPlace {
+    Entid(Entid),
+    TempId(String),
+    LookupRef(NamespacedKeyword, TypedValue),
+}
+
+impl From<Entid> for EntityPlace {
+    fn from(entid: Entid) -> EntityPlace {
+        EntityPlace::Entid(entid)
+    }
+}
+
+impl From<Entity> for EntityPlace {
+    fn from(entity: Entity) -> EntityPlace {
+        EntityPlace::Entid(entity.id)
+    }
+}
+
+impl<'a> From<&'a Entity> for EntityPlace {
+    fn from(entity: &'a Entity) -> EntityPlace {
+        EntityPlace::Entid(entity.id)
+    }
+}
+
+/// The value position of an assertion or retraction.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ValuePlace {
+    Value(TypedValue),
+    Entity(EntityPlace),
+}
+
+impl From<TypedValue> for ValuePlace {
+    fn from(value: TypedValue) -> ValuePlace {
+        ValuePlace::Value(value)
+    }
+}
+
+impl From<EntityPlace> for ValuePlace {
+    fn from(entity: EntityPlace) -> ValuePlace {
+        ValuePlace::Entity(entity)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Term {
+    Add(EntityPlace, NamespacedKeyword, ValuePlace),
+    Retract(EntityPlace, NamespacedKeyword, ValuePlace),
+}
+
+/// Tempids from `TransactionBuilder::tempid` start with this, and named tempids can't, so
+/// the two never collide.
+const TEMPID_PREFIX: &'static str = "~";
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TransactionBuilder {
+    terms: Vec<Term>,
+    next_tempid: i64,
+}
+
+impl TransactionBuilder {
+    pub fn new() -> TransactionBuilder {
+        TransactionBuilder::default()
+    }
+
+    /// Allocates a tempid that is unique within this transaction.
+    pub fn tempid(&mut self) -> EntityPlace {
+        self.next_tempid += 1;
+        EntityPlace::TempId(format!("{}t{}", TEMPID_PREFIX, self.next_tempid))
+    }
+
+    /// A tempid whose resolved entid can be read back from `TxReport::tempids` under `name`.
+    /// Panics if `name` starts with `~`, which is reserved for `tempid`.
+    pub fn named_tempid<T>(&mut self, name: T) -> EntityPlace where T: Into<String> {
+        let name = name.into();
+        assert!(!name.starts_with(TEMPID_PREFIX), "tempid names can't start with {:?}", TEMPID_PREFIX);
+        EntityPlace::TempId(name)
+    }
+
+    pub fn lookup_ref<V>(&self, attribute: NamespacedKeyword, value: V) -> EntityPlace where V: Into<TypedValue> {
+        EntityPlace::LookupRef(attribute, value.into())
+    }
+
+    pub fn add<E, V>(&mut self, entity: E, attribute: NamespacedKeyword, value: V) -> &mut Self
+        where E: Into<EntityPlace>, V: Into<ValuePlace> {
+        self.terms.push(Term::Add(entity.into(), attribute, value.into()));
+        self
+    }
+
+    pub fn retract<E, V>(&mut self, entity: E, attribute: NamespacedKeyword, value: V) -> &mut Self
+        where E: Into<EntityPlace>, V: Into<ValuePlace> {
+        self.terms.push(Term::Retract(entity.into(), attribute, value.into()));
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.terms.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.terms.len()
+    }
+
+    /// Renders the transaction as EDN suitable for `Conn::transact`.
+    pub fn to_edn(&self) -> String {
+        let mut edn = String::from("[");
+        for term in self.terms.iter() {
+            let (op, e, a, v) = match term {
+                &Term::Add(ref e, ref a, ref v) => (":db/add", e, a, v),
+                &Term::Retract(ref e, ref a, ref v) => (":db/retract", e, a, v),
+            };
+            edn.push_str("[");
+            edn.push_str(op);
+            edn.push(' ');
+            write_entity_place(&mut edn, e);
+            edn.push(' ');
+            write_keyword(&mut edn, a);
+            edn.push(' ');
+            match v {
+                &ValuePlace::Value(ref value) => write_typed_value(&mut edn, value),
+                &ValuePlace::Entity(ref entity) => write_entity_place(&mut edn, entity),
+            }
+            edn.push(']');
+        }
+        edn.push(']');
+        edn
+    }
+}
+
+fn write_keyword(edn: &mut String, keyword: &NamespacedKeyword) {
+    let _ = write!(edn, ":{}/{}", keyword.namespace, keyword.name);
+}
+
+fn write_string(edn: &mut String, s: &str) {
+    edn.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => edn.push_str("\\\""),
+            '\\' => edn.push_str("\\\\"),
+            '\n' => edn.push_str("\\n"),
+            '\r' => edn.push_str("\\r"),
+            '\t' => edn.push_str("\\t"),
+            c => edn.push(c),
+        }
+    }
+    edn.push('"');
+}
+
+fn write_entity_place(edn: &mut String, entity: &EntityPlace) {
+    match entity {
+        &EntityPlace::Entid(entid) => {
+            let _ = write!(edn, "{}", entid);
+        },
+        &EntityPlace::TempId(ref name) => write_string(edn, name),
+        &EntityPlace::LookupRef(ref attribute, ref value) => {
+            edn.push_str("(lookup-ref ");
+            write_keyword(edn, attribute);
+            edn.push(' ');
+            write_typed_value(edn, value);
+            edn.push(')');
+        },
+    }
+}
+
+fn write_typed_value(edn: &mut String, value: &TypedValue) {
+    match value {
+        &TypedValue::Ref(entid) => {
+            let _ = write!(edn, "{}", entid);
+        },
+        &TypedValue::Boolean(b) => {
+            let _ = write!(edn, "{}", b);
+        },
+        &TypedValue::Long(l) => {
+            let _ = write!(edn, "{}", l);
+        },
+        &TypedValue::Double(d) => {
+            // `{:?}` always includes a decimal point, so EDN won't read it back as a long.
+            let _ = write!(edn, "{:?}", d.into_inner());
+        },
+        &TypedValue::Instant(ref instant) => {
+            let micros = instant.timestamp() * 1_000_000 + i64::from(instant.timestamp_subsec_micros());
+            let _ = write!(edn, "#instmicros {}", micros);
+        },
+        &TypedValue::String(ref s) => write_string(edn, s),
+        &TypedValue::Keyword(ref keyword) => write_keyword(edn, keyword),
+        &TypedValue::Uuid(ref uuid) => {
+            let _ = write!(edn, "#uuid \"{}\"", uuid.hyphenated());
+        },
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use ToTypedValue;
+
+    #[test]
+    fn test_escapes_strings() {
+        let mut builder = TransactionBuilder::new();
+        builder.add(EntityPlace::Entid(65536),
+                    NamespacedKeyword::new("item", "name"),
+                    "a \"quoted\" \\ name\n".to_typed_value());
+        assert_eq!(builder.to_edn(), r#"[[:db/add 65536 :item/name "a \"quoted\" \\ name\n"]]"#);
+    }
+
+    #[test]
+    fn test_tempids_and_lookup_refs() {
+        let mut builder = TransactionBuilder::new();
+        let item = builder.named_tempid("item");
+        let label = builder.lookup_ref(NamespacedKeyword::new("label", "name"), "]\")".to_typed_value());
+        builder.add(item.clone(), NamespacedKeyword::new("item", "label"), label)
+               .retract(item, NamespacedKeyword::new("item", "completion_date"), TypedValue::Long(1));
+        assert_eq!(builder.len(), 2);
+        assert_eq!(builder.to_edn(),
+                   r#"[[:db/add "item" :item/label (lookup-ref :label/name "]\")")][:db/retract "item" :item/completion_date 1]]"#);
+    }
+
+    #[test]
+    fn test_tempids_and_named_tempids_differ() {
+        let mut builder = TransactionBuilder::new();
+        let tempid = builder.tempid();
+        assert_ne!(tempid, builder.named_tempid("t1"));
+        assert_ne!(tempid, builder.tempid());
+    }
+
+    #[test]
+    #[should_panic(expected = "tempid names can't start with")]
+    fn test_reserved_tempid_names_are_refused() {
+        let mut builder = TransactionBuilder::new();
+        builder.named_tempid("~t1");
+    }
+
+    #[test]
+    fn test_empty() {
+        let builder = TransactionBuilder::new();
+        assert!(builder.is_empty());
+        assert_eq!(builder.to_edn(), "[]");
+    }
+}
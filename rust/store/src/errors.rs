@@ -13,6 +13,7 @@
 use rusqlite;
 
 use mentat::errors as mentat;
+use mentat_db::errors as mentat_db;
 
 error_chain! {
     types {
@@ -25,5 +26,33 @@ error_chain! {
 
     links {
         MentatError(mentat::Error, mentat::ErrorKind);
+        DbError(mentat_db::Error, mentat_db::ErrorKind);
+    }
+
+    errors {
+        WrongKey {
+            description("The store couldn't be decrypted")
+            display("the key is wrong, or the store is encrypted and no key was given")
+        }
+
+        EmptyKey {
+            description("An encryption key was empty")
+            display("encryption keys can't be empty")
+        }
+
+        EncryptionUnsupported {
+            description("Encrypted stores need SQLCipher")
+            display("this build can't open encrypted stores; build with the `sqlcipher` feature")
+        }
+
+        VocabularyTooNew(name: String, installed: u32, supported: u32) {
+            description("The store has a newer version of a vocabulary than this code knows")
+            display("the store has version {} of the {} vocabulary, but only versions up to {} are supported", installed, name, supported)
+        }
+
+        NotEncrypted {
+            description("Only encrypted stores can be re-keyed")
+            display("the store isn't encrypted, so it can't be re-keyed")
+        }
     }
 }
@@ -46,6 +46,7 @@ use mentat_core::{
     Uuid,
 };
 
+use mentat_db::db::TypedSQLValue;
 use mentat_db::types::TxReport;
 
 use mentat::query::{
@@ -53,15 +54,32 @@ use mentat::query::{
     Variable,
 };
 
+use ffi_utils::log;
+
 use ordered_float::OrderedFloat;
 
 use rusqlite::{
-    Connection
+    Connection,
+    ErrorCode,
 };
+use rusqlite::types::Value;
 
 use time::Timespec;
 
+pub mod builder;
 pub mod errors;
+pub mod vocabulary;
+
+pub use builder::{
+    EntityPlace,
+    TransactionBuilder,
+    ValuePlace,
+};
+pub use vocabulary::{
+    Upgrade,
+    Vocabulary,
+    VocabularyRegistry,
+};
 
 use errors as store_errors;
 
@@ -221,6 +239,16 @@ impl<'a> ToInner<Uuid> for &'a TypedValue {
     }
 }
 
+/// A single assertion or retraction, as recorded in the transaction log.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TxDatom {
+    pub e: Entid,
+    pub a: Entid,
+    pub v: TypedValue,
+    pub tx: Entid,
+    pub added: bool,
+}
+
 #[derive(Debug)]
 pub struct StoreConnection {
     pub handle: Connection,
@@ -241,16 +269,160 @@ impl StoreConnection {
         Ok(self.store.conn.write().unwrap().transact(&mut self.handle, transaction)?)
     }
 
+    pub fn transact_builder(&mut self, builder: &TransactionBuilder) -> Result<TxReport, store_errors::Error> {
+        self.transact(&builder.to_edn())
+    }
+
+    /// Every datom transacted after `tx`, in transaction order.
+    pub fn transactions_after(&self, tx: Entid) -> Result<Vec<TxDatom>, store_errors::Error> {
+        let mut stmt = self.handle.prepare(
+            "SELECT e, a, v, value_type_tag, tx, added FROM transactions WHERE tx > ? ORDER BY tx, added")?;
+        let rows = stmt.query_and_then(&[&tx], |row| -> Result<TxDatom, store_errors::Error> {
+            let v: Value = row.get_checked(2)?;
+            let value_type_tag: i32 = row.get_checked(3)?;
+            Ok(TxDatom {
+                e: row.get_checked(0)?,
+                a: row.get_checked(1)?,
+                v: TypedValue::from_sql_value_pair(v, value_type_tag)?,
+                tx: row.get_checked(4)?,
+                added: row.get_checked(5)?,
+            })
+        })?;
+        rows.collect()
+    }
+
+    /// The value of a cardinality-one `attribute` of `e` as of just before `before_tx`,
+    /// or `None` if it had no value at that point.
+    pub fn value_before(&self, e: Entid, attribute: &NamespacedKeyword, before_tx: Entid) -> Result<Option<TypedValue>, store_errors::Error> {
+        let a = match self.entid_for_ident(attribute) {
+            Some(a) => a,
+            None => return Ok(None),
+        };
+        let mut stmt = self.handle.prepare(
+            "SELECT v, value_type_tag, added FROM transactions WHERE e = ? AND a = ? AND tx < ? ORDER BY tx DESC, added DESC LIMIT 1")?;
+        let mut rows = stmt.query(&[&e, &a, &before_tx])?;
+        match rows.next() {
+            Some(row) => {
+                let row = row?;
+                let added: bool = row.get_checked(2)?;
+                if !added {
+                    return Ok(None);
+                }
+                let v: Value = row.get_checked(0)?;
+                let value_type_tag: i32 = row.get_checked(1)?;
+                Ok(Some(TypedValue::from_sql_value_pair(v, value_type_tag)?))
+            },
+            None => Ok(None),
+        }
+    }
+
+    /// The id of the most recent transaction.
+    pub fn last_tx(&self) -> Result<Entid, store_errors::Error> {
+        Ok(self.handle.query_row("SELECT COALESCE(MAX(tx), 0) FROM transactions", &[], |row| row.get(0))?)
+    }
+
+    pub fn entid_for_ident(&self, ident: &NamespacedKeyword) -> Option<Entid> {
+        self.store.conn.read().unwrap().current_schema().get_entid(ident)
+    }
+
+    pub fn ident_for_entid(&self, entid: Entid) -> Option<NamespacedKeyword> {
+        self.store.conn.read().unwrap().current_schema().get_ident(entid).cloned()
+    }
+
+    pub fn is_multival(&self, attribute: &NamespacedKeyword) -> bool {
+        let schema = self.store.conn.read().unwrap().current_schema();
+        schema.get_entid(attribute)
+              .and_then(|entid| schema.attribute_for_entid(entid))
+              .map_or(false, |attribute| attribute.multival)
+    }
+
     pub fn fetch_schema(&self) -> edn::Value {
         self.store.conn.read().unwrap().current_schema().to_edn_value()
     }
 
     pub fn new_connection(&self) -> store_errors::Result<StoreConnection> {
+        let key = self.store.key.read().unwrap().clone();
         Ok(StoreConnection {
-            handle: new_connection(&self.store.uri)?,
+            handle: open_connection(&self.store.uri, key.as_ref().map(|key| key.as_str()))?,
             store: self.store.clone(),
         })
     }
+
+    /// Re-encrypts the store with `key`. Only stores that were opened with a key can be
+    /// re-keyed; connections opened afterwards with `new_connection` use the new key.
+    pub fn rekey(&mut self, key: &str) -> store_errors::Result<()> {
+        if key.is_empty() {
+            bail!(store_errors::ErrorKind::EmptyKey);
+        }
+        let mut current = self.store.key.write().unwrap();
+        if current.is_none() {
+            bail!(store_errors::ErrorKind::NotEncrypted);
+        }
+        self.handle.execute_batch(&format!("PRAGMA rekey = {};", quote(key)))?;
+        *current = Some(key.to_string());
+        Ok(())
+    }
+}
+
+/// `value` as a SQL string literal, for pragmas that can't take bound parameters.
+fn quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+/// Whether `error` is SQLite failing to read a file as a database, which is what a
+/// wrong key, or a missing one, looks like to SQLCipher.
+fn is_not_a_database(error: &rusqlite::Error) -> bool {
+    match error {
+        &rusqlite::Error::SqliteFailure(ref error, _) => error.code == ErrorCode::NotADatabase,
+        _ => false,
+    }
+}
+
+fn check_key<T>(result: rusqlite::Result<T>) -> store_errors::Result<T> {
+    result.map_err(|e| if is_not_a_database(&e) {
+        store_errors::ErrorKind::WrongKey.into()
+    } else {
+        e.into()
+    })
+}
+
+/// Opens the database at `uri`, or an in-memory one if `uri` is empty. With a key the
+/// database is encrypted, which needs SQLite to have been built with SQLCipher.
+fn open_connection(uri: &str, key: Option<&str>) -> store_errors::Result<Connection> {
+    let key = match key {
+        Some(key) => key,
+        None => return check_key(new_connection(uri)),
+    };
+    if key.is_empty() {
+        bail!(store_errors::ErrorKind::EmptyKey);
+    }
+    let connection = if uri.is_empty() {
+        Connection::open_in_memory()?
+    } else {
+        Connection::open(uri)?
+    };
+    // Plain SQLite ignores both of these pragmas, so ask for the cipher's version to
+    // find out whether the key will be used at all.
+    connection.execute_batch(&format!("PRAGMA key = {};", quote(key)))?;
+    let has_cipher = {
+        let mut stmt = connection.prepare("PRAGMA cipher_version")?;
+        let mut rows = stmt.query(&[])?;
+        rows.next().is_some()
+    };
+    if !has_cipher {
+        bail!(store_errors::ErrorKind::EncryptionUnsupported);
+    }
+    // SQLCipher only reads the file once it's used, which is when a wrong key shows up.
+    check_key(connection.query_row("SELECT count(*) FROM sqlite_master", &[], |row| row.get::<_, i64>(0)))?;
+    // The same settings as `mentat::new_connection`, except the page size, which an
+    // encrypted database sets with `cipher_page_size` instead.
+    connection.execute_batch("
+        PRAGMA journal_mode=wal;
+        PRAGMA wal_autocheckpoint=32;
+        PRAGMA journal_size_limit=3145728;
+        PRAGMA foreign_keys=ON;
+    ")?;
+    Ok(connection)
 }
 
 /// Store containing a SQLite connection
@@ -258,11 +430,14 @@ impl StoreConnection {
 pub struct Store {
     conn: Arc<RwLock<Conn>>,
     uri: String,
+    // Kept so that `new_connection` can open the store again, and shared so that a
+    // rekey through one connection applies to all of them.
+    key: Arc<RwLock<Option<String>>>,
 }
 
 impl Drop for Store {
     fn drop(&mut self) {
-        println!("{:?} is being deallocated", self);
+        log::d(&format!("{:?} is being deallocated", self));
     }
 }
 
@@ -273,22 +448,129 @@ impl fmt::Debug for Store {
 }
 
 impl Store {
-    pub fn new_store<T>(uri: T) -> Result<StoreConnection, store_errors::Error>
+    /// Opens the store at `uri`, encrypted with `key` if there is one. Opening an
+    /// encrypted store with the wrong key, or without one, fails with `WrongKey`.
+    pub fn new_store<T>(uri: T, key: Option<&str>) -> Result<StoreConnection, store_errors::Error>
         where T: Into<Option<String>> {
         let uri_string = uri.into().unwrap_or(String::new());
-        let mut connection = new_connection(&uri_string)?;
-        let store = Store::new(uri_string, &mut connection)?;
+        let mut connection = open_connection(&uri_string, key)?;
+        let store = Store::new(uri_string, key, &mut connection)?;
         Ok(StoreConnection {
             handle: connection,
             store: store,
         })
     }
 
-    fn new(uri: String,  connection: &mut Connection) -> Result<Self, store_errors::Error> {
+    fn new(uri: String, key: Option<&str>, connection: &mut Connection) -> Result<Self, store_errors::Error> {
         let c = Conn::connect(connection)?;
         Ok(Store {
             conn:Arc::new(RwLock::new(c)),
             uri: uri,
+            key: Arc::new(RwLock::new(key.map(|key| key.to_string()))),
         })
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::env;
+    use std::fs;
+    use std::process;
+    use std::sync::atomic::{
+        AtomicUsize,
+        Ordering,
+    };
+
+    use errors::ErrorKind;
+
+    /// A store in a directory of its own, so that tests running at once, here or in
+    /// another checkout, can't share one.
+    fn temp_store(name: &str) -> String {
+        static NEXT_DIRECTORY: AtomicUsize = AtomicUsize::new(0);
+        let directory = env::temp_dir().join(format!("toodle-store-{}-{}", process::id(), NEXT_DIRECTORY.fetch_add(1, Ordering::SeqCst)));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).expect("expected a temporary directory");
+        directory.join(format!("{}.db", name)).to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn test_quote() {
+        assert_eq!(quote("plain"), "'plain'");
+        assert_eq!(quote("it's"), "'it''s'");
+    }
+
+    #[test]
+    fn test_empty_keys_are_rejected() {
+        match Store::new_store(String::new(), Some("")) {
+            Err(e) => match e.kind() {
+                &ErrorKind::EmptyKey => (),
+                kind => panic!("expected EmptyKey, got {:?}", kind),
+            },
+            Ok(_) => panic!("expected an empty key to be rejected"),
+        }
+    }
+
+    #[test]
+    fn test_plain_stores_cant_be_rekeyed() {
+        let mut connection = Store::new_store(String::new(), None).expect("expected a store");
+        match connection.rekey("secret") {
+            Err(e) => match e.kind() {
+                &ErrorKind::NotEncrypted => (),
+                kind => panic!("expected NotEncrypted, got {:?}", kind),
+            },
+            Ok(_) => panic!("expected a plain store not to be rekeyed"),
+        }
+    }
+
+    #[cfg(not(feature = "sqlcipher"))]
+    #[test]
+    fn test_keys_need_sqlcipher() {
+        match Store::new_store(temp_store("unsupported"), Some("secret")) {
+            Err(e) => match e.kind() {
+                &ErrorKind::EncryptionUnsupported => (),
+                kind => panic!("expected EncryptionUnsupported, got {:?}", kind),
+            },
+            Ok(_) => panic!("expected a key to need SQLCipher"),
+        }
+    }
+
+    #[cfg(feature = "sqlcipher")]
+    #[test]
+    fn test_encrypted_stores() {
+        let uri = temp_store("encrypted");
+        {
+            let mut connection = Store::new_store(uri.clone(), Some("first")).expect("expected a store");
+            connection.transact(r#"[{:db/ident :test/name :db/valueType :db.type/string :db/cardinality :db.cardinality/one}]"#)
+                .expect("expected a transaction");
+            connection.transact(r#"[{:test/name "secret plans"}]"#).expect("expected a transaction");
+        }
+
+        let wrong_key = |key: Option<&str>| match Store::new_store(uri.clone(), key) {
+            Err(e) => match e.kind() {
+                &ErrorKind::WrongKey => (),
+                kind => panic!("expected WrongKey, got {:?}", kind),
+            },
+            Ok(_) => panic!("expected {:?} not to open the store", key),
+        };
+        wrong_key(Some("second"));
+        wrong_key(None);
+
+        let mut connection = Store::new_store(uri.clone(), Some("first")).expect("expected the store to reopen");
+        connection.rekey("second").expect("expected a rekey");
+        assert!(connection.new_connection().is_ok());
+        drop(connection);
+
+        wrong_key(Some("first"));
+        let connection = Store::new_store(uri.clone(), Some("second")).expect("expected the new key to work");
+        let names = connection.query(r#"[:find [?name ...] :where [_ :test/name ?name]]"#).expect("expected a query");
+        assert!(format!("{:?}", names).contains("secret plans"));
+        drop(connection);
+
+        for suffix in &["", "-wal"] {
+            let bytes = fs::read(format!("{}{}", uri, suffix)).unwrap_or(vec![]);
+            assert!(!bytes.windows(b"secret plans".len()).any(|window| window == b"secret plans"));
+        }
+    }
+}
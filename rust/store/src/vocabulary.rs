@@ -0,0 +1,295 @@
+// Copyright 2016 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! Named, versioned vocabularies and the migrations that bring a store up to date.
+//!
+//! A `Vocabulary` is an ordered list of migration steps; version N of it is what the
+//! first N steps leave behind. Each step transacts some EDN, usually attribute
+//! definitions, and can then transform existing data to fit. The version installed
+//! in each store is recorded as datoms of the `:store.vocabulary` attributes, so
+//! opening a store only runs the steps it hasn't seen.
+//!
+//! A step's schema is transacted first, so that its transform can use the attributes
+//! it defines. The transform's changes and the new version are then transacted
+//! together: either both happen or neither does, and a step interrupted in between
+//! runs again from the start on the next open. Schema EDN is safe to transact twice.
+
+use edn::NamespacedKeyword;
+
+use mentat::query::{
+    IntoResult,
+    Variable,
+};
+use mentat_core::TypedValue;
+
+use errors::{
+    ErrorKind,
+    Result,
+};
+use {
+    StoreConnection,
+    ToTypedValue,
+    TransactionBuilder,
+};
+
+/// Adds the changes that fit existing data to a step's schema to the transaction that
+/// records the step's version. The schema is already in place.
+pub type Transform = fn(&StoreConnection, &mut TransactionBuilder) -> Result<()>;
+
+/// The attributes that record which version of each vocabulary a store has.
+const VERSIONS: &'static str = r#"[
+    {   :db/ident       :store.vocabulary/name
+        :db/valueType   :db.type/string
+        :db/cardinality :db.cardinality/one
+        :db/unique      :db.unique/identity },
+    {   :db/ident       :store.vocabulary/version
+        :db/valueType   :db.type/long
+        :db/cardinality :db.cardinality/one }]"#;
+
+fn kw(name: &str) -> NamespacedKeyword {
+    NamespacedKeyword::new("store.vocabulary", name)
+}
+
+pub struct Migration {
+    /// EDN to transact. May be empty for steps that only transform data.
+    pub schema: &'static str,
+    pub transform: Option<Transform>,
+}
+
+pub struct Vocabulary {
+    pub name: &'static str,
+    migrations: Vec<Migration>,
+}
+
+impl Vocabulary {
+    pub fn new(name: &'static str) -> Vocabulary {
+        Vocabulary {
+            name: name,
+            migrations: vec![],
+        }
+    }
+
+    /// Adds a step that transacts `schema`, making the next version.
+    pub fn step(self, schema: &'static str) -> Vocabulary {
+        self.migration(Migration { schema: schema, transform: None })
+    }
+
+    /// Adds a step that transacts `schema` and then runs `transform`.
+    pub fn step_with(self, schema: &'static str, transform: Transform) -> Vocabulary {
+        self.migration(Migration { schema: schema, transform: Some(transform) })
+    }
+
+    fn migration(mut self, migration: Migration) -> Vocabulary {
+        self.migrations.push(migration);
+        self
+    }
+
+    /// The newest version, which `VocabularyRegistry::install` brings stores up to.
+    pub fn version(&self) -> u32 {
+        self.migrations.len() as u32
+    }
+}
+
+/// A vocabulary that `install` moved from one version to another.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Upgrade {
+    pub name: &'static str,
+    pub from: u32,
+    pub to: u32,
+}
+
+/// Every vocabulary a store should have, installed in the order they were registered.
+pub struct VocabularyRegistry {
+    vocabularies: Vec<Vocabulary>,
+}
+
+impl VocabularyRegistry {
+    pub fn new() -> VocabularyRegistry {
+        VocabularyRegistry { vocabularies: vec![] }
+    }
+
+    pub fn register(mut self, vocabulary: Vocabulary) -> VocabularyRegistry {
+        assert!(self.get(vocabulary.name).is_none(), "{} is already registered", vocabulary.name);
+        self.vocabularies.push(vocabulary);
+        self
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Vocabulary> {
+        self.vocabularies.iter().find(|vocabulary| vocabulary.name == name)
+    }
+
+    /// Runs every step that `connection`'s store hasn't seen yet, and returns the
+    /// vocabularies that changed. Fails without changing anything if the store has a
+    /// newer version of any vocabulary than this registry knows about.
+    pub fn install(&self, connection: &mut StoreConnection) -> Result<Vec<Upgrade>> {
+        let mut installed = Vec::with_capacity(self.vocabularies.len());
+        for vocabulary in self.vocabularies.iter() {
+            let version = installed_version(connection, vocabulary.name)?;
+            if version > vocabulary.version() {
+                bail!(ErrorKind::VocabularyTooNew(vocabulary.name.to_string(), version, vocabulary.version()));
+            }
+            installed.push(version);
+        }
+
+        let mut upgrades = vec![];
+        for (vocabulary, from) in self.vocabularies.iter().zip(installed) {
+            for (index, migration) in vocabulary.migrations.iter().enumerate().skip(from as usize) {
+                if connection.entid_for_ident(&kw("version")).is_none() {
+                    connection.transact(VERSIONS)?;
+                }
+                if !migration.schema.is_empty() {
+                    connection.transact(migration.schema)?;
+                }
+                let mut builder = TransactionBuilder::new();
+                if let Some(transform) = migration.transform {
+                    transform(connection, &mut builder)?;
+                }
+                record_version(&mut builder, vocabulary.name, index as u32 + 1);
+                connection.transact_builder(&builder)?;
+            }
+            if from < vocabulary.version() {
+                upgrades.push(Upgrade { name: vocabulary.name, from: from, to: vocabulary.version() });
+            }
+        }
+        Ok(upgrades)
+    }
+}
+
+/// The version of `name` installed in `connection`'s store, or 0 if it has none.
+pub fn installed_version(connection: &StoreConnection, name: &str) -> Result<u32> {
+    if connection.entid_for_ident(&kw("version")).is_some() {
+        let query = r#"[:find ?version .
+                        :in ?name
+                        :where
+                        [?vocabulary :store.vocabulary/name ?name]
+                        [?vocabulary :store.vocabulary/version ?version]]"#;
+        let version = connection.query_args(query, vec![(Variable::from_valid_name("?name"), name.to_string().to_typed_value())])
+                                .into_scalar_result()?;
+        if let Some(TypedValue::Long(version)) = version {
+            return Ok(version as u32);
+        }
+    }
+    Ok(0)
+}
+
+fn record_version(builder: &mut TransactionBuilder, name: &str, version: u32) {
+    let vocabulary = builder.tempid();
+    builder.add(vocabulary.clone(), kw("name"), name.to_string().to_typed_value())
+           .add(vocabulary, kw("version"), (version as i64).to_typed_value());
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use edn::NamespacedKeyword;
+    use mentat::query::IntoResult;
+    use mentat_core::TypedValue;
+
+    use {
+        EntityPlace,
+        Store,
+        ToTypedValue,
+        TransactionBuilder,
+    };
+
+    const TITLES: &'static str = r#"[
+        {   :db/ident       :note/title
+            :db/valueType   :db.type/string
+            :db/cardinality :db.cardinality/one }]"#;
+
+    const NAMES: &'static str = r#"[
+        {   :db/ident       :note/name
+            :db/valueType   :db.type/string
+            :db/cardinality :db.cardinality/one }]"#;
+
+    /// Copies every title to a name, as a step renaming an attribute would.
+    fn copy_titles(connection: &StoreConnection, builder: &mut TransactionBuilder) -> Result<()> {
+        let rows = connection.query("[:find ?e ?title :where [?e :note/title ?title]]").into_rel_result()?;
+        for row in rows {
+            if let TypedValue::Ref(e) = row[0] {
+                builder.add(EntityPlace::Entid(e), NamespacedKeyword::new("note", "name"), row[1].clone());
+            }
+        }
+        Ok(())
+    }
+
+    /// Copies titles too, but then gives up.
+    fn copy_titles_and_fail(connection: &StoreConnection, builder: &mut TransactionBuilder) -> Result<()> {
+        copy_titles(connection, builder)?;
+        bail!(ErrorKind::EmptyKey)
+    }
+
+    fn version_one() -> VocabularyRegistry {
+        VocabularyRegistry::new().register(Vocabulary::new("notes").step(TITLES))
+    }
+
+    fn version_two() -> VocabularyRegistry {
+        VocabularyRegistry::new().register(Vocabulary::new("notes").step(TITLES).step_with(NAMES, copy_titles))
+    }
+
+    #[test]
+    fn test_install_records_versions() {
+        let mut connection = Store::new_store(String::new(), None).expect("expected a store");
+        assert_eq!(installed_version(&connection, "notes").expect("expected a version"), 0);
+
+        let registry = version_two();
+        assert_eq!(registry.install(&mut connection).expect("expected an install"),
+                   vec![Upgrade { name: "notes", from: 0, to: 2 }]);
+        assert_eq!(installed_version(&connection, "notes").expect("expected a version"), 2);
+
+        // Nothing runs a second time.
+        assert_eq!(registry.install(&mut connection).expect("expected an install"), vec![]);
+    }
+
+    #[test]
+    fn test_upgrade_transforms_data() {
+        let mut connection = Store::new_store(String::new(), None).expect("expected a store");
+        version_one().install(&mut connection).expect("expected an install");
+        connection.transact(r#"[{:note/title "Groceries"}]"#).expect("expected a note");
+
+        assert_eq!(version_two().install(&mut connection).expect("expected an upgrade"),
+                   vec![Upgrade { name: "notes", from: 1, to: 2 }]);
+        let name = connection.query("[:find ?name . :where [_ :note/name ?name]]").into_scalar_result().expect("expected a name");
+        assert_eq!(name, Some("Groceries".to_typed_value()));
+    }
+
+    #[test]
+    fn test_failed_transforms_record_nothing() {
+        let mut connection = Store::new_store(String::new(), None).expect("expected a store");
+        version_one().install(&mut connection).expect("expected an install");
+        connection.transact(r#"[{:note/title "Groceries"}]"#).expect("expected a note");
+
+        let failing = VocabularyRegistry::new().register(Vocabulary::new("notes").step(TITLES).step_with(NAMES, copy_titles_and_fail));
+        assert!(failing.install(&mut connection).is_err());
+        assert_eq!(installed_version(&connection, "notes").expect("expected a version"), 1);
+        let name = connection.query("[:find ?name . :where [_ :note/name ?name]]").into_scalar_result().expect("expected a query");
+        assert_eq!(name, None);
+
+        // The step runs again in full next time.
+        assert_eq!(version_two().install(&mut connection).expect("expected an upgrade"),
+                   vec![Upgrade { name: "notes", from: 1, to: 2 }]);
+        assert_eq!(installed_version(&connection, "notes").expect("expected a version"), 2);
+    }
+
+    #[test]
+    fn test_newer_stores_are_refused() {
+        let mut connection = Store::new_store(String::new(), None).expect("expected a store");
+        version_two().install(&mut connection).expect("expected an install");
+        match version_one().install(&mut connection) {
+            Err(e) => match e.kind() {
+                &ErrorKind::VocabularyTooNew(ref name, 2, 1) => assert_eq!(name, "notes"),
+                kind => panic!("expected VocabularyTooNew, got {:?}", kind),
+            },
+            Ok(_) => panic!("expected an older registry to refuse a newer store"),
+        }
+        assert_eq!(installed_version(&connection, "notes").expect("expected a version"), 2);
+    }
+}
@@ -0,0 +1,178 @@
+// Copyright 2016 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! Just enough HTTP/1.1 for one request per connection, with bodies sized by
+//! `Content-Length`.
+
+use std::io::{
+    self,
+    BufRead,
+    Read,
+    Write,
+};
+
+/// Bigger bodies are refused rather than buffered.
+pub const MAX_BODY_LENGTH: usize = 16 * 1024 * 1024;
+
+/// The longest request line or header line, with its line ending.
+pub const MAX_LINE_LENGTH: usize = 8 * 1024;
+
+/// The most headers a request may have.
+pub const MAX_HEADERS: usize = 64;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    /// Everything after the `?`, if anything.
+    pub query: String,
+    pub body: Vec<u8>,
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// Reads a line into `line`, refusing one longer than `MAX_LINE_LENGTH` without reading
+/// the rest of it.
+fn read_line<R>(reader: &mut R, line: &mut String) -> io::Result<usize> where R: BufRead {
+    let read = reader.by_ref().take(MAX_LINE_LENGTH as u64 + 1).read_line(line)?;
+    if line.len() > MAX_LINE_LENGTH {
+        return Err(invalid("line too long"));
+    }
+    Ok(read)
+}
+
+pub fn read_request<R>(reader: &mut R) -> io::Result<Request> where R: BufRead {
+    let mut line = String::new();
+    read_line(reader, &mut line)?;
+    let (method, target) = {
+        let mut parts = line.split_whitespace();
+        match (parts.next(), parts.next(), parts.next()) {
+            (Some(method), Some(target), Some(version)) if version.starts_with("HTTP/1.") => (method.to_string(), target.to_string()),
+            _ => return Err(invalid("malformed request line")),
+        }
+    };
+
+    let mut length = 0;
+    let mut headers = 0;
+    loop {
+        line.clear();
+        if read_line(reader, &mut line)? == 0 {
+            return Err(invalid("truncated headers"));
+        }
+        let header = line.trim();
+        if header.is_empty() {
+            break;
+        }
+        headers += 1;
+        if headers > MAX_HEADERS {
+            return Err(invalid("too many headers"));
+        }
+        let mut parts = header.splitn(2, ':');
+        let name = parts.next().unwrap_or("").trim().to_lowercase();
+        let value = parts.next().unwrap_or("").trim();
+        match name.as_str() {
+            "content-length" => length = value.parse().map_err(|_| invalid("bad Content-Length"))?,
+            "transfer-encoding" => return Err(invalid("only Content-Length bodies are supported")),
+            _ => {},
+        }
+    }
+    if length > MAX_BODY_LENGTH {
+        return Err(invalid("body too large"));
+    }
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body)?;
+
+    let (path, query) = match target.find('?') {
+        Some(question) => (target[..question].to_string(), target[question + 1..].to_string()),
+        None => (target, String::new()),
+    };
+    Ok(Request {
+        method: method,
+        path: path,
+        query: query,
+        body: body,
+    })
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        _ => "Internal Server Error",
+    }
+}
+
+pub fn write_response<W>(writer: &mut W, status: u16, body: &[u8]) -> io::Result<()> where W: Write {
+    write!(writer, "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+           status, reason(status), body.len())?;
+    writer.write_all(body)?;
+    writer.flush()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::io::Cursor;
+
+    #[test]
+    fn test_read_request() {
+        let mut request = Cursor::new(&b"POST /users/alice/records?since=3 HTTP/1.1\r\nHost: localhost\r\ncontent-length: 2\r\n\r\n{}"[..]);
+        assert_eq!(read_request(&mut request).expect("expected a request"), Request {
+            method: "POST".to_string(),
+            path: "/users/alice/records".to_string(),
+            query: "since=3".to_string(),
+            body: b"{}".to_vec(),
+        });
+
+        let mut request = Cursor::new(&b"GET /users/alice/records HTTP/1.1\r\n\r\n"[..]);
+        let request = read_request(&mut request).expect("expected a request");
+        assert_eq!((request.query.as_str(), request.body.len()), ("", 0));
+    }
+
+    #[test]
+    fn test_read_bad_requests() {
+        for request in &[&b"GET\r\n\r\n"[..],
+                         &b"GET / HTTP/1.1\r\nContent-Length: 10\r\n\r\n{}"[..],
+                         &b"GET / HTTP/1.1\r\nContent-Length: lots\r\n\r\n"[..],
+                         &b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n"[..],
+                         &b"GET / HTTP/1.1\r\nHost: localhost\r\n"[..]] {
+            assert!(read_request(&mut Cursor::new(*request)).is_err(), "expected {:?} to be refused", String::from_utf8_lossy(request));
+        }
+    }
+
+    #[test]
+    fn test_read_oversized_requests() {
+        let long_line = format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(MAX_LINE_LENGTH));
+        let long_header = format!("GET / HTTP/1.1\r\nX-Padding: {}\r\n\r\n", "a".repeat(MAX_LINE_LENGTH));
+        let many_headers = format!("GET / HTTP/1.1\r\n{}\r\n", "X-Header: 1\r\n".repeat(MAX_HEADERS + 1));
+        let big_body = format!("POST / HTTP/1.1\r\nContent-Length: {}\r\n\r\n", MAX_BODY_LENGTH + 1);
+        for request in &[long_line, long_header, many_headers, big_body] {
+            let error = read_request(&mut Cursor::new(request.as_bytes())).expect_err("expected the request to be refused");
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        }
+
+        let most_headers = format!("GET / HTTP/1.1\r\n{}\r\n", "X-Header: 1\r\n".repeat(MAX_HEADERS));
+        assert!(read_request(&mut Cursor::new(most_headers.as_bytes())).is_ok());
+    }
+
+    #[test]
+    fn test_write_response() {
+        let mut response = vec![];
+        write_response(&mut response, 409, b"{\"head\":2}").expect("expected a response");
+        assert_eq!(String::from_utf8(response).expect("expected utf-8"),
+                   "HTTP/1.1 409 Conflict\r\nContent-Type: application/json\r\nContent-Length: 10\r\nConnection: close\r\n\r\n{\"head\":2}");
+    }
+}
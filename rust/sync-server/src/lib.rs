@@ -0,0 +1,282 @@
+// Copyright 2016 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! A reference server for syncing Toodle stores, speaking the protocol described in
+//! `toodle::remote`. Each user's log is kept in SQLite and lives at `/users/USER`, where
+//! a user is any name made of ASCII letters, digits, `-` and `_`.
+//!
+//! There's no authentication: anyone who can reach the server can read and write every
+//! log, so it should only listen on localhost.
+
+extern crate base64;
+extern crate rusqlite;
+extern crate serde;
+extern crate serde_json;
+extern crate toodle;
+
+mod http;
+pub mod storage;
+
+use std::io::{
+    self,
+    BufReader,
+};
+use std::net::{
+    SocketAddr,
+    TcpListener,
+    TcpStream,
+    ToSocketAddrs,
+};
+use std::panic::{
+    self,
+    AssertUnwindSafe,
+};
+use std::sync::{
+    mpsc,
+    Arc,
+    Mutex,
+};
+use std::thread;
+use std::time::Duration;
+
+use serde::Serialize;
+
+use toodle::remote::{
+    Download,
+    Failure,
+    Head,
+    Record,
+    Upload,
+};
+use toodle::sync::UploadOutcome;
+
+use http::Request;
+pub use storage::Storage;
+
+/// How long to wait for a client to finish sending its request.
+const TIMEOUT_SECONDS: u64 = 30;
+
+/// How many connections are answered at once.
+const WORKERS: usize = 8;
+
+/// How many accepted connections can wait for a worker. Once this many are waiting, the
+/// server stops accepting until a worker is free.
+const BACKLOG: usize = 64;
+
+/// A status and a JSON body.
+type Reply = (u16, Vec<u8>);
+
+fn reply<T>(status: u16, body: &T) -> Reply where T: Serialize {
+    (status, serde_json::to_vec(body).expect("expected replies to serialize"))
+}
+
+fn failure(status: u16, message: String) -> Reply {
+    reply(status, &Failure { error: message })
+}
+
+/// The user whose records `path` names, if it names any.
+fn records_user(path: &str) -> Option<&str> {
+    if !path.starts_with("/users/") || !path.ends_with("/records") || path.len() <= "/users/".len() + "/records".len() {
+        return None;
+    }
+    let user = &path["/users/".len()..path.len() - "/records".len()];
+    if !user.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        return None;
+    }
+    Some(user)
+}
+
+fn since(query: &str) -> Result<u64, String> {
+    for parameter in query.split('&') {
+        if parameter.starts_with("since=") {
+            let version = &parameter["since=".len()..];
+            return version.parse().map_err(|_| format!("{:?} isn't a version", version));
+        }
+    }
+    Ok(0)
+}
+
+fn download(storage: &Storage, user: &str, query: &str) -> Reply {
+    let version = match since(query) {
+        Ok(version) => version,
+        Err(message) => return failure(400, message),
+    };
+    match storage.head(user).and_then(|head| storage.download_since(user, version).map(|records| (head, records))) {
+        Ok((head, records)) => reply(200, &Download {
+            head: head,
+            records: records.into_iter().map(|(version, payload)| Record { version: version, payload: base64::encode(&payload) }).collect(),
+        }),
+        Err(e) => failure(500, e.to_string()),
+    }
+}
+
+fn upload(storage: &mut Storage, user: &str, body: &[u8]) -> Reply {
+    let upload: Upload = match serde_json::from_slice(body) {
+        Ok(upload) => upload,
+        Err(e) => return failure(400, format!("bad upload: {}", e)),
+    };
+    let records: Option<Vec<Vec<u8>>> = upload.records.iter().map(|record| base64::decode(record).ok()).collect();
+    let records = match records {
+        Some(records) => records,
+        None => return failure(400, "records must be base64".to_string()),
+    };
+    match storage.upload(user, upload.base, records) {
+        Ok(UploadOutcome::Accepted { head }) => reply(200, &Head { head: head }),
+        Ok(UploadOutcome::OutOfDate { head }) => reply(409, &Head { head: head }),
+        Err(e) => failure(500, e.to_string()),
+    }
+}
+
+fn respond(storage: &Mutex<Storage>, request: &Request) -> Reply {
+    let user = match records_user(&request.path) {
+        Some(user) => user,
+        None => return failure(404, format!("nothing at {}", request.path)),
+    };
+    // Holding the lock for the whole request keeps each upload's check and append atomic.
+    let mut storage = storage.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    match request.method.as_str() {
+        "GET" => download(&storage, user, &request.query),
+        "POST" => upload(&mut storage, user, &request.body),
+        method => failure(405, format!("can't {} records", method)),
+    }
+}
+
+fn serve(storage: &Mutex<Storage>, stream: TcpStream) -> io::Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(TIMEOUT_SECONDS)))?;
+    let (status, body) = match http::read_request(&mut BufReader::new(&stream)) {
+        Ok(request) => respond(storage, &request),
+        Err(e) => failure(400, e.to_string()),
+    };
+    http::write_response(&mut &stream, status, &body)
+}
+
+/// Answers connections from `receiver` until the server stops.
+fn work(receiver: &Mutex<mpsc::Receiver<TcpStream>>, storage: &Mutex<Storage>) {
+    loop {
+        let stream = {
+            let receiver = receiver.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            match receiver.recv() {
+                Ok(stream) => stream,
+                Err(_) => return,
+            }
+        };
+        // A panic only loses its own connection, not the worker.
+        match panic::catch_unwind(AssertUnwindSafe(|| serve(storage, stream))) {
+            Ok(Ok(())) => (),
+            Ok(Err(e)) => eprintln!("toodle-sync-server: can't answer a request: {}", e),
+            Err(_) => eprintln!("toodle-sync-server: answering a request panicked"),
+        }
+    }
+}
+
+/// Answers requests on a socket with a fixed number of worker threads.
+pub struct Server {
+    listener: TcpListener,
+    storage: Arc<Mutex<Storage>>,
+}
+
+impl Server {
+    pub fn bind<A>(address: A, storage: Storage) -> io::Result<Server> where A: ToSocketAddrs {
+        Ok(Server {
+            listener: TcpListener::bind(address)?,
+            storage: Arc::new(Mutex::new(storage)),
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Serves forever. Failures are reported on stderr and only affect their connection.
+    pub fn run(self) {
+        let (sender, receiver) = mpsc::sync_channel::<TcpStream>(BACKLOG);
+        let receiver = Arc::new(Mutex::new(receiver));
+        for _ in 0..WORKERS {
+            let receiver = receiver.clone();
+            let storage = self.storage.clone();
+            thread::spawn(move || work(&receiver, &storage));
+        }
+        for stream in self.listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    eprintln!("toodle-sync-server: can't accept a connection: {}", e);
+                    continue;
+                },
+            };
+            if sender.send(stream).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use serde_json::Value;
+
+    fn request(method: &str, target: &str, body: &str) -> Request {
+        let mut parts = target.splitn(2, '?');
+        Request {
+            method: method.to_string(),
+            path: parts.next().unwrap().to_string(),
+            query: parts.next().unwrap_or("").to_string(),
+            body: body.as_bytes().to_vec(),
+        }
+    }
+
+    fn call(storage: &Mutex<Storage>, method: &str, target: &str, body: &str) -> (u16, Value) {
+        let (status, body) = respond(storage, &request(method, target, body));
+        (status, serde_json::from_slice(&body).expect("expected a JSON reply"))
+    }
+
+    fn json_head(head: u64) -> Value {
+        serde_json::to_value(Head { head: head }).unwrap()
+    }
+
+    #[test]
+    fn test_upload_then_download() {
+        let storage = Mutex::new(Storage::open_in_memory().expect("expected storage"));
+        let upload = format!(r#"{{"base": 0, "records": ["{}", "{}"]}}"#, base64::encode(b"one"), base64::encode(b"two"));
+        assert_eq!(call(&storage, "POST", "/users/alice/records", &upload), (200, json_head(2)));
+
+        let (status, download) = call(&storage, "GET", "/users/alice/records?since=1", "");
+        assert_eq!(status, 200);
+        let download: Download = serde_json::from_value(download).expect("expected a download");
+        assert_eq!(download, Download {
+            head: 2,
+            records: vec![Record { version: 2, payload: base64::encode(b"two") }],
+        });
+    }
+
+    #[test]
+    fn test_out_of_date_uploads_conflict() {
+        let storage = Mutex::new(Storage::open_in_memory().expect("expected storage"));
+        let upload = format!(r#"{{"base": 0, "records": ["{}"]}}"#, base64::encode(b"one"));
+        call(&storage, "POST", "/users/alice/records", &upload);
+        assert_eq!(call(&storage, "POST", "/users/alice/records", &upload), (409, json_head(1)));
+        assert_eq!(call(&storage, "POST", "/users/bob/records", &upload), (200, json_head(1)));
+    }
+
+    #[test]
+    fn test_bad_requests() {
+        let storage = Mutex::new(Storage::open_in_memory().expect("expected storage"));
+        assert_eq!(call(&storage, "GET", "/users/alice/records?since=soon", "").0, 400);
+        assert_eq!(call(&storage, "POST", "/users/alice/records", r#"{"base": 0, "records": ["%%%%"]}"#).0, 400);
+        assert_eq!(call(&storage, "POST", "/users/alice/records", "[]").0, 400);
+        assert_eq!(call(&storage, "DELETE", "/users/alice/records", "").0, 405);
+        assert_eq!(call(&storage, "GET", "/users/../records", "").0, 404);
+        assert_eq!(call(&storage, "GET", "/users//records", "").0, 404);
+        assert_eq!(call(&storage, "GET", "/users/records", "").0, 404);
+        assert_eq!(call(&storage, "GET", "/", "").0, 404);
+    }
+}
@@ -0,0 +1,54 @@
+// Copyright 2016 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! `toodle-sync-server [--port PORT] [--db PATH]` serves the logs in PATH
+//! (`toodle-sync.db` by default) on localhost. Port 0 picks a free port; either way, the
+//! first line of output says where it's listening.
+
+extern crate toodle_sync_server;
+
+use std::env;
+use std::process;
+
+use toodle_sync_server::{
+    Server,
+    Storage,
+};
+
+const USAGE: &'static str = "usage: toodle-sync-server [--port PORT] [--db PATH]";
+
+const DEFAULT_PORT: u16 = 8088;
+const DEFAULT_DATABASE: &'static str = "toodle-sync.db";
+
+fn fail(code: i32, message: &str) -> ! {
+    eprintln!("toodle-sync-server: {}", message);
+    process::exit(code);
+}
+
+fn main() {
+    let mut port = DEFAULT_PORT;
+    let mut db = DEFAULT_DATABASE.to_string();
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match (arg.as_str(), args.next()) {
+            ("--port", Some(value)) => port = value.parse().unwrap_or_else(|_| fail(2, &format!("{:?} isn't a port\n{}", value, USAGE))),
+            ("--db", Some(value)) => db = value,
+            _ => fail(2, USAGE),
+        }
+    }
+
+    let storage = Storage::open(&db).unwrap_or_else(|e| fail(1, &format!("can't open {}: {}", db, e)));
+    let server = Server::bind(("127.0.0.1", port), storage).unwrap_or_else(|e| fail(1, &format!("can't listen on port {}: {}", port, e)));
+    match server.local_addr() {
+        Ok(address) => println!("listening on http://{}/", address),
+        Err(e) => fail(1, &e.to_string()),
+    }
+    server.run();
+}
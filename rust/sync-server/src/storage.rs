@@ -0,0 +1,114 @@
+// Copyright 2016 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+use std::path::Path;
+
+use rusqlite::{
+    self,
+    Connection,
+};
+
+use toodle::sync::UploadOutcome;
+
+/// Every user's log, kept in one SQLite table. A user's versions start at 1 and have no
+/// gaps, so the latest one is also the number of records.
+pub struct Storage {
+    connection: Connection,
+}
+
+fn head(connection: &Connection, user: &str) -> rusqlite::Result<u64> {
+    let head: i64 = connection.query_row("SELECT COALESCE(MAX(version), 0) FROM records WHERE user = ?", &[&user], |row| row.get(0))?;
+    Ok(head as u64)
+}
+
+impl Storage {
+    pub fn open<P>(path: P) -> rusqlite::Result<Storage> where P: AsRef<Path> {
+        Storage::init(Connection::open(path)?)
+    }
+
+    pub fn open_in_memory() -> rusqlite::Result<Storage> {
+        Storage::init(Connection::open_in_memory()?)
+    }
+
+    fn init(connection: Connection) -> rusqlite::Result<Storage> {
+        connection.execute_batch("CREATE TABLE IF NOT EXISTS records (
+                                      user TEXT NOT NULL,
+                                      version INTEGER NOT NULL,
+                                      payload BLOB NOT NULL,
+                                      PRIMARY KEY (user, version)
+                                  )")?;
+        Ok(Storage { connection: connection })
+    }
+
+    /// The latest version in `user`'s log, or 0 if it's empty.
+    pub fn head(&self, user: &str) -> rusqlite::Result<u64> {
+        head(&self.connection, user)
+    }
+
+    /// Every record in `user`'s log after `version`, paired with its version, in order.
+    pub fn download_since(&self, user: &str, version: u64) -> rusqlite::Result<Vec<(u64, Vec<u8>)>> {
+        let mut stmt = self.connection.prepare("SELECT version, payload FROM records WHERE user = ? AND version > ? ORDER BY version")?;
+        let records = stmt.query_map(&[&user, &(version as i64)], |row| (row.get::<_, i64>(0) as u64, row.get(1)))?;
+        records.collect()
+    }
+
+    /// Appends `records` to `user`'s log if and only if `base` is its latest version.
+    pub fn upload(&mut self, user: &str, base: u64, records: Vec<Vec<u8>>) -> rusqlite::Result<UploadOutcome> {
+        let transaction = self.connection.transaction()?;
+        let head = head(&transaction, user)?;
+        if base != head {
+            return Ok(UploadOutcome::OutOfDate { head: head });
+        }
+        for (offset, record) in records.iter().enumerate() {
+            let version = (head + 1 + offset as u64) as i64;
+            transaction.execute("INSERT INTO records (user, version, payload) VALUES (?, ?, ?)", &[&user, &version, record])?;
+        }
+        transaction.commit()?;
+        Ok(UploadOutcome::Accepted { head: head + records.len() as u64 })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn records(names: &[&str]) -> Vec<Vec<u8>> {
+        names.iter().map(|name| name.as_bytes().to_vec()).collect()
+    }
+
+    #[test]
+    fn test_upload_and_download() {
+        let mut storage = Storage::open_in_memory().expect("expected storage");
+        assert_eq!(storage.head("alice").expect("expected a head"), 0);
+        assert_eq!(storage.upload("alice", 0, records(&["one", "two"])).expect("expected an upload"), UploadOutcome::Accepted { head: 2 });
+        assert_eq!(storage.upload("alice", 2, records(&["three"])).expect("expected an upload"), UploadOutcome::Accepted { head: 3 });
+
+        assert_eq!(storage.download_since("alice", 1).expect("expected records"),
+                   vec![(2, b"two".to_vec()), (3, b"three".to_vec())]);
+        assert!(storage.download_since("alice", 3).expect("expected records").is_empty());
+    }
+
+    #[test]
+    fn test_out_of_date_uploads_are_rejected() {
+        let mut storage = Storage::open_in_memory().expect("expected storage");
+        storage.upload("alice", 0, records(&["one", "two"])).expect("expected an upload");
+        assert_eq!(storage.upload("alice", 1, records(&["late"])).expect("expected an upload"), UploadOutcome::OutOfDate { head: 2 });
+        assert_eq!(storage.upload("alice", 3, records(&["early"])).expect("expected an upload"), UploadOutcome::OutOfDate { head: 2 });
+        assert_eq!(storage.head("alice").expect("expected a head"), 2);
+    }
+
+    #[test]
+    fn test_users_have_separate_logs() {
+        let mut storage = Storage::open_in_memory().expect("expected storage");
+        storage.upload("alice", 0, records(&["alice's"])).expect("expected an upload");
+        assert_eq!(storage.upload("bob", 0, records(&["bob's"])).expect("expected an upload"), UploadOutcome::Accepted { head: 1 });
+        assert_eq!(storage.download_since("bob", 0).expect("expected records"), vec![(1, b"bob's".to_vec())]);
+    }
+}
@@ -0,0 +1,218 @@
+// Copyright 2016 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! Runs the `toodle-sync-server` binary and syncs stores through it.
+
+extern crate serde_json;
+extern crate toodle;
+
+use std::env;
+use std::fs::{
+    self,
+    File,
+};
+use std::io::{
+    BufRead,
+    BufReader,
+    Read,
+};
+use std::path::PathBuf;
+use std::process::{
+    self,
+    Child,
+    Command,
+    Stdio,
+};
+use std::sync::atomic::{
+    AtomicUsize,
+    Ordering,
+};
+
+use toodle::Toodle;
+use toodle::api::{
+    self,
+    FilterParams,
+    ItemRecord,
+    NewItem,
+    Request,
+    ServerSpec,
+};
+use toodle::encryption::{
+    EncryptedServer,
+    KeyBundle,
+};
+use toodle::remote::RemoteServer;
+use toodle::sync::{
+    SyncServer,
+    UploadOutcome,
+};
+
+fn server_binary() -> PathBuf {
+    // Tests are built in target/<profile>/deps, next to which cargo puts the binary.
+    let mut path = env::current_exe().expect("expected the test's path");
+    path.pop();
+    if path.ends_with("deps") {
+        path.pop();
+    }
+    path.push(format!("toodle-sync-server{}", env::consts::EXE_SUFFIX));
+    path
+}
+
+/// A directory of its own for each server's store, so that tests running at once, here or
+/// in another checkout, can't share one.
+fn temp_directory() -> PathBuf {
+    static NEXT_DIRECTORY: AtomicUsize = AtomicUsize::new(0);
+    let directory = env::temp_dir().join(format!("toodle-sync-server-{}-{}", process::id(), NEXT_DIRECTORY.fetch_add(1, Ordering::SeqCst)));
+    let _ = fs::remove_dir_all(&directory);
+    fs::create_dir_all(&directory).expect("expected a temporary directory");
+    directory
+}
+
+/// A running server, which is stopped when this is dropped.
+struct RunningServer {
+    process: Child,
+    url: String,
+    db: PathBuf,
+}
+
+impl RunningServer {
+    fn start(name: &str) -> RunningServer {
+        let db = temp_directory().join(format!("{}.db", name));
+        let mut process = Command::new(server_binary())
+            .arg("--port").arg("0")
+            .arg("--db").arg(&db)
+            .stdout(Stdio::piped())
+            .spawn()
+            .expect("expected the server to start");
+        let mut line = String::new();
+        BufReader::new(process.stdout.as_mut().expect("expected stdout")).read_line(&mut line).expect("expected the server's address");
+        let url = line.trim().trim_start_matches("listening on ").trim_end_matches('/').to_string();
+        RunningServer { process: process, url: url, db: db }
+    }
+
+    fn user(&self, user: &str) -> String {
+        format!("{}/users/{}", self.url, user)
+    }
+}
+
+impl Drop for RunningServer {
+    fn drop(&mut self) {
+        let _ = self.process.kill();
+        let _ = self.process.wait();
+    }
+}
+
+fn add(toodle: &mut Toodle, name: &str, labels: Vec<String>) {
+    api::execute(toodle, Request::CreateItem(NewItem {
+        name: name.to_string(),
+        due_date: None,
+        completion_date: None,
+        labels: labels,
+        notes: None,
+        priority: None,
+        starred: false,
+        parent: None,
+        recurrence: None,
+    })).expect("expected an item");
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack.windows(needle.len()).any(|window| window == needle)
+}
+
+/// Every item's name and labels, sorted by name.
+fn summary(toodle: &mut Toodle) -> Vec<(String, Vec<String>)> {
+    let items = api::execute(toodle, Request::GetItems { filter: FilterParams::default() }).expect("expected items");
+    let items: Vec<ItemRecord> = serde_json::from_value(items).expect("expected item records");
+    let mut summary: Vec<(String, Vec<String>)> = items.into_iter()
+        .map(|item| (item.name, item.labels.into_iter().map(|label| label.name).collect()))
+        .collect();
+    summary.sort();
+    summary
+}
+
+#[test]
+fn test_three_stores_converge() {
+    let server = RunningServer::start("converge");
+    let url = server.user("alice");
+    let mut stores: Vec<Toodle> = (0..3).map(|_| Toodle::new(String::new(), None).expect("expected a new toodle")).collect();
+
+    api::execute(&mut stores[0], Request::CreateLabel { name: "home".to_string(), color: "#ff0000".to_string() }).expect("expected a label");
+    add(&mut stores[0], "Buy milk", vec!["home".to_string()]);
+    add(&mut stores[1], "Write report", vec![]);
+    add(&mut stores[2], "Book flights", vec![]);
+
+    // The first round brings each store what the stores before it uploaded, and the
+    // second brings the rest. The last store syncs through the JSON API, like the apps.
+    for _ in 0..2 {
+        for store in stores.iter_mut().take(2) {
+            store.sync(&mut RemoteServer::new(&url).expect("expected a server")).expect("expected a sync");
+        }
+        api::execute(&mut stores[2], Request::Sync { server: ServerSpec::Http { url: url.clone(), keys: None } }).expect("expected a sync");
+    }
+
+    let expected = vec![
+        ("Book flights".to_string(), vec![]),
+        ("Buy milk".to_string(), vec!["home".to_string()]),
+        ("Write report".to_string(), vec![]),
+    ];
+    for store in stores.iter_mut() {
+        assert_eq!(summary(store), expected);
+    }
+}
+
+#[test]
+fn test_out_of_date_uploads_are_rejected() {
+    let server = RunningServer::start("out-of-date");
+    let mut first = RemoteServer::new(&server.user("alice")).expect("expected a server");
+    let mut second = RemoteServer::new(&server.user("alice")).expect("expected a server");
+
+    assert_eq!(first.upload(0, vec![b"one".to_vec(), b"two".to_vec()]).expect("expected an upload"), UploadOutcome::Accepted { head: 2 });
+    assert_eq!(second.upload(0, vec![b"three".to_vec()]).expect("expected an upload"), UploadOutcome::OutOfDate { head: 2 });
+    assert_eq!(second.download_since(1).expect("expected records"), vec![(2, b"two".to_vec())]);
+    assert_eq!(second.upload(2, vec![b"three".to_vec()]).expect("expected an upload"), UploadOutcome::Accepted { head: 3 });
+
+    // Other users' logs are untouched.
+    let mut bob = RemoteServer::new(&server.user("bob")).expect("expected a server");
+    assert!(bob.download_since(0).expect("expected records").is_empty());
+}
+
+#[test]
+fn test_encrypted_records_are_opaque_to_the_server() {
+    let server = RunningServer::start("encrypted");
+    let url = server.user("alice");
+    let keys = KeyBundle::generate().expect("expected keys");
+    let mut first = Toodle::new(String::new(), None).expect("expected a new toodle");
+    let mut second = Toodle::new(String::new(), None).expect("expected a new toodle");
+    api::execute(&mut first, Request::CreateLabel { name: "groceries".to_string(), color: "#ff0000".to_string() }).expect("expected a label");
+    add(&mut first, "Buy oat milk", vec!["groceries".to_string()]);
+
+    for store in vec![&mut first, &mut second] {
+        store.sync(&mut EncryptedServer::new(RemoteServer::new(&url).expect("expected a server"), &keys).expect("expected a server"))
+            .expect("expected a sync");
+    }
+    assert_eq!(summary(&mut second), vec![("Buy oat milk".to_string(), vec!["groceries".to_string()])]);
+
+    // Bob doesn't encrypt, which shows that names would be visible if they were there.
+    let mut bob = Toodle::new(String::new(), None).expect("expected a new toodle");
+    add(&mut bob, "Unencrypted", vec![]);
+    bob.sync(&mut RemoteServer::new(&server.user("bob")).expect("expected a server")).expect("expected a sync");
+
+    let mut raw = RemoteServer::new(&url).expect("expected a server");
+    let records = raw.download_since(0).expect("expected records");
+    assert!(!records.is_empty());
+    let mut db = vec![];
+    File::open(&server.db).and_then(|mut file| file.read_to_end(&mut db)).expect("expected the server's database");
+    assert!(contains(&db, b"Unencrypted"));
+    for plaintext in &[&b"oat milk"[..], b"groceries", b"#ff0000"] {
+        assert!(records.iter().all(|&(_, ref record)| !contains(record, plaintext)));
+        assert!(!contains(&db, plaintext), "expected {:?} to be hidden", String::from_utf8_lossy(plaintext));
+    }
+}
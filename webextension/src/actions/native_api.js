@@ -0,0 +1,59 @@
+// Talks to the toodle-native-host binary, which serves the same operations as FakeApi
+// from a Toodle store on disk. See rust/native-host.
+const HOST_NAME = 'toodle';
+
+let port = null;
+let nextId = 1;
+const pending = new Map();
+
+function connect() {
+  if (port) {
+    return port;
+  }
+  port = browser.runtime.connectNative(HOST_NAME);
+  port.onMessage.addListener(reply => {
+    const callbacks = pending.get(reply.id);
+    if (!callbacks) {
+      return;
+    }
+    pending.delete(reply.id);
+    if (reply.error) {
+      callbacks.reject(new Error(reply.error.message));
+    } else {
+      callbacks.resolve(reply.result);
+    }
+  });
+  port.onDisconnect.addListener(p => {
+    const error = new Error((p.error && p.error.message) || 'The native host disconnected.');
+    for (let callbacks of pending.values()) {
+      callbacks.reject(error);
+    }
+    pending.clear();
+    port = null;
+  });
+  return port;
+}
+
+function call(method, ...args) {
+  return new Promise((resolve, reject) => {
+    const id = nextId++;
+    pending.set(id, {resolve, reject});
+    connect().postMessage({id, method, args});
+  });
+}
+
+const NativeApi = {
+  createTodo: name => call('createTodo', name),
+  removeTodo: uuid => call('removeTodo', uuid),
+  getTodos: () => call('getTodos'),
+  todoChangeName: (uuid, newTodoName) => call('todoChangeName', uuid, newTodoName),
+  todoChangeDueDate: (uuid, dueDate) => call('todoChangeDueDate', uuid, dueDate),
+  todoChangeCompletionDate: (uuid, completionDate) => call('todoChangeCompletionDate', uuid, completionDate),
+  todoAddLabel: (uuid, labelName) => call('todoAddLabel', uuid, labelName),
+  todoRemoveLabel: (uuid, labelName) => call('todoRemoveLabel', uuid, labelName),
+  getLabels: () => call('getLabels'),
+  addLabel: (name, color) => call('addLabel', name, color),
+  removeLabel: labelName => call('removeLabel', labelName)
+};
+
+export default NativeApi;